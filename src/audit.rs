@@ -0,0 +1,515 @@
+use crate::aws::{all_entries, retry_config, timeout_config, EC2Clients, Entry};
+use crate::config::{Description, OutputFormat, PrefixListId};
+use crate::exit::Exit;
+
+use aws_sdk_ec2::client::Client as EC2Client;
+use aws_sdk_ec2::model::PrefixListEntry;
+use color_eyre::{Report, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::future::Future;
+use std::time::Duration;
+
+/// How bad a problem found by the `verify` subcommand is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    /// Untidy, but harmless
+    Info,
+    /// Likely a mistake
+    Warning,
+    /// AWS shouldn't even allow it
+    Error,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            Self::Info => "info",
+            Self::Warning => "warning",
+            Self::Error => "error",
+        })
+    }
+}
+
+/// An entry, or pair of entries, breaking one of the rules a tidy prefix list follows.
+#[derive(Debug, Serialize)]
+pub struct Finding {
+    pub level: Level,
+    pub prefix_list_id: String,
+    /// Such as `overlap`, for filtering the JSON output
+    pub kind: &'static str,
+    pub message: String,
+}
+
+/// An entry as shown by the `list` subcommand.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ListedEntry {
+    pub prefix_list_id: String,
+    pub cidr: String,
+    pub description: String,
+    /// Whether it carries the description given, always false without one
+    pub owned: bool,
+    /// The host owning it, for entries written with --per-host
+    pub host: Option<String>,
+}
+
+impl ListedEntry {
+    fn new(prefix_list_id: &PrefixListId, entry: Entry, description: Option<&Description>) -> Self {
+        Self {
+            prefix_list_id: prefix_list_id.to_string(),
+            cidr: entry.cidr.to_string(),
+            owned: description.is_some_and(|description| description.matches_owned_entry(&entry)),
+            host: description
+                .and_then(|description| description.host(&entry))
+                .map(String::from),
+            description: entry.description,
+        }
+    }
+}
+
+/// Checks the entries of a prefix list, returning what's wrong with them.
+pub fn check(prefix_list_id: &PrefixListId, raw: &[PrefixListEntry]) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    let mut finding = |level, kind, message| {
+        findings.push(Finding {
+            level,
+            prefix_list_id: prefix_list_id.to_string(),
+            kind,
+            message,
+        })
+    };
+
+    let mut entries = Vec::with_capacity(raw.len());
+    for entry in raw {
+        match Entry::try_from(entry) {
+            Ok(entry) => entries.push(entry),
+            Err(err) => finding(
+                Level::Error,
+                "malformed",
+                format!("{}: {}", describe_raw(entry), err),
+            ),
+        }
+    }
+
+    for entry in &entries {
+        if entry.description.is_empty() {
+            finding(
+                Level::Info,
+                "no-description",
+                format!("{} has no description", entry.cidr),
+            );
+        } else if entry.description.chars().count() > Description::MAX_LENGTH {
+            finding(
+                Level::Warning,
+                "long-description",
+                format!(
+                    "{} has a description longer than {} characters",
+                    entry.cidr,
+                    Description::MAX_LENGTH
+                ),
+            );
+        }
+    }
+
+    // Host bits don't matter to AWS, so 10.0.0.1/8 is the same network as 10.0.0.0/8
+    for (i, a) in entries.iter().enumerate() {
+        for b in &entries[i + 1..] {
+            let (net_a, net_b) = (a.cidr.trunc(), b.cidr.trunc());
+            if net_a == net_b {
+                finding(
+                    Level::Error,
+                    "duplicate",
+                    format!("{} and {} are the same network", describe(a), describe(b)),
+                );
+            } else if net_a.contains(&net_b) || net_b.contains(&net_a) {
+                let (outer, inner) = match net_a.contains(&net_b) {
+                    true => (a, b),
+                    false => (b, a),
+                };
+                finding(
+                    Level::Warning,
+                    "overlap",
+                    format!("{} contains {}", describe(outer), describe(inner)),
+                );
+            }
+        }
+    }
+
+    findings
+}
+
+fn describe(entry: &Entry) -> String {
+    describe_cidr(&entry.cidr.to_string(), &entry.description)
+}
+
+fn describe_raw(entry: &PrefixListEntry) -> String {
+    describe_cidr(
+        entry.cidr.as_deref().unwrap_or("an entry without a CIDR"),
+        entry.description.as_deref().unwrap_or_default(),
+    )
+}
+
+fn describe_cidr(cidr: &str, description: &str) -> String {
+    match description.is_empty() {
+        true => cidr.to_string(),
+        false => format!("{} (\"{}\")", cidr, description),
+    }
+}
+
+/// Runs the `verify` subcommand, telling how doorman should exit.
+pub fn run(
+    prefix_list_ids: Vec<PrefixListId>,
+    region: Option<String>,
+    fail_on: Level,
+    output: OutputFormat,
+) -> Exit {
+    block_on(async {
+        let client = client(region).await;
+        verify(&client, &prefix_list_ids, fail_on, output).await
+    })
+}
+
+/// Runs the `list` subcommand, telling how doorman should exit.
+pub fn run_list(
+    prefix_list_ids: Vec<PrefixListId>,
+    region: Option<String>,
+    description: Option<Description>,
+    output: OutputFormat,
+) -> Exit {
+    block_on(async {
+        let client = client(region).await;
+        list(&client, &prefix_list_ids, description.as_ref(), output).await
+    })
+}
+
+/// Subcommands run before the main runtime is started, so they get their own. Errors go to stderr, leaving stdout to
+/// the output.
+pub fn block_on(subcommand: impl Future<Output = Result<Exit>>) -> Exit {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(Report::from)
+        .and_then(|runtime| runtime.block_on(subcommand))
+        .unwrap_or_else(|err| {
+            eprintln!("Error: {:#}", err);
+            Exit::of(&err)
+        })
+}
+
+pub async fn client(region: Option<String>) -> EC2Client {
+    // The defaults of --aws-connect-timeout, --aws-timeout and --aws-max-attempts
+    let timeout_config = timeout_config(Duration::from_secs(3), Duration::from_secs(10));
+    let retry_config = retry_config(3);
+    let (client, _) = EC2Clients::new(region, timeout_config, retry_config)
+        .get(None)
+        .await;
+    client
+}
+
+/// Prints the entries of every prefix list. Malformed ones are left out, with a warning on stderr.
+async fn list(
+    client: &EC2Client,
+    prefix_list_ids: &[PrefixListId],
+    description: Option<&Description>,
+    output: OutputFormat,
+) -> Result<Exit> {
+    let mut listed = Vec::new();
+    for prefix_list_id in prefix_list_ids {
+        for entry in all_entries(client, prefix_list_id).await? {
+            match Entry::try_from(&entry) {
+                Ok(entry) => listed.push(ListedEntry::new(prefix_list_id, entry, description)),
+                Err(err) => eprintln!(
+                    "Warning: skipping malformed entry {} in {}: {}",
+                    describe_raw(&entry),
+                    prefix_list_id,
+                    err
+                ),
+            }
+        }
+    }
+
+    match output {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&listed)?),
+        OutputFormat::Text => {
+            for entry in &listed {
+                println!(
+                    "{}  {:<18}  {:<5}  {:<20}  {}",
+                    entry.prefix_list_id,
+                    entry.cidr,
+                    if entry.owned { "owned" } else { "" },
+                    entry.host.as_deref().unwrap_or_default(),
+                    entry.description
+                );
+            }
+        }
+    }
+    Ok(Exit::Success)
+}
+
+/// Checks every prefix list, printing the findings, and tells how doorman should exit.
+///
+/// Finding anything at `fail_on` or above is a failure.
+async fn verify(
+    client: &EC2Client,
+    prefix_list_ids: &[PrefixListId],
+    fail_on: Level,
+    output: OutputFormat,
+) -> Result<Exit> {
+    let mut findings = Vec::new();
+    let mut counts = Vec::with_capacity(prefix_list_ids.len());
+    for prefix_list_id in prefix_list_ids {
+        let entries = all_entries(client, prefix_list_id).await?;
+        counts.push((prefix_list_id, entries.len()));
+        findings.extend(check(prefix_list_id, &entries));
+    }
+
+    match output {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&findings)?),
+        OutputFormat::Text => {
+            for (prefix_list_id, count) in counts {
+                let found = findings
+                    .iter()
+                    .filter(|finding| finding.prefix_list_id == prefix_list_id.as_str())
+                    .count();
+                println!(
+                    "{}: {} entries, {} problem(s) found",
+                    prefix_list_id, count, found
+                );
+            }
+            for finding in &findings {
+                println!(
+                    "{}  {:<7}  {}: {}",
+                    finding.prefix_list_id, finding.level, finding.kind, finding.message
+                );
+            }
+        }
+    }
+
+    Ok(verdict(&findings, fail_on))
+}
+
+/// Fails if anything was found at `fail_on` or above.
+fn verdict(findings: &[Finding], fail_on: Level) -> Exit {
+    match findings.iter().any(|finding| finding.level >= fail_on) {
+        true => Exit::Failure,
+        false => Exit::Success,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id() -> PrefixListId {
+        "pl-12345678".parse().unwrap()
+    }
+
+    fn raw(cidr: Option<&str>, description: &str) -> PrefixListEntry {
+        PrefixListEntry::builder()
+            .set_cidr(cidr.map(String::from))
+            .set_description(Some(description.to_string()).filter(|d| !d.is_empty()))
+            .build()
+    }
+
+    /// The findings as levels, kinds and messages.
+    fn found(entries: &[PrefixListEntry]) -> Vec<(Level, &'static str, String)> {
+        check(&id(), entries)
+            .into_iter()
+            .map(|finding| {
+                assert_eq!(finding.prefix_list_id, "pl-12345678");
+                (finding.level, finding.kind, finding.message)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn a_tidy_prefix_list_has_no_findings() {
+        assert!(found(&[]).is_empty());
+        assert!(found(&[
+            raw(Some("192.0.2.1/32"), "office"),
+            raw(Some("198.51.100.0/24"), "vpn"),
+            raw(Some("2001:db8::/64"), "office v6"),
+        ])
+        .is_empty());
+    }
+
+    #[test]
+    fn malformed_entries_are_errors() {
+        assert_eq!(
+            found(&[raw(None, "office"), raw(Some("not a cidr"), "")])
+                .into_iter()
+                .map(|(level, kind, message)| (
+                    level,
+                    kind,
+                    message.split(':').next().unwrap().to_string()
+                ))
+                .collect::<Vec<_>>(),
+            [
+                (
+                    Level::Error,
+                    "malformed",
+                    "an entry without a CIDR (\"office\")".to_string()
+                ),
+                (Level::Error, "malformed", "not a cidr".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn the_same_network_twice_is_an_error() {
+        // Host bits don't make it another network
+        assert_eq!(
+            found(&[
+                raw(Some("10.0.0.1/8"), "office"),
+                raw(Some("10.0.0.0/8"), "vpn")
+            ]),
+            [(
+                Level::Error,
+                "duplicate",
+                "10.0.0.1/8 (\"office\") and 10.0.0.0/8 (\"vpn\") are the same network".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn overlaps_are_warnings_whichever_comes_first() {
+        assert_eq!(
+            found(&[
+                raw(Some("192.0.2.1/32"), "office"),
+                raw(Some("192.0.2.0/24"), "lab"),
+                raw(Some("2001:db8::/32"), "lab v6"),
+                raw(Some("2001:db8::1/128"), "laptop"),
+            ]),
+            [
+                (
+                    Level::Warning,
+                    "overlap",
+                    "192.0.2.0/24 (\"lab\") contains 192.0.2.1/32 (\"office\")".to_string()
+                ),
+                (
+                    Level::Warning,
+                    "overlap",
+                    "2001:db8::/32 (\"lab v6\") contains 2001:db8::1/128 (\"laptop\")".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn descriptions_missing_or_too_long() {
+        let long = "x".repeat(Description::MAX_LENGTH + 1);
+        assert_eq!(
+            found(&[
+                raw(Some("192.0.2.1/32"), ""),
+                raw(Some("192.0.2.2/32"), &long),
+                raw(Some("192.0.2.3/32"), &"x".repeat(Description::MAX_LENGTH)),
+            ]),
+            [
+                (
+                    Level::Info,
+                    "no-description",
+                    "192.0.2.1/32 has no description".to_string()
+                ),
+                (
+                    Level::Warning,
+                    "long-description",
+                    format!(
+                        "192.0.2.2/32 has a description longer than {} characters",
+                        Description::MAX_LENGTH
+                    )
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn the_threshold_picks_the_exit_code() {
+        let findings = check(
+            &id(),
+            &[
+                raw(Some("192.0.2.1/32"), ""),
+                raw(Some("192.0.2.0/24"), "lab"),
+            ],
+        );
+        assert_eq!(verdict(&findings, Level::Info), Exit::Failure);
+        assert_eq!(verdict(&findings, Level::Warning), Exit::Failure);
+        assert_eq!(verdict(&findings, Level::Error), Exit::Success);
+        assert_eq!(verdict(&[], Level::Info), Exit::Success);
+    }
+
+    #[test]
+    fn findings_as_json() {
+        let findings = check(&id(), &[raw(Some("192.0.2.1/32"), "")]);
+        assert_eq!(
+            serde_json::to_value(&findings).unwrap(),
+            serde_json::json!([{
+                "level": "info",
+                "prefix_list_id": "pl-12345678",
+                "kind": "no-description",
+                "message": "192.0.2.1/32 has no description",
+            }])
+        );
+        assert!(Level::Info < Level::Warning && Level::Warning < Level::Error);
+        assert_eq!(format!("{:<7}|", Level::Error), "error  |");
+    }
+
+    fn entry(cidr: &str, description: &str) -> Entry {
+        Entry {
+            cidr: cidr.parse().unwrap(),
+            description: description.to_string(),
+        }
+    }
+
+    #[test]
+    fn listed_entries_tell_which_are_owned() {
+        let office: Description = "office".parse().unwrap();
+        let listed = |cidr, description| {
+            let listed = ListedEntry::new(&id(), entry(cidr, description), Some(&office));
+            (listed.owned, listed.host)
+        };
+        assert_eq!(listed("192.0.2.1/32", "office"), (true, None));
+        assert_eq!(
+            listed("192.0.2.2/32", "office @ laptop"),
+            (true, Some("laptop".to_string()))
+        );
+        assert_eq!(listed("192.0.2.3/32", "officer"), (false, None));
+        assert_eq!(listed("192.0.2.4/32", ""), (false, None));
+
+        // Nothing is owned without a description to go by
+        let listed = ListedEntry::new(&id(), entry("192.0.2.1/32", "office"), None);
+        assert!(!listed.owned);
+    }
+
+    #[test]
+    fn listed_entries_survive_a_round_trip() {
+        let office: Description = "office".parse().unwrap();
+        let listed = vec![
+            ListedEntry::new(
+                &id(),
+                entry("192.0.2.1/32", "office @ laptop"),
+                Some(&office),
+            ),
+            ListedEntry::new(&id(), entry("2001:db8::/64", ""), Some(&office)),
+        ];
+        let json = serde_json::to_string_pretty(&listed).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            value[0],
+            serde_json::json!({
+                "prefix_list_id": "pl-12345678",
+                "cidr": "192.0.2.1/32",
+                "description": "office @ laptop",
+                "owned": true,
+                "host": "laptop",
+            })
+        );
+
+        let read: Vec<ListedEntry> = serde_json::from_str(&json).unwrap();
+        assert_eq!(read.len(), 2);
+        assert_eq!(read[1].cidr, "2001:db8::/64");
+        assert_eq!(read[1].description, "");
+        assert!(!read[1].owned);
+        assert_eq!(read[1].host, None);
+    }
+}
@@ -0,0 +1,365 @@
+use crate::aws::AWSError;
+use crate::cidr::HostCidr;
+use crate::config::{PrefixListId, Since};
+use crate::exit::Exit;
+
+use aws_sdk_ec2::model::ManagedPrefixList;
+use chrono::{DateTime, Utc};
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::warn;
+
+/// A modification doorman made or attempted, as a line of the audit log.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Record {
+    pub at: DateTime<Utc>,
+    pub hostname: String,
+    /// What was modified, only prefix lists for now
+    pub target_type: String,
+    pub target_id: String,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    /// The version the modification was made against
+    pub version_before: Option<i64>,
+    /// The version it led to, if it went through
+    pub version: Option<i64>,
+    /// `ok` or `error`
+    pub outcome: String,
+    pub error: Option<String>,
+    /// Only known for failures, successful responses don't expose it
+    pub request_id: Option<String>,
+}
+
+/// Appends a line per modification to a file, separately from the logs.
+///
+/// Each line is written at once to a file opened for appending, so lines from concurrent writers don't interleave.
+/// The file is never truncated, rotating it is left to the usual tools.
+#[derive(Clone)]
+pub struct AuditLog(Arc<Inner>);
+
+struct Inner {
+    path: PathBuf,
+    hostname: String,
+}
+
+impl AuditLog {
+    pub fn new(path: &Path) -> Self {
+        let hostname = hostname::get()
+            .map(|hostname| hostname.to_string_lossy().into_owned())
+            .unwrap_or_else(|err| {
+                warn!("Failed to get the hostname for the audit log: {}", err);
+                "unknown".to_string()
+            });
+        Self(Arc::new(Inner {
+            path: path.to_path_buf(),
+            hostname,
+        }))
+    }
+
+    /// Records a modification of `prefix_list_id` from `version_before`, along with how it went.
+    ///
+    /// Failing to write is only logged, the modification stands either way.
+    pub fn modification(
+        &self,
+        prefix_list_id: &PrefixListId,
+        version_before: Option<i64>,
+        added: &[&HostCidr],
+        removed: &[IpNet],
+        result: &Result<Option<ManagedPrefixList>, AWSError>,
+    ) {
+        let record = Record {
+            at: Utc::now(),
+            hostname: self.0.hostname.clone(),
+            target_type: "prefix_list".to_string(),
+            target_id: prefix_list_id.to_string(),
+            added: added.iter().map(ToString::to_string).collect(),
+            removed: removed.iter().map(ToString::to_string).collect(),
+            version_before,
+            version: result
+                .as_ref()
+                .ok()
+                .and_then(|prefix_list| prefix_list.as_ref()?.version),
+            outcome: match result {
+                Ok(_) => "ok",
+                Err(_) => "error",
+            }
+            .to_string(),
+            error: result.as_ref().err().map(ToString::to_string),
+            request_id: result
+                .as_ref()
+                .err()
+                .and_then(AWSError::request_id)
+                .map(String::from),
+        };
+        if let Err(err) = self.append(&record) {
+            warn!(
+                "Failed to write to the audit log {}: {}",
+                self.0.path.display(),
+                err
+            );
+        }
+    }
+
+    fn append(&self, record: &Record) -> std::io::Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.0.path)?
+            .write_all(&line)
+    }
+}
+
+/// Runs the `audit` subcommand, printing the records from `since` on, and tells how doorman should exit.
+///
+/// Lines which can't be parsed, e.g. one cut short by a full disk, are skipped with a warning.
+pub fn run(path: &Path, since: Option<Since>) -> Exit {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(err) => {
+            eprintln!("Error: failed to read {}: {}", path.display(), err);
+            return Exit::Failure;
+        }
+    };
+    for record in records(&content, since.map(|since| since.at(Utc::now()))) {
+        println!("{}", describe(&record));
+    }
+    Exit::Success
+}
+
+/// The records of `content` from `since` on, skipping the lines which can't be parsed with a warning.
+fn records(content: &str, since: Option<DateTime<Utc>>) -> Vec<Record> {
+    let mut records = Vec::new();
+    for (number, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record: Record = match serde_json::from_str(line) {
+            Ok(record) => record,
+            Err(err) => {
+                eprintln!("Warning: skipping line {}: {}", number + 1, err);
+                continue;
+            }
+        };
+        if since.is_some_and(|since| record.at < since) {
+            continue;
+        }
+        records.push(record);
+    }
+    records
+}
+
+fn describe(record: &Record) -> String {
+    let version = |version: Option<i64>| {
+        version.map_or_else(|| "?".to_string(), |version| version.to_string())
+    };
+    let mut line = format!(
+        "{}  {}  {} {}  {}  v{} -> v{}",
+        record.at.format("%Y-%m-%d %H:%M:%S"),
+        record.hostname,
+        record.target_type,
+        record.target_id,
+        record.outcome,
+        version(record.version_before),
+        version(record.version),
+    );
+    for cidr in &record.added {
+        line.push_str(&format!("  +{}", cidr));
+    }
+    for cidr in &record.removed {
+        line.push_str(&format!("  -{}", cidr));
+    }
+    if let Some(error) = &record.error {
+        line.push_str(&format!("\n    {}", error));
+    }
+    if let Some(request_id) = &record.request_id {
+        line.push_str(&format!("\n    request ID {}", request_id));
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aws::{AWSClient, FakeApi, RequestIds};
+
+    const ID: &str = "pl-12345678";
+
+    fn id() -> PrefixListId {
+        ID.parse().unwrap()
+    }
+
+    fn host(ip: &str) -> HostCidr {
+        ip.parse().unwrap()
+    }
+
+    /// The records written to `path`, checking each is on its own line.
+    fn written(path: &Path) -> Vec<Record> {
+        let content = fs::read_to_string(path).unwrap();
+        assert!(content.ends_with('\n'));
+        content
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    fn record(at: &str, outcome: &str) -> Record {
+        Record {
+            at: at.parse().unwrap(),
+            hostname: "gateway".to_string(),
+            target_type: "prefix_list".to_string(),
+            target_id: ID.to_string(),
+            added: vec!["192.0.2.2/32".to_string()],
+            removed: vec!["192.0.2.1/32".to_string()],
+            version_before: Some(3),
+            version: (outcome == "ok").then_some(4),
+            outcome: outcome.to_string(),
+            error: None,
+            request_id: None,
+        }
+    }
+
+    #[test]
+    fn modifications_are_appended_a_line_each() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        let earlier = serde_json::to_string(&record("2022-09-01T10:00:00Z", "ok")).unwrap() + "\n";
+        fs::write(&path, &earlier).unwrap();
+        let audit_log = AuditLog::new(&path);
+
+        let modified = ManagedPrefixList::builder().version(5).build();
+        audit_log.modification(
+            &id(),
+            Some(4),
+            &[&host("192.0.2.3")],
+            &["192.0.2.2/32".parse().unwrap()],
+            &Ok(Some(modified)),
+        );
+        let failure = AWSError::Service {
+            operation: "ModifyManagedPrefixList",
+            code: Some("IncorrectState".to_string()),
+            message: "modify-in-progress".to_string(),
+            request: RequestIds {
+                request_id: Some("4e5f6a7b".to_string()),
+                extended_request_id: None,
+            },
+        };
+        audit_log.modification(&id(), Some(5), &[&host("192.0.2.4")], &[], &Err(failure));
+
+        // What was there is left alone
+        assert!(fs::read_to_string(&path).unwrap().starts_with(&earlier));
+        let records = written(&path);
+        assert_eq!(records.len(), 3);
+        let (ok, failed) = (&records[1], &records[2]);
+        assert_eq!(ok.target_type, "prefix_list");
+        assert_eq!(ok.target_id, ID);
+        assert_eq!(ok.added, ["192.0.2.3/32"]);
+        assert_eq!(ok.removed, ["192.0.2.2/32"]);
+        assert_eq!((ok.version_before, ok.version), (Some(4), Some(5)));
+        assert_eq!(ok.outcome, "ok");
+        assert_eq!((&ok.error, &ok.request_id), (&None, &None));
+        assert!(!ok.hostname.is_empty());
+
+        assert_eq!(failed.outcome, "error");
+        assert_eq!((failed.version_before, failed.version), (Some(5), None));
+        assert!(failed.error.as_deref().unwrap().contains("IncorrectState"));
+        assert_eq!(failed.request_id.as_deref(), Some("4e5f6a7b"));
+    }
+
+    #[test]
+    fn a_log_which_cant_be_written_is_only_warned_about() {
+        let dir = tempfile::tempdir().unwrap();
+        // A directory can't be opened for appending
+        let audit_log = AuditLog::new(dir.path());
+        audit_log.modification(&id(), Some(1), &[&host("192.0.2.1")], &[], &Ok(None));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn the_client_records_what_it_modifies() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        let api = FakeApi::default().with_prefix_list(ID, None, &[("192.0.2.1/32", "office")]);
+        let aws = AWSClient::builder()
+            .api(api.clone())
+            .description("office".parse().unwrap())
+            .audit_log(AuditLog::new(&path))
+            .build()
+            .unwrap();
+
+        let pl = aws.get_prefix_list(&id()).await.unwrap();
+        aws.modify_entries(
+            &pl,
+            vec![&host("192.0.2.2")],
+            vec!["192.0.2.1/32".parse().unwrap()],
+        )
+        .await
+        .unwrap();
+        let pl = aws.wait_until_settled(&id()).await.unwrap();
+        api.fail_next_modification("InternalError");
+        assert!(aws
+            .modify_entries(&pl, vec![&host("192.0.2.3")], vec![])
+            .await
+            .is_err());
+
+        let records = written(&path);
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].outcome, "ok");
+        assert_eq!(records[0].added, ["192.0.2.2/32"]);
+        assert_eq!(records[0].removed, ["192.0.2.1/32"]);
+        // AWS answers while the modification is in progress, with the version it started from
+        assert_eq!(
+            (records[0].version_before, records[0].version),
+            (Some(1), Some(1))
+        );
+        assert_eq!(records[1].outcome, "error");
+        assert_eq!(records[1].added, ["192.0.2.3/32"]);
+        assert_eq!(
+            (records[1].version_before, records[1].version),
+            (Some(2), None)
+        );
+    }
+
+    #[test]
+    fn records_are_filtered_by_date() {
+        let lines = [
+            serde_json::to_string(&record("2022-09-01T10:00:00Z", "ok")).unwrap(),
+            String::new(),
+            "{\"at\": \"2022-09-0".to_string(),
+            serde_json::to_string(&record("2022-09-08T10:00:00Z", "error")).unwrap(),
+            serde_json::to_string(&record("2022-09-09T10:00:00Z", "ok")).unwrap(),
+        ];
+        let content = lines.join("\n");
+        // The cut-short line is skipped
+        assert_eq!(records(&content, None).len(), 3);
+
+        let now = "2022-09-10T10:00:00Z".parse().unwrap();
+        let since = "7d".parse::<Since>().unwrap().at(now);
+        let recent = records(&content, Some(since));
+        let outcomes: Vec<&str> = recent
+            .iter()
+            .map(|record| record.outcome.as_str())
+            .collect();
+        assert_eq!(outcomes, ["error", "ok"]);
+    }
+
+    #[test]
+    fn records_are_printed_a_line_each() {
+        assert_eq!(
+            describe(&record("2022-09-01T10:00:00Z", "ok")),
+            "2022-09-01 10:00:00  gateway  prefix_list pl-12345678  ok  v3 -> v4  +192.0.2.2/32  -192.0.2.1/32"
+        );
+        let mut failed = record("2022-09-01T10:00:00Z", "error");
+        failed.error = Some("AWS refused ModifyManagedPrefixList".to_string());
+        failed.request_id = Some("4e5f6a7b".to_string());
+        assert_eq!(
+            describe(&failed),
+            "2022-09-01 10:00:00  gateway  prefix_list pl-12345678  error  v3 -> v?  +192.0.2.2/32  -192.0.2.1/32\n    \
+             AWS refused ModifyManagedPrefixList\n    request ID 4e5f6a7b"
+        );
+    }
+}
@@ -0,0 +1,1080 @@
+use super::AWSError;
+use crate::config::PrefixListId;
+
+use async_trait::async_trait;
+use aws_sdk_ec2::client::Client as EC2Client;
+use aws_sdk_ec2::model::{
+    AddPrefixListEntry, ManagedPrefixList, PrefixListEntry, RemovePrefixListEntry, ResourceType,
+    TagSpecification,
+};
+
+/// A page of results, along with the token for the next one if there is one.
+pub type Page<T> = (Vec<T>, Option<String>);
+
+/// The prefix list calls doorman makes, each being a single request.
+///
+/// [`AWSClient`](super::AWSClient) makes the decisions, such as which entries to remove or how long to wait, on top of
+/// these. It only goes through this trait, so it doesn't need to talk to EC2 itself.
+#[async_trait]
+pub trait PrefixListApi: Send + Sync {
+    /// `DescribeManagedPrefixLists` restricted to `prefix_list_id`.
+    async fn describe_prefix_list(
+        &self,
+        prefix_list_id: &PrefixListId,
+    ) -> Result<Page<ManagedPrefixList>, AWSError>;
+
+    /// `GetManagedPrefixListEntries`, starting at `next_token`.
+    async fn get_entries(
+        &self,
+        prefix_list_id: &PrefixListId,
+        next_token: Option<String>,
+    ) -> Result<Page<PrefixListEntry>, AWSError>;
+
+    /// `ModifyManagedPrefixList`, which AWS rejects unless the prefix list is still at `current_version`.
+    async fn modify_entries(
+        &self,
+        prefix_list_id: &PrefixListId,
+        current_version: Option<i64>,
+        add: Vec<AddPrefixListEntry>,
+        remove: Vec<RemovePrefixListEntry>,
+    ) -> Result<Option<ManagedPrefixList>, AWSError>;
+
+    /// `CreateManagedPrefixList` with the same name, size, address family and tags as `template`.
+    async fn create_prefix_list_like(
+        &self,
+        template: &ManagedPrefixList,
+    ) -> Result<Option<ManagedPrefixList>, AWSError>;
+}
+
+/// Every entry of the prefix list, going through all the pages.
+pub async fn all_entries(
+    api: &(impl PrefixListApi + ?Sized),
+    prefix_list_id: &PrefixListId,
+) -> Result<Vec<PrefixListEntry>, AWSError> {
+    let mut token = None;
+    let mut all = Vec::new();
+    loop {
+        let (entries, next_token) = api.get_entries(prefix_list_id, token).await?;
+        all.extend(entries);
+
+        token = next_token;
+        if token.is_none() {
+            return Ok(all);
+        }
+    }
+}
+
+#[async_trait]
+impl PrefixListApi for EC2Client {
+    async fn describe_prefix_list(
+        &self,
+        prefix_list_id: &PrefixListId,
+    ) -> Result<Page<ManagedPrefixList>, AWSError> {
+        let response = self
+            .describe_managed_prefix_lists()
+            .prefix_list_ids(prefix_list_id.as_str())
+            .send()
+            .await
+            .map_err(|err| AWSError::from_sdk("DescribeManagedPrefixLists", err))?;
+        Ok((
+            response.prefix_lists.unwrap_or_default(),
+            response.next_token,
+        ))
+    }
+
+    async fn get_entries(
+        &self,
+        prefix_list_id: &PrefixListId,
+        next_token: Option<String>,
+    ) -> Result<Page<PrefixListEntry>, AWSError> {
+        let response = self
+            .get_managed_prefix_list_entries()
+            .prefix_list_id(prefix_list_id.as_str())
+            .set_next_token(next_token)
+            .send()
+            .await
+            .map_err(|err| AWSError::from_sdk("GetManagedPrefixListEntries", err))?;
+        Ok((response.entries.unwrap_or_default(), response.next_token))
+    }
+
+    async fn modify_entries(
+        &self,
+        prefix_list_id: &PrefixListId,
+        current_version: Option<i64>,
+        add: Vec<AddPrefixListEntry>,
+        remove: Vec<RemovePrefixListEntry>,
+    ) -> Result<Option<ManagedPrefixList>, AWSError> {
+        let response = self
+            .modify_managed_prefix_list()
+            .prefix_list_id(prefix_list_id.as_str())
+            .set_current_version(current_version)
+            .set_add_entries(Some(add))
+            .set_remove_entries(Some(remove))
+            .send()
+            .await
+            .map_err(|err| AWSError::from_sdk("ModifyManagedPrefixList", err))?;
+        Ok(response.prefix_list)
+    }
+
+    async fn create_prefix_list_like(
+        &self,
+        template: &ManagedPrefixList,
+    ) -> Result<Option<ManagedPrefixList>, AWSError> {
+        let tags = template
+            .tags
+            .clone()
+            .filter(|tags| !tags.is_empty())
+            .map(|tags| {
+                vec![TagSpecification::builder()
+                    .resource_type(ResourceType::PrefixList)
+                    .set_tags(Some(tags))
+                    .build()]
+            });
+        let response = self
+            .create_managed_prefix_list()
+            .set_prefix_list_name(template.prefix_list_name.clone())
+            .set_max_entries(template.max_entries)
+            .set_address_family(template.address_family.clone())
+            .set_tag_specifications(tags)
+            .send()
+            .await
+            .map_err(|err| AWSError::from_sdk("CreateManagedPrefixList", err))?;
+        Ok(response.prefix_list)
+    }
+}
+
+/// An in-memory [`PrefixListApi`] for tests, which behaves like EC2 where doorman cares.
+///
+/// A modification is only visible once the prefix list was described a few more times, like with EC2: until then it's
+/// `ModifyInProgress` at its previous version. Clones share the same prefix lists, so tests keep a handle on what the
+/// client they built sees.
+#[cfg(test)]
+pub mod fake {
+    use super::{Page, PrefixListApi};
+    use crate::aws::AWSError;
+    use crate::config::PrefixListId;
+
+    use async_trait::async_trait;
+    use aws_sdk_ec2::model::{
+        AddPrefixListEntry, ManagedPrefixList, PrefixListEntry, PrefixListState,
+        RemovePrefixListEntry,
+    };
+    use std::collections::{HashMap, VecDeque};
+    use std::sync::{Arc, Mutex};
+    use tokio::time::{sleep, Duration, Instant};
+
+    /// EC2 returns at most 100 entries per page.
+    const PAGE_SIZE: usize = 100;
+
+    #[derive(Clone, Default)]
+    pub struct FakeApi(Arc<Mutex<State>>);
+
+    /// A modification as it was requested.
+    #[derive(Clone, Debug)]
+    pub struct Modification {
+        pub prefix_list_id: String,
+        pub current_version: Option<i64>,
+        pub add: Vec<AddPrefixListEntry>,
+        pub remove: Vec<String>,
+    }
+
+    struct State {
+        prefix_lists: HashMap<String, FakePrefixList>,
+        page_size: usize,
+        /// How many describes a modification stays in progress for
+        settle_after: usize,
+        describes: usize,
+        entry_pages: usize,
+        modifications: Vec<Modification>,
+        /// Every operation called, and when
+        calls: Vec<(Instant, &'static str)>,
+        /// Error codes the next modifications fail with
+        modify_failures: VecDeque<String>,
+        /// Whether every call fails as if EC2 couldn't be reached
+        unreachable: bool,
+        /// How long modifying each prefix list takes, if not immediate
+        modify_delays: HashMap<String, Duration>,
+    }
+
+    impl Default for State {
+        fn default() -> Self {
+            Self {
+                prefix_lists: HashMap::new(),
+                page_size: PAGE_SIZE,
+                settle_after: 1,
+                describes: 0,
+                entry_pages: 0,
+                modifications: Vec::new(),
+                calls: Vec::new(),
+                modify_failures: VecDeque::new(),
+                unreachable: false,
+                modify_delays: HashMap::new(),
+            }
+        }
+    }
+
+    struct FakePrefixList {
+        version: i64,
+        max_entries: Option<i32>,
+        entries: Vec<(String, String)>,
+        /// Describes left before the modification in progress completes
+        in_progress: usize,
+        /// The state once nothing is in progress
+        settled: PrefixListState,
+        /// States and versions the next describes return instead, whatever happens
+        script: VecDeque<(PrefixListState, i64)>,
+    }
+
+    impl FakePrefixList {
+        fn to_sdk(
+            &self,
+            prefix_list_id: &str,
+            state: PrefixListState,
+            version: i64,
+        ) -> ManagedPrefixList {
+            ManagedPrefixList::builder()
+                .prefix_list_id(prefix_list_id)
+                .prefix_list_name("fake")
+                .version(version)
+                .set_max_entries(self.max_entries)
+                .address_family("IPv4")
+                .state(state)
+                .build()
+        }
+    }
+
+    impl FakeApi {
+        /// Adds a settled prefix list at version 1, holding `entries` as CIDRs and descriptions. An empty one is
+        /// missing altogether.
+        pub fn with_prefix_list(
+            self,
+            prefix_list_id: &str,
+            max_entries: Option<i32>,
+            entries: &[(&str, &str)],
+        ) -> Self {
+            self.0.lock().unwrap().prefix_lists.insert(
+                prefix_list_id.to_string(),
+                FakePrefixList {
+                    version: 1,
+                    max_entries,
+                    entries: entries
+                        .iter()
+                        .map(|(cidr, description)| (cidr.to_string(), description.to_string()))
+                        .collect(),
+                    in_progress: 0,
+                    settled: PrefixListState::ModifyComplete,
+                    script: VecDeque::new(),
+                },
+            );
+            self
+        }
+
+        pub fn page_size(self, page_size: usize) -> Self {
+            self.0.lock().unwrap().page_size = page_size;
+            self
+        }
+
+        /// How many describes a modification stays in progress for, 1 by default.
+        pub fn settle_after(self, describes: usize) -> Self {
+            self.0.lock().unwrap().settle_after = describes;
+            self
+        }
+
+        /// Makes the next describes return these states and versions, before going back to the actual ones.
+        pub fn script(&self, prefix_list_id: &str, states: &[(PrefixListState, i64)]) {
+            self.with(prefix_list_id, |prefix_list| {
+                prefix_list.script.extend(states.iter().cloned())
+            });
+        }
+
+        /// Makes the next modification fail with `code`, without changing anything.
+        pub fn fail_next_modification(&self, code: &str) {
+            self.0
+                .lock()
+                .unwrap()
+                .modify_failures
+                .push_back(code.to_string());
+        }
+
+        /// Makes every call fail as if EC2 couldn't be reached, or not anymore.
+        pub fn unreachable(&self, unreachable: bool) {
+            self.0.lock().unwrap().unreachable = unreachable;
+        }
+
+        /// Makes modifying the prefix list take `delay`, the modification only happening once it's over.
+        pub fn slow_modifications(&self, prefix_list_id: &str, delay: Duration) {
+            self.0
+                .lock()
+                .unwrap()
+                .modify_delays
+                .insert(prefix_list_id.to_string(), delay);
+        }
+
+        /// A modification made by someone else, which only bumps the version.
+        pub fn bump(&self, prefix_list_id: &str) {
+            self.with(prefix_list_id, |prefix_list| prefix_list.version += 1);
+        }
+
+        pub fn delete(&self, prefix_list_id: &str) {
+            self.0.lock().unwrap().prefix_lists.remove(prefix_list_id);
+        }
+
+        /// The current entries, as CIDRs and descriptions, including those of a modification in progress.
+        pub fn entries(&self, prefix_list_id: &str) -> Vec<(String, String)> {
+            self.with(prefix_list_id, |prefix_list| prefix_list.entries.clone())
+        }
+
+        /// The version once the modification in progress completes.
+        pub fn version(&self, prefix_list_id: &str) -> i64 {
+            self.with(prefix_list_id, |prefix_list| prefix_list.version)
+        }
+
+        pub fn describes(&self) -> usize {
+            self.0.lock().unwrap().describes
+        }
+
+        pub fn entry_pages(&self) -> usize {
+            self.0.lock().unwrap().entry_pages
+        }
+
+        pub fn modifications(&self) -> Vec<Modification> {
+            self.0.lock().unwrap().modifications.clone()
+        }
+
+        /// The operations called so far, in order, and when.
+        pub fn calls(&self) -> Vec<(Instant, &'static str)> {
+            self.0.lock().unwrap().calls.clone()
+        }
+
+        fn with<T>(&self, prefix_list_id: &str, f: impl FnOnce(&mut FakePrefixList) -> T) -> T {
+            f(self
+                .0
+                .lock()
+                .unwrap()
+                .prefix_lists
+                .get_mut(prefix_list_id)
+                .expect("no such prefix list in the fake"))
+        }
+    }
+
+    fn unreachable(operation: &'static str) -> AWSError {
+        AWSError::Dispatch {
+            operation,
+            message: "connection refused".to_string(),
+        }
+    }
+
+    fn service_error(operation: &'static str, code: &str, message: String) -> AWSError {
+        AWSError::Service {
+            operation,
+            code: Some(code.to_string()),
+            message,
+            request: Default::default(),
+        }
+    }
+
+    fn not_found(prefix_list_id: &PrefixListId) -> AWSError {
+        AWSError::NotFound {
+            prefix_list_id: prefix_list_id.to_string(),
+        }
+    }
+
+    #[async_trait]
+    impl PrefixListApi for FakeApi {
+        async fn describe_prefix_list(
+            &self,
+            prefix_list_id: &PrefixListId,
+        ) -> Result<Page<ManagedPrefixList>, AWSError> {
+            const OPERATION: &str = "DescribeManagedPrefixLists";
+            let mut state = self.0.lock().unwrap();
+            state.calls.push((Instant::now(), OPERATION));
+            if state.unreachable {
+                return Err(unreachable(OPERATION));
+            }
+            state.describes += 1;
+            let prefix_list = match state.prefix_lists.get_mut(prefix_list_id.as_str()) {
+                Some(prefix_list) => prefix_list,
+                None => return Ok((vec![], None)),
+            };
+            let (pl_state, version) = match prefix_list.script.pop_front() {
+                Some(scripted) => scripted,
+                None if prefix_list.in_progress > 0 => {
+                    prefix_list.in_progress -= 1;
+                    (PrefixListState::ModifyInProgress, prefix_list.version - 1)
+                }
+                None => (prefix_list.settled.clone(), prefix_list.version),
+            };
+            Ok((
+                vec![prefix_list.to_sdk(prefix_list_id.as_str(), pl_state, version)],
+                None,
+            ))
+        }
+
+        async fn get_entries(
+            &self,
+            prefix_list_id: &PrefixListId,
+            next_token: Option<String>,
+        ) -> Result<Page<PrefixListEntry>, AWSError> {
+            const OPERATION: &str = "GetManagedPrefixListEntries";
+            let mut state = self.0.lock().unwrap();
+            state.calls.push((Instant::now(), OPERATION));
+            if state.unreachable {
+                return Err(unreachable(OPERATION));
+            }
+            state.entry_pages += 1;
+            let page_size = state.page_size;
+            let prefix_list = state
+                .prefix_lists
+                .get(prefix_list_id.as_str())
+                .ok_or_else(|| not_found(prefix_list_id))?;
+            let start: usize = next_token.map_or(0, |token| token.parse().unwrap());
+            let end = (start + page_size).min(prefix_list.entries.len());
+            Ok((
+                prefix_list.entries[start..end]
+                    .iter()
+                    .map(|(cidr, description)| {
+                        PrefixListEntry::builder()
+                            .set_cidr(Some(cidr.clone()).filter(|c| !c.is_empty()))
+                            .set_description(Some(description.clone()).filter(|d| !d.is_empty()))
+                            .build()
+                    })
+                    .collect(),
+                Some(end.to_string()).filter(|_| end < prefix_list.entries.len()),
+            ))
+        }
+
+        async fn modify_entries(
+            &self,
+            prefix_list_id: &PrefixListId,
+            current_version: Option<i64>,
+            add: Vec<AddPrefixListEntry>,
+            remove: Vec<RemovePrefixListEntry>,
+        ) -> Result<Option<ManagedPrefixList>, AWSError> {
+            const OPERATION: &str = "ModifyManagedPrefixList";
+            let delay = {
+                let mut state = self.0.lock().unwrap();
+                state.calls.push((Instant::now(), OPERATION));
+                state.modify_delays.get(prefix_list_id.as_str()).copied()
+            };
+            if let Some(delay) = delay {
+                sleep(delay).await;
+            }
+            let mut state = self.0.lock().unwrap();
+            if state.unreachable {
+                return Err(unreachable(OPERATION));
+            }
+            let remove: Vec<String> = remove.into_iter().filter_map(|entry| entry.cidr).collect();
+            state.modifications.push(Modification {
+                prefix_list_id: prefix_list_id.to_string(),
+                current_version,
+                add: add.clone(),
+                remove: remove.clone(),
+            });
+            if let Some(code) = state.modify_failures.pop_front() {
+                return Err(service_error(
+                    OPERATION,
+                    &code,
+                    "scripted failure".to_string(),
+                ));
+            }
+            let settle_after = state.settle_after;
+            let prefix_list = state
+                .prefix_lists
+                .get_mut(prefix_list_id.as_str())
+                .ok_or_else(|| not_found(prefix_list_id))?;
+            if prefix_list.in_progress > 0 {
+                return Err(service_error(
+                    OPERATION,
+                    "IncorrectState",
+                    "a modification is in progress".to_string(),
+                ));
+            }
+            if current_version.is_some_and(|version| version != prefix_list.version) {
+                return Err(service_error(
+                    OPERATION,
+                    "PrefixListVersionMismatch",
+                    format!(
+                        "the prefix list is at version {}, not {}",
+                        prefix_list.version,
+                        current_version.unwrap_or_default()
+                    ),
+                ));
+            }
+
+            let mut entries = prefix_list.entries.clone();
+            entries.retain(|(cidr, _)| !remove.contains(cidr));
+            for entry in add {
+                let cidr = entry.cidr.unwrap_or_default();
+                entries.retain(|(existing, _)| *existing != cidr);
+                entries.push((cidr, entry.description.unwrap_or_default()));
+            }
+            if prefix_list
+                .max_entries
+                .is_some_and(|max| entries.len() > max as usize)
+            {
+                return Err(service_error(
+                    OPERATION,
+                    "PrefixListMaxEntriesExceeded",
+                    format!(
+                        "the prefix list holds at most {:?} entries",
+                        prefix_list.max_entries
+                    ),
+                ));
+            }
+            prefix_list.entries = entries;
+            prefix_list.version += 1;
+            prefix_list.in_progress = settle_after;
+            prefix_list.settled = PrefixListState::ModifyComplete;
+            Ok(Some(prefix_list.to_sdk(
+                prefix_list_id.as_str(),
+                PrefixListState::ModifyInProgress,
+                prefix_list.version - 1,
+            )))
+        }
+
+        async fn create_prefix_list_like(
+            &self,
+            template: &ManagedPrefixList,
+        ) -> Result<Option<ManagedPrefixList>, AWSError> {
+            const OPERATION: &str = "CreateManagedPrefixList";
+            let mut state = self.0.lock().unwrap();
+            state.calls.push((Instant::now(), OPERATION));
+            if state.unreachable {
+                return Err(unreachable(OPERATION));
+            }
+            let prefix_list_id = format!("pl-{:08x}", state.prefix_lists.len() + 1);
+            let prefix_list = FakePrefixList {
+                version: 1,
+                max_entries: template.max_entries,
+                entries: Vec::new(),
+                in_progress: 0,
+                settled: PrefixListState::CreateComplete,
+                script: VecDeque::from([(PrefixListState::CreateInProgress, 1)]),
+            };
+            let created = prefix_list.to_sdk(&prefix_list_id, PrefixListState::CreateInProgress, 1);
+            state.prefix_lists.insert(prefix_list_id, prefix_list);
+            Ok(Some(created))
+        }
+    }
+
+    mod tests {
+        use super::*;
+        use crate::aws::all_entries;
+
+        fn id(s: &str) -> PrefixListId {
+            s.parse().unwrap()
+        }
+
+        fn add(cidr: &str) -> AddPrefixListEntry {
+            AddPrefixListEntry::builder()
+                .cidr(cidr)
+                .description("office")
+                .build()
+        }
+
+        fn remove(cidr: &str) -> RemovePrefixListEntry {
+            RemovePrefixListEntry::builder().cidr(cidr).build()
+        }
+
+        async fn describe(api: &FakeApi, prefix_list_id: &str) -> (PrefixListState, i64) {
+            let (prefix_lists, _) = api.describe_prefix_list(&id(prefix_list_id)).await.unwrap();
+            let prefix_list = &prefix_lists[0];
+            (
+                prefix_list.state.clone().unwrap(),
+                prefix_list.version.unwrap(),
+            )
+        }
+
+        #[tokio::test]
+        async fn modify_goes_through_in_progress() {
+            let api = FakeApi::default()
+                .with_prefix_list("pl-12345678", None, &[("192.0.2.1/32", "office")])
+                .settle_after(2);
+            let modified = api
+                .modify_entries(
+                    &id("pl-12345678"),
+                    Some(1),
+                    vec![add("192.0.2.2/32")],
+                    vec![remove("192.0.2.1/32")],
+                )
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(modified.state, Some(PrefixListState::ModifyInProgress));
+            assert_eq!(modified.version, Some(1));
+
+            assert_eq!(
+                describe(&api, "pl-12345678").await,
+                (PrefixListState::ModifyInProgress, 1)
+            );
+            assert_eq!(
+                describe(&api, "pl-12345678").await,
+                (PrefixListState::ModifyInProgress, 1)
+            );
+            assert_eq!(
+                describe(&api, "pl-12345678").await,
+                (PrefixListState::ModifyComplete, 2)
+            );
+            assert_eq!(
+                api.entries("pl-12345678"),
+                vec![("192.0.2.2/32".to_string(), "office".to_string())]
+            );
+            assert_eq!(api.modifications().len(), 1);
+        }
+
+        #[tokio::test]
+        async fn version_conflicts() {
+            let api = FakeApi::default().with_prefix_list("pl-12345678", None, &[]);
+            api.bump("pl-12345678");
+            let err = api
+                .modify_entries(
+                    &id("pl-12345678"),
+                    Some(1),
+                    vec![add("192.0.2.1/32")],
+                    vec![],
+                )
+                .await
+                .unwrap_err();
+            assert!(
+                matches!(err, AWSError::Service { code: Some(ref code), .. } if code == "PrefixListVersionMismatch"),
+                "{:?}",
+                err
+            );
+            assert!(api.entries("pl-12345678").is_empty());
+
+            // Nor can it be modified again before the modification in progress completes
+            api.modify_entries(
+                &id("pl-12345678"),
+                Some(2),
+                vec![add("192.0.2.1/32")],
+                vec![],
+            )
+            .await
+            .unwrap();
+            let err = api
+                .modify_entries(
+                    &id("pl-12345678"),
+                    Some(3),
+                    vec![add("192.0.2.2/32")],
+                    vec![],
+                )
+                .await
+                .unwrap_err();
+            assert!(
+                matches!(err, AWSError::Service { code: Some(ref code), .. } if code == "IncorrectState")
+            );
+        }
+
+        #[tokio::test]
+        async fn full_list() {
+            let api = FakeApi::default().with_prefix_list(
+                "pl-12345678",
+                Some(2),
+                &[("192.0.2.1/32", "office"), ("192.0.2.2/32", "vpn")],
+            );
+            let err = api
+                .modify_entries(
+                    &id("pl-12345678"),
+                    Some(1),
+                    vec![add("192.0.2.3/32")],
+                    vec![],
+                )
+                .await
+                .unwrap_err();
+            assert!(
+                matches!(err, AWSError::Service { code: Some(ref code), .. } if code == "PrefixListMaxEntriesExceeded")
+            );
+            assert_eq!(api.version("pl-12345678"), 1);
+
+            // Replacing an entry fits
+            api.modify_entries(
+                &id("pl-12345678"),
+                Some(1),
+                vec![add("192.0.2.3/32")],
+                vec![remove("192.0.2.1/32")],
+            )
+            .await
+            .unwrap();
+            assert_eq!(api.version("pl-12345678"), 2);
+        }
+
+        #[tokio::test]
+        async fn pages_and_missing_lists() {
+            let entries: Vec<(String, String)> = (0..250)
+                .map(|i| (format!("10.0.{}.{}/32", i / 256, i % 256), String::new()))
+                .collect();
+            let entries: Vec<(&str, &str)> = entries
+                .iter()
+                .map(|(cidr, description)| (cidr.as_str(), description.as_str()))
+                .collect();
+            let api = FakeApi::default()
+                .with_prefix_list("pl-12345678", None, &entries)
+                .page_size(100);
+            assert_eq!(
+                all_entries(&api, &id("pl-12345678")).await.unwrap().len(),
+                250
+            );
+            assert_eq!(api.entry_pages(), 3);
+
+            let (prefix_lists, _) = api.describe_prefix_list(&id("pl-87654321")).await.unwrap();
+            assert!(prefix_lists.is_empty());
+            assert!(matches!(
+                api.get_entries(&id("pl-87654321"), None).await,
+                Err(AWSError::NotFound { .. })
+            ));
+        }
+    }
+}
+
+/// The EC2 implementation, against canned responses rather than AWS.
+#[cfg(test)]
+mod replay {
+    use super::PrefixListApi;
+    use crate::aws::{retry_config, AWSClient, AWSError, EntryFilter};
+    use crate::cidr::HostCidr;
+    use crate::config::PrefixListId;
+    use crate::exit::Exit;
+
+    use aws_sdk_ec2::client::Client as EC2Client;
+    use aws_sdk_ec2::{Config, Credentials, Region};
+    use aws_smithy_async::rt::sleep::default_async_sleep;
+    use aws_smithy_client::test_connection::TestConnection;
+    use aws_smithy_http::body::SdkBody;
+    use reqwest::Url;
+    use std::collections::HashMap;
+
+    const ID: &str = "pl-12345678";
+
+    fn id() -> PrefixListId {
+        ID.parse().unwrap()
+    }
+
+    fn response(status: u16, body: String) -> http::Response<String> {
+        http::Response::builder().status(status).body(body).unwrap()
+    }
+
+    /// A connection answering each request with the next of `responses`, made by an EC2 client trying at most
+    /// `max_attempts` times.
+    fn replayed(
+        responses: Vec<http::Response<String>>,
+        max_attempts: u32,
+    ) -> (EC2Client, TestConnection<String>) {
+        // Requests are checked once sent rather than against expected ones
+        let events = responses
+            .into_iter()
+            .map(|response| (http::Request::new(SdkBody::empty()), response))
+            .collect();
+        let conn = TestConnection::new(events);
+        let conf = Config::builder()
+            .region(Region::new("us-east-1"))
+            .credentials_provider(Credentials::new("test", "test", None, None, "test"))
+            .retry_config(retry_config(max_attempts))
+            // Without one, the SDK doesn't retry
+            .sleep_impl(default_async_sleep().unwrap())
+            .build();
+        (EC2Client::from_conf_conn(conf, conn.clone()), conn)
+    }
+
+    fn client(ec2: EC2Client) -> AWSClient {
+        AWSClient::builder()
+            .api(ec2)
+            .description("office".parse().unwrap())
+            .build()
+            .unwrap()
+    }
+
+    /// The form-encoded parameters of the `n`th request.
+    fn params(conn: &TestConnection<String>, n: usize) -> HashMap<String, String> {
+        let requests = conn.requests();
+        let body = std::str::from_utf8(requests[n].actual.body().bytes().unwrap()).unwrap();
+        // Only for the decoding, the URL itself doesn't matter
+        Url::parse(&format!("http://localhost/?{}", body))
+            .unwrap()
+            .query_pairs()
+            .into_owned()
+            .collect()
+    }
+
+    fn prefix_list(version: i64) -> String {
+        format!(
+            "<prefixListId>{}</prefixListId><addressFamily>IPv4</addressFamily><state>modify-complete</state>\
+             <prefixListName>office</prefixListName><maxEntries>10</maxEntries><version>{}</version>",
+            ID, version
+        )
+    }
+
+    fn described(prefix_lists: &[String], next_token: Option<&str>) -> http::Response<String> {
+        let items: String = prefix_lists
+            .iter()
+            .map(|prefix_list| format!("<item>{}</item>", prefix_list))
+            .collect();
+        let next_token = next_token
+            .map(|token| format!("<nextToken>{}</nextToken>", token))
+            .unwrap_or_default();
+        response(
+            200,
+            format!(
+                "<DescribeManagedPrefixListsResponse xmlns=\"http://ec2.amazonaws.com/doc/2016-11-15/\">\
+                 <requestId>req-describe</requestId><prefixListSet>{}</prefixListSet>{}\
+                 </DescribeManagedPrefixListsResponse>",
+                items, next_token
+            ),
+        )
+    }
+
+    fn entries(entries: &[(&str, &str)], next_token: Option<&str>) -> http::Response<String> {
+        let items: String = entries
+            .iter()
+            .map(|(cidr, description)| {
+                format!(
+                    "<item><cidr>{}</cidr><description>{}</description></item>",
+                    cidr, description
+                )
+            })
+            .collect();
+        let next_token = next_token
+            .map(|token| format!("<nextToken>{}</nextToken>", token))
+            .unwrap_or_default();
+        response(
+            200,
+            format!(
+                "<GetManagedPrefixListEntriesResponse xmlns=\"http://ec2.amazonaws.com/doc/2016-11-15/\">\
+                 <requestId>req-entries</requestId><entrySet>{}</entrySet>{}\
+                 </GetManagedPrefixListEntriesResponse>",
+                items, next_token
+            ),
+        )
+    }
+
+    fn modified(version: i64) -> http::Response<String> {
+        response(
+            200,
+            format!(
+                "<ModifyManagedPrefixListResponse xmlns=\"http://ec2.amazonaws.com/doc/2016-11-15/\">\
+                 <requestId>req-modify</requestId><prefixList>{}</prefixList>\
+                 </ModifyManagedPrefixListResponse>",
+                prefix_list(version).replace("modify-complete", "modify-in-progress")
+            ),
+        )
+    }
+
+    fn error(status: u16, code: &str, request_id: &str) -> http::Response<String> {
+        response(
+            status,
+            format!(
+                "<Response><Errors><Error><Code>{}</Code><Message>{} happened</Message></Error></Errors>\
+                 <RequestID>{}</RequestID></Response>",
+                code, code, request_id
+            ),
+        )
+    }
+
+    #[tokio::test]
+    async fn describe_found() {
+        let (ec2, conn) = replayed(vec![described(&[prefix_list(3)], None)], 1);
+        let pl = client(ec2).get_prefix_list(&id()).await.unwrap();
+        assert_eq!(pl.prefix_list_id.as_deref(), Some(ID));
+        assert_eq!(pl.version, Some(3));
+        assert_eq!(pl.max_entries, Some(10));
+
+        let params = params(&conn, 0);
+        assert_eq!(params["Action"], "DescribeManagedPrefixLists");
+        assert_eq!(params["PrefixListId.1"], ID);
+    }
+
+    #[tokio::test]
+    async fn describe_not_found() {
+        let (ec2, _) = replayed(vec![described(&[], None)], 1);
+        let err = client(ec2).get_prefix_list(&id()).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<AWSError>(),
+            Some(AWSError::NotFound { prefix_list_id }) if prefix_list_id == ID
+        ));
+        assert_eq!(Exit::of(&err), Exit::PrefixListNotFound);
+
+        // What EC2 actually answers for an ID that doesn't exist
+        let (ec2, _) = replayed(
+            vec![error(400, "InvalidPrefixListID.NotFound", "req-missing")],
+            1,
+        );
+        let err = client(ec2).get_prefix_list(&id()).await.unwrap_err();
+        match err.downcast_ref::<AWSError>() {
+            Some(AWSError::Service {
+                operation,
+                code,
+                request,
+                ..
+            }) => {
+                assert_eq!(*operation, "DescribeManagedPrefixLists");
+                assert_eq!(code.as_deref(), Some("InvalidPrefixListID.NotFound"));
+                assert_eq!(request.request_id.as_deref(), Some("req-missing"));
+            }
+            other => panic!("unexpected error {:?}", other),
+        }
+        assert_eq!(Exit::of(&err), Exit::PrefixListNotFound);
+    }
+
+    #[tokio::test]
+    async fn describe_paginated() {
+        let (ec2, _) = replayed(vec![described(&[prefix_list(3)], Some("more"))], 1);
+        let (prefix_lists, next_token) = ec2.describe_prefix_list(&id()).await.unwrap();
+        assert_eq!(prefix_lists.len(), 1);
+        assert_eq!(next_token.as_deref(), Some("more"));
+
+        // Describing a single ID should never be paginated
+        let (ec2, _) = replayed(vec![described(&[prefix_list(3)], Some("more"))], 1);
+        let err = client(ec2).get_prefix_list(&id()).await.unwrap_err();
+        assert!(format!("{:?}", err).contains("too many"), "{:?}", err);
+    }
+
+    #[tokio::test]
+    async fn entries_go_through_the_pages() {
+        let (ec2, conn) = replayed(
+            vec![
+                described(&[prefix_list(3)], None),
+                entries(&[("192.0.2.1/32", "office")], Some("page-2")),
+                entries(&[("198.51.100.0/24", "")], None),
+            ],
+            1,
+        );
+        let aws = client(ec2);
+        let pl = aws.get_prefix_list(&id()).await.unwrap();
+        let listed = aws.entries(&pl, EntryFilter::All).await.unwrap();
+        assert_eq!(
+            listed
+                .iter()
+                .map(|entry| (entry.cidr.to_string(), entry.description.as_str()))
+                .collect::<Vec<_>>(),
+            [
+                ("192.0.2.1/32".to_string(), "office"),
+                ("198.51.100.0/24".to_string(), "")
+            ]
+        );
+
+        let first = params(&conn, 1);
+        assert_eq!(first["Action"], "GetManagedPrefixListEntries");
+        assert_eq!(first["PrefixListId"], ID);
+        assert!(!first.contains_key("NextToken"));
+        assert_eq!(params(&conn, 2)["NextToken"], "page-2");
+    }
+
+    #[tokio::test]
+    async fn modify_sends_the_description_and_version() {
+        let (ec2, conn) = replayed(vec![described(&[prefix_list(3)], None), modified(3)], 1);
+        let aws = client(ec2);
+        let pl = aws.get_prefix_list(&id()).await.unwrap();
+        let new = HostCidr::from("192.0.2.2".parse::<std::net::IpAddr>().unwrap());
+        let modified = aws
+            .modify_entries(&pl, vec![&new], vec!["192.0.2.1/32".parse().unwrap()])
+            .await
+            .unwrap();
+        // AWS answers with the version it started from
+        assert_eq!(modified.version, Some(4));
+
+        let params = params(&conn, 1);
+        assert_eq!(params["Action"], "ModifyManagedPrefixList");
+        assert_eq!(params["PrefixListId"], ID);
+        assert_eq!(params["CurrentVersion"], "3");
+        assert_eq!(params["AddEntry.1.Cidr"], "192.0.2.2/32");
+        assert_eq!(params["AddEntry.1.Description"], "office");
+        assert_eq!(params["RemoveEntry.1.Cidr"], "192.0.2.1/32");
+        assert!(!params.contains_key("RemoveEntry.1.Description"));
+    }
+
+    #[tokio::test]
+    async fn modify_version_conflict() {
+        let (ec2, conn) = replayed(
+            vec![
+                described(&[prefix_list(3)], None),
+                error(400, "PrefixListVersionMismatch", "req-conflict"),
+            ],
+            // Not retried by the SDK, doorman describes the prefix list again instead
+            3,
+        );
+        let aws = client(ec2);
+        let pl = aws.get_prefix_list(&id()).await.unwrap();
+        let new = HostCidr::from("192.0.2.2".parse::<std::net::IpAddr>().unwrap());
+        let err = aws
+            .modify_entries(&pl, vec![&new], vec![])
+            .await
+            .unwrap_err();
+        match err.downcast_ref::<AWSError>() {
+            Some(AWSError::Service {
+                operation,
+                code,
+                message,
+                request,
+            }) => {
+                assert_eq!(*operation, "ModifyManagedPrefixList");
+                assert_eq!(code.as_deref(), Some("PrefixListVersionMismatch"));
+                assert!(
+                    message.contains("PrefixListVersionMismatch happened"),
+                    "{}",
+                    message
+                );
+                assert_eq!(request.request_id.as_deref(), Some("req-conflict"));
+            }
+            other => panic!("unexpected error {:?}", other),
+        }
+        assert_eq!(conn.requests().len(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn modify_throttled() {
+        // The SDK retries a throttled call
+        let (ec2, conn) = replayed(
+            vec![
+                error(503, "RequestLimitExceeded", "req-throttled"),
+                modified(3),
+            ],
+            2,
+        );
+        let modified = ec2
+            .modify_entries(&id(), Some(3), vec![], vec![])
+            .await
+            .unwrap();
+        assert_eq!(modified.and_then(|pl| pl.version), Some(3));
+        assert_eq!(conn.requests().len(), 2);
+
+        // Until it runs out of attempts
+        let (ec2, conn) = replayed(vec![error(503, "RequestLimitExceeded", "req-throttled")], 1);
+        let err = ec2
+            .modify_entries(&id(), Some(3), vec![], vec![])
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            &err,
+            AWSError::Service { operation: "ModifyManagedPrefixList", code: Some(code), request, .. }
+                if code == "RequestLimitExceeded" && request.request_id.as_deref() == Some("req-throttled")
+        ));
+        assert_eq!(Exit::of(&err.into()), Exit::AwsUnavailable);
+        assert_eq!(conn.requests().len(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn modify_throttled_is_only_retried_by_the_sdk() {
+        // More throttles than attempts, so running out of responses doesn't stop the retries
+        let throttled = (0..10).map(|_| error(503, "RequestLimitExceeded", "req-throttled"));
+        let (ec2, conn) = replayed(
+            std::iter::once(described(&[prefix_list(3)], None))
+                .chain(throttled)
+                .collect(),
+            3,
+        );
+        let aws = client(ec2);
+        let pl = aws.get_prefix_list(&id()).await.unwrap();
+        let new = HostCidr::from("192.0.2.2".parse::<std::net::IpAddr>().unwrap());
+        let err = aws
+            .modify_entries(&pl, vec![&new], vec![])
+            .await
+            .unwrap_err();
+
+        // The SDK's three attempts, which doorman doesn't repeat within the check
+        let sent = conn.requests().len();
+        let modifications = (0..sent)
+            .filter(|n| params(&conn, *n)["Action"] == "ModifyManagedPrefixList")
+            .count();
+        assert_eq!(modifications, 3);
+        assert_eq!(Exit::of(&err), Exit::AwsUnavailable);
+    }
+}
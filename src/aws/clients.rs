@@ -0,0 +1,228 @@
+use super::AWSError;
+
+use aws_config::meta::region::RegionProviderChain;
+use aws_config::ConfigLoader;
+use aws_sdk_dynamodb::Client as DynamoDBClient;
+use aws_sdk_ec2::client::Client as EC2Client;
+use aws_sdk_ec2::{Endpoint, Region};
+use aws_sdk_sts::Client as STSClient;
+use aws_smithy_types::retry::RetryConfig;
+use aws_smithy_types::timeout;
+use aws_smithy_types::tristate::TriState;
+use reqwest::Url;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Who AWS sees doorman as.
+pub struct CallerIdentity {
+    pub account: String,
+    pub arn: String,
+}
+
+/// The timeouts of the AWS calls: connecting gets `connect`, while `call` bounds each read as well as the whole call,
+/// retries included.
+pub fn timeout_config(connect: Duration, call: Duration) -> timeout::Config {
+    timeout::Config::new()
+        .with_api_timeouts(timeout::Api::new().with_call_timeout(TriState::Set(call)))
+        .with_http_timeouts(
+            timeout::Http::new()
+                .with_connect_timeout(TriState::Set(connect))
+                .with_read_timeout(TriState::Set(call)),
+        )
+}
+
+/// How the SDK retries failed calls, in its standard mode, which is the only one it implements so far.
+///
+/// The attempts all happen within the call timeout of [`timeout_config`].
+pub fn retry_config(max_attempts: u32) -> RetryConfig {
+    RetryConfig::new().with_max_attempts(max_attempts)
+}
+
+/// Builds EC2 clients, keeping a single one per region.
+pub struct EC2Clients {
+    default_region: Option<String>,
+    timeout_config: timeout::Config,
+    retry_config: RetryConfig,
+    endpoint: Option<Url>,
+    clients: HashMap<Option<String>, (EC2Client, Option<Region>)>,
+}
+
+impl EC2Clients {
+    /// The operation timeout covers all the attempts, so it bounds how long the SDK keeps retrying.
+    pub fn new(
+        default_region: Option<String>,
+        timeout_config: timeout::Config,
+        retry_config: RetryConfig,
+    ) -> Self {
+        Self {
+            default_region,
+            timeout_config,
+            retry_config,
+            endpoint: None,
+            clients: HashMap::new(),
+        }
+    }
+
+    /// Sends every call to `endpoint` rather than to AWS, such as a LocalStack container.
+    pub fn endpoint(mut self, endpoint: Option<Url>) -> Self {
+        self.endpoint = endpoint;
+        self
+    }
+
+    /// Returns the client for the region, along with the region it resolved to.
+    ///
+    /// Without a region, the default one is used, falling back to the usual AWS resolution chain.
+    pub async fn get(&mut self, region: Option<&str>) -> (EC2Client, Option<Region>) {
+        let region = region
+            .map(String::from)
+            .or_else(|| self.default_region.clone());
+
+        if let Some(client) = self.clients.get(&region) {
+            return client.clone();
+        }
+
+        let shared_config = self.loader(region.clone()).load().await;
+        let client = (
+            EC2Client::new(&shared_config),
+            shared_config.region().cloned(),
+        );
+        self.clients.insert(region, client.clone());
+        client
+    }
+
+    /// Asks STS which account and principal the credentials belong to, through the default region.
+    pub async fn caller_identity(&self) -> Result<CallerIdentity, AWSError> {
+        let shared_config = self.loader(self.default_region.clone()).load().await;
+        let response = STSClient::new(&shared_config)
+            .get_caller_identity()
+            .send()
+            .await
+            .map_err(|err| AWSError::from_sdk("GetCallerIdentity", err))?;
+        Ok(CallerIdentity {
+            account: response.account.unwrap_or_else(|| "<unknown>".to_string()),
+            arn: response.arn.unwrap_or_else(|| "<unknown>".to_string()),
+        })
+    }
+
+    /// A DynamoDB client for the default region.
+    pub async fn dynamodb(&self) -> DynamoDBClient {
+        let shared_config = self.loader(self.default_region.clone()).load().await;
+        DynamoDBClient::new(&shared_config)
+    }
+
+    fn loader(&self, region: Option<String>) -> ConfigLoader {
+        let region_provider =
+            RegionProviderChain::first_try(region.map(Region::new)).or_default_provider();
+        let loader = aws_config::from_env()
+            .region(region_provider)
+            .timeout_config(self.timeout_config.clone())
+            .retry_config(self.retry_config.clone());
+        match &self.endpoint {
+            // A URL is always a valid URI
+            Some(endpoint) => loader.endpoint_resolver(Endpoint::immutable(
+                endpoint.as_str().parse().expect("invalid endpoint URI"),
+            )),
+            None => loader,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aws::PrefixListApi;
+    use aws_sdk_ec2::Credentials;
+    use aws_smithy_types::retry::RetryMode;
+    use std::time::Instant;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn call_timeout_bounds_all_attempts() {
+        let timeouts = timeout_config(Duration::from_secs(3), Duration::from_secs(10));
+        assert_eq!(
+            timeouts.api.call_timeout(),
+            TriState::Set(Duration::from_secs(10))
+        );
+        // Each attempt isn't bounded by itself, only all of them together
+        assert_eq!(timeouts.api.call_attempt_timeout(), TriState::Unset);
+        assert_eq!(
+            timeouts.http.connect_timeout(),
+            TriState::Set(Duration::from_secs(3))
+        );
+        assert_eq!(
+            timeouts.http.read_timeout(),
+            TriState::Set(Duration::from_secs(10))
+        );
+    }
+
+    #[test]
+    fn retries_use_the_standard_mode() {
+        let retries = retry_config(5);
+        assert_eq!(retries.mode(), RetryMode::Standard);
+        assert_eq!(retries.max_attempts(), 5);
+    }
+
+    #[tokio::test]
+    async fn clients_get_both_configs() {
+        let clients = EC2Clients::new(
+            Some("eu-west-3".to_string()),
+            timeout_config(Duration::from_secs(3), Duration::from_secs(10)),
+            retry_config(5),
+        );
+        let shared_config = clients.loader(clients.default_region.clone()).load().await;
+        assert_eq!(
+            shared_config.region().map(Region::as_ref),
+            Some("eu-west-3")
+        );
+        assert_eq!(
+            shared_config.retry_config().map(RetryConfig::max_attempts),
+            Some(5)
+        );
+        assert_eq!(
+            shared_config
+                .timeout_config()
+                .map(|timeouts| timeouts.api.call_timeout()),
+            Some(TriState::Set(Duration::from_secs(10)))
+        );
+    }
+
+    #[tokio::test]
+    async fn calls_to_a_black_hole_time_out_within_the_bound() {
+        // Takes the connections, but never answers
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = Url::parse(&format!("http://{}", listener.local_addr().unwrap())).unwrap();
+        tokio::spawn(async move {
+            let mut held = Vec::new();
+            while let Ok((stream, _)) = listener.accept().await {
+                held.push(stream);
+            }
+        });
+
+        let clients = EC2Clients::new(
+            Some("us-east-1".to_string()),
+            timeout_config(Duration::from_secs(1), Duration::from_secs(1)),
+            retry_config(3),
+        )
+        .endpoint(Some(endpoint));
+        let shared_config = clients
+            .loader(clients.default_region.clone())
+            .credentials_provider(Credentials::new("test", "test", None, None, "test"))
+            .load()
+            .await;
+        let ec2 = EC2Client::new(&shared_config);
+
+        let start = Instant::now();
+        let err = ec2
+            .describe_prefix_list(&"pl-12345678".parse().unwrap())
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            AWSError::Timeout {
+                operation: "DescribeManagedPrefixLists"
+            }
+        ));
+        // The retries don't stretch the call past its timeout
+        assert!(start.elapsed() < Duration::from_secs(3));
+    }
+}
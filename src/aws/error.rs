@@ -1,24 +1,532 @@
+use aws_sdk_ec2::types::SdkError;
+use aws_smithy_types::retry::ProvideErrorKind;
+use color_eyre::Report;
 use core::fmt;
+use hyper::{HeaderMap, StatusCode};
+use ipnet::IpNet;
 use std::error::Error;
 
 #[derive(Debug)]
 pub enum AWSError {
-    NothingToDo(String),
-    CardinalityError(String),
-    GetEntriesError(RusotoError<GetManagedPrefixListEntriesError>),
-    ModifyError(RusotoError<ModifyManagedPrefixListError>),
+    /// The call didn't complete within the configured timeout.
+    Timeout { operation: &'static str },
+    /// The request couldn't be sent, e.g. because the endpoint is unreachable.
+    Dispatch {
+        operation: &'static str,
+        message: String,
+    },
+    /// AWS returned an error.
+    Service {
+        operation: &'static str,
+        code: Option<String>,
+        message: String,
+        request: RequestIds,
+    },
+    /// The prefix list doesn't exist, or isn't visible with these credentials.
+    NotFound { prefix_list_id: String },
+    /// The prefix list has no room left, and removing doorman's own entries wouldn't make enough.
+    Full {
+        prefix_list_id: String,
+        max_entries: usize,
+        /// Entries not owned by doorman, which it won't remove
+        foreign: usize,
+    },
+    /// Anything else, such as failing to build the request or to read the response.
+    Other {
+        operation: &'static str,
+        message: String,
+        request: RequestIds,
+    },
+}
+
+/// The IDs AWS gave a request, which its support asks for.
+#[derive(Debug, Default)]
+pub struct RequestIds {
+    pub request_id: Option<String>,
+    /// Only some services send it
+    pub extended_request_id: Option<String>,
+}
+
+impl RequestIds {
+    /// The request ID is usually in the headers, but EC2 may only put it in the XML body.
+    pub fn from_response(headers: &HeaderMap, body: Option<&[u8]>) -> Self {
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(String::from)
+        };
+        Self {
+            request_id: header("x-amzn-requestid")
+                .or_else(|| header("x-amz-request-id"))
+                .or_else(|| body.and_then(request_id_in_body)),
+            extended_request_id: header("x-amz-id-2"),
+        }
+    }
+}
+
+fn request_id_in_body(body: &[u8]) -> Option<String> {
+    let body = std::str::from_utf8(body).ok()?;
+    ["RequestID", "RequestId"].iter().find_map(|tag| {
+        let (_, rest) = body.split_once(&format!("<{}>", tag))?;
+        let (id, _) = rest.split_once(&format!("</{}>", tag))?;
+        Some(id.trim().to_string())
+    })
+}
+
+/// How much of the body is kept when describing a response the SDK couldn't make sense of.
+const BODY_SNIPPET_CHARS: usize = 200;
+
+/// The status and the start of the body, e.g. of an error page from a proxy rather than AWS.
+///
+/// The body may be anything, so this never fails.
+pub fn describe_response(status: StatusCode, body: Option<&[u8]>) -> String {
+    let body = body.map(String::from_utf8_lossy).unwrap_or_default();
+    let body = body.split_whitespace().collect::<Vec<_>>().join(" ");
+    match body.char_indices().nth(BODY_SNIPPET_CHARS) {
+        _ if body.is_empty() => format!("HTTP {} with an empty body", status),
+        Some((end, _)) => format!("HTTP {}: {}...", status, &body[..end]),
+        None => format!("HTTP {}: {}", status, body),
+    }
+}
+
+impl fmt::Display for RequestIds {
+    /// Nothing if there are no IDs, else they're parenthesized, to be appended to a message.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.request_id, &self.extended_request_id) {
+            (Some(id), Some(extended)) => {
+                write!(f, " (request ID {}, extended request ID {})", id, extended)
+            }
+            (Some(id), None) => write!(f, " (request ID {})", id),
+            (None, Some(extended)) => write!(f, " (extended request ID {})", extended),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+impl AWSError {
+    /// The ID AWS gave the failed request, if it answered with one.
+    pub fn request_id(&self) -> Option<&str> {
+        match self {
+            Self::Service { request, .. } | Self::Other { request, .. } => {
+                request.request_id.as_deref()
+            }
+            _ => None,
+        }
+    }
+
+    pub fn from_sdk<E>(operation: &'static str, err: SdkError<E>) -> Self
+    where
+        E: ProvideErrorKind + Error,
+    {
+        match err {
+            SdkError::TimeoutError(_) => Self::Timeout { operation },
+            SdkError::DispatchFailure(err) if err.is_timeout() => Self::Timeout { operation },
+            SdkError::DispatchFailure(err) => Self::Dispatch {
+                operation,
+                message: err.to_string(),
+            },
+            SdkError::ServiceError { err, raw } => {
+                let http = raw.http();
+                let body = http.body().bytes();
+                // Without a code, the body likely isn't an AWS error at all
+                let message = match err.code() {
+                    Some(_) => err.to_string(),
+                    None => format!("{} ({})", err, describe_response(http.status(), body)),
+                };
+                Self::Service {
+                    operation,
+                    code: err.code().map(String::from),
+                    message,
+                    request: RequestIds::from_response(http.headers(), body),
+                }
+            }
+            SdkError::ResponseError { err, raw } => {
+                let http = raw.http();
+                let body = http.body().bytes();
+                Self::Other {
+                    operation,
+                    message: format!("{} ({})", err, describe_response(http.status(), body)),
+                    request: RequestIds::from_response(http.headers(), body),
+                }
+            }
+            err => Self::Other {
+                operation,
+                message: err.to_string(),
+                request: RequestIds::default(),
+            },
+        }
+    }
 }
 
 impl Error for AWSError {}
 
+/// What an [`AWSClient`](super::AWSClient) operation was working on when it failed, attached to its errors.
+///
+/// Operations call each other, only the innermost one is kept as it's the most precise.
+#[derive(Clone, Debug)]
+pub struct OperationContext {
+    pub operation: &'static str,
+    /// Empty if unknown
+    pub prefix_list_id: String,
+    /// The CIDRs being looked for, added or removed
+    pub cidrs: Vec<IpNet>,
+    /// Which of doorman's tries at updating the target this was, starting at 1
+    pub attempt: u32,
+}
+
+impl OperationContext {
+    pub fn new(operation: &'static str, prefix_list_id: &str) -> Self {
+        Self {
+            operation,
+            prefix_list_id: prefix_list_id.to_string(),
+            cidrs: Vec::new(),
+            attempt: 1,
+        }
+    }
+
+    pub fn cidrs(mut self, cidrs: impl IntoIterator<Item = IpNet>) -> Self {
+        self.cidrs.extend(cidrs);
+        self
+    }
+
+    /// Records that `err` happened on doorman's `attempt`th try, if an operation's context is attached to it.
+    pub fn retried(err: &mut Report, attempt: u32) {
+        if let Some(context) = err.downcast_mut::<Self>() {
+            context.attempt = attempt;
+        }
+    }
+
+    /// Attaches itself to `err`, unless an inner operation already did.
+    pub fn attach(self, err: Report) -> Report {
+        match err.downcast_ref::<Self>() {
+            Some(_) => err,
+            None => err.wrap_err(self),
+        }
+    }
+}
+
+impl fmt::Display for OperationContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.operation)?;
+        if !self.prefix_list_id.is_empty() {
+            write!(f, " on {}", self.prefix_list_id)?;
+        }
+        if !self.cidrs.is_empty() {
+            let cidrs: Vec<String> = self.cidrs.iter().map(ToString::to_string).collect();
+            write!(f, " for {}", cidrs.join(", "))?;
+        }
+        // Only retries are worth pointing out
+        if self.attempt > 1 {
+            write!(f, " (attempt {})", self.attempt)?;
+        }
+        Ok(())
+    }
+}
+
 impl fmt::Display for AWSError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Self::CardinalityError(msg) => write!(f, "{}", msg),
-            Self::DescribeError(err) => write!(f, "{}", err),
-            Self::GetEntriesError(err) => write!(f, "{}", err),
-            Self::ModifyError(err) => write!(f, "{}", err),
-            Self::NothingToDo(err) => write!(f, "{}", err),
+            Self::Timeout { operation } => write!(f, "{} timed out", operation),
+            Self::Dispatch { operation, message } => {
+                write!(f, "{} couldn't reach AWS: {}", operation, message)
+            }
+            Self::NotFound { prefix_list_id } => {
+                write!(f, "Prefix list {} was not found.", prefix_list_id)
+            }
+            Self::Full {
+                prefix_list_id,
+                max_entries,
+                foreign,
+            } => write!(
+                f,
+                "Prefix list {} is full ({} entries max), {} foreign entries present",
+                prefix_list_id, max_entries, foreign
+            ),
+            Self::Service {
+                operation,
+                code: Some(code),
+                message,
+                request,
+            } => write!(
+                f,
+                "{} failed with {}: {}{}",
+                operation, code, message, request
+            ),
+            Self::Service {
+                operation,
+                code: None,
+                message,
+                request,
+            }
+            | Self::Other {
+                operation,
+                message,
+                request,
+            } => write!(f, "{} failed: {}{}", operation, message, request),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_sdk_ec2::error::ModifyManagedPrefixListError;
+    use aws_smithy_http::body::SdkBody;
+    use aws_smithy_http::operation;
+    use aws_smithy_http::result::ConnectorError;
+    use color_eyre::eyre::eyre;
+
+    type ModifyError = SdkError<ModifyManagedPrefixListError>;
+
+    fn raw(status: u16, headers: &[(&str, &str)], body: &str) -> operation::Response {
+        let mut response = http::Response::builder().status(status);
+        for (name, value) in headers {
+            response = response.header(*name, *value);
+        }
+        operation::Response::new(response.body(SdkBody::from(body)).unwrap())
+    }
+
+    fn service_error(code: Option<&str>, raw: operation::Response) -> ModifyError {
+        let mut err = aws_smithy_types::Error::builder();
+        err.message("it went wrong");
+        if let Some(code) = code {
+            err.code(code);
+        }
+        SdkError::ServiceError {
+            err: ModifyManagedPrefixListError::generic(err.build()),
+            raw,
+        }
+    }
+
+    fn converted(err: ModifyError) -> AWSError {
+        AWSError::from_sdk("ModifyManagedPrefixList", err)
+    }
+
+    const XML_BODY: &str = "<Response><Errors><Error><Code>InternalError</Code></Error></Errors>\
+                            <RequestID>req-body</RequestID></Response>";
+
+    #[test]
+    fn request_id_from_the_header() {
+        let err = converted(service_error(
+            Some("InternalError"),
+            raw(500, &[("x-amzn-requestid", "req-header")], ""),
+        ));
+        assert_eq!(err.request_id(), Some("req-header"));
+        assert_eq!(
+            err.to_string(),
+            "ModifyManagedPrefixList failed with InternalError: \
+             Error { code: \"InternalError\", message: \"it went wrong\" } (request ID req-header)"
+        );
+    }
+
+    #[test]
+    fn request_id_from_the_xml_body() {
+        let err = converted(service_error(
+            Some("InternalError"),
+            raw(500, &[], XML_BODY),
+        ));
+        assert_eq!(err.request_id(), Some("req-body"));
+        assert!(err.to_string().ends_with(" (request ID req-body)"));
+    }
+
+    #[test]
+    fn header_wins_over_the_body() {
+        let err = converted(service_error(
+            Some("InternalError"),
+            raw(500, &[("x-amz-request-id", "req-header")], XML_BODY),
+        ));
+        assert_eq!(err.request_id(), Some("req-header"));
+    }
+
+    #[test]
+    fn extended_request_id() {
+        let err = converted(service_error(
+            Some("InternalError"),
+            raw(
+                500,
+                &[
+                    ("x-amzn-requestid", "req-header"),
+                    ("x-amz-id-2", "req-extended"),
+                ],
+                "",
+            ),
+        ));
+        assert!(err
+            .to_string()
+            .ends_with(" (request ID req-header, extended request ID req-extended)"));
+    }
+
+    #[test]
+    fn no_request_id() {
+        let err = converted(service_error(Some("InternalError"), raw(500, &[], "")));
+        assert_eq!(err.request_id(), None);
+        assert!(err.to_string().ends_with("it went wrong\" }"), "{}", err);
+    }
+
+    #[test]
+    fn request_id_of_an_unreadable_response() {
+        let err = converted(SdkError::ResponseError {
+            err: "unexpected end of input".into(),
+            raw: raw(
+                502,
+                &[("x-amzn-requestid", "req-header")],
+                "<html>Bad gateway</html>",
+            ),
+        });
+        assert!(matches!(err, AWSError::Other { .. }));
+        assert_eq!(err.request_id(), Some("req-header"));
+        assert_eq!(
+            err.to_string(),
+            "ModifyManagedPrefixList failed: unexpected end of input \
+             (HTTP 502 Bad Gateway: <html>Bad gateway</html>) (request ID req-header)"
+        );
+    }
+
+    #[test]
+    fn errors_without_a_code_describe_the_response() {
+        let err = converted(service_error(
+            None,
+            raw(503, &[], "<html>Service Unavailable</html>"),
+        ));
+        assert!(matches!(err, AWSError::Service { code: None, .. }));
+        assert!(
+            err.to_string()
+                .contains("(HTTP 503 Service Unavailable: <html>Service Unavailable</html>)"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn timeouts_and_dispatch_failures() {
+        let err = converted(SdkError::TimeoutError("too slow".into()));
+        assert!(matches!(
+            err,
+            AWSError::Timeout {
+                operation: "ModifyManagedPrefixList"
+            }
+        ));
+        assert_eq!(err.request_id(), None);
+
+        let err = converted(SdkError::DispatchFailure(ConnectorError::timeout(
+            "connect timed out".into(),
+        )));
+        assert!(matches!(err, AWSError::Timeout { .. }));
+
+        let err = converted(SdkError::DispatchFailure(ConnectorError::io(
+            "connection refused".into(),
+        )));
+        assert!(matches!(err, AWSError::Dispatch { .. }));
+        assert_eq!(err.request_id(), None);
+    }
+
+    fn context() -> OperationContext {
+        OperationContext::new("modify_entries", "pl-12345678").cidrs([
+            "192.0.2.1/32".parse().unwrap(),
+            "192.0.2.2/32".parse().unwrap(),
+        ])
+    }
+
+    #[test]
+    fn context_renders_the_operation_prefix_list_and_cidrs() {
+        assert_eq!(
+            context().to_string(),
+            "modify_entries on pl-12345678 for 192.0.2.1/32, 192.0.2.2/32"
+        );
+        assert_eq!(
+            OperationContext::new("get_prefix_list", "").to_string(),
+            "get_prefix_list"
+        );
+    }
+
+    #[test]
+    fn context_renders_retries() {
+        let mut err = context().attach(eyre!("InternalError"));
+        OperationContext::retried(&mut err, 2);
+        assert_eq!(err.downcast_ref::<OperationContext>().unwrap().attempt, 2);
+        assert_eq!(
+            format!("{:#}", err),
+            "modify_entries on pl-12345678 for 192.0.2.1/32, 192.0.2.2/32 (attempt 2): InternalError"
+        );
+    }
+
+    #[test]
+    fn context_is_attached_once() {
+        let inner = OperationContext::new("list_entries", "pl-12345678");
+        let err = context().attach(inner.attach(eyre!("InternalError")));
+        assert_eq!(
+            format!("{:#}", err),
+            "list_entries on pl-12345678: InternalError"
+        );
+    }
+
+    #[test]
+    fn retried_leaves_errors_without_context_alone() {
+        let mut err = eyre!("InternalError");
+        OperationContext::retried(&mut err, 2);
+        assert_eq!(format!("{:#}", err), "InternalError");
+    }
+
+    #[test]
+    fn describe_an_xml_response() {
+        assert_eq!(
+            describe_response(
+                StatusCode::SERVICE_UNAVAILABLE,
+                Some(b"<Response>\n  <Errors><Error><Code>Unavailable</Code></Error></Errors>\n</Response>")
+            ),
+            "HTTP 503 Service Unavailable: \
+             <Response> <Errors><Error><Code>Unavailable</Code></Error></Errors> </Response>"
+        );
+    }
+
+    #[test]
+    fn describe_an_html_response() {
+        let page = format!(
+            "<html>\n<body>\n<h1>Bad gateway</h1>\n{}</body>\n</html>",
+            "<p>The proxy failed.</p>\n".repeat(20)
+        );
+        let described = describe_response(StatusCode::BAD_GATEWAY, Some(page.as_bytes()));
+        // Cut short, on a single line
+        assert!(
+            described.starts_with("HTTP 502 Bad Gateway: <html> <body> <h1>Bad gateway</h1> <p>"),
+            "{}",
+            described
+        );
+        assert!(described.ends_with("..."));
+        assert!(!described.contains('\n'));
+        assert_eq!(
+            described.chars().count(),
+            "HTTP 502 Bad Gateway: ...".len() + BODY_SNIPPET_CHARS
+        );
+    }
+
+    #[test]
+    fn describe_an_empty_response() {
+        assert_eq!(
+            describe_response(StatusCode::INTERNAL_SERVER_ERROR, Some(b"")),
+            "HTTP 500 Internal Server Error with an empty body"
+        );
+        assert_eq!(
+            describe_response(StatusCode::INTERNAL_SERVER_ERROR, Some(b" \n ")),
+            "HTTP 500 Internal Server Error with an empty body"
+        );
+        // Such as a streaming body
+        assert_eq!(
+            describe_response(StatusCode::INTERNAL_SERVER_ERROR, None),
+            "HTTP 500 Internal Server Error with an empty body"
+        );
+    }
+
+    #[test]
+    fn describe_an_invalid_utf8_response() {
+        assert_eq!(
+            describe_response(StatusCode::BAD_GATEWAY, Some(b"bad \xff\xfe gateway")),
+            "HTTP 502 Bad Gateway: bad \u{fffd}\u{fffd} gateway"
+        );
+        // Truncating never splits a character
+        let body = "\u{e9}".repeat(BODY_SNIPPET_CHARS + 1);
+        let described = describe_response(StatusCode::BAD_GATEWAY, Some(body.as_bytes()));
+        assert!(described.ends_with(&format!("{}...", "\u{e9}".repeat(3))));
+    }
+}
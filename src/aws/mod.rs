@@ -1,191 +1,1983 @@
-use aws_sdk_ec2::client::Client as EC2Client;
+use crate::audit_log::AuditLog;
+use crate::cidr::HostCidr;
+use crate::config::{Description, PrefixListId};
+use crate::stats::Stats;
+
 use aws_sdk_ec2::model::{
     AddPrefixListEntry, ManagedPrefixList, PrefixListEntry, PrefixListState, RemovePrefixListEntry,
 };
+use chrono::Utc;
 use color_eyre::{eyre::eyre, Report, Result};
 use ipnet::IpNet;
+use rand::Rng;
+use std::cell::Cell;
 use std::cmp::min;
-use tokio::time::{sleep, timeout, Duration};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Mutex;
+use tokio::time::{sleep, timeout, Duration, Instant};
+use tracing::{debug, debug_span, field, warn, Instrument, Span};
+
+mod api;
+mod clients;
+mod error;
+
+#[cfg(test)]
+pub use self::api::fake::FakeApi;
+pub use self::api::{all_entries, Page, PrefixListApi};
+pub use self::clients::{retry_config, timeout_config, CallerIdentity, EC2Clients};
+#[cfg(any(test, fuzzing))]
+pub use self::error::{describe_response, RequestIds};
+pub use self::error::{AWSError, OperationContext};
+
+/// Longest pause between two looks at a prefix list while waiting on it, the cap of [`PollBackoff`].
+pub const WAIT_MAX_POLL_SECS: u64 = 15;
+
+/// How long [`AWSClient::wait_for_state`] waits by default, in seconds.
+pub const DEFAULT_WAIT_TIMEOUT_SECS: u64 = 60;
+
+/// How often [`AWSClient::wait_for_state`] and [`AWSClient::wait_until_settled`] look at the prefix list.
+///
+/// The pauses double from `initial` up to `max`, each shortened by up to a quarter so several doorman don't poll in
+/// lockstep.
+#[derive(Clone, Copy, Debug)]
+pub struct PollBackoff {
+    pub initial: Duration,
+    pub max: Duration,
+}
+
+impl Default for PollBackoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(WAIT_MAX_POLL_SECS),
+        }
+    }
+}
 
-// pub use self::error::AWSError;
+impl PollBackoff {
+    /// The pause following `delay`.
+    fn next(&self, delay: Duration) -> Duration {
+        min(delay * 2, self.max)
+    }
+
+    fn jittered(delay: Duration) -> Duration {
+        delay.mul_f64(rand::thread_rng().gen_range(0.75..=1.0))
+    }
+}
 
 // pub type AWSResult<T> = Result<T, AWSError>;
 
-// pub struct Entry {
-//     cidr: IpNet,
-//     description: String,
-// }
-//
-// impl TryFrom<&PrefixListEntry> for Entry {
-//     type Error = Report;
-//
-//     fn try_from(value: &PrefixListEntry) -> Result<Self> {
-//         let cidr = IpNet::from_str(value.cidr.as_ref().ok_or_else(|| eyre!("empty cidr"))?)?;
-//         let description = value.description.clone().unwrap_or_else(String::new);
-//         Ok(Self { cidr, description })
-//     }
-// }
+/// What [`AWSClient::cleanup`] did to a prefix list.
+#[derive(Debug, Default)]
+pub struct Cleaned {
+    /// CIDRs of the entries removed
+    pub removed: Vec<IpNet>,
+    /// The version the removal led to, if anything was removed
+    pub version: Option<i64>,
+    /// Malformed entries left alone, as their description and what's wrong with them
+    pub skipped: Vec<String>,
+    /// CIDRs of owned entries left alone as someone else wrote them
+    pub conflicts: Vec<IpNet>,
+}
+
+/// What [`AWSClient::collapse_owned`] did to a prefix list.
+#[derive(Debug, Default)]
+pub struct Collapsed {
+    /// CIDR of the owned entry left, if there's one
+    pub kept: Option<IpNet>,
+    /// CIDRs of the duplicates removed
+    pub removed: Vec<IpNet>,
+    /// The version the removal led to, if anything was removed
+    pub version: Option<i64>,
+}
+
+/// Which entries [`AWSClient::entries`] returns.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EntryFilter {
+    #[default]
+    All,
+    /// Those owned by doorman, as decided by [`Description::matches_owned_entry`]
+    Owned,
+    /// Those for this CIDR, whoever owns them
+    Cidr(IpNet),
+}
+
+impl EntryFilter {
+    fn matches(&self, entry: &Entry, description: &Description) -> bool {
+        match self {
+            Self::All => true,
+            Self::Owned => description.matches_owned_entry(entry),
+            Self::Cidr(cidr) => entry.cidr == *cidr,
+        }
+    }
+
+    /// Whether this would keep a malformed entry, which can only be told by its description.
+    fn keeps_malformed(&self, entry: &PrefixListEntry, description: &Description) -> bool {
+        match self {
+            Self::All => true,
+            Self::Owned => description.owns(entry.description.as_deref().unwrap_or_default()),
+            // A CIDR which can't be parsed isn't this one
+            Self::Cidr(_) => false,
+        }
+    }
+}
+
+/// An entry of a prefix list, with the SDK's optional fields resolved.
+///
+/// Everything past listing the entries works with this rather than [`PrefixListEntry`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub cidr: IpNet,
+    /// Empty if the entry has none
+    pub description: String,
+}
+
+impl TryFrom<&PrefixListEntry> for Entry {
+    type Error = Report;
+
+    /// Fails if the CIDR is missing or can't be parsed, which AWS shouldn't allow.
+    fn try_from(value: &PrefixListEntry) -> Result<Self> {
+        let cidr = value
+            .cidr
+            .as_deref()
+            .filter(|cidr| !cidr.is_empty())
+            .ok_or_else(|| eyre!("entry without a CIDR"))?;
+        let cidr = cidr
+            .parse()
+            .map_err(|err| eyre!("entry with an invalid CIDR \"{}\": {}", cidr, err))?;
+        let description = value.description.clone().unwrap_or_default();
+        Ok(Self { cidr, description })
+    }
+}
 
 pub struct AWSClient {
-    ec2_client: EC2Client,
+    api: Box<dyn PrefixListApi>,
     // prefix_list_v4_id: String,
     // prefix_list_v6_id: String,
-    description: String,
+    description: Description,
+    /// How long to wait for a change to complete, in seconds
+    wait_timeout: u64,
+    /// How often to look at a prefix list while waiting on it
+    poll_backoff: PollBackoff,
+    /// The entries last listed per prefix list ID, along with the version they belong to
+    entry_cache: Mutex<HashMap<String, (i64, Vec<PrefixListEntry>)>>,
+    /// Counts the entry cache hits and misses, and times the operations, if set
+    stats: Option<Stats>,
+    /// Records every modification, if set
+    audit_log: Option<AuditLog>,
+    /// How long the entries added last, written as their expiry in the description, if set
+    entry_ttl: Option<chrono::Duration>,
 }
 
-impl AWSClient {
-    pub fn new(ec2_client: EC2Client, description: &str) -> Self {
-        Self {
-            ec2_client,
-            description: description.to_string(),
-        }
+/// Builds an [`AWSClient`], with only the API and description being required.
+#[derive(Default)]
+pub struct AWSClientBuilder {
+    api: Option<Box<dyn PrefixListApi>>,
+    description: Option<Description>,
+    wait_timeout: Option<u64>,
+    poll_backoff: Option<PollBackoff>,
+    stats: Option<Stats>,
+    audit_log: Option<AuditLog>,
+    entry_ttl: Option<chrono::Duration>,
+}
+
+impl AWSClientBuilder {
+    /// Usually an EC2 client.
+    pub fn api(mut self, api: impl PrefixListApi + 'static) -> Self {
+        self.api = Some(Box::new(api));
+        self
     }
 
-    pub async fn get_prefix_list(&self, prefix_list_id: &str) -> Result<ManagedPrefixList> {
-        let response = self
-            .ec2_client
-            .describe_managed_prefix_lists()
-            .prefix_list_ids(prefix_list_id)
-            .send()
-            .await?;
+    /// Tells the entries owned by doorman apart, and is set on the ones it adds.
+    pub fn description(mut self, description: Description) -> Self {
+        self.description = Some(description);
+        self
+    }
 
-        // This should only return 0 or 1 prefix lists, any more is an error
-        if response.prefix_lists.is_none() || response.prefix_lists.as_ref().unwrap().is_empty() {
-            return Err(eyre!("Prefix list {} was not found.", prefix_list_id));
-        }
+    /// In seconds, [`DEFAULT_WAIT_TIMEOUT_SECS`] if not set.
+    pub fn wait_timeout(mut self, wait_timeout: u64) -> Self {
+        self.wait_timeout = Some(wait_timeout);
+        self
+    }
+
+    /// [`PollBackoff::default`] if not set.
+    pub fn poll_backoff(mut self, poll_backoff: PollBackoff) -> Self {
+        self.poll_backoff = Some(poll_backoff);
+        self
+    }
+
+    pub fn stats(mut self, stats: Stats) -> Self {
+        self.stats = Some(stats);
+        self
+    }
+
+    pub fn audit_log(mut self, audit_log: AuditLog) -> Self {
+        self.audit_log = Some(audit_log);
+        self
+    }
+
+    /// Makes the entries added carry their expiry, `entry_ttl` from when they're written.
+    pub fn entry_ttl(mut self, entry_ttl: chrono::Duration) -> Self {
+        self.entry_ttl = Some(entry_ttl);
+        self
+    }
 
-        let prefix_lists = response.prefix_lists.unwrap();
-        if response.next_token.is_some() || prefix_lists.len() > 1 {
+    pub fn build(self) -> Result<AWSClient> {
+        let api = self.api.ok_or_else(|| eyre!("No API to reach AWS with."))?;
+        let description = self
+            .description
+            .ok_or_else(|| eyre!("No description for the entries."))?;
+        let wait_timeout = self.wait_timeout.unwrap_or(DEFAULT_WAIT_TIMEOUT_SECS);
+        if wait_timeout == 0 {
+            return Err(eyre!("The wait timeout must be at least one second."));
+        }
+        let poll_backoff = self.poll_backoff.unwrap_or_default();
+        if poll_backoff.initial.is_zero() || poll_backoff.initial > poll_backoff.max {
             return Err(eyre!(
-                "Found too many prefix lists! This shouldn't happen..."
+                "The first poll pause must be more than zero, and at most the longest one."
             ));
         }
+        Ok(AWSClient {
+            api,
+            description,
+            wait_timeout,
+            poll_backoff,
+            entry_cache: Mutex::default(),
+            stats: self.stats,
+            audit_log: self.audit_log,
+            entry_ttl: self.entry_ttl,
+        })
+    }
+}
 
-        Ok(prefix_lists[0].clone())
+impl AWSClient {
+    pub fn builder() -> AWSClientBuilder {
+        AWSClientBuilder::default()
     }
 
-    // pub async fn get_v4_entries(&self) -> Result<Vec<Entry>> {
-    //     self.get_prefix_list_entries(&self.prefix_list_v4_id).await
-    // }
-    //
-    // pub async fn get_v6_entries(&self) -> Result<Vec<Entry>> {
-    //     self.get_prefix_list_entries(&self.prefix_list_v6_id).await
-    // }
+    pub async fn get_prefix_list(
+        &self,
+        prefix_list_id: &PrefixListId,
+    ) -> Result<ManagedPrefixList> {
+        traced(
+            self.stats.as_ref(),
+            OperationContext::new("get_prefix_list", prefix_list_id.as_str()),
+            debug_span!(
+                "get_prefix_list",
+                prefix_list_id = prefix_list_id.as_str(),
+                duration_ms = field::Empty,
+                outcome = field::Empty
+            ),
+            async {
+                let (prefix_lists, next_token) =
+                    self.api.describe_prefix_list(prefix_list_id).await?;
 
-    async fn get_prefix_list_entries(&self, prefix_list_id: &str) -> Result<Vec<PrefixListEntry>> {
-        let mut token = None;
-        let mut total_entries = Vec::new();
+                // This should only return 0 or 1 prefix lists, any more is an error
+                if prefix_lists.is_empty() {
+                    return Err(AWSError::NotFound {
+                        prefix_list_id: prefix_list_id.to_string(),
+                    }
+                    .into());
+                }
 
-        loop {
-            let response = self
-                .ec2_client
-                .get_managed_prefix_list_entries()
-                .prefix_list_id(prefix_list_id)
-                .set_next_token(token.clone())
-                .send()
-                .await?;
-
-            if let Some(entries) = response.entries {
-                entries
-                    .into_iter()
-                    .for_each(|entry| total_entries.push(entry))
-            };
+                if next_token.is_some() || prefix_lists.len() > 1 {
+                    return Err(eyre!(
+                        "Found too many prefix lists! This shouldn't happen..."
+                    ));
+                }
+
+                Ok(prefix_lists[0].clone())
+            },
+        )
+        .await
+    }
 
-            token = response.next_token;
-            if token.is_none() {
-                break;
+    /// The entries of the prefix list selected by `filter`, across all pages.
+    ///
+    /// `prefix_list` is the prefix list as just described, which tells whether the entries listed last still hold.
+    /// Malformed entries the filter would leave out are skipped with a warning, as anyone sharing the prefix list may
+    /// have added them. This only fails on a malformed entry the filter would keep.
+    pub async fn entries(
+        &self,
+        prefix_list: &ManagedPrefixList,
+        filter: EntryFilter,
+    ) -> Result<Vec<Entry>> {
+        let mut entries = Vec::new();
+        for raw in self.list_entries(prefix_list).await? {
+            match Entry::try_from(&raw) {
+                Ok(entry) if filter.matches(&entry, &self.description) => entries.push(entry),
+                Ok(_) => {}
+                Err(err) if filter.keeps_malformed(&raw, &self.description) => {
+                    return Err(OperationContext::new("entries", id_str(prefix_list)).attach(err))
+                }
+                Err(err) => warn!(
+                    "Skipping malformed entry {} in {}: {}",
+                    raw.description
+                        .as_deref()
+                        .unwrap_or("without a description"),
+                    id_str(prefix_list),
+                    err
+                ),
             }
         }
+        Ok(entries)
+    }
+
+    /// All the entries as returned by AWS, for callers which can live with malformed ones.
+    ///
+    /// The entries are only listed again once the version of the prefix list changed, which saves going through
+    /// every page of a large one. The version is the one of `prefix_list`, as the caller described it.
+    async fn list_entries(&self, prefix_list: &ManagedPrefixList) -> Result<Vec<PrefixListEntry>> {
+        traced(
+            self.stats.as_ref(),
+            OperationContext::new("list_entries", id_str(prefix_list)),
+            debug_span!(
+                "list_entries",
+                prefix_list_id = id_str(prefix_list),
+                duration_ms = field::Empty,
+                outcome = field::Empty
+            ),
+            async {
+                let prefix_list_id = parse_id(prefix_list)?;
+                // Entries listed during a modification may not match the version
+                let version = match prefix_list.state {
+                    Some(
+                        PrefixListState::CreateComplete
+                        | PrefixListState::ModifyComplete
+                        | PrefixListState::RestoreComplete,
+                    ) => prefix_list.version,
+                    _ => None,
+                };
+                if let Some(version) = version {
+                    let cache = self.entry_cache.lock().unwrap();
+                    if let Some((_, entries)) = cache
+                        .get(prefix_list_id.as_str())
+                        .filter(|(cached, _)| *cached == version)
+                    {
+                        debug!("Reusing the entries of version {}.", version);
+                        self.count_entry_cache(true);
+                        return Ok(entries.clone());
+                    }
+                }
+                self.count_entry_cache(false);
+
+                let total_entries = all_entries(&*self.api, &prefix_list_id).await?;
+
+                if let Some(version) = version {
+                    self.entry_cache
+                        .lock()
+                        .unwrap()
+                        .insert(prefix_list_id.to_string(), (version, total_entries.clone()));
+                }
+                Ok(total_entries)
+            },
+        )
+        .await
+    }
+
+    fn count_entry_cache(&self, hit: bool) {
+        if let Some(stats) = &self.stats {
+            stats.entry_cache(hit);
+        }
+    }
+
+    /// Whether the prefix list holds an entry for `cidr` owned by doorman.
+    pub async fn has_owned_entry(
+        &self,
+        prefix_list: &ManagedPrefixList,
+        cidr: &IpNet,
+    ) -> Result<bool> {
+        traced(
+            self.stats.as_ref(),
+            OperationContext::new("has_owned_entry", id_str(prefix_list)).cidrs([*cidr]),
+            debug_span!(
+                "has_owned_entry",
+                prefix_list_id = id_str(prefix_list),
+                cidr = field::display(cidr),
+                duration_ms = field::Empty,
+                outcome = field::Empty
+            ),
+            async {
+                Ok(self
+                    .entries(prefix_list, EntryFilter::Cidr(*cidr))
+                    .await?
+                    .iter()
+                    .any(|entry| self.description.matches_owned_entry(entry)))
+            },
+        )
+        .await
+    }
+
+    /// The entry for `cidr` not owned by doorman, if there's one, e.g. added by a teammate sharing the same IP.
+    ///
+    /// A prefix list holds a single entry per CIDR, so doorman can't add its own alongside.
+    pub async fn foreign_entry(
+        &self,
+        prefix_list: &ManagedPrefixList,
+        cidr: &IpNet,
+    ) -> Result<Option<Entry>> {
+        traced(
+            self.stats.as_ref(),
+            OperationContext::new("foreign_entry", id_str(prefix_list)).cidrs([*cidr]),
+            debug_span!(
+                "foreign_entry",
+                prefix_list_id = id_str(prefix_list),
+                cidr = field::display(cidr),
+                duration_ms = field::Empty,
+                outcome = field::Empty
+            ),
+            async {
+                Ok(self
+                    .entries(prefix_list, EntryFilter::Cidr(*cidr))
+                    .await?
+                    .into_iter()
+                    .find(|entry| !self.description.matches_owned_entry(entry)))
+            },
+        )
+        .await
+    }
 
-        Ok(total_entries)
+    /// Doorman's entries to remove along with `remove`, so that `add` fits in the prefix list.
+    ///
+    /// The oldest go first, by the timestamp embedded in their description, those without one being taken for the
+    /// oldest. Other people's entries are never removed to make room: if doorman's own aren't enough, this fails with
+    /// [`AWSError::Full`].
+    pub async fn make_room(
+        &self,
+        prefix_list: &ManagedPrefixList,
+        add: &[&HostCidr],
+        remove: &[IpNet],
+    ) -> Result<Vec<IpNet>> {
+        let prefix_list_id = prefix_list.prefix_list_id.as_deref().unwrap_or_default();
+        // Without a limit, there's always room
+        let max_entries = match prefix_list.max_entries {
+            Some(max_entries) => usize::try_from(max_entries).unwrap_or_default(),
+            None => return Ok(vec![]),
+        };
+        traced(
+            self.stats.as_ref(),
+            OperationContext::new("make_room", prefix_list_id)
+                .cidrs(add.iter().map(|cidr| cidr.net())),
+            debug_span!(
+                "make_room",
+                prefix_list_id,
+                max_entries,
+                duration_ms = field::Empty,
+                outcome = field::Empty
+            ),
+            async {
+                let entries = self.entries(prefix_list, EntryFilter::All).await?;
+                let kept: Vec<&Entry> = entries
+                    .iter()
+                    .filter(|entry| !remove.contains(&entry.cidr))
+                    .collect();
+                let added = add
+                    .iter()
+                    .filter(|cidr| !kept.iter().any(|entry| entry.cidr == cidr.net()))
+                    .count();
+                let excess = (kept.len() + added).saturating_sub(max_entries);
+                if excess == 0 {
+                    return Ok(vec![]);
+                }
+
+                let mut owned: Vec<&Entry> = kept
+                    .iter()
+                    .copied()
+                    .filter(|entry| {
+                        self.description.matches_owned_entry(entry)
+                            && !add.iter().any(|cidr| cidr.net() == entry.cidr)
+                    })
+                    .collect();
+                if owned.len() < excess {
+                    return Err(AWSError::Full {
+                        prefix_list_id: prefix_list_id.to_string(),
+                        max_entries,
+                        foreign: kept
+                            .iter()
+                            .filter(|entry| !self.description.matches_owned_entry(entry))
+                            .count(),
+                    }
+                    .into());
+                }
+                owned.sort_by_key(|entry| self.description.embedded_timestamp(entry));
+                let sacrificed: Vec<IpNet> =
+                    owned[..excess].iter().map(|entry| entry.cidr).collect();
+                for entry in &owned[..excess] {
+                    warn!(
+                        "{} is full ({} entries max), removing doorman's oldest entry {} (\"{}\") to make room.",
+                        prefix_list_id, max_entries, entry.cidr, entry.description
+                    );
+                }
+                Ok(sacrificed)
+            },
+        )
+        .await
     }
 
     /// Modify the prefix list by adding and / or removing an entry.
+    ///
+    /// Only doorman's own entries are added, but any CIDR may be removed, e.g. when cleaning up.
+    ///
+    /// The prefix list returned carries the version the modification leads to, which is what to wait for. AWS answers
+    /// with the version it started from while the modification is in progress.
     pub async fn modify_entries(
         &self,
         prefix_list: &ManagedPrefixList,
-        add: Vec<&IpNet>,
-        remove: Vec<&IpNet>,
+        add: Vec<&HostCidr>,
+        remove: Vec<IpNet>,
     ) -> Result<ManagedPrefixList> {
-        let add_entries = add
-            .iter()
-            .map(|net| {
-                AddPrefixListEntry::builder()
-                    .cidr(net.to_string())
-                    .description(&self.description)
-                    .build()
-            })
-            .collect();
-        let remove_entries = remove
-            .iter()
-            .map(|net| {
-                RemovePrefixListEntry::builder()
-                    .cidr(net.to_string())
-                    .build()
-            })
-            .collect();
-        let response = self
-            .ec2_client
-            .modify_managed_prefix_list()
-            .prefix_list_id(prefix_list.prefix_list_id.as_ref().unwrap())
-            .set_current_version(prefix_list.version)
-            .set_add_entries(Some(add_entries))
-            .set_remove_entries(Some(remove_entries))
-            .send()
-            .await?;
-        response
-            .prefix_list
-            .ok_or_else(|| eyre!("Modify Prefix List didn't return a prefix list."))
-    }
-
-    /// Removes entries having the configured description
-    pub async fn cleanup(&self, prefix_list_id: &str) -> Result<ManagedPrefixList> {
-        let entries = self.get_prefix_list_entries(prefix_list_id).await?;
-
-        let ips_to_clean: Vec<IpNet> = entries
-            .iter()
-            .filter_map(|entry| {
-                if entry.description.as_ref() == Some(&self.description) {
-                    Some(entry.cidr.as_ref().unwrap().parse().unwrap())
-                } else {
-                    None
+        traced(
+            self.stats.as_ref(),
+            OperationContext::new(
+                "modify_entries",
+                prefix_list.prefix_list_id.as_deref().unwrap_or_default(),
+            )
+            .cidrs(
+                add.iter()
+                    .map(|cidr| cidr.net())
+                    .chain(remove.iter().copied()),
+            ),
+            debug_span!(
+                "modify_entries",
+                prefix_list_id = prefix_list.prefix_list_id.as_deref().unwrap_or_default(),
+                version = prefix_list.version.unwrap_or_default(),
+                version_after = field::Empty,
+                duration_ms = field::Empty,
+                outcome = field::Empty
+            ),
+            async {
+                let description = match self.entry_ttl {
+                    Some(ttl) => self.description.with_expiry(Utc::now() + ttl),
+                    None => self.description.as_str().to_string(),
+                };
+                let add_entries = add
+                    .iter()
+                    .map(|net| {
+                        AddPrefixListEntry::builder()
+                            .cidr(net.to_string())
+                            .description(&description)
+                            .build()
+                    })
+                    .collect();
+                let remove_entries = remove
+                    .iter()
+                    .map(|net| {
+                        RemovePrefixListEntry::builder()
+                            .cidr(net.to_string())
+                            .build()
+                    })
+                    .collect();
+                let prefix_list_id = parse_id(prefix_list)?;
+                // Whether or not the modification goes through, the cached entries can't be trusted anymore
+                self.entry_cache
+                    .lock()
+                    .unwrap()
+                    .remove(prefix_list_id.as_str());
+                let result = self
+                    .api
+                    .modify_entries(
+                        &prefix_list_id,
+                        prefix_list.version,
+                        add_entries,
+                        remove_entries,
+                    )
+                    .await;
+                if let Some(audit_log) = &self.audit_log {
+                    audit_log.modification(
+                        &prefix_list_id,
+                        prefix_list.version,
+                        &add,
+                        &remove,
+                        &result,
+                    );
                 }
-            })
-            .collect();
+                let mut modified = result?
+                    .ok_or_else(|| eyre!("Modify Prefix List didn't return a prefix list."))?;
+                let version = match (modified.version, prefix_list.version) {
+                    (Some(after), Some(before)) => Some(after.max(before + 1)),
+                    (after, before) => after.or(before.map(|before| before + 1)),
+                };
+                if let Some(version) = version {
+                    Span::current().record("version_after", version);
+                }
+                modified.version = version;
+                Ok(modified)
+            },
+        )
+        .await
+    }
 
-        let pl = self.get_prefix_list(prefix_list_id).await?;
-        if ips_to_clean.is_empty() {
-            Ok(pl)
-        } else {
-            self.modify_entries(&pl, vec![], ips_to_clean.iter().collect())
-                .await
-        }
+    /// Creates a prefix list with the same name, size, address family and tags as `deleted`, returning its ID and
+    /// the prefix list once it's usable.
+    ///
+    /// The new prefix list has a new ID, and no entries.
+    pub async fn recreate_prefix_list(
+        &self,
+        deleted: &ManagedPrefixList,
+    ) -> Result<(PrefixListId, ManagedPrefixList)> {
+        traced(
+            self.stats.as_ref(),
+            OperationContext::new(
+                "recreate_prefix_list",
+                deleted.prefix_list_id.as_deref().unwrap_or_default(),
+            ),
+            debug_span!(
+                "recreate_prefix_list",
+                prefix_list_id = deleted.prefix_list_id.as_deref().unwrap_or_default(),
+                duration_ms = field::Empty,
+                outcome = field::Empty
+            ),
+            async {
+                let id: PrefixListId = self
+                    .api
+                    .create_prefix_list_like(deleted)
+                    .await?
+                    .and_then(|prefix_list| prefix_list.prefix_list_id)
+                    .ok_or_else(|| eyre!("Create Prefix List didn't return a prefix list."))?
+                    .parse()
+                    .map_err(|err| eyre!("AWS returned an unexpected prefix list ID: {}", err))?;
+                let created = self
+                    .wait_for_state(&id, PrefixListState::CreateComplete, None)
+                    .await?;
+                Ok((id, created))
+            },
+        )
+        .await
+    }
+
+    /// Removes all but one of doorman's entries in a single modification, waiting for it to complete.
+    ///
+    /// The entry for `keep` is the one left if there's one, otherwise the newest by the timestamp embedded in its
+    /// description.
+    pub async fn collapse_owned(
+        &self,
+        prefix_list_id: &PrefixListId,
+        keep: Option<&IpNet>,
+    ) -> Result<Collapsed> {
+        traced(
+            self.stats.as_ref(),
+            OperationContext::new("collapse_owned", prefix_list_id.as_str()).cidrs(keep.copied()),
+            debug_span!(
+                "collapse_owned",
+                prefix_list_id = prefix_list_id.as_str(),
+                duration_ms = field::Empty,
+                outcome = field::Empty
+            ),
+            async {
+                let pl = self.wait_until_settled(prefix_list_id).await?;
+                let owned = self.entries(&pl, EntryFilter::Owned).await?;
+                let kept = owned
+                    .iter()
+                    .find(|entry| Some(&entry.cidr) == keep)
+                    .or_else(|| {
+                        owned
+                            .iter()
+                            .max_by_key(|entry| self.description.embedded_timestamp(entry))
+                    })
+                    .map(|entry| entry.cidr);
+                let mut collapsed = Collapsed {
+                    kept,
+                    removed: owned
+                        .iter()
+                        .map(|entry| entry.cidr)
+                        .filter(|cidr| Some(*cidr) != kept)
+                        .collect(),
+                    version: None,
+                };
+                if collapsed.removed.is_empty() {
+                    return Ok(collapsed);
+                }
+                let modified = self
+                    .modify_entries(&pl, vec![], collapsed.removed.clone())
+                    .await?;
+                collapsed.version = self
+                    .wait_for_state(
+                        prefix_list_id,
+                        PrefixListState::ModifyComplete,
+                        modified.version,
+                    )
+                    .await?
+                    .version;
+                Ok(collapsed)
+            },
+        )
+        .await
+    }
+
+    /// Removes entries owned by doorman, as decided by [`Description::matches_owned_entry`].
+    ///
+    /// Malformed entries can't be told apart, let alone removed, so they're skipped rather than failing the cleanup.
+    ///
+    /// With `ours`, owned entries for other CIDRs were written by someone else, such as another doorman sharing the
+    /// description. They're left alone and reported as conflicts. Without it, every owned entry is removed.
+    ///
+    /// `step` tells what's being done, so callers timing this out can tell where it got stuck.
+    pub async fn cleanup(
+        &self,
+        prefix_list_id: &PrefixListId,
+        ours: Option<&[IpNet]>,
+        step: &Cell<&'static str>,
+    ) -> Result<Cleaned> {
+        traced(
+            self.stats.as_ref(),
+            OperationContext::new("cleanup", prefix_list_id.as_str()),
+            debug_span!(
+                "cleanup",
+                prefix_list_id = prefix_list_id.as_str(),
+                duration_ms = field::Empty,
+                outcome = field::Empty
+            ),
+            async {
+                // A modification still in progress would make ours fail, and change the entries
+                step.set("waiting for a modification in progress to end");
+                let pl = self.wait_until_settled(prefix_list_id).await?;
+                step.set("listing the entries");
+                let mut cleaned = Cleaned::default();
+                for entry in self.list_entries(&pl).await? {
+                    match Entry::try_from(&entry) {
+                        Ok(entry) if !self.description.matches_owned_entry(&entry) => {}
+                        Ok(entry) if ours.is_none_or(|ours| ours.contains(&entry.cidr)) => {
+                            cleaned.removed.push(entry.cidr)
+                        }
+                        Ok(entry) => cleaned.conflicts.push(entry.cidr),
+                        Err(err) => {
+                            let skipped = format!(
+                                "{} ({})",
+                                entry.description.as_deref().unwrap_or("no description"),
+                                err
+                            );
+                            warn!("Skipping malformed entry {}.", skipped);
+                            cleaned.skipped.push(skipped);
+                        }
+                    }
+                }
+
+                if cleaned.removed.is_empty() {
+                    return Ok(cleaned);
+                }
+                step.set("removing the entries");
+                let modified = self
+                    .modify_entries(&pl, vec![], cleaned.removed.clone())
+                    .await?;
+                cleaned.version = modified.version;
+                Ok(cleaned)
+            },
+        )
+        .await
     }
 
+    /// Waits for changes in progress to end, returning the prefix list at its latest version.
+    ///
+    /// This doesn't give up by itself, callers are expected to bound it.
+    pub async fn wait_until_settled(
+        &self,
+        prefix_list_id: &PrefixListId,
+    ) -> Result<ManagedPrefixList> {
+        traced(
+            self.stats.as_ref(),
+            OperationContext::new("wait_until_settled", prefix_list_id.as_str()),
+            debug_span!(
+                "wait_until_settled",
+                prefix_list_id = prefix_list_id.as_str(),
+                duration_ms = field::Empty,
+                outcome = field::Empty
+            ),
+            self.poll(prefix_list_id, |mpl| {
+                Ok(!matches!(
+                    mpl.state,
+                    Some(
+                        PrefixListState::CreateInProgress
+                            | PrefixListState::ModifyInProgress
+                            | PrefixListState::RestoreInProgress
+                    )
+                ))
+            }),
+        )
+        .await
+    }
+
+    /// Polls the prefix list until it reaches `state` at `min_version` or later, right away then backing off.
+    ///
+    /// Right after a modification, AWS may still describe the prefix list as it was before, complete at the previous
+    /// version. Waiting for the version [`AWSClient::modify_entries`] returned keeps that from passing for the
+    /// modification being over.
+    ///
+    /// This gives up after the wait timeout, however long the pause in progress, and as soon as the prefix list ends
+    /// up in a failed state at `min_version` or later, since it won't leave it by itself. A failed state at an earlier
+    /// version is what the modification being waited on may still fix.
     pub async fn wait_for_state(
         &self,
-        prefix_list_id: &str,
+        prefix_list_id: &PrefixListId,
         state: PrefixListState,
-        wait_timeout: Option<u64>,
+        min_version: Option<i64>,
     ) -> Result<ManagedPrefixList> {
-        timeout(
-            Duration::from_secs(wait_timeout.unwrap_or(60)),
-            async move {
-                // Wait at least one second, as the change doesn't finish immediately.
-                let mut duration = Duration::from_secs(1);
-                let max_duration = Duration::from_secs(5);
-
-                loop {
-                    sleep(duration).await;
-                    let mpl = self.get_prefix_list(prefix_list_id).await?;
-                    if mpl.state.as_ref() == Some(&state) {
-                        return Ok::<ManagedPrefixList, Report>(mpl);
+        traced(
+            self.stats.as_ref(),
+            OperationContext::new("wait_for_state", prefix_list_id.as_str()),
+            debug_span!(
+                "wait_for_state",
+                prefix_list_id = prefix_list_id.as_str(),
+                state = state.as_str(),
+                version = field::Empty,
+                duration_ms = field::Empty,
+                outcome = field::Empty
+            ),
+            async {
+                let polled = self.poll(prefix_list_id, |mpl| {
+                    let current = min_version
+                        .is_none_or(|min| mpl.version.is_some_and(|version| version >= min));
+                    if let Some(
+                        failed @ (PrefixListState::CreateFailed
+                        | PrefixListState::ModifyFailed
+                        | PrefixListState::RestoreFailed
+                        | PrefixListState::DeleteFailed
+                        | PrefixListState::DeleteComplete),
+                    ) = &mpl.state
+                    {
+                        if current {
+                            return Err(eyre!(
+                                "The prefix list is {} instead of {}: {}",
+                                failed.as_str(),
+                                state.as_str(),
+                                mpl.state_message.as_deref().unwrap_or("no reason given")
+                            ));
+                        }
                     }
-
-                    duration = min(duration + Duration::from_secs(1), max_duration);
+                    Ok(current && mpl.state.as_ref() == Some(&state))
+                });
+                let mpl = timeout(Duration::from_secs(self.wait_timeout), polled)
+                    .await
+                    .unwrap_or_else(|_| {
+                        Err(eyre!(
+                            "The prefix list still wasn't {} after {} seconds.",
+                            state.as_str(),
+                            self.wait_timeout
+                        ))
+                    })?;
+                if let Some(version) = mpl.version {
+                    Span::current().record("version", version);
                 }
+                Ok(mpl)
             },
         )
-        .await?
+        .await
+    }
+
+    /// Describes the prefix list right away, then until `done` says so or fails, backing off in between.
+    async fn poll(
+        &self,
+        prefix_list_id: &PrefixListId,
+        done: impl Fn(&ManagedPrefixList) -> Result<bool>,
+    ) -> Result<ManagedPrefixList> {
+        let poll = self.poll_backoff;
+        let mut delay = poll.initial;
+        loop {
+            let mpl = self.get_prefix_list(prefix_list_id).await?;
+            if done(&mpl)? {
+                return Ok(mpl);
+            }
+
+            let pause = PollBackoff::jittered(delay);
+            debug!(
+                "The prefix list is {} at version {}, checking again in {} ms.",
+                mpl.state
+                    .as_ref()
+                    .map_or("in no known state", PrefixListState::as_str),
+                mpl.version
+                    .map_or_else(|| "?".to_string(), |version| version.to_string()),
+                pause.as_millis()
+            );
+            sleep(pause).await;
+            delay = poll.next(delay);
+        }
+    }
+}
+
+/// The ID of a prefix list as AWS described it, empty if it didn't.
+fn id_str(prefix_list: &ManagedPrefixList) -> &str {
+    prefix_list.prefix_list_id.as_deref().unwrap_or_default()
+}
+
+fn parse_id(prefix_list: &ManagedPrefixList) -> Result<PrefixListId> {
+    id_str(prefix_list)
+        .parse()
+        .map_err(|err| eyre!("Unexpected prefix list ID: {}", err))
+}
+
+/// Runs an AWS operation in `span`, recording how long it took and whether it succeeded.
+///
+/// Errors get `context` attached, so they tell what failed. The duration also goes to `stats`, so the metrics agree
+/// with the spans.
+async fn traced<T>(
+    stats: Option<&Stats>,
+    context: OperationContext,
+    span: Span,
+    operation: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    let name = context.operation;
+    let started = Instant::now();
+    let result = operation
+        .instrument(span.clone())
+        .await
+        .map_err(|err| context.attach(err));
+    let took = started.elapsed();
+    if let Some(stats) = stats {
+        stats.operation(name, took);
+    }
+    let duration_ms = took.as_millis() as u64;
+    let outcome = match result {
+        Ok(_) => "ok",
+        Err(_) => "error",
+    };
+    span.record("duration_ms", duration_ms);
+    span.record("outcome", outcome);
+    span.in_scope(|| debug!("Finished: {} in {} ms", outcome, duration_ms));
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: &str = "pl-12345678";
+
+    fn id() -> PrefixListId {
+        ID.parse().unwrap()
+    }
+
+    fn client(api: &FakeApi) -> AWSClient {
+        AWSClient::builder()
+            .api(api.clone())
+            .description("office".parse().unwrap())
+            .build()
+            .unwrap()
+    }
+
+    fn host(ip: &str) -> HostCidr {
+        HostCidr::from(ip.parse::<std::net::IpAddr>().unwrap())
+    }
+
+    fn net(cidr: &str) -> IpNet {
+        cidr.parse().unwrap()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn modify_entries_replaces_ours() {
+        let api = FakeApi::default().with_prefix_list(ID, None, &[("192.0.2.1/32", "office")]);
+        let aws = client(&api);
+        let pl = aws.get_prefix_list(&id()).await.unwrap();
+        let new = host("192.0.2.2");
+        let modified = aws
+            .modify_entries(&pl, vec![&new], vec![net("192.0.2.1/32")])
+            .await
+            .unwrap();
+        assert_eq!(modified.version, Some(2));
+        let settled = aws
+            .wait_for_state(&id(), PrefixListState::ModifyComplete, modified.version)
+            .await
+            .unwrap();
+        assert_eq!(settled.version, Some(2));
+        assert_eq!(
+            api.entries(ID),
+            vec![("192.0.2.2/32".to_string(), "office".to_string())]
+        );
+        let modification = &api.modifications()[0];
+        assert_eq!(modification.prefix_list_id, ID);
+        assert_eq!(modification.current_version, Some(1));
+        assert_eq!(modification.add[0].cidr.as_deref(), Some("192.0.2.2/32"));
+        assert_eq!(modification.remove, vec!["192.0.2.1/32".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn modify_entries_surfaces_failures() {
+        let api = FakeApi::default().with_prefix_list(ID, None, &[]);
+        let aws = client(&api);
+        let pl = aws.get_prefix_list(&id()).await.unwrap();
+        api.fail_next_modification("UnauthorizedOperation");
+        let err = aws
+            .modify_entries(&pl, vec![&host("192.0.2.1")], vec![])
+            .await
+            .unwrap_err();
+        assert!(
+            format!("{:?}", err).contains("UnauthorizedOperation"),
+            "{:?}",
+            err
+        );
+        assert!(api.entries(ID).is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn every_operation_names_its_prefix_list_in_errors() {
+        // Full, so making room has to look at the entries
+        let api = FakeApi::default().with_prefix_list(ID, Some(1), &[("192.0.2.9/32", "office")]);
+        let aws = client(&api);
+        let pl = aws.get_prefix_list(&id()).await.unwrap();
+        api.unreachable(true);
+        let cidr = net("192.0.2.1/32");
+        let new = host("192.0.2.1");
+
+        // The context is the one of the call which failed, as it's attached once
+        let errors = [
+            ("get_prefix_list", aws.get_prefix_list(&id()).await.err()),
+            (
+                "list_entries",
+                aws.entries(&pl, EntryFilter::All).await.err(),
+            ),
+            ("list_entries", aws.has_owned_entry(&pl, &cidr).await.err()),
+            ("list_entries", aws.foreign_entry(&pl, &cidr).await.err()),
+            ("list_entries", aws.make_room(&pl, &[&new], &[]).await.err()),
+            (
+                "modify_entries",
+                aws.modify_entries(&pl, vec![&new], vec![]).await.err(),
+            ),
+            (
+                "recreate_prefix_list",
+                aws.recreate_prefix_list(&pl).await.err(),
+            ),
+            (
+                "get_prefix_list",
+                aws.collapse_owned(&id(), Some(&cidr)).await.err(),
+            ),
+            (
+                "get_prefix_list",
+                aws.cleanup(&id(), None, &Cell::new("")).await.err(),
+            ),
+            ("get_prefix_list", aws.wait_until_settled(&id()).await.err()),
+            (
+                "get_prefix_list",
+                aws.wait_for_state(&id(), PrefixListState::ModifyComplete, None)
+                    .await
+                    .err(),
+            ),
+        ];
+        for (operation, err) in errors {
+            let err = err.unwrap_or_else(|| panic!("{} didn't fail", operation));
+            let formatted = format!("{:#}", err);
+            assert!(
+                formatted.starts_with(&format!("{} on {}", operation, ID)),
+                "{}",
+                formatted
+            );
+            let context = err.downcast_ref::<OperationContext>().unwrap();
+            assert_eq!(context.prefix_list_id, ID);
+        }
+        // Along with the CIDRs involved
+        let err = aws
+            .modify_entries(&pl, vec![&new], vec![net("192.0.2.9/32")])
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<OperationContext>().unwrap().cidrs,
+            [cidr, net("192.0.2.9/32")]
+        );
+    }
+
+    #[tokio::test]
+    async fn cleanup_removes_owned_entries_only() {
+        let api = FakeApi::default().with_prefix_list(
+            ID,
+            None,
+            &[
+                ("192.0.2.1/32", "office"),
+                ("192.0.2.2/32", "office @ laptop"),
+                ("192.0.2.3/32", "officer"),
+                ("192.0.2.4/32", ""),
+            ],
+        );
+        let cleaned = client(&api)
+            .cleanup(&id(), None, &Cell::new(""))
+            .await
+            .unwrap();
+        assert_eq!(
+            cleaned.removed,
+            vec![net("192.0.2.1/32"), net("192.0.2.2/32")]
+        );
+        assert!(cleaned.conflicts.is_empty());
+        assert_eq!(
+            api.entries(ID),
+            vec![
+                ("192.0.2.3/32".to_string(), "officer".to_string()),
+                ("192.0.2.4/32".to_string(), String::new()),
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cleanup_for_a_host_leaves_the_other_hosts_alone() {
+        let api = FakeApi::default().with_prefix_list(
+            ID,
+            None,
+            &[
+                ("192.0.2.1/32", "office @ laptop"),
+                ("192.0.2.2/32", "office @ desktop"),
+                ("192.0.2.3/32", "office"),
+            ],
+        );
+        let laptop = AWSClient::builder()
+            .api(api.clone())
+            .description(
+                "office"
+                    .parse::<Description>()
+                    .unwrap()
+                    .for_host("laptop")
+                    .unwrap(),
+            )
+            .build()
+            .unwrap();
+        let cleaned = laptop.cleanup(&id(), None, &Cell::new("")).await.unwrap();
+        assert_eq!(cleaned.removed, [net("192.0.2.1/32")]);
+        assert_eq!(api.entries(ID).len(), 2);
+
+        // The shared description sweeps every host's entries
+        api.bump(ID);
+        let cleaned = client(&api)
+            .cleanup(&id(), None, &Cell::new(""))
+            .await
+            .unwrap();
+        assert_eq!(cleaned.removed, [net("192.0.2.2/32"), net("192.0.2.3/32")]);
+        assert!(api.entries(ID).is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn entries_carry_their_expiry_with_a_ttl() {
+        let api = FakeApi::default().with_prefix_list(ID, None, &[]);
+        let aws = AWSClient::builder()
+            .api(api.clone())
+            .description("office".parse().unwrap())
+            .entry_ttl(chrono::Duration::hours(1))
+            .build()
+            .unwrap();
+        let pl = aws.get_prefix_list(&id()).await.unwrap();
+        let written = Utc::now();
+        aws.modify_entries(&pl, vec![&host("192.0.2.1")], vec![])
+            .await
+            .unwrap();
+
+        let (cidr, description) = api.entries(ID).pop().unwrap();
+        assert_eq!(cidr, "192.0.2.1/32");
+        let entry = Entry {
+            cidr: net(&cidr),
+            description,
+        };
+        let office: Description = "office".parse().unwrap();
+        assert!(office.matches_owned_entry(&entry));
+        let expires = office.embedded_timestamp(&entry).unwrap();
+        assert!(
+            (expires - written - chrono::Duration::hours(1))
+                .num_seconds()
+                .abs()
+                <= 1
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cleanup_skips_malformed_entries() {
+        let api = FakeApi::default().with_prefix_list(
+            ID,
+            None,
+            &[
+                ("", "office"),
+                ("192.0.2.1/32", "office"),
+                ("not a cidr", "office"),
+                ("192.0.2.2/32", "someone"),
+                ("", ""),
+            ],
+        );
+        let cleaned = client(&api)
+            .cleanup(&id(), None, &Cell::new(""))
+            .await
+            .unwrap();
+        // The well formed owned one is still removed
+        assert_eq!(cleaned.removed, vec![net("192.0.2.1/32")]);
+        assert_eq!(cleaned.version, Some(2));
+        assert_eq!(cleaned.skipped.len(), 3);
+        assert!(
+            cleaned.skipped[0].starts_with("office ("),
+            "{:?}",
+            cleaned.skipped
+        );
+        assert!(
+            cleaned.skipped[1].starts_with("office ("),
+            "{:?}",
+            cleaned.skipped
+        );
+        assert!(
+            cleaned.skipped[2].starts_with("no description ("),
+            "{:?}",
+            cleaned.skipped
+        );
+        assert_eq!(
+            api.entries(ID)
+                .into_iter()
+                .map(|(cidr, _)| cidr)
+                .collect::<Vec<_>>(),
+            ["", "not a cidr", "192.0.2.2/32", ""]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cleanup_with_only_malformed_entries_changes_nothing() {
+        let api = FakeApi::default().with_prefix_list(ID, None, &[("", "office")]);
+        let cleaned = client(&api)
+            .cleanup(&id(), None, &Cell::new(""))
+            .await
+            .unwrap();
+        assert!(cleaned.removed.is_empty());
+        assert_eq!(cleaned.skipped.len(), 1);
+        assert_eq!(cleaned.version, None);
+        assert!(api.modifications().is_empty());
+    }
+
+    #[tokio::test]
+    async fn cleanup_leaves_others_writes_as_conflicts() {
+        let api = FakeApi::default().with_prefix_list(
+            ID,
+            None,
+            &[("192.0.2.1/32", "office"), ("192.0.2.2/32", "office")],
+        );
+        let cleaned = client(&api)
+            .cleanup(&id(), Some(&[net("192.0.2.1/32")]), &Cell::new(""))
+            .await
+            .unwrap();
+        assert_eq!(cleaned.removed, vec![net("192.0.2.1/32")]);
+        assert_eq!(cleaned.conflicts, vec![net("192.0.2.2/32")]);
+        // The version the removal leads to, not the one AWS answers with while it's in progress
+        assert_eq!(cleaned.version, Some(2));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cleanup_waits_for_a_modification_in_progress() {
+        let api = FakeApi::default()
+            .with_prefix_list(ID, None, &[("192.0.2.1/32", "office")])
+            .settle_after(3);
+        let aws = client(&api);
+        let pl = aws.get_prefix_list(&id()).await.unwrap();
+        aws.modify_entries(&pl, vec![&host("192.0.2.2")], vec![])
+            .await
+            .unwrap();
+        let cleaned = aws.cleanup(&id(), None, &Cell::new("")).await.unwrap();
+        assert_eq!(cleaned.removed.len(), 2);
+        assert_eq!(api.modifications()[1].current_version, Some(2));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn collapse_owned_keeps_the_current_ip_of_two() {
+        let api = FakeApi::default().with_prefix_list(
+            ID,
+            None,
+            &[
+                ("192.0.2.1/32", "office @ 2022-09-02T10:00:00Z"),
+                ("192.0.2.2/32", "office @ 2022-09-01T10:00:00Z"),
+                ("192.0.2.3/32", "officer"),
+            ],
+        );
+        let collapsed = client(&api)
+            .collapse_owned(&id(), Some(&net("192.0.2.2/32")))
+            .await
+            .unwrap();
+        // The older one, since it's for the current IP
+        assert_eq!(collapsed.kept, Some(net("192.0.2.2/32")));
+        assert_eq!(collapsed.removed, vec![net("192.0.2.1/32")]);
+        assert_eq!(collapsed.version, Some(2));
+        assert_eq!(
+            api.entries(ID),
+            vec![
+                (
+                    "192.0.2.2/32".to_string(),
+                    "office @ 2022-09-01T10:00:00Z".to_string()
+                ),
+                ("192.0.2.3/32".to_string(), "officer".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn collapse_owned_keeps_the_newest_of_five_in_one_modification() {
+        let api = FakeApi::default().with_prefix_list(
+            ID,
+            None,
+            &[
+                ("192.0.2.1/32", "office @ 2022-09-01T10:00:00Z"),
+                ("192.0.2.2/32", "office"),
+                ("192.0.2.3/32", "office @ 2022-09-03T10:00:00Z"),
+                ("192.0.2.4/32", "office @ 2022-09-02T10:00:00Z"),
+                ("192.0.2.5/32", "office @ laptop"),
+                ("192.0.2.6/32", "someone else"),
+            ],
+        );
+        // None of them is for the current IP
+        let collapsed = client(&api)
+            .collapse_owned(&id(), Some(&net("198.51.100.7/32")))
+            .await
+            .unwrap();
+        assert_eq!(collapsed.kept, Some(net("192.0.2.3/32")));
+        assert_eq!(
+            collapsed.removed,
+            vec![
+                net("192.0.2.1/32"),
+                net("192.0.2.2/32"),
+                net("192.0.2.4/32"),
+                net("192.0.2.5/32"),
+            ]
+        );
+        assert_eq!(api.modifications().len(), 1);
+        assert!(api.modifications()[0].add.is_empty());
+        assert_eq!(
+            api.entries(ID)
+                .into_iter()
+                .map(|(cidr, _)| cidr)
+                .collect::<Vec<_>>(),
+            ["192.0.2.3/32", "192.0.2.6/32"]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn collapse_owned_leaves_a_single_entry_alone() {
+        let api = FakeApi::default().with_prefix_list(ID, None, &[("192.0.2.1/32", "office")]);
+        let collapsed = client(&api)
+            .collapse_owned(&id(), Some(&net("198.51.100.7/32")))
+            .await
+            .unwrap();
+        assert_eq!(collapsed.kept, Some(net("192.0.2.1/32")));
+        assert!(collapsed.removed.is_empty());
+        assert_eq!(collapsed.version, None);
+        assert!(api.modifications().is_empty());
+    }
+
+    /// Entries of every kind, over three pages of two.
+    fn mixed() -> FakeApi {
+        FakeApi::default().page_size(2).with_prefix_list(
+            ID,
+            None,
+            &[
+                ("192.0.2.1/32", "office"),
+                ("192.0.2.2/32", "someone"),
+                ("192.0.2.3/32", "office @ laptop"),
+                ("192.0.2.4/32", "officer"),
+                ("198.51.100.0/24", ""),
+                ("192.0.2.5/32", "office @ 2022-09-01T10:00:00Z"),
+            ],
+        )
+    }
+
+    fn cidrs(entries: &[Entry]) -> Vec<String> {
+        entries.iter().map(|entry| entry.cidr.to_string()).collect()
+    }
+
+    #[tokio::test]
+    async fn entries_are_filtered_across_the_pages() {
+        let api = mixed();
+        let aws = client(&api);
+        let pl = aws.get_prefix_list(&id()).await.unwrap();
+
+        let all = aws.entries(&pl, EntryFilter::All).await.unwrap();
+        assert_eq!(api.entry_pages(), 3);
+        assert_eq!(
+            cidrs(&all),
+            [
+                "192.0.2.1/32",
+                "192.0.2.2/32",
+                "192.0.2.3/32",
+                "192.0.2.4/32",
+                "198.51.100.0/24",
+                "192.0.2.5/32"
+            ]
+        );
+        assert_eq!(all[4].description, "");
+
+        let owned = aws.entries(&pl, EntryFilter::Owned).await.unwrap();
+        assert_eq!(
+            cidrs(&owned),
+            ["192.0.2.1/32", "192.0.2.3/32", "192.0.2.5/32"]
+        );
+        let for_cidr = aws
+            .entries(&pl, EntryFilter::Cidr(net("192.0.2.2/32")))
+            .await
+            .unwrap();
+        assert_eq!(
+            for_cidr,
+            [Entry {
+                cidr: net("192.0.2.2/32"),
+                description: "someone".to_string()
+            }]
+        );
+        assert!(aws
+            .entries(&pl, EntryFilter::Cidr(net("192.0.2.9/32")))
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn entries_only_fail_on_a_malformed_one_they_would_keep() {
+        let office = Entry {
+            cidr: net("192.0.2.1/32"),
+            description: "office".to_string(),
+        };
+        let api = FakeApi::default().with_prefix_list(
+            ID,
+            None,
+            &[
+                ("192.0.2.1/32", "office"),
+                ("not a cidr", "someone"),
+                ("", ""),
+            ],
+        );
+        let aws = client(&api);
+        let pl = aws.get_prefix_list(&id()).await.unwrap();
+        assert_eq!(
+            aws.entries(&pl, EntryFilter::Owned).await.unwrap(),
+            vec![office.clone()]
+        );
+        assert_eq!(
+            aws.entries(&pl, EntryFilter::Cidr(office.cidr))
+                .await
+                .unwrap(),
+            vec![office.clone()]
+        );
+        assert!(aws.entries(&pl, EntryFilter::All).await.is_err());
+
+        // One of doorman's own can't be told apart from the others, so it still fails
+        let api = FakeApi::default().with_prefix_list(
+            ID,
+            None,
+            &[("192.0.2.1/32", "office"), ("not a cidr", "office")],
+        );
+        let aws = client(&api);
+        let pl = aws.get_prefix_list(&id()).await.unwrap();
+        assert!(aws.entries(&pl, EntryFilter::Owned).await.is_err());
+        assert_eq!(
+            aws.entries(&pl, EntryFilter::Cidr(office.cidr))
+                .await
+                .unwrap(),
+            [office]
+        );
+    }
+
+    #[tokio::test]
+    async fn owned_and_foreign_entries_for_a_cidr() {
+        let api = mixed();
+        let aws = client(&api);
+        let pl = aws.get_prefix_list(&id()).await.unwrap();
+
+        assert!(aws
+            .has_owned_entry(&pl, &net("192.0.2.3/32"))
+            .await
+            .unwrap());
+        assert!(!aws
+            .has_owned_entry(&pl, &net("192.0.2.4/32"))
+            .await
+            .unwrap());
+        assert!(!aws
+            .has_owned_entry(&pl, &net("192.0.2.9/32"))
+            .await
+            .unwrap());
+
+        assert_eq!(
+            aws.foreign_entry(&pl, &net("192.0.2.4/32"))
+                .await
+                .unwrap()
+                .map(|entry| entry.description),
+            Some("officer".to_string())
+        );
+        assert_eq!(
+            aws.foreign_entry(&pl, &net("192.0.2.1/32")).await.unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_malformed_foreign_entry_doesnt_break_the_conflict_check() {
+        let api = FakeApi::default().with_prefix_list(
+            ID,
+            None,
+            &[
+                ("192.0.2.1/32", "office"),
+                ("not a cidr", "someone"),
+                ("192.0.2.2/3x", ""),
+                ("192.0.2.3/32", "someone"),
+            ],
+        );
+        let aws = client(&api);
+        let pl = aws.get_prefix_list(&id()).await.unwrap();
+
+        assert!(aws
+            .has_owned_entry(&pl, &net("192.0.2.1/32"))
+            .await
+            .unwrap());
+        assert_eq!(
+            aws.foreign_entry(&pl, &net("192.0.2.1/32")).await.unwrap(),
+            None
+        );
+        assert_eq!(
+            aws.foreign_entry(&pl, &net("192.0.2.3/32"))
+                .await
+                .unwrap()
+                .map(|entry| entry.description),
+            Some("someone".to_string())
+        );
+        // Nothing to collapse, and the malformed entries are left alone
+        let collapsed = aws
+            .collapse_owned(&id(), Some(&net("192.0.2.1/32")))
+            .await
+            .unwrap();
+        assert!(collapsed.removed.is_empty());
+        assert!(api.modifications().is_empty());
+    }
+
+    #[tokio::test]
+    async fn missing_and_unreachable_prefix_lists() {
+        let api = FakeApi::default().with_prefix_list(ID, None, &[]);
+        let aws = client(&api);
+        api.unreachable(true);
+        let err = aws.get_prefix_list(&id()).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<AWSError>(),
+            Some(AWSError::Dispatch { .. })
+        ));
+
+        api.unreachable(false);
+        api.delete(ID);
+        let err = aws.get_prefix_list(&id()).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<AWSError>(),
+            Some(AWSError::NotFound { .. })
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn wait_for_state_follows_the_script() {
+        let api = FakeApi::default().with_prefix_list(ID, None, &[]);
+        api.script(
+            ID,
+            &[
+                (PrefixListState::ModifyInProgress, 1),
+                (PrefixListState::ModifyComplete, 2),
+            ],
+        );
+        let settled = client(&api)
+            .wait_for_state(&id(), PrefixListState::ModifyComplete, None)
+            .await
+            .unwrap();
+        assert_eq!(settled.version, Some(2));
+        assert_eq!(api.describes(), 2);
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let poll = PollBackoff::default();
+        let mut delay = poll.initial;
+        let mut delays = vec![];
+        for _ in 0..6 {
+            delays.push(delay.as_secs());
+            delay = poll.next(delay);
+        }
+        assert_eq!(delays, vec![1, 2, 4, 8, 15, 15]);
+        for _ in 0..100 {
+            let jittered = PollBackoff::jittered(poll.max);
+            assert!(jittered >= poll.max.mul_f64(0.75) && jittered <= poll.max);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn wait_for_state_skips_a_stale_complete() {
+        let api = FakeApi::default().with_prefix_list(ID, None, &[]);
+        let aws = client(&api);
+        let pl = aws.get_prefix_list(&id()).await.unwrap();
+        let modified = aws
+            .modify_entries(&pl, vec![&host("192.0.2.1")], vec![])
+            .await
+            .unwrap();
+        // AWS may not show the modification at all at first
+        api.script(ID, &[(PrefixListState::ModifyComplete, 1)]);
+        let settled = aws
+            .wait_for_state(&id(), PrefixListState::ModifyComplete, modified.version)
+            .await
+            .unwrap();
+        assert_eq!(settled.version, Some(2));
+        // The stale one, the one in progress, and the complete one
+        assert_eq!(api.describes(), 1 + 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn wait_for_state_looks_right_away_then_backs_off() {
+        let api = FakeApi::default()
+            .with_prefix_list(ID, None, &[])
+            .settle_after(3);
+        let aws = client(&api);
+        let pl = aws.get_prefix_list(&id()).await.unwrap();
+        let modified = aws
+            .modify_entries(&pl, vec![&host("192.0.2.1")], vec![])
+            .await
+            .unwrap();
+        let started = Instant::now();
+        let id = id();
+        let mut waiting =
+            Box::pin(aws.wait_for_state(&id, PrefixListState::ModifyComplete, modified.version));
+        // The first look comes before any pause
+        assert!(timeout(Duration::from_millis(1), &mut waiting)
+            .await
+            .is_err());
+        assert_eq!(api.describes(), 1 + 1);
+
+        waiting.await.unwrap();
+        // Then 1s, 2s and 4s between the 4 polls, each shortened by up to a quarter
+        let waited = started.elapsed();
+        assert!(
+            waited >= Duration::from_millis(5250) && waited <= Duration::from_secs(7),
+            "{:?}",
+            waited
+        );
+        assert_eq!(api.describes(), 1 + 4);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn wait_until_settled_uses_the_same_backoff() {
+        let api = FakeApi::default()
+            .with_prefix_list(ID, None, &[])
+            .settle_after(2);
+        let aws = client(&api);
+        let pl = aws.get_prefix_list(&id()).await.unwrap();
+        aws.modify_entries(&pl, vec![&host("192.0.2.1")], vec![])
+            .await
+            .unwrap();
+        let started = Instant::now();
+        let settled = aws.wait_until_settled(&id()).await.unwrap();
+        assert_eq!(settled.version, Some(2));
+        // Right away, then after 1s and 2s
+        let waited = started.elapsed();
+        assert!(
+            waited >= Duration::from_millis(2250) && waited <= Duration::from_secs(3),
+            "{:?}",
+            waited
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn poll_backoff_is_injectable() {
+        let api = FakeApi::default()
+            .with_prefix_list(ID, None, &[])
+            .settle_after(3);
+        let aws = AWSClient::builder()
+            .api(api.clone())
+            .description("office".parse().unwrap())
+            .poll_backoff(PollBackoff {
+                initial: Duration::from_millis(100),
+                max: Duration::from_millis(200),
+            })
+            .build()
+            .unwrap();
+        let pl = aws.get_prefix_list(&id()).await.unwrap();
+        let modified = aws
+            .modify_entries(&pl, vec![&host("192.0.2.1")], vec![])
+            .await
+            .unwrap();
+        let started = Instant::now();
+        aws.wait_for_state(&id(), PrefixListState::ModifyComplete, modified.version)
+            .await
+            .unwrap();
+        // Right away, then after 100ms, 200ms and 200ms
+        let waited = started.elapsed();
+        assert!(
+            waited >= Duration::from_millis(375) && waited <= Duration::from_millis(500),
+            "{:?}",
+            waited
+        );
+    }
+
+    #[test]
+    fn builder_rejects_bad_settings() {
+        let builder = || {
+            AWSClient::builder()
+                .api(FakeApi::default())
+                .description("office".parse().unwrap())
+        };
+        assert!(builder().build().is_ok());
+        assert!(builder().wait_timeout(0).build().is_err());
+        assert!(builder()
+            .poll_backoff(PollBackoff {
+                initial: Duration::ZERO,
+                max: Duration::from_secs(1),
+            })
+            .build()
+            .is_err());
+        assert!(builder()
+            .poll_backoff(PollBackoff {
+                initial: Duration::from_secs(2),
+                max: Duration::from_secs(1),
+            })
+            .build()
+            .is_err());
+        assert!(AWSClient::builder()
+            .api(FakeApi::default())
+            .build()
+            .is_err());
+    }
+
+    /// A client waiting 10s, against a prefix list whose describes return `states` at version 1 first.
+    fn scripted(states: &[PrefixListState]) -> (FakeApi, AWSClient) {
+        let api = FakeApi::default().with_prefix_list(ID, None, &[]);
+        let script: Vec<(PrefixListState, i64)> =
+            states.iter().map(|state| (state.clone(), 1)).collect();
+        api.script(ID, &script);
+        let aws = AWSClient::builder()
+            .api(api.clone())
+            .description("office".parse().unwrap())
+            .wait_timeout(10)
+            .build()
+            .unwrap();
+        (api, aws)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn wait_for_state_immediate_success() {
+        let (api, aws) = scripted(&[PrefixListState::ModifyComplete]);
+        let settled = aws
+            .wait_for_state(&id(), PrefixListState::ModifyComplete, Some(1))
+            .await
+            .unwrap();
+        assert_eq!(settled.state, Some(PrefixListState::ModifyComplete));
+        assert_eq!(api.describes(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn wait_for_state_success_on_the_third_poll() {
+        let (api, aws) = scripted(&[
+            PrefixListState::ModifyInProgress,
+            PrefixListState::ModifyInProgress,
+            PrefixListState::ModifyComplete,
+        ]);
+        let started = Instant::now();
+        aws.wait_for_state(&id(), PrefixListState::ModifyComplete, Some(1))
+            .await
+            .unwrap();
+        assert_eq!(api.describes(), 3);
+        // Right away, then after 1s and 2s
+        let waited = started.elapsed();
+        assert!(
+            waited >= Duration::from_millis(2250) && waited <= Duration::from_secs(3),
+            "{:?}",
+            waited
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn wait_for_state_fails_when_the_modification_fails() {
+        let (api, aws) = scripted(&[
+            PrefixListState::ModifyInProgress,
+            PrefixListState::ModifyFailed,
+            PrefixListState::ModifyFailed,
+        ]);
+        let err = aws
+            .wait_for_state(&id(), PrefixListState::ModifyComplete, Some(1))
+            .await
+            .unwrap_err();
+        let message = format!("{:#}", err);
+        assert!(
+            message.contains("The prefix list is modify-failed instead of modify-complete"),
+            "{}",
+            message
+        );
+        // Given up on the first failed state, without waiting for the timeout
+        assert_eq!(api.describes(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn wait_for_state_ignores_a_failed_state_from_before() {
+        let api = FakeApi::default().with_prefix_list(ID, None, &[]);
+        api.script(
+            ID,
+            &[
+                (PrefixListState::ModifyFailed, 1),
+                (PrefixListState::ModifyComplete, 2),
+            ],
+        );
+        let aws = AWSClient::builder()
+            .api(api.clone())
+            .description("office".parse().unwrap())
+            .wait_timeout(10)
+            .build()
+            .unwrap();
+        let settled = aws
+            .wait_for_state(&id(), PrefixListState::ModifyComplete, Some(2))
+            .await
+            .unwrap();
+        assert_eq!(settled.version, Some(2));
+        assert_eq!(api.describes(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn wait_for_state_times_out() {
+        let (_, aws) = scripted(&vec![PrefixListState::ModifyInProgress; 20]);
+        let err = aws
+            .wait_for_state(&id(), PrefixListState::ModifyComplete, Some(1))
+            .await
+            .unwrap_err();
+        let message = format!("{:#}", err);
+        assert!(
+            message.contains("wait_for_state on pl-12345678"),
+            "{}",
+            message
+        );
+        assert!(
+            message.contains("The prefix list still wasn't modify-complete after 10 seconds."),
+            "{}",
+            message
+        );
+    }
+
+    /// A client counting its entry cache hits and misses, for a prefix list of 250 entries in 3 pages.
+    fn cached() -> (FakeApi, AWSClient, Stats) {
+        let entries: Vec<(String, &str)> = (0..250)
+            .map(|i| (format!("10.0.{}.{}/32", i / 256, i % 256), "other"))
+            .collect();
+        let entries: Vec<(&str, &str)> = entries
+            .iter()
+            .map(|(cidr, description)| (cidr.as_str(), *description))
+            .collect();
+        let api = FakeApi::default().with_prefix_list(ID, Some(1000), &entries);
+        let stats = Stats::new(&[]);
+        let aws = AWSClient::builder()
+            .api(api.clone())
+            .description("office".parse().unwrap())
+            .stats(stats.clone())
+            .build()
+            .unwrap();
+        (api, aws, stats)
+    }
+
+    fn cache_counts(stats: &Stats) -> (u64, u64) {
+        let snapshot = stats.snapshot();
+        (snapshot.entry_cache_hits, snapshot.entry_cache_misses)
+    }
+
+    #[tokio::test]
+    async fn entries_are_reused_at_the_same_version() {
+        let (api, aws, stats) = cached();
+        let pl = aws.get_prefix_list(&id()).await.unwrap();
+        assert_eq!(aws.entries(&pl, EntryFilter::All).await.unwrap().len(), 250);
+        assert_eq!(api.entry_pages(), 3);
+        assert!(!aws.has_owned_entry(&pl, &net("10.0.0.1/32")).await.unwrap());
+        assert!(aws
+            .foreign_entry(&pl, &net("10.0.0.1/32"))
+            .await
+            .unwrap()
+            .is_some());
+        assert_eq!(api.entry_pages(), 3);
+        // Only the caller described the prefix list
+        assert_eq!(api.describes(), 1);
+        assert_eq!(cache_counts(&stats), (2, 1));
+    }
+
+    #[tokio::test]
+    async fn entries_are_listed_again_after_a_version_bump() {
+        let (api, aws, stats) = cached();
+        let pl = aws.get_prefix_list(&id()).await.unwrap();
+        aws.entries(&pl, EntryFilter::All).await.unwrap();
+        api.bump(ID);
+        let pl = aws.get_prefix_list(&id()).await.unwrap();
+        aws.entries(&pl, EntryFilter::All).await.unwrap();
+        assert_eq!(api.entry_pages(), 6);
+        assert_eq!(cache_counts(&stats), (0, 2));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn own_modifications_invalidate_the_cache() {
+        let (api, aws, stats) = cached();
+        let pl = aws.get_prefix_list(&id()).await.unwrap();
+        aws.entries(&pl, EntryFilter::All).await.unwrap();
+        // Whether or not it goes through
+        api.fail_next_modification("IncorrectState");
+        assert!(aws
+            .modify_entries(&pl, vec![&host("192.0.2.1")], vec![])
+            .await
+            .is_err());
+        aws.entries(&pl, EntryFilter::All).await.unwrap();
+        assert_eq!(cache_counts(&stats), (0, 2));
+
+        let modified = aws
+            .modify_entries(&pl, vec![&host("192.0.2.1")], vec![])
+            .await
+            .unwrap();
+        let pl = aws
+            .wait_for_state(&id(), PrefixListState::ModifyComplete, modified.version)
+            .await
+            .unwrap();
+        let owned = aws.entries(&pl, EntryFilter::Owned).await.unwrap();
+        assert_eq!(owned.len(), 1);
+        assert_eq!(cache_counts(&stats), (0, 3));
+    }
+
+    #[tokio::test]
+    async fn entries_in_progress_are_not_cached() {
+        let (api, aws, stats) = cached();
+        api.script(
+            ID,
+            &[
+                (PrefixListState::ModifyInProgress, 1),
+                (PrefixListState::ModifyInProgress, 1),
+            ],
+        );
+        for _ in 0..2 {
+            let pl = aws.get_prefix_list(&id()).await.unwrap();
+            aws.entries(&pl, EntryFilter::All).await.unwrap();
+        }
+        let pl = aws.get_prefix_list(&id()).await.unwrap();
+        aws.entries(&pl, EntryFilter::All).await.unwrap();
+        aws.entries(&pl, EntryFilter::All).await.unwrap();
+        assert_eq!(cache_counts(&stats), (1, 3));
+    }
+
+    fn sdk_entry(cidr: Option<&str>, description: Option<&str>) -> PrefixListEntry {
+        PrefixListEntry::builder()
+            .set_cidr(cidr.map(String::from))
+            .set_description(description.map(String::from))
+            .build()
+    }
+
+    #[test]
+    fn entry_with_a_description() {
+        let entry = Entry::try_from(&sdk_entry(Some("192.0.2.1/32"), Some("office"))).unwrap();
+        assert_eq!(
+            entry,
+            Entry {
+                cidr: net("192.0.2.1/32"),
+                description: "office".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn entry_without_a_description() {
+        let entry = Entry::try_from(&sdk_entry(Some("192.0.2.1/32"), None)).unwrap();
+        assert_eq!(entry.cidr, net("192.0.2.1/32"));
+        assert_eq!(entry.description, "");
+    }
+
+    #[test]
+    fn entry_without_a_cidr() {
+        for cidr in [None, Some("")] {
+            let err = Entry::try_from(&sdk_entry(cidr, Some("office"))).unwrap_err();
+            assert_eq!(err.to_string(), "entry without a CIDR");
+        }
+    }
+
+    #[test]
+    fn entry_with_an_invalid_cidr() {
+        let err = Entry::try_from(&sdk_entry(Some("192.0.2.300/32"), None)).unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("entry with an invalid CIDR \"192.0.2.300/32\": "),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn entry_with_an_ipv6_cidr() {
+        let entry = Entry::try_from(&sdk_entry(Some("2001:db8::/64"), Some("office"))).unwrap();
+        assert_eq!(entry.cidr, net("2001:db8::/64"));
+        assert!(matches!(entry.cidr, IpNet::V6(_)));
     }
 }
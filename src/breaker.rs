@@ -0,0 +1,158 @@
+use crate::exit::Exit;
+
+use std::sync::{Arc, Mutex};
+use tokio::time::{Duration, Instant};
+
+/// Stops hammering AWS when modifying the prefix lists keeps failing the same way, e.g. because of a broken IAM
+/// policy.
+///
+/// After `threshold` checks in a row failing with the same kind of error, the circuit opens: the prefix lists are
+/// only tried again once per probe interval, until a probe succeeds or the circuit is reset on the control socket.
+#[derive(Clone)]
+pub struct Breaker(Arc<Mutex<Inner>>);
+
+struct Inner {
+    /// 0 never opens the circuit
+    threshold: u32,
+    probe_every: Duration,
+    /// The kind of the failures in a row, as told by their exit status
+    class: Option<Exit>,
+    failures: u32,
+    /// When the next probe may run, set while the circuit is open
+    probe_at: Option<Instant>,
+}
+
+impl Breaker {
+    pub fn new(threshold: u32, probe_every: Duration) -> Self {
+        Self(Arc::new(Mutex::new(Inner {
+            threshold,
+            probe_every,
+            class: None,
+            failures: 0,
+            probe_at: None,
+        })))
+    }
+
+    /// Whether the prefix lists may be modified now, which while open only holds once the probe is due.
+    pub fn allows(&self) -> bool {
+        self.0
+            .lock()
+            .unwrap()
+            .probe_at
+            .is_none_or(|at| Instant::now() >= at)
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.0.lock().unwrap().probe_at.is_some()
+    }
+
+    /// How many checks in a row failed with the same kind of error.
+    pub fn failures(&self) -> u32 {
+        self.0.lock().unwrap().failures
+    }
+
+    pub fn probe_every(&self) -> Duration {
+        self.0.lock().unwrap().probe_every
+    }
+
+    /// Records a check which failed to modify a prefix list with `class`, telling whether this opened the circuit.
+    ///
+    /// A failed probe keeps the circuit open until the next one.
+    pub fn failed(&self, class: Exit) -> bool {
+        let mut inner = self.0.lock().unwrap();
+        if inner.threshold == 0 {
+            return false;
+        }
+        match inner.class == Some(class) {
+            true => inner.failures += 1,
+            false => {
+                inner.class = Some(class);
+                inner.failures = 1;
+            }
+        }
+        let was_open = inner.probe_at.is_some();
+        if was_open || inner.failures >= inner.threshold {
+            inner.probe_at = Some(Instant::now() + inner.probe_every);
+        }
+        !was_open && inner.probe_at.is_some()
+    }
+
+    /// Forgets the failures, closing the circuit, after a check which modified the prefix lists as needed or on
+    /// request. Tells whether it was open.
+    pub fn reset(&self) -> bool {
+        let mut inner = self.0.lock().unwrap();
+        inner.class = None;
+        inner.failures = 0;
+        inner.probe_at.take().is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::advance;
+
+    #[tokio::test(start_paused = true)]
+    async fn opens_after_the_same_failure_in_a_row() {
+        let breaker = Breaker::new(3, Duration::from_secs(900));
+        assert!(!breaker.failed(Exit::AwsUnavailable));
+        assert!(!breaker.failed(Exit::AwsUnavailable));
+        assert!(breaker.allows());
+        assert!(breaker.failed(Exit::AwsUnavailable));
+        assert!(breaker.is_open());
+        assert_eq!(breaker.failures(), 3);
+        assert!(!breaker.allows());
+
+        advance(Duration::from_secs(899)).await;
+        assert!(!breaker.allows());
+        advance(Duration::from_secs(1)).await;
+        // Due for a probe, although still open until it succeeds
+        assert!(breaker.allows());
+        assert!(breaker.is_open());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_failed_probe_waits_for_the_next_one() {
+        let breaker = Breaker::new(1, Duration::from_secs(900));
+        assert!(breaker.failed(Exit::AwsUnavailable));
+        advance(Duration::from_secs(900)).await;
+        // Only opening is told about
+        assert!(!breaker.failed(Exit::AwsUnavailable));
+        assert!(!breaker.allows());
+        advance(Duration::from_secs(900)).await;
+        assert!(breaker.allows());
+    }
+
+    #[test]
+    fn a_different_failure_starts_counting_again() {
+        let breaker = Breaker::new(3, Duration::from_secs(900));
+        breaker.failed(Exit::AwsUnavailable);
+        breaker.failed(Exit::AwsUnavailable);
+        assert!(!breaker.failed(Exit::Failure));
+        assert_eq!(breaker.failures(), 1);
+        assert!(!breaker.is_open());
+    }
+
+    #[test]
+    fn resetting_closes_the_circuit() {
+        let breaker = Breaker::new(2, Duration::from_secs(900));
+        breaker.failed(Exit::AwsUnavailable);
+        // Nothing to close yet, but the failure is forgotten
+        assert!(!breaker.reset());
+        assert!(!breaker.failed(Exit::AwsUnavailable));
+        assert!(breaker.failed(Exit::AwsUnavailable));
+        assert!(breaker.reset());
+        assert!(!breaker.is_open());
+        assert!(breaker.allows());
+        assert_eq!(breaker.failures(), 0);
+    }
+
+    #[test]
+    fn a_threshold_of_zero_never_opens() {
+        let breaker = Breaker::new(0, Duration::from_secs(900));
+        for _ in 0..100 {
+            assert!(!breaker.failed(Exit::AwsUnavailable));
+        }
+        assert!(!breaker.is_open());
+    }
+}
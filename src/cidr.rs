@@ -0,0 +1,145 @@
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// The entry doorman manages for an address: a /32 for IPv4, or a /128 for IPv6.
+///
+/// The prefix length is checked against the address family when building one, so they can't be mixed up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "IpNet", into = "IpNet")]
+pub struct HostCidr(IpNet);
+
+impl HostCidr {
+    /// Fails unless `prefix_len` covers the whole address.
+    pub fn new(addr: IpAddr, prefix_len: u8) -> Result<Self, String> {
+        let host_len = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        if prefix_len != host_len {
+            return Err(format!(
+                "{} is managed as a single address, so its prefix length must be {}, not {}",
+                addr, host_len, prefix_len
+            ));
+        }
+        Ok(Self::from(addr))
+    }
+
+    pub fn addr(&self) -> IpAddr {
+        self.0.addr()
+    }
+
+    pub fn net(&self) -> IpNet {
+        self.0
+    }
+}
+
+impl From<IpAddr> for HostCidr {
+    fn from(addr: IpAddr) -> Self {
+        // This picks the prefix length of the address family
+        Self(IpNet::from(addr))
+    }
+}
+
+impl TryFrom<IpNet> for HostCidr {
+    type Error = String;
+
+    fn try_from(net: IpNet) -> Result<Self, Self::Error> {
+        Self::new(net.addr(), net.prefix_len())
+    }
+}
+
+impl From<HostCidr> for IpNet {
+    fn from(cidr: HostCidr) -> Self {
+        cidr.0
+    }
+}
+
+impl fmt::Display for HostCidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromStr for HostCidr {
+    type Err = String;
+
+    /// Either a CIDR with the host prefix length, or a bare address.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse::<IpNet>() {
+            Ok(net) => net.try_into(),
+            Err(_) => s
+                .parse::<IpAddr>()
+                .map(Self::from)
+                .map_err(|_| format!("{} is neither an IP address nor a CIDR", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn host_cidrs_of_both_families() {
+        let v4 = HostCidr::new("192.0.2.1".parse().unwrap(), 32).unwrap();
+        assert_eq!(v4.to_string(), "192.0.2.1/32");
+        assert_eq!(v4.addr(), "192.0.2.1".parse::<IpAddr>().unwrap());
+
+        let v6 = HostCidr::new("2001:db8::1".parse().unwrap(), 128).unwrap();
+        assert_eq!(v6.to_string(), "2001:db8::1/128");
+        assert_eq!(v6.net(), "2001:db8::1/128".parse::<IpNet>().unwrap());
+    }
+
+    #[test]
+    fn addresses_get_the_host_prefix_length() {
+        let v4 = HostCidr::from("192.0.2.1".parse::<IpAddr>().unwrap());
+        assert_eq!(v4.net().prefix_len(), 32);
+        let v6 = HostCidr::from("2001:db8::1".parse::<IpAddr>().unwrap());
+        assert_eq!(v6.net().prefix_len(), 128);
+    }
+
+    #[test]
+    fn non_host_prefixes_are_rejected() {
+        assert_eq!(
+            HostCidr::new("192.0.2.1".parse().unwrap(), 128).unwrap_err(),
+            "192.0.2.1 is managed as a single address, so its prefix length must be 32, not 128"
+        );
+        assert_eq!(
+            HostCidr::new("2001:db8::1".parse().unwrap(), 32).unwrap_err(),
+            "2001:db8::1 is managed as a single address, so its prefix length must be 128, not 32"
+        );
+        assert!(HostCidr::try_from("192.0.2.0/24".parse::<IpNet>().unwrap()).is_err());
+        assert!("2001:db8::/64".parse::<HostCidr>().is_err());
+    }
+
+    #[test]
+    fn display_and_from_str_round_trip() {
+        for cidr in ["192.0.2.1/32", "2001:db8::1/128"] {
+            assert_eq!(cidr.parse::<HostCidr>().unwrap().to_string(), cidr);
+        }
+        assert_eq!(
+            "192.0.2.1".parse::<HostCidr>().unwrap().to_string(),
+            "192.0.2.1/32"
+        );
+        assert_eq!(
+            "office".parse::<HostCidr>().unwrap_err(),
+            "office is neither an IP address nor a CIDR"
+        );
+    }
+
+    #[test]
+    fn serde_checks_the_prefix_length() {
+        let cidr: HostCidr = serde_json::from_str("\"192.0.2.1/32\"").unwrap();
+        assert_eq!(serde_json::to_string(&cidr).unwrap(), "\"192.0.2.1/32\"");
+        assert!(serde_json::from_str::<HostCidr>("\"192.0.2.0/24\"").is_err());
+    }
+
+    #[test]
+    fn converting_back_to_a_network() {
+        let cidr = HostCidr::from("192.0.2.1".parse::<IpAddr>().unwrap());
+        assert_eq!(IpNet::from(cidr), "192.0.2.1/32".parse::<IpNet>().unwrap());
+    }
+}
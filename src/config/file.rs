@@ -0,0 +1,286 @@
+use crate::config::{Description, PrefixListId, Target};
+use crate::notification::{Severity, TemplateSet, BACKEND_NAMES};
+
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+/// Contents of the TOML configuration file.
+///
+/// ```toml
+/// [[target]]
+/// name = "office"
+/// prefix_list_id = "pl-1234567890abcdef0"
+/// description = "doorman"
+/// region = "eu-west-1"
+/// notify = false
+///
+/// [templates.slack]
+/// updated = ":white_check_mark: {target} on {hostname}: {old_ip} -> {new_ip}"
+///
+/// [notify.slack]
+/// min_level = "change"
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    #[serde(default, rename = "target")]
+    targets: Vec<TargetSection>,
+    /// Notification body templates, per backend
+    #[serde(default)]
+    templates: HashMap<String, TemplateSet>,
+    /// Notification settings, per backend
+    #[serde(default)]
+    notify: HashMap<String, NotifySection>,
+}
+
+/// What the configuration file sets, for the selected targets.
+#[derive(Debug)]
+pub struct Loaded {
+    pub targets: Vec<Target>,
+    /// Notification body templates, per backend
+    pub templates: HashMap<String, TemplateSet>,
+    /// Least severe events sent, per backend
+    pub min_levels: HashMap<String, Severity>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NotifySection {
+    /// Least severe events sent to this backend
+    min_level: Option<Severity>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TargetSection {
+    name: String,
+    prefix_list_id: PrefixListId,
+    description: Description,
+    region: Option<String>,
+    /// Whether updates to this target trigger notifications. Failures are always notified, and the backends and their
+    /// levels in `[notify]` are shared by every target.
+    #[serde(default = "default_notify")]
+    notify: bool,
+}
+
+fn default_notify() -> bool {
+    true
+}
+
+impl ConfigFile {
+    fn load(path: &Path) -> Result<Self, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|err| format!("failed to read {}: {}", path.display(), err))?;
+        let config_file: Self = toml::from_str(&content)
+            .map_err(|err| format!("failed to parse {}: {}", path.display(), err))?;
+        config_file.validate()?;
+        Ok(config_file)
+    }
+
+    /// Loads the file, keeping the targets whose name is in `selection`, or all of them if there is no selection.
+    pub fn read(path: &Path, selection: Option<Vec<String>>) -> Result<Loaded, String> {
+        let mut file = Self::load(path)?;
+        Ok(Loaded {
+            templates: file.take_templates(),
+            min_levels: file.min_levels(),
+            targets: file.into_targets(selection)?,
+        })
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.targets.is_empty() {
+            return Err("the configuration file doesn't define any target".to_string());
+        }
+
+        let mut names = HashSet::new();
+        for target in &self.targets {
+            if !names.insert(target.name.as_str()) {
+                return Err(format!(
+                    "target '{}' is defined more than once",
+                    target.name
+                ));
+            }
+        }
+
+        for (section, names) in [
+            ("templates", self.templates.keys().collect::<Vec<_>>()),
+            ("notify", self.notify.keys().collect()),
+        ] {
+            if let Some(name) = names
+                .into_iter()
+                .find(|name| !BACKEND_NAMES.contains(&name.as_str()))
+            {
+                return Err(format!(
+                    "{} given for unknown notification backend '{}', expected one of {}",
+                    section,
+                    name,
+                    BACKEND_NAMES.join(", ")
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Takes the notification templates out, leaving none.
+    fn take_templates(&mut self) -> HashMap<String, TemplateSet> {
+        std::mem::take(&mut self.templates)
+    }
+
+    /// Returns the least severe events sent, for the backends which set it.
+    fn min_levels(&self) -> HashMap<String, Severity> {
+        self.notify
+            .iter()
+            .filter_map(|(name, section)| section.min_level.map(|level| (name.clone(), level)))
+            .collect()
+    }
+
+    /// Returns the targets whose name is in `selection`, or all of them if there is no selection.
+    fn into_targets(self, selection: Option<Vec<String>>) -> Result<Vec<Target>, String> {
+        if let Some(selection) = &selection {
+            let known: HashSet<&str> = self.targets.iter().map(|t| t.name.as_str()).collect();
+            let unknown: Vec<&str> = selection
+                .iter()
+                .map(String::as_str)
+                .filter(|name| !known.contains(name))
+                .collect();
+            if !unknown.is_empty() {
+                return Err(format!("unknown target(s): {}", unknown.join(", ")));
+            }
+        }
+
+        Ok(self
+            .targets
+            .into_iter()
+            .filter(|target| {
+                selection
+                    .as_ref()
+                    .is_none_or(|selection| selection.contains(&target.name))
+            })
+            .map(|section| Target {
+                name: section.name,
+                prefix_list_id: section.prefix_list_id,
+                description: section.description,
+                region: section.region,
+                notify: section.notify,
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OFFICE: &str = r#"
+[[target]]
+name = "office"
+prefix_list_id = "pl-1234567890abcdef1"
+description = "doorman"
+region = "eu-west-1"
+"#;
+
+    const HOME: &str = r#"
+[[target]]
+name = "home"
+prefix_list_id = "pl-1234567890abcdef2"
+description = "doorman"
+notify = false
+"#;
+
+    fn read(content: &str, selection: Option<&[&str]>) -> Result<Loaded, String> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("doorman.toml");
+        fs::write(&path, content).unwrap();
+        ConfigFile::read(
+            &path,
+            selection.map(|names| names.iter().map(ToString::to_string).collect()),
+        )
+    }
+
+    fn names(loaded: &Loaded) -> Vec<&str> {
+        loaded
+            .targets
+            .iter()
+            .map(|target| target.name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn every_target_is_read_unless_selected() {
+        let content = format!(
+            "{}{}\n[templates.slack]\nupdated = \"{{target}}: {{new_ip}}\"\n\n[notify.slack]\nmin_level = \"change\"\n",
+            OFFICE, HOME
+        );
+        let loaded = read(&content, None).unwrap();
+        assert_eq!(names(&loaded), vec!["office", "home"]);
+        assert!(loaded.templates.contains_key("slack"));
+        assert_eq!(loaded.min_levels.get("slack"), Some(&Severity::Change));
+
+        let office = &loaded.targets[0];
+        assert_eq!(office.prefix_list_id.as_str(), "pl-1234567890abcdef1");
+        assert_eq!(office.region.as_deref(), Some("eu-west-1"));
+        assert!(office.notify);
+
+        // The region is resolved later, from --region or the usual AWS settings
+        let home = &loaded.targets[1];
+        assert_eq!(home.region, None);
+        assert!(!home.notify);
+    }
+
+    #[test]
+    fn only_selected_targets_are_kept() {
+        let content = format!("{}{}", OFFICE, HOME);
+        assert_eq!(
+            names(&read(&content, Some(&["home"])).unwrap()),
+            vec!["home"]
+        );
+        assert_eq!(
+            read(&content, Some(&["home", "staging", "lab"])).unwrap_err(),
+            "unknown target(s): staging, lab"
+        );
+    }
+
+    #[test]
+    fn a_file_without_targets_is_rejected() {
+        assert_eq!(
+            read("[notify.slack]\nmin_level = \"error\"\n", None).unwrap_err(),
+            "the configuration file doesn't define any target"
+        );
+    }
+
+    #[test]
+    fn target_names_are_unique() {
+        let content = format!("{}{}", OFFICE, OFFICE);
+        assert_eq!(
+            read(&content, None).unwrap_err(),
+            "target 'office' is defined more than once"
+        );
+    }
+
+    #[test]
+    fn settings_are_only_given_for_known_backends() {
+        for section in ["templates.growl", "notify.growl"] {
+            let content = format!("{}\n[{}]\n", OFFICE, section);
+            let err = read(&content, None).unwrap_err();
+            assert!(
+                err.contains("for unknown notification backend 'growl', expected one of desktop"),
+                "{}",
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_and_invalid_settings_are_rejected() {
+        let content = format!("{}notifications = false\n", OFFICE);
+        let err = read(&content, None).unwrap_err();
+        assert!(err.contains("unknown field `notifications`"), "{}", err);
+
+        let content = OFFICE.replace("pl-1234567890abcdef1", "pl-1");
+        let err = read(&content, None).unwrap_err();
+        assert!(err.starts_with("failed to parse"), "{}", err);
+    }
+}
@@ -0,0 +1,674 @@
+use crate::config::{Config, OutputFormat, SummaryEvery};
+use crate::notification::Severity;
+
+use aws_config::environment::region::EnvironmentVariableRegionProvider;
+use aws_config::meta::region::RegionProviderChain;
+use aws_config::profile::ProfileFileRegionProvider;
+use aws_sdk_ec2::Region;
+use color_eyre::Result;
+use reqwest::Url;
+use serde::Serialize;
+
+/// Replaces secrets in the output.
+const REDACTED: &str = "<redacted>";
+
+/// The configuration as doorman will actually use it, including derived values.
+#[derive(Serialize)]
+struct EffectiveConfig<'a> {
+    config_file: Option<String>,
+    state_file: Option<String>,
+    status_file: Option<String>,
+    audit_log: Option<String>,
+    targets: Vec<EffectiveTarget<'a>>,
+    aws_timeout: u64,
+    aws_connect_timeout: u64,
+    aws_max_attempts: u32,
+    endpoint_url: Option<String>,
+    ip: Option<String>,
+    shutdown_timeout: u64,
+    cleanup_timeout: u64,
+    slow_update: u64,
+    breaker_threshold: u32,
+    breaker_probe: u64,
+    notify_backends: Vec<EffectiveBackend>,
+    notify_webhook: Option<EffectiveWebhook>,
+    notify_slack_url: Option<String>,
+    notify_discord_url: Option<String>,
+    notify_telegram_chat_id: Option<&'a str>,
+    notify_smtp: Option<String>,
+    notify_ntfy_url: Option<String>,
+    notify_gotify_url: Option<String>,
+    notify_pushover: Option<String>,
+    notify_ca_cert: Option<String>,
+    notify_backend: String,
+    notify_log: bool,
+    notify_reminder_interval: u64,
+    notify_dedup_window: u64,
+    on_change: Option<String>,
+    on_failure: Option<String>,
+    mqtt: Option<String>,
+    metrics_listen: Option<String>,
+    otlp_endpoint: Option<String>,
+    health_listen: Option<String>,
+    control_socket: Option<String>,
+    pause_windows: Vec<String>,
+    offline_check: Option<String>,
+    interval: u64,
+    max_interval: Option<u64>,
+    wait_timeout: u64,
+    wait_timeout_adjusted: bool,
+    cleanup: bool,
+    allow_multiple: bool,
+    takeover: bool,
+    adopt: bool,
+    per_host: bool,
+    all_hosts: bool,
+    entry_ttl: Option<u64>,
+    force_update: bool,
+    simulate: Option<String>,
+    record: Option<String>,
+    leader_table: Option<String>,
+    leader_key: String,
+    leader_lease: u64,
+    recreate_on_delete: bool,
+    verify_every: Option<u64>,
+    /// `hour`, `<never>` or a number of checks
+    summary_every: String,
+    verbose: bool,
+    log_timestamp: String,
+    log_template: String,
+    event_log: Option<String>,
+    log_file: Option<String>,
+    daemon: bool,
+    pid_file: Option<String>,
+}
+
+#[derive(Serialize)]
+struct EffectiveWebhook {
+    url: String,
+    header: Option<String>,
+    timeout: u64,
+}
+
+/// A notification backend along with the least severe events it gets.
+#[derive(Serialize)]
+struct EffectiveBackend {
+    name: &'static str,
+    min_level: &'static str,
+}
+
+#[derive(Serialize)]
+struct EffectiveTarget<'a> {
+    name: &'a str,
+    prefix_list_id: &'a str,
+    description: &'a str,
+    region: Option<String>,
+    notify: bool,
+}
+
+/// Prints the effective configuration.
+///
+/// This must not make any network calls, so the region is only resolved from the command line, the environment and
+/// the profile files, never from IMDS.
+pub async fn show_config(config: &Config) -> Result<()> {
+    let default_region = RegionProviderChain::first_try(config.region.clone().map(Region::new))
+        .or_else(EnvironmentVariableRegionProvider::new())
+        .or_else(ProfileFileRegionProvider::builder().build())
+        .region()
+        .await
+        .map(|region| region.to_string());
+
+    let effective = EffectiveConfig {
+        config_file: config
+            .config_file
+            .as_ref()
+            .map(|path| path.display().to_string()),
+        state_file: config
+            .state_file
+            .as_ref()
+            .map(|path| path.display().to_string()),
+        status_file: config
+            .status_file
+            .as_ref()
+            .map(|path| path.display().to_string()),
+        audit_log: config
+            .audit_log
+            .as_ref()
+            .map(|path| path.display().to_string()),
+        targets: config
+            .targets
+            .iter()
+            .map(|target| EffectiveTarget {
+                name: &target.name,
+                prefix_list_id: target.prefix_list_id.as_str(),
+                description: target.description.as_str(),
+                region: target.region.clone().or_else(|| default_region.clone()),
+                notify: target.notify,
+            })
+            .collect(),
+        notify_backends: config
+            .notify
+            .backend_names()
+            .into_iter()
+            .map(|name| EffectiveBackend {
+                name,
+                min_level: config
+                    .notify
+                    .min_levels
+                    .get(name)
+                    .copied()
+                    .unwrap_or(Severity::Info)
+                    .as_str(),
+            })
+            .collect(),
+        aws_timeout: config.aws_timeout,
+        aws_connect_timeout: config.aws_connect_timeout,
+        aws_max_attempts: config.aws_max_attempts,
+        endpoint_url: config.endpoint_url.as_ref().map(redact_url),
+        ip: config.ip.map(|ip| ip.to_string()),
+        shutdown_timeout: config.shutdown_timeout,
+        cleanup_timeout: config.cleanup_timeout,
+        slow_update: config.slow_update,
+        breaker_threshold: config.breaker_threshold,
+        breaker_probe: config.breaker_probe,
+        notify_webhook: config
+            .notify
+            .webhook
+            .as_ref()
+            .map(|webhook| EffectiveWebhook {
+                url: redact_url(&webhook.url),
+                header: webhook
+                    .header
+                    .as_ref()
+                    .map(|header| format!("{}: {}", header.name, REDACTED)),
+                timeout: webhook.timeout,
+            }),
+        notify_slack_url: config.notify.slack_url.as_ref().map(redact_url),
+        notify_discord_url: config.notify.discord_url.as_ref().map(redact_url),
+        notify_telegram_chat_id: config
+            .notify
+            .telegram
+            .as_ref()
+            .map(|telegram| telegram.chat_id.as_str()),
+        notify_smtp: config.notify.smtp.as_ref().map(|smtp| {
+            let to: Vec<String> = smtp.to.iter().map(ToString::to_string).collect();
+            format!(
+                "{}{} ({:?}) from {} to {}{}",
+                smtp.host,
+                smtp.port
+                    .map_or_else(String::new, |port| format!(":{}", port)),
+                smtp.tls,
+                smtp.from,
+                to.join(", "),
+                smtp.credentials
+                    .as_ref()
+                    .map_or_else(String::new, |(username, _)| format!(
+                        " as {}, password {}",
+                        username, REDACTED
+                    )),
+            )
+        }),
+        notify_ntfy_url: config.notify.ntfy_url.as_ref().map(redact_url),
+        // The Gotify token is passed separately, so the URL can be shown whole
+        notify_gotify_url: config
+            .notify
+            .gotify
+            .as_ref()
+            .map(|gotify| gotify.url.to_string()),
+        notify_pushover: config.notify.pushover.as_ref().map(|pushover| {
+            format!(
+                "user {}{}, token {}",
+                pushover.user,
+                pushover
+                    .device
+                    .as_ref()
+                    .map_or_else(String::new, |device| format!(" on {}", device)),
+                REDACTED
+            )
+        }),
+        notify_ca_cert: config
+            .notify
+            .ca_cert
+            .as_ref()
+            .map(|path| path.display().to_string()),
+        notify_backend: format!("{:?}", config.notify.desktop).to_lowercase(),
+        notify_log: config.notify.log,
+        notify_reminder_interval: config.notify.reminder_interval,
+        notify_dedup_window: config.notify.dedup_window,
+        on_change: config
+            .notify
+            .hook
+            .as_ref()
+            .and_then(|hook| hook.on_change.as_ref())
+            .map(|path| path.display().to_string()),
+        on_failure: config
+            .notify
+            .hook
+            .as_ref()
+            .and_then(|hook| hook.on_failure.as_ref())
+            .map(|path| path.display().to_string()),
+        mqtt: config
+            .mqtt
+            .as_ref()
+            .map(|mqtt| format!("{} on {}", redact_url(&mqtt.url), mqtt.topic)),
+        metrics_listen: config.metrics_listen.map(|addr| addr.to_string()),
+        otlp_endpoint: config.otlp_endpoint.as_ref().map(ToString::to_string),
+        health_listen: config.health_listen.map(|addr| addr.to_string()),
+        control_socket: config
+            .control_socket
+            .as_ref()
+            .map(|path| path.display().to_string()),
+        pause_windows: config
+            .pause_windows
+            .iter()
+            .map(ToString::to_string)
+            .collect(),
+        offline_check: config.offline_check.clone(),
+        interval: config.interval,
+        max_interval: config.max_interval,
+        wait_timeout: config.wait_timeout,
+        wait_timeout_adjusted: config.wait_timeout_adjusted,
+        cleanup: config.cleanup,
+        allow_multiple: config.allow_multiple,
+        takeover: config.takeover,
+        adopt: config.adopt,
+        per_host: config.per_host,
+        all_hosts: config.all_hosts,
+        entry_ttl: config.entry_ttl,
+        force_update: config.force_update,
+        simulate: config
+            .simulate
+            .as_ref()
+            .map(|path| path.display().to_string()),
+        record: config
+            .record
+            .as_ref()
+            .map(|path| path.display().to_string()),
+        leader_table: config.leader_table.clone(),
+        leader_key: config.leader_key.clone(),
+        leader_lease: config.leader_lease,
+        recreate_on_delete: config.recreate_on_delete,
+        verify_every: config.verify_every,
+        summary_every: match config.summary_every {
+            SummaryEvery::Hour => "hour".to_string(),
+            SummaryEvery::Checks(checks) => format!("{} checks", checks),
+            SummaryEvery::Never => "<never>".to_string(),
+        },
+        verbose: config.verbose,
+        log_timestamp: format!("{:?}", config.log_timestamp).to_lowercase(),
+        log_template: config.log_template.to_string(),
+        event_log: config
+            .event_log
+            .map(|level| level.to_string().to_lowercase()),
+        log_file: config
+            .log_file
+            .as_ref()
+            .map(|path| path.display().to_string()),
+        daemon: config.daemon,
+        pid_file: config
+            .pid_file
+            .as_ref()
+            .map(|path| path.display().to_string()),
+    };
+
+    match config.output {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&effective)?),
+        OutputFormat::Text => print_table(&effective),
+    }
+
+    Ok(())
+}
+
+fn print_table(config: &EffectiveConfig) {
+    let mut rows = vec![
+        (
+            "config file".to_string(),
+            config
+                .config_file
+                .clone()
+                .unwrap_or_else(|| "<none>".to_string()),
+        ),
+        (
+            "state file".to_string(),
+            config
+                .state_file
+                .clone()
+                .unwrap_or_else(|| "<none>".to_string()),
+        ),
+        (
+            "status file".to_string(),
+            config
+                .status_file
+                .clone()
+                .unwrap_or_else(|| "<none>".to_string()),
+        ),
+        (
+            "audit log".to_string(),
+            config
+                .audit_log
+                .clone()
+                .unwrap_or_else(|| "<none>".to_string()),
+        ),
+        (
+            "aws timeout".to_string(),
+            format!("{}s", config.aws_timeout),
+        ),
+        (
+            "aws connect timeout".to_string(),
+            format!("{}s", config.aws_connect_timeout),
+        ),
+        (
+            "aws max attempts".to_string(),
+            config.aws_max_attempts.to_string(),
+        ),
+        (
+            "endpoint url".to_string(),
+            config
+                .endpoint_url
+                .clone()
+                .unwrap_or_else(|| "<aws>".to_string()),
+        ),
+        (
+            "ip".to_string(),
+            config
+                .ip
+                .clone()
+                .unwrap_or_else(|| "<looked up>".to_string()),
+        ),
+        (
+            "shutdown timeout".to_string(),
+            format!("{}s", config.shutdown_timeout),
+        ),
+        (
+            "cleanup timeout".to_string(),
+            format!("{}s", config.cleanup_timeout),
+        ),
+        (
+            "slow update".to_string(),
+            format!("{}s", config.slow_update),
+        ),
+        (
+            "breaker threshold".to_string(),
+            config.breaker_threshold.to_string(),
+        ),
+        (
+            "breaker probe".to_string(),
+            format!("{}s", config.breaker_probe),
+        ),
+        (
+            "webhook".to_string(),
+            config.notify_webhook.as_ref().map_or_else(
+                || "<none>".to_string(),
+                |webhook| match &webhook.header {
+                    Some(header) => format!(
+                        "{} with header '{}', {}s timeout",
+                        webhook.url, header, webhook.timeout
+                    ),
+                    None => format!("{}, {}s timeout", webhook.url, webhook.timeout),
+                },
+            ),
+        ),
+        (
+            "slack".to_string(),
+            config
+                .notify_slack_url
+                .clone()
+                .unwrap_or_else(|| "<none>".to_string()),
+        ),
+        (
+            "discord".to_string(),
+            config
+                .notify_discord_url
+                .clone()
+                .unwrap_or_else(|| "<none>".to_string()),
+        ),
+        (
+            "telegram".to_string(),
+            config.notify_telegram_chat_id.map_or_else(
+                || "<none>".to_string(),
+                |chat_id| format!("chat {}, token {}", chat_id, REDACTED),
+            ),
+        ),
+        (
+            "smtp".to_string(),
+            config
+                .notify_smtp
+                .clone()
+                .unwrap_or_else(|| "<none>".to_string()),
+        ),
+        (
+            "ntfy".to_string(),
+            config
+                .notify_ntfy_url
+                .clone()
+                .unwrap_or_else(|| "<none>".to_string()),
+        ),
+        (
+            "gotify".to_string(),
+            config.notify_gotify_url.as_ref().map_or_else(
+                || "<none>".to_string(),
+                |url| format!("{}, token {}", url, REDACTED),
+            ),
+        ),
+        (
+            "pushover".to_string(),
+            config
+                .notify_pushover
+                .clone()
+                .unwrap_or_else(|| "<none>".to_string()),
+        ),
+        (
+            "notification ca cert".to_string(),
+            config
+                .notify_ca_cert
+                .clone()
+                .unwrap_or_else(|| "<none>".to_string()),
+        ),
+        ("notify backend".to_string(), config.notify_backend.clone()),
+        ("notify log".to_string(), config.notify_log.to_string()),
+        (
+            "notify reminder interval".to_string(),
+            format!("{}s", config.notify_reminder_interval),
+        ),
+        (
+            "notify dedup window".to_string(),
+            format!("{}s", config.notify_dedup_window),
+        ),
+        (
+            "on change".to_string(),
+            config
+                .on_change
+                .clone()
+                .unwrap_or_else(|| "<none>".to_string()),
+        ),
+        (
+            "on failure".to_string(),
+            config
+                .on_failure
+                .clone()
+                .unwrap_or_else(|| "<none>".to_string()),
+        ),
+        (
+            "mqtt".to_string(),
+            config.mqtt.clone().unwrap_or_else(|| "<none>".to_string()),
+        ),
+        (
+            "metrics listen".to_string(),
+            config
+                .metrics_listen
+                .clone()
+                .unwrap_or_else(|| "<none>".to_string()),
+        ),
+        (
+            "otlp endpoint".to_string(),
+            config
+                .otlp_endpoint
+                .clone()
+                .unwrap_or_else(|| "<none>".to_string()),
+        ),
+        (
+            "health listen".to_string(),
+            config
+                .health_listen
+                .clone()
+                .unwrap_or_else(|| "<none>".to_string()),
+        ),
+        (
+            "control socket".to_string(),
+            config
+                .control_socket
+                .clone()
+                .unwrap_or_else(|| "<none>".to_string()),
+        ),
+        (
+            "pause windows".to_string(),
+            match config.pause_windows.is_empty() {
+                true => "<none>".to_string(),
+                false => config.pause_windows.join(", "),
+            },
+        ),
+        (
+            "offline check".to_string(),
+            config
+                .offline_check
+                .clone()
+                .unwrap_or_else(|| "<none>".to_string()),
+        ),
+        ("interval".to_string(), format!("{}s", config.interval)),
+        (
+            "max interval".to_string(),
+            config.max_interval.map_or_else(
+                || "<not adaptive>".to_string(),
+                |max_interval| format!("{}s", max_interval),
+            ),
+        ),
+        (
+            "wait timeout".to_string(),
+            match config.wait_timeout_adjusted {
+                true => format!("{}s (shortened to fit the interval)", config.wait_timeout),
+                false => format!("{}s", config.wait_timeout),
+            },
+        ),
+        ("cleanup".to_string(), config.cleanup.to_string()),
+        (
+            "allow multiple".to_string(),
+            config.allow_multiple.to_string(),
+        ),
+        ("takeover".to_string(), config.takeover.to_string()),
+        ("adopt".to_string(), config.adopt.to_string()),
+        ("per host".to_string(), config.per_host.to_string()),
+        ("all hosts".to_string(), config.all_hosts.to_string()),
+        (
+            "entry TTL".to_string(),
+            config
+                .entry_ttl
+                .map_or_else(|| "<none>".to_string(), |ttl| format!("{}s", ttl)),
+        ),
+        ("force update".to_string(), config.force_update.to_string()),
+        (
+            "simulate".to_string(),
+            config
+                .simulate
+                .clone()
+                .unwrap_or_else(|| "<none>".to_string()),
+        ),
+        (
+            "record".to_string(),
+            config
+                .record
+                .clone()
+                .unwrap_or_else(|| "<none>".to_string()),
+        ),
+        (
+            "leader table".to_string(),
+            config
+                .leader_table
+                .clone()
+                .unwrap_or_else(|| "<none>".to_string()),
+        ),
+        ("leader key".to_string(), config.leader_key.clone()),
+        (
+            "leader lease".to_string(),
+            format!("{}s", config.leader_lease),
+        ),
+        (
+            "recreate on delete".to_string(),
+            config.recreate_on_delete.to_string(),
+        ),
+        (
+            "verify every".to_string(),
+            config.verify_every.map_or_else(
+                || "<never>".to_string(),
+                |checks| format!("{} checks", checks),
+            ),
+        ),
+        ("summary every".to_string(), config.summary_every.clone()),
+        ("verbose".to_string(), config.verbose.to_string()),
+        ("log timestamp".to_string(), config.log_timestamp.clone()),
+        ("log template".to_string(), config.log_template.clone()),
+        (
+            "event log".to_string(),
+            config
+                .event_log
+                .clone()
+                .unwrap_or_else(|| "<none>".to_string()),
+        ),
+        (
+            "log file".to_string(),
+            config
+                .log_file
+                .clone()
+                .unwrap_or_else(|| "<stdout>".to_string()),
+        ),
+        ("daemon".to_string(), config.daemon.to_string()),
+        (
+            "pid file".to_string(),
+            config
+                .pid_file
+                .clone()
+                .unwrap_or_else(|| "<none>".to_string()),
+        ),
+    ];
+
+    for target in &config.targets {
+        let prefix = format!("[{}]", target.name);
+        rows.extend([
+            (
+                format!("{} prefix list id", prefix),
+                target.prefix_list_id.to_string(),
+            ),
+            (
+                format!("{} description", prefix),
+                target.description.to_string(),
+            ),
+            (
+                format!("{} region", prefix),
+                target
+                    .region
+                    .clone()
+                    .unwrap_or_else(|| "<not set>".to_string()),
+            ),
+            (format!("{} notify", prefix), target.notify.to_string()),
+        ]);
+    }
+
+    for backend in &config.notify_backends {
+        rows.push((
+            format!("notify {} min level", backend.name),
+            backend.min_level.to_string(),
+        ));
+    }
+
+    let width = rows.iter().map(|(key, _)| key.len()).max().unwrap_or(0);
+    for (key, value) in rows {
+        println!("{:width$}  {}", key, value, width = width);
+    }
+}
+
+/// Only keeps the scheme and host of a URL, as webhook URLs often embed a token.
+fn redact_url(url: &Url) -> String {
+    match url.host_str() {
+        Some(host) => format!("{}://{}/{}", url.scheme(), host, REDACTED),
+        None => REDACTED.to_string(),
+    }
+}
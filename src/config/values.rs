@@ -0,0 +1,741 @@
+use crate::aws::Entry;
+
+use chrono::{DateTime, Local, NaiveTime, SecondsFormat, Timelike, Utc};
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// ID of an AWS managed prefix list, such as `pl-1234567890abcdef0`.
+///
+/// The only way to build one is by parsing, so any value of this type is well-formed.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct PrefixListId(String);
+
+impl PrefixListId {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for PrefixListId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        lazy_static! {
+            static ref RE: Regex =
+                Regex::new(r"\A(?i:pl-([[:xdigit:]]{8}|[[:xdigit:]]{17}))\z").unwrap();
+        }
+        match RE.is_match(s) {
+            true => Ok(Self(s.to_string())),
+            false => Err("the expected format is 'pl-1234567890abcdef0'".to_string()),
+        }
+    }
+}
+
+impl TryFrom<String> for PrefixListId {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<PrefixListId> for String {
+    fn from(value: PrefixListId) -> Self {
+        value.0
+    }
+}
+
+impl AsRef<str> for PrefixListId {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for PrefixListId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// A target given on the command line as `PREFIX_LIST_ID[@REGION]`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TargetSpec {
+    pub prefix_list_id: PrefixListId,
+    pub region: Option<String>,
+}
+
+impl FromStr for TargetSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (prefix_list_id, region) = match s.split_once('@') {
+            Some((_, "")) => return Err("the region after '@' must not be empty".to_string()),
+            Some((prefix_list_id, region)) => (prefix_list_id, Some(region.to_string())),
+            None => (s, None),
+        };
+        Ok(Self {
+            prefix_list_id: prefix_list_id.parse()?,
+            region,
+        })
+    }
+}
+
+impl fmt::Display for TargetSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.region {
+            Some(region) => write!(f, "{}@{}", self.prefix_list_id, region),
+            None => write!(f, "{}", self.prefix_list_id),
+        }
+    }
+}
+
+/// Description of the prefix list entries managed by doorman.
+///
+/// Doorman considers it owns every entry carrying this description, so it's what decides which entries get modified
+/// or cleaned up. An owned entry may also carry a suffix after [`Description::SUFFIX_SEPARATOR`], such as a timestamp.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Description(String);
+
+impl Description {
+    /// AWS limits entry descriptions to 255 characters.
+    pub const MAX_LENGTH: usize = 255;
+
+    /// Separates the description from any suffix embedded in the entry description.
+    pub const SUFFIX_SEPARATOR: &'static str = " @ ";
+
+    /// Punctuation allowed by AWS in descriptions, on top of ASCII letters and digits.
+    const ALLOWED_PUNCTUATION: &'static str = " ._-:/()#,@[]+=&;{}!$*";
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Whether a prefix list entry is owned by doorman.
+    ///
+    /// This is the case if the entry's description is exactly ours, or ours followed by a suffix. Entries without a
+    /// description are never owned.
+    pub fn matches_owned_entry(&self, entry: &Entry) -> bool {
+        self.owns(&entry.description)
+    }
+
+    /// Like [`Description::matches_owned_entry`], from the description alone, e.g. that of an entry which can't be
+    /// parsed.
+    pub fn owns(&self, description: &str) -> bool {
+        // Ours is never empty, so an empty description never matches
+        match description {
+            description if description == self.0 => true,
+            description => description
+                .strip_prefix(self.0.as_str())
+                .is_some_and(|rest| rest.starts_with(Self::SUFFIX_SEPARATOR)),
+        }
+    }
+
+    /// This description followed by `hostname`, so that each host owns its own entries. The entries of every host are
+    /// still owned by this description, which is what sweeping them relies on.
+    ///
+    /// Characters AWS doesn't allow in descriptions are replaced with `-`.
+    pub fn for_host(&self, hostname: &str) -> Result<Self, String> {
+        let hostname: String = hostname
+            .chars()
+            .map(|c| match c.is_ascii_alphanumeric() || ".-_".contains(c) {
+                true => c,
+                false => '-',
+            })
+            .collect();
+        let description = format!("{}{}{}", self.0, Self::SUFFIX_SEPARATOR, hostname);
+        Self::check(&description)?;
+        Ok(Self(description))
+    }
+
+    /// The host owning an entry written with [`Description::for_host`], if it was.
+    pub fn host<'a>(&self, entry: &'a Entry) -> Option<&'a str> {
+        let host = entry
+            .description
+            .strip_prefix(self.0.as_str())?
+            .strip_prefix(Self::SUFFIX_SEPARATOR)?
+            .split(Self::SUFFIX_SEPARATOR)
+            .next()?;
+        // A timestamp right after the description isn't a host
+        match DateTime::parse_from_rfc3339(host.trim()) {
+            Ok(_) => None,
+            Err(_) => Some(host),
+        }
+    }
+
+    /// The description of an entry expiring at `at`, as written with `--entry-ttl`.
+    pub fn with_expiry(&self, at: DateTime<Utc>) -> String {
+        format!(
+            "{}{}{}",
+            self.0,
+            Self::SUFFIX_SEPARATOR,
+            at.to_rfc3339_opts(SecondsFormat::Secs, true)
+        )
+    }
+
+    /// The RFC 3339 timestamp ending an owned entry's description, such as the expiry written with `--entry-ttl`, if
+    /// it has one.
+    pub fn embedded_timestamp(&self, entry: &Entry) -> Option<DateTime<Utc>> {
+        let suffix = entry
+            .description
+            .strip_prefix(self.0.as_str())?
+            .strip_prefix(Self::SUFFIX_SEPARATOR)?
+            .rsplit(Self::SUFFIX_SEPARATOR)
+            .next()?;
+        DateTime::parse_from_rfc3339(suffix.trim())
+            .ok()
+            .map(|at| at.with_timezone(&Utc))
+    }
+
+    /// Checks what AWS accepts in a description.
+    fn check(s: &str) -> Result<(), String> {
+        if s.is_empty() {
+            return Err("must not be empty".to_string());
+        }
+        if s.chars().count() > Self::MAX_LENGTH {
+            return Err(format!(
+                "must be at most {} characters long",
+                Self::MAX_LENGTH
+            ));
+        }
+        if let Some(c) = s
+            .chars()
+            .find(|c| !c.is_ascii_alphanumeric() && !Self::ALLOWED_PUNCTUATION.contains(*c))
+        {
+            return Err(format!(
+                "invalid character '{}', only letters, digits, spaces and {} are allowed",
+                c,
+                Self::ALLOWED_PUNCTUATION.trim_start()
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Description {
+    type Err = String;
+
+    /// Also rejects [`Description::SUFFIX_SEPARATOR`], which would make doorman own entries whose description only
+    /// starts with ours.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::check(s)?;
+        if s.contains(Self::SUFFIX_SEPARATOR) {
+            return Err(format!(
+                "must not contain '{}', which separates the description from its suffix",
+                Self::SUFFIX_SEPARATOR.trim()
+            ));
+        }
+        Ok(Self(s.to_string()))
+    }
+}
+
+impl TryFrom<String> for Description {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<Description> for String {
+    fn from(value: Description) -> Self {
+        value.0
+    }
+}
+
+impl PartialEq<str> for Description {
+    fn eq(&self, other: &str) -> bool {
+        self.0 == other
+    }
+}
+
+impl PartialEq<&str> for Description {
+    fn eq(&self, other: &&str) -> bool {
+        self.0 == *other
+    }
+}
+
+impl fmt::Display for Description {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// An HTTP header given as `Name: value`.
+///
+/// Its value usually holds a token, so it's left out of the debug output.
+#[derive(Clone, PartialEq, Eq)]
+pub struct HeaderSpec {
+    pub name: String,
+    pub value: String,
+}
+
+impl FromStr for HeaderSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, value) = s
+            .split_once(':')
+            .ok_or_else(|| "the expected format is 'Name: value'".to_string())?;
+        let name = name.trim();
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_graphic()) {
+            return Err(format!("invalid header name '{}'", name));
+        }
+        Ok(Self {
+            name: name.to_string(),
+            value: value.trim().to_string(),
+        })
+    }
+}
+
+impl fmt::Debug for HeaderSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HeaderSpec")
+            .field("name", &self.name)
+            .field("value", &"<redacted>")
+            .finish()
+    }
+}
+
+/// A daily maintenance window given as `HH:MM-HH:MM`, in local time unless it ends with `Z` for UTC.
+///
+/// Local times follow the system's timezone, daylight saving included. A window ending before it starts spans
+/// midnight.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PauseWindow {
+    start: NaiveTime,
+    end: NaiveTime,
+    utc: bool,
+}
+
+impl PauseWindow {
+    /// How long until the window ends, if `now` is within it.
+    pub fn remaining(&self, now: DateTime<Utc>) -> Option<chrono::Duration> {
+        let time = match self.utc {
+            true => now.time(),
+            false => now.with_timezone(&Local).time(),
+        };
+        let seconds = |time: NaiveTime| i64::from(time.num_seconds_from_midnight());
+        let length = (seconds(self.end) - seconds(self.start)).rem_euclid(SECONDS_PER_DAY);
+        let elapsed = (seconds(time) - seconds(self.start)).rem_euclid(SECONDS_PER_DAY);
+        match elapsed < length {
+            true => Some(chrono::Duration::seconds(length - elapsed)),
+            false => None,
+        }
+    }
+}
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+impl FromStr for PauseWindow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (range, utc) = match s.strip_suffix('Z') {
+            Some(range) => (range, true),
+            None => (s, false),
+        };
+        let (start, end) = range.split_once('-').ok_or_else(|| {
+            "the expected format is 'HH:MM-HH:MM', optionally followed by 'Z'".to_string()
+        })?;
+        let parse = |time: &str| {
+            NaiveTime::parse_from_str(time.trim(), "%H:%M")
+                .map_err(|err| format!("invalid time '{}': {}", time, err))
+        };
+        let (start, end) = (parse(start)?, parse(end)?);
+        if start == end {
+            return Err("the window must not be empty".to_string());
+        }
+        Ok(Self { start, end, utc })
+    }
+}
+
+impl fmt::Display for PauseWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}-{}{}",
+            self.start.format("%H:%M"),
+            self.end.format("%H:%M"),
+            match self.utc {
+                true => "Z",
+                false => "",
+            }
+        )
+    }
+}
+
+/// A point in time given as an age such as `30m`, `12h`, `7d` or `2w`, or as an RFC 3339 timestamp.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Since {
+    Ago(chrono::Duration),
+    At(DateTime<Utc>),
+}
+
+impl Since {
+    /// The point in time, ages counting back from `now`.
+    pub fn at(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        match self {
+            Self::Ago(age) => now - *age,
+            Self::At(at) => *at,
+        }
+    }
+}
+
+impl FromStr for Since {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(at) = DateTime::parse_from_rfc3339(s) {
+            return Ok(Self::At(at.with_timezone(&Utc)));
+        }
+        let unit = s
+            .chars()
+            .last()
+            .ok_or_else(|| "must not be empty".to_string())?;
+        // Small enough that going back never overflows
+        let count: u16 = s[..s.len() - unit.len_utf8()].parse().map_err(|_| {
+            "the expected format is a number followed by s, m, h, d or w, or an RFC 3339 timestamp".to_string()
+        })?;
+        let count = i64::from(count);
+        let age = match unit {
+            's' => chrono::Duration::seconds(count),
+            'm' => chrono::Duration::minutes(count),
+            'h' => chrono::Duration::hours(count),
+            'd' => chrono::Duration::days(count),
+            'w' => chrono::Duration::weeks(count),
+            _ => return Err(format!("unknown unit '{}', expected s, m, h, d or w", unit)),
+        };
+        Ok(Self::Ago(age))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(description: &str) -> Entry {
+        Entry {
+            cidr: "192.0.2.1/32".parse().unwrap(),
+            description: description.to_string(),
+        }
+    }
+
+    fn office() -> Description {
+        "office".parse().unwrap()
+    }
+
+    #[test]
+    fn owns_exact_match() {
+        assert!(office().matches_owned_entry(&entry("office")));
+    }
+
+    #[test]
+    fn owns_suffixed_match() {
+        assert!(office().matches_owned_entry(&entry("office @ laptop")));
+        assert!(office().matches_owned_entry(&entry("office @ 2022-09-01T10:00:00Z")));
+    }
+
+    #[test]
+    fn does_not_own_shared_prefix() {
+        assert!(!office().matches_owned_entry(&entry("officer")));
+        assert!(!office().matches_owned_entry(&entry("office2")));
+        assert!(!office().matches_owned_entry(&entry("office@laptop")));
+        assert!(!office().matches_owned_entry(&entry("")));
+    }
+
+    #[test]
+    fn rejects_suffix_separator() {
+        assert!("office @ laptop".parse::<Description>().is_err());
+        assert!("office@laptop".parse::<Description>().is_ok());
+    }
+
+    #[test]
+    fn rejects_empty_and_invalid() {
+        assert!("".parse::<Description>().is_err());
+        assert!("office%".parse::<Description>().is_err());
+        assert!("a"
+            .repeat(Description::MAX_LENGTH + 1)
+            .parse::<Description>()
+            .is_err());
+    }
+
+    #[test]
+    fn per_host_descriptions() {
+        let description = office().for_host("my laptop").unwrap();
+        assert_eq!(description, "office @ my-laptop");
+        assert!(office().matches_owned_entry(&entry(description.as_str())));
+        assert_eq!(
+            office().host(&entry("office @ my-laptop")),
+            Some("my-laptop")
+        );
+        assert_eq!(office().host(&entry("office @ 2022-09-01T10:00:00Z")), None);
+    }
+
+    #[test]
+    fn per_host_descriptions_only_own_their_host() {
+        let laptop = office().for_host("laptop").unwrap();
+        assert!(laptop.matches_owned_entry(&entry("office @ laptop")));
+        assert!(laptop.matches_owned_entry(&entry("office @ laptop @ 2022-09-01T10:00:00Z")));
+        assert!(!laptop.matches_owned_entry(&entry("office @ desktop")));
+        assert!(!laptop.matches_owned_entry(&entry("office @ laptop2")));
+        assert!(!laptop.matches_owned_entry(&entry("office")));
+
+        assert_eq!(
+            office().host(&entry("office @ laptop @ 2022-09-01T10:00:00Z")),
+            Some("laptop")
+        );
+        assert_eq!(office().host(&entry("office")), None);
+        assert_eq!(office().host(&entry("officer @ laptop")), None);
+        // Too long once the hostname is added
+        assert!(office()
+            .for_host(&"a".repeat(Description::MAX_LENGTH))
+            .is_err());
+    }
+
+    #[test]
+    fn embedded_timestamps() {
+        let at = "2022-09-01T10:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        let expiring = office().with_expiry(at);
+        assert_eq!(office().embedded_timestamp(&entry(&expiring)), Some(at));
+        assert_eq!(office().embedded_timestamp(&entry("office @ laptop")), None);
+        // Per-host entries carry it after the host
+        assert_eq!(
+            office().embedded_timestamp(&entry("office @ laptop @ 2022-09-01T10:00:00Z")),
+            Some(at)
+        );
+        assert_eq!(
+            office().embedded_timestamp(&entry("officer @ 2022-09-01T10:00:00Z")),
+            None
+        );
+    }
+
+    #[test]
+    fn prefix_list_ids() {
+        assert!("pl-12345678".parse::<PrefixListId>().is_ok());
+        assert!("pl-1234567890abcdef0".parse::<PrefixListId>().is_ok());
+        assert!("PL-1234567890ABCDEF0".parse::<PrefixListId>().is_ok());
+        assert!("pl-1234567".parse::<PrefixListId>().is_err());
+        assert!("pl-1234567890abcdef".parse::<PrefixListId>().is_err());
+        assert!("pl-1234567g".parse::<PrefixListId>().is_err());
+        assert!(" pl-12345678".parse::<PrefixListId>().is_err());
+        assert!("sg-12345678".parse::<PrefixListId>().is_err());
+    }
+
+    #[test]
+    fn target_specs() {
+        let target: TargetSpec = "pl-12345678@eu-west-3".parse().unwrap();
+        assert_eq!(target.prefix_list_id, "pl-12345678".parse().unwrap());
+        assert_eq!(target.region.as_deref(), Some("eu-west-3"));
+        assert_eq!(target.to_string(), "pl-12345678@eu-west-3");
+
+        let target: TargetSpec = "pl-12345678".parse().unwrap();
+        assert_eq!(target.region, None);
+        assert_eq!(target.to_string(), "pl-12345678");
+
+        assert!("pl-12345678@".parse::<TargetSpec>().is_err());
+        assert!("nope@eu-west-3".parse::<TargetSpec>().is_err());
+    }
+
+    #[test]
+    fn header_specs() {
+        let header: HeaderSpec = "Authorization: Bearer abc:def".parse().unwrap();
+        assert_eq!(header.name, "Authorization");
+        assert_eq!(header.value, "Bearer abc:def");
+        assert!(!format!("{:?}", header).contains("abc"));
+
+        assert!("Authorization".parse::<HeaderSpec>().is_err());
+        assert!(": value".parse::<HeaderSpec>().is_err());
+        assert!("Bad Name: value".parse::<HeaderSpec>().is_err());
+    }
+
+    #[test]
+    fn pause_windows() {
+        let window: PauseWindow = "22:00-02:00Z".parse().unwrap();
+        assert_eq!(window.to_string(), "22:00-02:00Z");
+        let at = |time: &str| format!("2022-09-01T{}Z", time).parse().unwrap();
+        assert_eq!(
+            window.remaining(at("23:00:00")),
+            Some(chrono::Duration::hours(3))
+        );
+        assert_eq!(
+            window.remaining(at("01:30:00")),
+            Some(chrono::Duration::minutes(30))
+        );
+        assert_eq!(window.remaining(at("02:00:00")), None);
+        assert_eq!(window.remaining(at("12:00:00")), None);
+
+        assert!("10:00-10:00".parse::<PauseWindow>().is_err());
+        assert!("10:00".parse::<PauseWindow>().is_err());
+        assert!("25:00-26:00".parse::<PauseWindow>().is_err());
+    }
+
+    #[test]
+    fn since() {
+        let now: DateTime<Utc> = "2022-09-01T10:00:00Z".parse().unwrap();
+        let at = |s: &str| s.parse::<Since>().unwrap().at(now);
+        assert_eq!(at("30s"), now - chrono::Duration::seconds(30));
+        assert_eq!(at("30m"), now - chrono::Duration::minutes(30));
+        assert_eq!(at("12h"), now - chrono::Duration::hours(12));
+        assert_eq!(at("7d"), now - chrono::Duration::days(7));
+        assert_eq!(at("2w"), now - chrono::Duration::weeks(2));
+        assert_eq!(
+            at("2022-08-01T00:00:00+02:00"),
+            "2022-07-31T22:00:00Z".parse::<DateTime<Utc>>().unwrap()
+        );
+
+        assert!("".parse::<Since>().is_err());
+        assert!("7y".parse::<Since>().is_err());
+        assert!("d".parse::<Since>().is_err());
+        assert!("-7d".parse::<Since>().is_err());
+        assert!("99999d".parse::<Since>().is_err());
+    }
+
+    /// Checked against reference predicates rather than the parsers' own regexes and loops.
+    mod properties {
+        use super::*;
+        use proptest::prelude::*;
+
+        fn is_valid_id(s: &str) -> bool {
+            let Some(suffix) = s
+                .get(..3)
+                .filter(|prefix| prefix.eq_ignore_ascii_case("pl-"))
+                .and(s.get(3..))
+            else {
+                return false;
+            };
+            (suffix.len() == 8 || suffix.len() == 17)
+                && suffix.chars().all(|c| c.is_ascii_hexdigit())
+        }
+
+        fn is_valid_description(s: &str) -> bool {
+            let count = s.chars().count();
+            (1..=Description::MAX_LENGTH).contains(&count)
+                && s.chars().all(|c| {
+                    c.is_ascii_alphanumeric() || Description::ALLOWED_PUNCTUATION.contains(c)
+                })
+                && !s.contains(Description::SUFFIX_SEPARATOR)
+        }
+
+        fn valid_id() -> impl Strategy<Value = String> {
+            (
+                "(?i:pl)-",
+                prop_oneof!["[[:xdigit:]]{8}", "[[:xdigit:]]{17}"],
+            )
+                .prop_map(|(prefix, suffix)| prefix + &suffix)
+        }
+
+        fn invalid_id() -> impl Strategy<Value = String> {
+            prop_oneof![
+                // Wrong prefix
+                "(sg|pl_|p-|pl|xpl-)[[:xdigit:]]{8}",
+                // Wrong lengths
+                "pl-[[:xdigit:]]{0,7}",
+                "pl-[[:xdigit:]]{9,16}",
+                "pl-[[:xdigit:]]{18,30}",
+                // Not hexadecimal, or not ASCII
+                "pl-[[:xdigit:]]{7}[g-zG-Z]",
+                "pl-[[:xdigit:]]{7}\\PC",
+                "\\PC*",
+            ]
+        }
+
+        /// Descriptions of any length around the limit, mostly of allowed characters.
+        fn description() -> impl Strategy<Value = String> {
+            prop_oneof![
+                "[a-zA-Z0-9 ._:/()#,@\\[\\]+=&;{}!$*-]{1,300}",
+                "[a-z]{250,260}",
+                ".{0,10}",
+            ]
+        }
+
+        proptest! {
+            #[test]
+            fn valid_prefix_list_ids_parse(s in valid_id()) {
+                prop_assert!(is_valid_id(&s));
+                let id: PrefixListId = s.parse().unwrap();
+                prop_assert_eq!(id.as_str(), s.as_str());
+            }
+
+            #[test]
+            fn prefix_list_ids_agree_with_the_reference(
+                s in prop_oneof![valid_id(), invalid_id()]
+            ) {
+                prop_assert_eq!(s.parse::<PrefixListId>().is_ok(), is_valid_id(&s));
+            }
+
+            #[test]
+            fn descriptions_agree_with_the_reference(s in description()) {
+                let parsed = s.parse::<Description>();
+                prop_assert_eq!(parsed.is_ok(), is_valid_description(&s), "{:?}", s);
+            }
+
+            #[test]
+            fn target_specs_round_trip(
+                id in valid_id(),
+                region in proptest::option::of("[a-z]{2}-[a-z]+-[0-9]")
+            ) {
+                let s = match &region {
+                    Some(region) => format!("{}@{}", id, region),
+                    None => id.clone(),
+                };
+                let target: TargetSpec = s.parse().unwrap();
+                prop_assert_eq!(target.prefix_list_id.as_str(), id.as_str());
+                prop_assert_eq!(target.region, region);
+            }
+
+            #[test]
+            fn since_ages_parse(count in any::<u16>(), unit in "[smhdw]") {
+                let age = match unit.as_str() {
+                    "s" => chrono::Duration::seconds(count.into()),
+                    "m" => chrono::Duration::minutes(count.into()),
+                    "h" => chrono::Duration::hours(count.into()),
+                    "d" => chrono::Duration::days(count.into()),
+                    _ => chrono::Duration::weeks(count.into()),
+                };
+                let s = format!("{}{}", count, unit);
+                prop_assert_eq!(s.parse::<Since>(), Ok(Since::Ago(age)));
+            }
+
+            #[test]
+            fn since_rejects_other_ages(count in 65_536u32.., unit in "[smhdw]") {
+                let s = format!("{}{}", count, unit);
+                prop_assert!(s.parse::<Since>().is_err());
+            }
+
+            #[test]
+            fn pause_windows_round_trip(
+                start in 0u32..1440,
+                end in 0u32..1440,
+                utc in any::<bool>()
+            ) {
+                let s = format!(
+                    "{:02}:{:02}-{:02}:{:02}{}",
+                    start / 60,
+                    start % 60,
+                    end / 60,
+                    end % 60,
+                    if utc { "Z" } else { "" }
+                );
+                match s.parse::<PauseWindow>() {
+                    Ok(window) => prop_assert_eq!(window.to_string(), s),
+                    Err(_) => prop_assert_eq!(start, end),
+                }
+            }
+
+            #[test]
+            fn parsers_never_panic(s in "\\PC*") {
+                let _ = s.parse::<PrefixListId>();
+                let _ = s.parse::<TargetSpec>();
+                let _ = s.parse::<Description>();
+                let _ = s.parse::<HeaderSpec>();
+                let _ = s.parse::<PauseWindow>();
+                let _ = s.parse::<Since>();
+            }
+        }
+    }
+}
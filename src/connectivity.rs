@@ -0,0 +1,42 @@
+use tokio::net::{lookup_host, TcpStream};
+use tokio::time::{timeout, Duration};
+
+/// How long resolving and connecting may take before the network is considered down.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Tells being offline, e.g. on a plane, from failing to get the IP or to reach AWS.
+///
+/// The network is considered up when a well-known endpoint can be resolved and connected to quickly.
+pub struct Connectivity {
+    /// As `HOST:PORT`
+    endpoint: String,
+}
+
+impl Connectivity {
+    pub fn new(endpoint: String) -> Self {
+        Self { endpoint }
+    }
+
+    /// Returns why the endpoint can't be reached, if it can't.
+    pub async fn check(&self) -> Result<(), String> {
+        timeout(CHECK_TIMEOUT, async {
+            let addr = lookup_host(&self.endpoint)
+                .await
+                .map_err(|err| format!("failed to resolve {}: {}", self.endpoint, err))?
+                .next()
+                .ok_or_else(|| format!("{} has no address", self.endpoint))?;
+            TcpStream::connect(addr)
+                .await
+                .map(drop)
+                .map_err(|err| format!("failed to connect to {}: {}", self.endpoint, err))
+        })
+        .await
+        .unwrap_or_else(|_| {
+            Err(format!(
+                "{} didn't answer within {} seconds",
+                self.endpoint,
+                CHECK_TIMEOUT.as_secs()
+            ))
+        })
+    }
+}
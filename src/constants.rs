@@ -1,3 +0,0 @@
-pub enum ExitCodes {
-    FailedIPGuess = 1,
-}
\ No newline at end of file
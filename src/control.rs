@@ -0,0 +1,539 @@
+use crate::breaker::Breaker;
+use crate::pause::Pause;
+use crate::recheck::Recheck;
+use crate::stats::{Stats, TargetStats};
+
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// What `ctl` asks the running doorman, one request per connection, as a line of JSON.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+pub enum Request {
+    Status,
+    CheckNow,
+    /// Check right away, rewriting the entries even if they look fine
+    ForceUpdate,
+    /// Remove the entries and stop, as on SIGTERM
+    Cleanup,
+    /// Leave the prefix lists alone until resumed
+    Pause,
+    Resume,
+    /// Close the circuit opened by repeated failures, trying the prefix lists right away
+    ResetBreaker,
+}
+
+/// The answer to a [`Request`], also a line of JSON.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "result", rename_all = "kebab-case")]
+pub enum Response {
+    Status {
+        summary: String,
+        ip: Option<IpNet>,
+        paused: bool,
+        /// Whether the prefix lists are only tried once per probe interval, after failing repeatedly
+        circuit_open: bool,
+        /// `leader`, `follower` or `unknown`, when running as a redundant pair
+        role: Option<String>,
+        /// Seconds between checks, as currently in effect
+        interval: u64,
+        targets: BTreeMap<String, TargetStats>,
+    },
+    /// The request was passed on to the main loop
+    Accepted {
+        message: String,
+    },
+    Error {
+        message: String,
+    },
+}
+
+/// Requests are small, anything longer is cut short.
+#[cfg(unix)]
+const MAX_REQUEST_BYTES: u64 = 4096;
+
+/// Answers requests on a Unix socket until shut down.
+///
+/// Status requests are answered from the shared counters. Checks and cleanups are only passed on to the main loop,
+/// so it stays the only one changing the prefix lists.
+pub struct ControlServer {
+    path: PathBuf,
+    cleanup: mpsc::Receiver<()>,
+    handle: JoinHandle<()>,
+}
+
+impl ControlServer {
+    /// Fails right away if the socket can't be created, or if another doorman answers on it.
+    ///
+    /// The socket is only readable and writable by the user running doorman.
+    #[cfg(unix)]
+    pub fn start(
+        path: &Path,
+        stats: Stats,
+        recheck: Recheck,
+        pause: Pause,
+        breaker: Breaker,
+    ) -> io::Result<Self> {
+        use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+        use tokio::net::UnixListener;
+        use tracing::{debug, error, info};
+
+        // A socket left behind by a doorman which didn't stop cleanly would make binding fail
+        if let Ok(metadata) = fs::symlink_metadata(path) {
+            if std::os::unix::net::UnixStream::connect(path).is_ok() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("another doorman answers on {}", path.display()),
+                ));
+            }
+            if metadata.file_type().is_socket() {
+                fs::remove_file(path)?;
+            }
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+        info!("Listening for control requests on {}", path.display());
+
+        let (requests, cleanup) = mpsc::channel(1);
+        let handle = tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        let (stats, recheck, pause, breaker, requests) = (
+                            stats.clone(),
+                            recheck.clone(),
+                            pause.clone(),
+                            breaker.clone(),
+                            requests.clone(),
+                        );
+                        tokio::spawn(async move {
+                            if let Err(err) =
+                                respond(stream, &stats, &recheck, &pause, &breaker, &requests).await
+                            {
+                                debug!("Failed to answer a control request: {}", err);
+                            }
+                        });
+                    }
+                    Err(err) => error!("Failed to accept a control connection: {}", err),
+                }
+            }
+        });
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            cleanup,
+            handle,
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn start(
+        _path: &Path,
+        _stats: Stats,
+        _recheck: Recheck,
+        _pause: Pause,
+        _breaker: Breaker,
+    ) -> io::Result<Self> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "the control socket is only supported on Unix",
+        ))
+    }
+
+    /// Resolves once a cleanup was requested.
+    pub async fn cleanup_requested(&mut self) {
+        if self.cleanup.recv().await.is_none() {
+            // The server stopped, no request can come anymore
+            std::future::pending::<()>().await;
+        }
+    }
+
+    /// Stops answering and removes the socket.
+    pub fn shutdown(self) {
+        self.handle.abort();
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(unix)]
+async fn respond(
+    stream: tokio::net::UnixStream,
+    stats: &Stats,
+    recheck: &Recheck,
+    pause: &Pause,
+    breaker: &Breaker,
+    requests: &mpsc::Sender<()>,
+) -> io::Result<()> {
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tracing::{debug, info};
+
+    let (read, mut write) = stream.into_split();
+    let mut line = String::new();
+    BufReader::new(read.take(MAX_REQUEST_BYTES))
+        .read_line(&mut line)
+        .await?;
+    let response = match serde_json::from_str::<Request>(&line) {
+        Err(err) => Response::Error {
+            message: format!("invalid request: {}", err),
+        },
+        Ok(request) => {
+            debug!("Received {:?} on the control socket.", request);
+            match request {
+                Request::Status => {
+                    let snapshot = stats.snapshot();
+                    Response::Status {
+                        summary: snapshot.summary(),
+                        ip: snapshot.ip,
+                        paused: snapshot.paused,
+                        circuit_open: snapshot.circuit_open,
+                        role: snapshot.role.map(|role| role.as_str().to_string()),
+                        interval: snapshot.interval.as_secs(),
+                        targets: snapshot.targets,
+                    }
+                }
+                Request::CheckNow => {
+                    recheck.trigger();
+                    Response::Accepted {
+                        message: "checking now".to_string(),
+                    }
+                }
+                Request::ForceUpdate => {
+                    info!("Forced update requested on the control socket.");
+                    recheck.force();
+                    recheck.trigger();
+                    Response::Accepted {
+                        message: "rewriting the entries now".to_string(),
+                    }
+                }
+                // Checking right away applies the change, and updates the status
+                Request::Pause | Request::Resume => {
+                    let paused = matches!(request, Request::Pause);
+                    info!(
+                        "{} on the control socket.",
+                        match paused {
+                            true => "Pause requested",
+                            false => "Resume requested",
+                        }
+                    );
+                    pause.set_manual(paused);
+                    recheck.trigger();
+                    Response::Accepted {
+                        message: match paused {
+                            true => "pausing the prefix list modifications",
+                            false => "resuming the prefix list modifications",
+                        }
+                        .to_string(),
+                    }
+                }
+                Request::ResetBreaker => {
+                    info!("Circuit breaker reset requested on the control socket.");
+                    let message = match breaker.reset() {
+                        true => "closing the circuit, trying the prefix lists now",
+                        false => "the circuit wasn't open, checking now",
+                    };
+                    recheck.trigger();
+                    Response::Accepted {
+                        message: message.to_string(),
+                    }
+                }
+                Request::Cleanup => {
+                    info!("Cleanup requested on the control socket.");
+                    // A full channel means a cleanup is already on its way
+                    match requests.try_send(()) {
+                        Err(mpsc::error::TrySendError::Closed(())) => Response::Error {
+                            message: "doorman is already stopping".to_string(),
+                        },
+                        _ => Response::Accepted {
+                            message: "cleaning up and stopping".to_string(),
+                        },
+                    }
+                }
+            }
+        }
+    };
+
+    let mut body = serde_json::to_vec(&response)?;
+    body.push(b'\n');
+    write.write_all(&body).await
+}
+
+/// Sends `request` to the doorman listening on `path` and waits for its answer.
+///
+/// This is used by the `ctl` subcommand, before any runtime is started, so it blocks.
+#[cfg(unix)]
+pub fn send(path: &Path, request: Request) -> io::Result<Response> {
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::UnixStream;
+    use std::time::Duration;
+
+    let mut stream = UnixStream::connect(path)?;
+    stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+    let mut body = serde_json::to_vec(&request)?;
+    body.push(b'\n');
+    stream.write_all(&body)?;
+
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    Ok(serde_json::from_str(&line)?)
+}
+
+#[cfg(not(unix))]
+pub fn send(_path: &Path, _request: Request) -> io::Result<Response> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "the control socket is only supported on Unix",
+    ))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::config::Target;
+    use crate::exit::Exit;
+    use std::os::unix::fs::PermissionsExt;
+    use std::time::Duration;
+    use tempfile::TempDir;
+    use tokio::time::timeout;
+
+    struct Running {
+        _dir: TempDir,
+        path: PathBuf,
+        stats: Stats,
+        recheck: Recheck,
+        pause: Pause,
+        breaker: Breaker,
+        server: ControlServer,
+    }
+
+    fn start() -> Running {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("doorman.sock");
+        let target = Target {
+            name: "office".to_string(),
+            prefix_list_id: "pl-12345678".parse().unwrap(),
+            description: "office".parse().unwrap(),
+            region: None,
+            notify: true,
+        };
+        let stats = Stats::new([&target]);
+        let recheck = Recheck::default();
+        let pause = Pause::new(Vec::new());
+        let breaker = Breaker::new(3, Duration::from_secs(300));
+        let server = ControlServer::start(
+            &path,
+            stats.clone(),
+            recheck.clone(),
+            pause.clone(),
+            breaker.clone(),
+        )
+        .unwrap();
+        Running {
+            _dir: dir,
+            path,
+            stats,
+            recheck,
+            pause,
+            breaker,
+            server,
+        }
+    }
+
+    /// Sends the request as `ctl` does, from a thread of its own since it blocks.
+    async fn ask(path: &Path, request: Request) -> Response {
+        let path = path.to_path_buf();
+        tokio::task::spawn_blocking(move || send(&path, request))
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    /// Whether sending a request fails, such as when nothing listens.
+    async fn ask_fails(path: &Path) -> bool {
+        let path = path.to_path_buf();
+        tokio::task::spawn_blocking(move || send(&path, Request::Status).is_err())
+            .await
+            .unwrap()
+    }
+
+    fn accepted(response: Response) -> String {
+        match response {
+            Response::Accepted { message } => message,
+            other => panic!("expected the request to be accepted, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn status_round_trip() {
+        let running = start();
+        running.stats.ip("192.0.2.1/32".parse().unwrap());
+        running.stats.interval(Duration::from_secs(60));
+        running.pause.set_manual(true);
+
+        match ask(&running.path, Request::Status).await {
+            Response::Status {
+                ip,
+                paused,
+                circuit_open,
+                role,
+                interval,
+                targets,
+                ..
+            } => {
+                assert_eq!(ip, Some("192.0.2.1/32".parse().unwrap()));
+                assert!(!paused, "only a check records the pause");
+                assert!(!circuit_open);
+                assert_eq!(role, None);
+                assert_eq!(interval, 60);
+                assert_eq!(targets["office"].prefix_list_id, "pl-12345678");
+            }
+            other => panic!("expected a status, got {:?}", other),
+        }
+        running.server.shutdown();
+    }
+
+    #[tokio::test]
+    async fn triggers_reach_the_main_loop() {
+        let running = start();
+
+        assert_eq!(
+            accepted(ask(&running.path, Request::CheckNow).await),
+            "checking now"
+        );
+        timeout(Duration::from_secs(1), running.recheck.requested())
+            .await
+            .unwrap();
+        assert!(!running.recheck.take_force());
+
+        accepted(ask(&running.path, Request::ForceUpdate).await);
+        timeout(Duration::from_secs(1), running.recheck.requested())
+            .await
+            .unwrap();
+        assert!(running.recheck.take_force());
+
+        accepted(ask(&running.path, Request::Pause).await);
+        assert!(running.pause.reason().is_some());
+        accepted(ask(&running.path, Request::Resume).await);
+        assert!(running.pause.reason().is_none());
+
+        assert_eq!(
+            accepted(ask(&running.path, Request::ResetBreaker).await),
+            "the circuit wasn't open, checking now"
+        );
+        running.server.shutdown();
+    }
+
+    #[tokio::test]
+    async fn resetting_the_breaker_closes_the_circuit_and_checks() {
+        let running = start();
+        for _ in 0..3 {
+            running.breaker.failed(Exit::AwsUnavailable);
+        }
+        assert!(running.breaker.is_open());
+
+        assert_eq!(
+            accepted(ask(&running.path, Request::ResetBreaker).await),
+            "closing the circuit, trying the prefix lists now"
+        );
+        assert!(!running.breaker.is_open());
+        assert_eq!(running.breaker.failures(), 0);
+        timeout(Duration::from_secs(1), running.recheck.requested())
+            .await
+            .unwrap();
+        running.server.shutdown();
+    }
+
+    #[tokio::test]
+    async fn cleanup_is_passed_on() {
+        let mut running = start();
+        assert_eq!(
+            accepted(ask(&running.path, Request::Cleanup).await),
+            "cleaning up and stopping"
+        );
+        // Asking again before the main loop gets to it changes nothing
+        accepted(ask(&running.path, Request::Cleanup).await);
+        timeout(Duration::from_secs(1), running.server.cleanup_requested())
+            .await
+            .unwrap();
+        running.server.shutdown();
+    }
+
+    #[tokio::test]
+    async fn invalid_requests_are_answered_with_an_error() {
+        let running = start();
+        let path = running.path.clone();
+        let response = tokio::task::spawn_blocking(move || {
+            use std::io::{BufRead, BufReader, Write};
+
+            let mut stream = std::os::unix::net::UnixStream::connect(&path).unwrap();
+            stream.write_all(b"{\"command\": \"explode\"}\n").unwrap();
+            let mut line = String::new();
+            BufReader::new(stream).read_line(&mut line).unwrap();
+            serde_json::from_str::<Response>(&line).unwrap()
+        })
+        .await
+        .unwrap();
+        match response {
+            Response::Error { message } => {
+                assert!(message.starts_with("invalid request: "), "{}", message)
+            }
+            other => panic!("expected an error, got {:?}", other),
+        }
+        running.server.shutdown();
+    }
+
+    #[tokio::test]
+    async fn socket_is_private_and_removed_on_shutdown() {
+        let running = start();
+        let mode = fs::metadata(&running.path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        let path = running.path.clone();
+        running.server.shutdown();
+        assert!(!path.exists());
+        assert!(ask_fails(&path).await);
+    }
+
+    #[tokio::test]
+    async fn another_doorman_keeps_its_socket() {
+        let running = start();
+        let err = ControlServer::start(
+            &running.path,
+            running.stats.clone(),
+            running.recheck.clone(),
+            running.pause.clone(),
+            Breaker::new(3, Duration::from_secs(300)),
+        )
+        .err()
+        .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+        running.server.shutdown();
+    }
+
+    #[tokio::test]
+    async fn a_stale_socket_is_replaced() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("doorman.sock");
+        // Left behind by a doorman which didn't stop cleanly
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+
+        let server = ControlServer::start(
+            &path,
+            Stats::new([]),
+            Recheck::default(),
+            Pause::new(Vec::new()),
+            Breaker::new(3, Duration::from_secs(300)),
+        )
+        .unwrap();
+        accepted(ask(&path, Request::CheckNow).await);
+        server.shutdown();
+    }
+}
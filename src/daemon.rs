@@ -0,0 +1,29 @@
+use color_eyre::{eyre::WrapErr, Result};
+use daemonize::Daemonize;
+use std::env;
+use std::fs::{File, OpenOptions};
+use std::path::Path;
+
+/// Forks into the background and detaches from the terminal, writing the child's PID to `pid_file` if given.
+///
+/// Standard output and error go to `log_file`, so panics and errors end up next to the logs. The working directory
+/// is kept, so relative paths given on the command line still work. This must run before the Tokio runtime starts.
+pub fn daemonize(log_file: &Path, pid_file: Option<&Path>) -> Result<()> {
+    let mut daemon = Daemonize::new()
+        .working_directory(env::current_dir()?)
+        .umask(0o027u32)
+        .stdout(open_log(log_file)?)
+        .stderr(open_log(log_file)?);
+    if let Some(pid_file) = pid_file {
+        daemon = daemon.pid_file(pid_file).chown_pid_file(false);
+    }
+    daemon.start().wrap_err("Failed to run in the background")
+}
+
+fn open_log(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .wrap_err_with(|| format!("Failed to open the log file {}", path.display()))
+}
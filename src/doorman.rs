@@ -0,0 +1,3009 @@
+use crate::aws::{AWSClient, Entry, EntryFilter, OperationContext};
+use crate::breaker::Breaker;
+use crate::cidr::HostCidr;
+use crate::config::{Config, ConfigFile, SummaryEvery, Target};
+use crate::connectivity::Connectivity;
+use crate::control::ControlServer;
+use crate::exit::Exit;
+use crate::health::Health;
+use crate::leader::{Election, Role};
+use crate::mqtt::MqttPublisher;
+use crate::notification::{Dispatcher, EventKind, NotificationEvent};
+use crate::pause::Pause;
+use crate::recheck::Recheck;
+use crate::reload::Reload;
+use crate::schedule::{Outcome, Schedule};
+use crate::shutdown::{Shutdown, ShutdownSignal};
+use crate::simulate::{Addresses, IpSource};
+use crate::state::State;
+use crate::stats::Stats;
+use crate::status::{StatusFile, TargetStatus};
+use crate::systemd::Systemd;
+
+use aws_sdk_ec2::model::{ManagedPrefixList, PrefixListState};
+use aws_sdk_ec2::Region;
+use chrono::{DateTime, Local, Utc};
+use color_eyre::{eyre::eyre, Report, Result};
+use ipnet::IpNet;
+use std::cell::Cell;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::Path;
+use tokio::time::{sleep, timeout, timeout_at, Duration, Instant};
+use tracing::{debug, debug_span, error, field, info, info_span, warn, Instrument, Span};
+
+/// A target along with the client managing it.
+pub struct TargetClient {
+    pub target: Target,
+    pub aws: AWSClient,
+    /// The region the client resolved to
+    pub region: Option<Region>,
+}
+
+/// What a doorman is started with, as set up by `work`.
+pub struct Parts {
+    pub config: Config,
+    pub targets: Vec<TargetClient>,
+    /// What doorman knows of each target, in the same order
+    pub syncs: Vec<TargetSync>,
+    pub notifier: Dispatcher,
+    pub stats: Stats,
+    pub health: Health,
+    pub recheck: Recheck,
+    pub reload: Reload,
+    pub pause: Pause,
+    pub breaker: Breaker,
+    pub election: Option<Election>,
+    /// The role found when starting, when running as a redundant pair
+    pub role: Option<Role>,
+    pub schedule: Schedule,
+    pub state: State,
+    pub status_file: Option<StatusFile>,
+    pub systemd: Systemd,
+    pub mqtt: Option<MqttPublisher>,
+    pub connectivity: Option<Connectivity>,
+    pub ip_source: Box<dyn IpSource>,
+    pub current_cidr: Option<HostCidr>,
+    pub last_update: Option<DateTime<Local>>,
+}
+
+/// Keeps the prefix lists up to date with the external IP, one check at a time.
+pub struct Doorman {
+    config: Config,
+    targets: Vec<TargetClient>,
+    /// What doorman knows of each target, in the same order
+    syncs: Vec<TargetSync>,
+    notifier: Dispatcher,
+    stats: Stats,
+    health: Health,
+    recheck: Recheck,
+    reload: Reload,
+    pause: Pause,
+    breaker: Breaker,
+    election: Option<Election>,
+    /// The role last acted on, when running as a redundant pair
+    role: Option<Role>,
+    schedule: Schedule,
+    state: State,
+    status_file: Option<StatusFile>,
+    systemd: Systemd,
+    mqtt: Option<MqttPublisher>,
+    connectivity: Option<Connectivity>,
+    ip_source: Box<dyn IpSource>,
+    current_cidr: Option<HostCidr>,
+    last_update: Option<DateTime<Local>>,
+    /// Verification passes failed in a row
+    verify_failures: u32,
+    /// Whether the last check left the prefix lists alone
+    paused: bool,
+    /// Whether the last check only found an IPv6 address, keeping the entries as they were
+    v4_unavailable: bool,
+    /// Whether the last check found an IPv4 address, whatever the state file had
+    found_ip: bool,
+    /// When the network was found down, if it still is
+    offline_since: Option<Instant>,
+    /// How many checks were started
+    ticks: u64,
+    /// When the hourly summary is next logged
+    next_summary: Instant,
+}
+
+impl Doorman {
+    pub fn new(parts: Parts) -> Self {
+        let Parts {
+            config,
+            targets,
+            syncs,
+            notifier,
+            stats,
+            health,
+            recheck,
+            reload,
+            pause,
+            breaker,
+            election,
+            role,
+            schedule,
+            state,
+            status_file,
+            systemd,
+            mqtt,
+            connectivity,
+            ip_source,
+            current_cidr,
+            last_update,
+        } = parts;
+        Self {
+            config,
+            targets,
+            syncs,
+            notifier,
+            stats,
+            health,
+            recheck,
+            reload,
+            pause,
+            breaker,
+            election,
+            role,
+            schedule,
+            state,
+            status_file,
+            systemd,
+            mqtt,
+            connectivity,
+            ip_source,
+            current_cidr,
+            last_update,
+            verify_failures: 0,
+            paused: false,
+            v4_unavailable: false,
+            found_ip: false,
+            offline_since: None,
+            ticks: 0,
+            next_summary: Instant::now() + SUMMARY_INTERVAL,
+        }
+    }
+
+    /// Checks the IP on schedule until asked to stop, returning why.
+    pub async fn run(
+        &mut self,
+        mut signals: impl Shutdown,
+        control: &mut Option<ControlServer>,
+    ) -> Result<ShutdownSignal> {
+        let shutdown_timeout = Duration::from_secs(self.config.shutdown_timeout);
+        loop {
+            tokio::select! {
+                _ = next_check(&mut self.schedule, &self.recheck) => {
+                    let check = self.tick();
+                    tokio::pin!(check);
+
+                    // Cleaning up while the entry is being modified would race with the modification
+                    tokio::select! {
+                        result = &mut check => result?,
+                        signal = signals.recv() => {
+                            exit_on_next_signal(signals);
+                            info!("Received {}. Letting the update in progress finish, press Ctrl-C again to exit right away...", signal);
+                            match timeout(shutdown_timeout, &mut check).await {
+                                Ok(Ok(())) => debug!("The update in progress finished."),
+                                Ok(Err(err)) => warn!("The update in progress failed: {:#}", err),
+                                Err(_) => warn!("The update in progress didn't finish in time, cleaning up anyway."),
+                            }
+                            return Ok(signal);
+                        }
+                    }
+                }
+                signal = signals.recv() => {
+                    exit_on_next_signal(signals);
+                    return Ok(signal);
+                }
+                _ = self.reload.requested() => self.reload_config(),
+                _ = cleanup_requested(control) => {
+                    exit_on_next_signal(signals);
+                    return Ok(ShutdownSignal::ControlSocket);
+                }
+            }
+        }
+    }
+
+    /// Applies the notification templates and least severe events from the configuration file again.
+    ///
+    /// The targets are only read when starting, changing them still takes a restart. An invalid file is only warned
+    /// about, the settings in use staying as they are.
+    fn reload_config(&mut self) {
+        self.systemd.reloading();
+        match &self.config.config_file {
+            None => info!("There is no configuration file to reload."),
+            Some(path) => match ConfigFile::read(path, self.config.target_selection.clone()) {
+                Ok(loaded) => {
+                    // With --per-host, the description in use follows the one in the file with the hostname
+                    let same_targets =
+                        loaded.targets.len() == self.config.targets.len()
+                            && loaded.targets.iter().zip(&self.config.targets).all(
+                                |(read, used)| {
+                                    read.name == used.name
+                                        && read.prefix_list_id == used.prefix_list_id
+                                        && read.description.owns(used.description.as_str())
+                                        && read.region == used.region
+                                        && read.notify == used.notify
+                                },
+                            );
+                    if !same_targets {
+                        warn!(
+                            "The targets in {} changed, restart doorman to apply them.",
+                            path.display()
+                        );
+                    }
+                    self.notifier.reload(loaded.templates, loaded.min_levels);
+                    info!(
+                        "Reloaded the notification settings from {}.",
+                        path.display()
+                    );
+                }
+                Err(err) => warn!(
+                    "Failed to reload the configuration, keeping the current one: {}",
+                    err
+                ),
+            },
+        }
+        self.systemd
+            .reloaded(&status(self.current_cidr, self.last_update));
+    }
+
+    /// Checks the external IP once, updating the prefix lists if it changed or someone else modified them.
+    ///
+    /// This only fails when doorman should stop.
+    async fn tick(&mut self) -> Result<()> {
+        self.ticks += 1;
+        let tick = self.ticks;
+        let span = info_span!("check", tick, ip = field::Empty);
+        self.check(tick).instrument(span).await
+    }
+
+    /// Whether the summary should be logged before this check.
+    fn summary_due(&mut self, tick: u64) -> bool {
+        match self.config.summary_every {
+            SummaryEvery::Hour => {
+                let now = Instant::now();
+                if now < self.next_summary {
+                    return false;
+                }
+                self.next_summary = now + SUMMARY_INTERVAL;
+                true
+            }
+            SummaryEvery::Checks(checks) => tick.is_multiple_of(checks),
+            SummaryEvery::Never => false,
+        }
+    }
+
+    async fn check(&mut self, tick: u64) -> Result<()> {
+        if self.summary_due(tick) {
+            info!("{}", self.stats.snapshot().summary());
+        }
+        self.health.tick();
+        self.found_ip = false;
+        if !self.online().await {
+            return Ok(());
+        }
+        // Updates are timed from here, as that's when a new IP starts being waited for
+        let detecting = Instant::now();
+        match self.ip_source.addresses().await {
+            Err(err) => {
+                error!("Failed to retrieve external IP: {}", err);
+                self.health.failed(&err);
+                self.notifier
+                    .dispatch(&NotificationEvent::ip_lookup_failed(&err));
+                self.lookup_failed(format!("Failed to retrieve external IP: {}", err));
+            }
+            Ok(Addresses {
+                v4: None,
+                v6: Some(v6),
+            }) => self.v4_unavailable(v6),
+            Ok(Addresses { v4: None, .. }) => {
+                error!("Failed to retrieve external IP. None found...");
+                self.health.failed("No IP found");
+                self.notifier
+                    .dispatch(&NotificationEvent::ip_lookup_failed("No IP found..."));
+                self.lookup_failed("Failed to retrieve external IP: none found".to_string());
+            }
+            Ok(Addresses {
+                v4: Some(new_ip), ..
+            }) => return self.found(tick, new_ip, detecting).await,
+        }
+        Ok(())
+    }
+
+    /// Whether the network is up, always the case without an offline check.
+    async fn online(&mut self) -> bool {
+        let connectivity = match &self.connectivity {
+            Some(connectivity) => connectivity,
+            None => return true,
+        };
+        match connectivity.check().await {
+            Err(err) => {
+                // Failures are expected while offline, so nothing is tried nor notified
+                if self.offline_since.is_none() {
+                    warn!(
+                        "Offline, {}. Skipping the checks until the network is back.",
+                        err
+                    );
+                    self.offline_since = Some(Instant::now());
+                    self.health.offline(true);
+                }
+                self.systemd.checked(&format!(
+                    "Offline. {}",
+                    status(self.current_cidr, self.last_update)
+                ));
+                false
+            }
+            Ok(()) => {
+                if let Some(since) = self.offline_since.take() {
+                    info!(
+                        "Back online after {} seconds, reconciling the prefix lists.",
+                        since.elapsed().as_secs()
+                    );
+                    self.health.offline(false);
+                    self.reconcile();
+                }
+                true
+            }
+        }
+    }
+
+    /// Looks at the entries on the next update whatever the version, as they may have changed meanwhile.
+    fn reconcile(&mut self) {
+        for sync in &mut self.syncs {
+            sync.prefix_list.version = None;
+        }
+    }
+
+    /// No IP was found, which `failure` tells about in the status file.
+    fn lookup_failed(&mut self, failure: String) {
+        self.stats.ip_detection_failed();
+        if let Some(status_file) = self.status_file.as_mut() {
+            status_file.checked(
+                self.current_cidr.map(|cidr| cidr.addr()),
+                Some(failure),
+                self.last_update.map(|at| at.with_timezone(&Utc)),
+                target_statuses(&self.targets, &self.syncs),
+            );
+        }
+        self.systemd.checked(&format!(
+            "Failed to retrieve external IP. {}",
+            status(self.current_cidr, self.last_update)
+        ));
+        adapt(&mut self.schedule, &self.stats, Outcome::Failed);
+    }
+
+    /// Only `v6` was found, which isn't a failure: the network works, it just can't be given to the IPv4 prefix lists.
+    fn v4_unavailable(&mut self, v6: Ipv6Addr) {
+        if !self.v4_unavailable {
+            warn!("Only found the IPv6 address {}, while doorman only manages IPv4 entries. Leaving the entries for {} alone until an IPv4 address is found again.", v6, self.current_cidr.map_or_else(|| "no IP".to_string(), |cidr| cidr.to_string()));
+            self.v4_unavailable = true;
+        }
+        self.notifier.recovered(EventKind::IpLookupFailed, None);
+        self.health.checked(None);
+        if let Some(status_file) = self.status_file.as_mut() {
+            status_file.degraded(Some("v4 unavailable".to_string()));
+            status_file.checked(
+                self.current_cidr.map(|cidr| cidr.addr()),
+                None,
+                self.last_update.map(|at| at.with_timezone(&Utc)),
+                target_statuses(&self.targets, &self.syncs),
+            );
+        }
+        self.systemd.checked(&format!(
+            "Degraded: v4 unavailable. {}",
+            status(self.current_cidr, self.last_update)
+        ));
+        adapt(&mut self.schedule, &self.stats, Outcome::Stable);
+    }
+
+    /// Brings the prefix lists to `new_ip`, unless paused or following.
+    async fn found(&mut self, tick: u64, new_ip: Ipv4Addr, detecting: Instant) -> Result<()> {
+        self.found_ip = true;
+        self.notifier.recovered(EventKind::IpLookupFailed, None);
+        if self.v4_unavailable {
+            info!("Found an IPv4 address again.");
+            self.v4_unavailable = false;
+            if let Some(status_file) = self.status_file.as_mut() {
+                status_file.degraded(None);
+            }
+        }
+
+        // A v4 address always makes a /32
+        let new_cidr = HostCidr::from(IpAddr::V4(new_ip));
+        Span::current().record("ip", field::display(new_cidr));
+
+        self.stats.ip(new_cidr.net());
+        if Some(new_cidr) == self.current_cidr {
+            debug!("External IP didn't change.");
+        }
+
+        if self.pausing(new_cidr) || !self.leading(new_cidr) {
+            return Ok(());
+        }
+        let (failure, updated) = self.update_targets(tick, new_cidr, detecting).await?;
+        if self
+            .config
+            .verify_every
+            .is_some_and(|every| tick.is_multiple_of(every))
+        {
+            self.verify_entries(new_cidr).await;
+        }
+        if let (true, Some(mqtt)) = (updated, &self.mqtt) {
+            mqtt.publish(&new_cidr.net());
+        }
+        self.checked(new_cidr, failure, updated);
+        Ok(())
+    }
+
+    /// Whether the prefix lists are left alone for now, during a pause window.
+    fn pausing(&mut self, new_cidr: HostCidr) -> bool {
+        if let Some((reason, ends_in)) = self.pause.reason() {
+            if !self.paused {
+                info!("Pausing the prefix list modifications, {}.", reason);
+                // Reconciling as soon as the window ends, rather than on the next tick
+                if let Some(ends_in) = ends_in {
+                    let recheck = self.recheck.clone();
+                    tokio::spawn(async move {
+                        sleep(ends_in).await;
+                        recheck.trigger();
+                    });
+                }
+            }
+            self.paused = true;
+            self.stats.paused(true);
+            info!(
+                "External IP is {}, leaving the prefix lists alone while paused.",
+                new_cidr
+            );
+            self.systemd.checked(&format!(
+                "Paused {}. {}",
+                reason,
+                status(self.current_cidr, self.last_update)
+            ));
+            return true;
+        }
+        if self.paused {
+            info!(
+                "Resuming the prefix list modifications, reconciling them with {}.",
+                new_cidr
+            );
+            self.paused = false;
+            self.stats.paused(false);
+            self.reconcile();
+        }
+        false
+    }
+
+    /// Whether this doorman modifies the prefix lists, which only the leader of a redundant pair does.
+    fn leading(&mut self, new_cidr: HostCidr) -> bool {
+        let election = match &self.election {
+            Some(election) => election,
+            None => return true,
+        };
+        let role = election.role();
+        if Some(role) != self.role {
+            match role {
+                Role::Leader => info!(
+                    "Now leading, as {}. Replacing the previous leader's entries with {}.",
+                    election.owner(),
+                    new_cidr
+                ),
+                Role::Follower => info!("Now following, another doorman modifies the prefix lists."),
+                Role::Unknown => warn!("Can't tell whether this doorman leads, leaving the prefix lists alone until the lease can be renewed."),
+            }
+            self.notifier
+                .dispatch(&NotificationEvent::leadership_changed(
+                    election.owner(),
+                    role,
+                ));
+            if role == Role::Leader {
+                self.recheck.force();
+            }
+            self.role = Some(role);
+            self.stats.role(role);
+        }
+        if role == Role::Leader {
+            return true;
+        }
+        self.health.checked(None);
+        if let Some(status_file) = self.status_file.as_mut() {
+            status_file.checked(
+                self.current_cidr.map(|cidr| cidr.addr()),
+                None,
+                self.last_update.map(|at| at.with_timezone(&Utc)),
+                target_statuses(&self.targets, &self.syncs),
+            );
+        }
+        self.systemd.checked(&format!(
+            "{}. {}",
+            match role {
+                Role::Follower => "Following",
+                _ => "Leadership unknown",
+            },
+            status(self.current_cidr, self.last_update)
+        ));
+        adapt(&mut self.schedule, &self.stats, Outcome::Stable);
+        false
+    }
+
+    /// Updates each target due for it, returning the last error if any, and whether an entry now holds `new_cidr`.
+    ///
+    /// This only fails when doorman should stop.
+    async fn update_targets(
+        &mut self,
+        tick: u64,
+        new_cidr: HostCidr,
+        detecting: Instant,
+    ) -> Result<(Option<String>, bool)> {
+        // Taken once the IP is known, so a failed lookup doesn't lose the request
+        let forced = self.recheck.take_force();
+        // The last error of this check, if any
+        let mut failure = None;
+        let mut updated = false;
+        // The kind of the last failure to modify a prefix list, and whether any was tried
+        let mut failed_with = None;
+        let mut attempted = false;
+        let probing = self.breaker.is_open();
+        let blocked = !self.breaker.allows();
+        if blocked {
+            debug!("Circuit open after {} failure(s) in a row, leaving the prefix lists alone until the next probe.", self.breaker.failures());
+        } else if probing {
+            info!("Circuit open, probing the prefix lists.");
+        }
+        let Self {
+            config,
+            targets,
+            syncs,
+            notifier,
+            stats,
+            state,
+            ..
+        } = self;
+        for (target, sync) in targets.iter_mut().zip(syncs.iter_mut()) {
+            let name = &target.target.name;
+            if blocked {
+                failure = failure.or_else(|| sync.last_error.clone());
+                continue;
+            }
+            if tick < sync.retry_at {
+                // The request would be gone by the time the backoff is over
+                if !forced {
+                    debug!("[{}] Backing off after {} failure(s).", name, sync.failures);
+                    failure = sync.last_error.clone();
+                    continue;
+                }
+                info!(
+                    "[{}] Forced to try again despite {} failure(s).",
+                    name, sync.failures
+                );
+            }
+            attempted = true;
+
+            let old_cidr = sync.cidr;
+            let started = Instant::now();
+            let span = debug_span!(
+                "target",
+                name = target.target.name.as_str(),
+                prefix_list_id = target.target.prefix_list_id.as_str(),
+                failures = sync.failures
+            );
+            let attempt = sync.failures + 1;
+            let result = sync_target(target, sync, new_cidr, forced, config, notifier, state)
+                .instrument(span)
+                .await
+                .map_err(|mut err| {
+                    OperationContext::retried(&mut err, attempt);
+                    err
+                });
+            let name = &target.target.name;
+            match result {
+                Ok(()) => {
+                    sync.succeeded();
+                    notifier.recovered(EventKind::UpdateFailed, Some(name));
+                    // Someone else's entry holds the IP, doorman's own is added once it's gone
+                    if sync.foreign.is_some() {
+                        continue;
+                    }
+                    if old_cidr == Some(new_cidr) {
+                        stats.in_sync(&target.target, sync.prefix_list.version);
+                        continue;
+                    }
+                    info!("[{}] Updated prefix list IP to {}", name, new_cidr);
+                    let took = detecting.elapsed();
+                    stats.update_took(took);
+                    if took > Duration::from_secs(config.slow_update) {
+                        warn!("[{}] The update took {:.1} seconds, longer than {} seconds. Look at the AWS operations' latency in the metrics or the debug logs.", name, took.as_secs_f64(), config.slow_update);
+                    }
+                    stats.ip_changed(&target.target, sync.prefix_list.version);
+                    if target.target.notify {
+                        notifier.dispatch(&NotificationEvent::updated(
+                            &target.target,
+                            old_cidr.map(|cidr| cidr.net()),
+                            new_cidr.net(),
+                            sync.prefix_list.version,
+                            started.elapsed(),
+                        ));
+                    }
+                    updated = true;
+                }
+                Err(err) if is_fatal(&err, attempt) => {
+                    notifier.dispatch(&match Exit::of(&err) {
+                        Exit::PrefixListNotFound => {
+                            NotificationEvent::prefix_list_deleted(&target.target, None)
+                        }
+                        _ => NotificationEvent::update_failed(&target.target, &err),
+                    });
+                    // Stopping right after would drop the notification
+                    notifier.shutdown().await;
+                    return Err(err.wrap_err(format!("[{}] Giving up", name)));
+                }
+                Err(err) => {
+                    sync.failed(tick, name, &err);
+                    error!("[{}] Failed to update the prefix list, {} time(s) in a row, retrying in {} check(s): {:#}", name, sync.failures, sync.retry_at - tick, err);
+                    // While the circuit is open, the failures are only told about as a whole
+                    if !probing {
+                        notifier.dispatch(&NotificationEvent::update_failed(&target.target, &err));
+                    }
+                    stats.update_failed(&target.target);
+                    failure = sync.last_error.clone();
+                    failed_with = Some(Exit::of(&err));
+                }
+            }
+        }
+        match (failed_with, &failure) {
+            (Some(class), Some(error)) => {
+                let opened = self.breaker.failed(class);
+                if opened {
+                    warn!("Failed to modify the prefix lists {} checks in a row, only trying again every {} seconds.", self.breaker.failures(), self.breaker.probe_every().as_secs());
+                }
+                // Failed probes are reminders, which the dispatcher spaces out
+                if opened || probing {
+                    self.notifier.dispatch(&NotificationEvent::circuit_open(
+                        error,
+                        self.breaker.failures(),
+                        self.breaker.probe_every(),
+                    ));
+                }
+            }
+            (None, None) if attempted => {
+                if self.breaker.reset() {
+                    info!("Modified the prefix lists again, closing the circuit.");
+                }
+                self.notifier.recovered(EventKind::CircuitOpen, None);
+            }
+            _ => {}
+        }
+        self.stats.circuit_open(self.breaker.is_open());
+        Ok((failure, updated))
+    }
+
+    /// Checks that every prefix list holds `new_cidr`, a single failure being possibly a change still propagating.
+    async fn verify_entries(&mut self, new_cidr: HostCidr) {
+        let problem = verify(&self.targets, &new_cidr).await;
+        match &problem {
+            None => {
+                debug!("Verified that the prefix lists hold {}.", new_cidr);
+                self.verify_failures = 0;
+                self.stats.verified();
+                self.notifier.recovered(EventKind::VerificationFailed, None);
+            }
+            Some(problem) => {
+                self.verify_failures += 1;
+                warn!(
+                    "Verification failed, {} time(s) in a row: {}",
+                    self.verify_failures, problem
+                );
+                if self.verify_failures > 1 {
+                    self.notifier
+                        .dispatch(&NotificationEvent::verification_failed(
+                            problem,
+                            self.verify_failures,
+                        ));
+                }
+            }
+        }
+        self.health.verified(problem.clone());
+        if let Some(status_file) = self.status_file.as_mut() {
+            status_file.verified(problem);
+        }
+        if self.config.takeover {
+            collapse_duplicates(
+                &self.targets,
+                &mut self.syncs,
+                Some(new_cidr),
+                &mut self.state,
+                Duration::from_secs(self.config.wait_timeout),
+            )
+            .await;
+        }
+    }
+
+    /// Records how the check with `new_cidr` went, `updated` if an entry now holds it.
+    fn checked(&mut self, new_cidr: HostCidr, failure: Option<String>, updated: bool) {
+        self.health.checked(failure.clone());
+        let outcome = match (&failure, Some(new_cidr) == self.current_cidr) {
+            (Some(_), _) => Outcome::Failed,
+            (None, true) => Outcome::Stable,
+            (None, false) => Outcome::Changed,
+        };
+        adapt(&mut self.schedule, &self.stats, outcome);
+        self.current_cidr = Some(new_cidr);
+        if updated {
+            self.last_update = Some(Local::now());
+            self.state.cidr = self.current_cidr;
+        }
+        self.state.queued(self.notifier.pending());
+        if let (true, Some(path)) = (updated || self.state.changed(), &self.config.state_file) {
+            self.state.save(path);
+        }
+        if let Some(status_file) = self.status_file.as_mut() {
+            status_file.checked(
+                self.current_cidr.map(|cidr| cidr.addr()),
+                failure,
+                self.last_update.map(|at| at.with_timezone(&Utc)),
+                target_statuses(&self.targets, &self.syncs),
+            );
+        }
+        self.systemd
+            .checked(&status(self.current_cidr, self.last_update));
+    }
+
+    /// Checks the IP a single time, leaving the entries for the next run to take over through the state file.
+    ///
+    /// Unlike the checks of [`Doorman::run`], this fails if the prefix lists couldn't be updated.
+    pub async fn once(&mut self) -> Result<()> {
+        self.tick().await?;
+        if let Some(mqtt) = &self.mqtt {
+            mqtt.shutdown().await;
+        }
+        self.notifier.shutdown().await;
+        self.state.queued(self.notifier.pending());
+        if let (true, Some(path)) = (self.state.changed(), &self.config.state_file) {
+            self.state.save(path);
+        }
+        // The state file may have given an IP this run never found
+        if !self.found_ip {
+            return Err(eyre!("Failed to retrieve the external IP."));
+        }
+        match self.syncs.iter().find_map(|sync| sync.last_error.as_ref()) {
+            Some(error) => Err(eyre!("Failed to update the prefix lists: {}", error)),
+            None => Ok(()),
+        }
+    }
+
+    /// Removes the entries after `signal`, within the shutdown timeout.
+    pub async fn stop(mut self, signal: ShutdownSignal) -> CleanupReport {
+        info!(
+            "Received {}. Cleaning up, press Ctrl-C again to exit right away...",
+            signal
+        );
+        self.systemd.stopping();
+        if let Some(mqtt) = &self.mqtt {
+            mqtt.shutdown().await;
+        }
+        // Entries for other IPs carrying the description weren't written by this instance
+        let ours: Option<Vec<IpNet>> = match self.config.takeover {
+            true => None,
+            false => Some(
+                self.syncs
+                    .iter()
+                    .filter_map(|sync| sync.cidr.map(|cidr| cidr.net()))
+                    .collect(),
+            ),
+        };
+        let leading = self
+            .election
+            .as_ref()
+            .is_none_or(|election| election.role() == Role::Leader);
+        let report = match leading {
+            true => {
+                cleanup(
+                    &self.targets,
+                    ours.as_deref(),
+                    &self.notifier,
+                    Duration::from_secs(self.config.shutdown_timeout),
+                )
+                .await
+            }
+            // The entries are the leader's
+            false => CleanupReport {
+                lines: vec!["Not the leader, leaving the entries alone.".to_string()],
+                failed: Vec::new(),
+                versions: Vec::new(),
+            },
+        };
+        // Once the entries are gone, so the other doorman adds its own
+        if let Some(election) = &self.election {
+            election.release().await;
+        }
+        self.notifier.dispatch(&NotificationEvent::shutdown(format!(
+            "Received {}.\n{}",
+            signal,
+            report.summary()
+        )));
+        self.notifier.shutdown().await;
+        if let Some(path) = &self.config.state_file {
+            self.state.queued(self.notifier.pending());
+            report.save(&mut self.state, path);
+        }
+        report
+    }
+}
+
+/// How many authentication failures in a row are tolerated, e.g. while credentials are being rotated.
+const MAX_AUTH_FAILURES: u32 = 3;
+/// Most checks skipped before trying a failing target again.
+const MAX_BACKOFF_CHECKS: u64 = 8;
+/// How often the summary is logged, unless `--summary-every` counts checks.
+const SUMMARY_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Where a target stands, as far as doorman knows.
+pub struct TargetSync {
+    pub prefix_list: ManagedPrefixList,
+    /// The IP doorman put in the prefix list
+    pub cidr: Option<HostCidr>,
+    /// Someone else's entry for the current IP, which stands in for doorman's own
+    foreign: Option<Entry>,
+    /// When doorman last wrote its entry, which tells when the expiry needs refreshing
+    written_at: Option<DateTime<Utc>>,
+    /// Whether to take over someone else's entry for the current IP, until the target was first reconciled
+    pub adopt: bool,
+    /// How many times in a row updating the target failed
+    failures: u32,
+    /// The target isn't tried again before this check
+    retry_at: u64,
+    last_error: Option<String>,
+}
+
+impl TargetSync {
+    pub fn new(prefix_list: ManagedPrefixList) -> Self {
+        Self {
+            prefix_list,
+            cidr: None,
+            foreign: None,
+            written_at: None,
+            adopt: false,
+            failures: 0,
+            retry_at: 0,
+            last_error: None,
+        }
+    }
+
+    fn succeeded(&mut self) {
+        // Only entries found when starting are adopted
+        self.adopt = false;
+        self.failures = 0;
+        self.retry_at = 0;
+        self.last_error = None;
+    }
+
+    /// Waits twice as many checks after each failure in a row, up to [`MAX_BACKOFF_CHECKS`].
+    fn failed(&mut self, tick: u64, name: &str, err: &Report) {
+        self.failures += 1;
+        let backoff = 2u64
+            .saturating_pow(self.failures - 1)
+            .min(MAX_BACKOFF_CHECKS);
+        self.retry_at = tick + backoff;
+        self.last_error = Some(format!("{}: {:#}", name, err));
+    }
+}
+
+/// Whether retrying can't help, so doorman should stop.
+///
+/// This is the case when the prefix list is gone, or when AWS keeps rejecting the credentials. Anything else, such
+/// as timeouts, may go away by itself.
+fn is_fatal(err: &Report, failures: u32) -> bool {
+    match Exit::of(err) {
+        Exit::PrefixListNotFound => true,
+        Exit::AwsAuth => failures >= MAX_AUTH_FAILURES,
+        _ => false,
+    }
+}
+
+/// Adapts the interval to how the check went, keeping the counters up to date.
+fn adapt(schedule: &mut Schedule, stats: &Stats, outcome: Outcome) {
+    schedule.checked(outcome);
+    stats.interval(schedule.current());
+}
+
+/// Resolves on the next tick, or earlier if a check was requested.
+async fn next_check(schedule: &mut Schedule, recheck: &Recheck) {
+    tokio::select! {
+        _ = schedule.tick() => {}
+        _ = recheck.requested() => info!("Checking now, as requested."),
+    }
+}
+
+/// Resolves once a cleanup was requested on the control socket, if there is one.
+async fn cleanup_requested(control: &mut Option<ControlServer>) {
+    match control {
+        Some(control) => control.cleanup_requested().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Brings the target's entry to `new_cidr`, writing it again if `forced`.
+///
+/// A deleted prefix list is recreated if the configuration asks for it.
+async fn sync_target(
+    target: &mut TargetClient,
+    sync: &mut TargetSync,
+    new_cidr: HostCidr,
+    forced: bool,
+    config: &Config,
+    notifier: &Dispatcher,
+    state: &mut State,
+) -> Result<()> {
+    let result = match sync.cidr == Some(new_cidr) {
+        _ if forced => force_entry(target, sync, new_cidr, state).await,
+        true if refresh_due(config.entry_ttl, sync) => {
+            refresh_entry(target, sync, &new_cidr, notifier, state).await
+        }
+        true => restore_entry(target, sync, &new_cidr, notifier, state).await,
+        false => update_entry(target, sync, new_cidr, notifier, state).await,
+    };
+    match result {
+        Err(err) if config.recreate_on_delete && Exit::of(&err) == Exit::PrefixListNotFound => {
+            warn!(
+                "[{}] Prefix list {} was deleted, recreating it...",
+                target.target.name, target.target.prefix_list_id
+            );
+            match recreate(target, sync).await {
+                Ok(()) => {
+                    notifier.dispatch(&NotificationEvent::prefix_list_deleted(
+                        &target.target,
+                        Some(&target.target.prefix_list_id),
+                    ));
+                    saw_version(state, &target.target, sync.prefix_list.version, true);
+                    update_entry(target, sync, new_cidr, notifier, state).await
+                }
+                Err(recreate_err) => {
+                    Err(recreate_err.wrap_err("Failed to recreate the deleted prefix list"))
+                }
+            }
+        }
+        result => result,
+    }
+}
+
+/// Puts the target's entry back if someone else removed it, unless they replaced it with their own.
+///
+/// Listing the entries is only needed when the prefix list changed since we last saw it.
+async fn restore_entry(
+    target: &TargetClient,
+    sync: &mut TargetSync,
+    cidr: &HostCidr,
+    notifier: &Dispatcher,
+    state: &mut State,
+) -> Result<()> {
+    let TargetClient { target, aws, .. } = target;
+    let latest = aws.get_prefix_list(&target.prefix_list_id).await?;
+    saw_version(state, target, latest.version, false);
+    if latest.version == sync.prefix_list.version {
+        return Ok(());
+    }
+    if aws.has_owned_entry(&latest, &cidr.net()).await? {
+        sync.prefix_list = latest;
+        return Ok(());
+    }
+    // Whoever removed ours may have added their own, which doorman waits for to go away
+    let standing_in = sync
+        .foreign
+        .as_ref()
+        .is_some_and(|entry| entry.cidr == cidr.net());
+    let mut remove = match foreign_entry(target, aws, &latest, sync, cidr, notifier).await? {
+        Foreign::Waiting => {
+            sync.prefix_list = latest;
+            return Ok(());
+        }
+        Foreign::Adopted(entry) => vec![entry.cidr],
+        Foreign::Absent => {
+            if !standing_in {
+                warn!(
+                    "[{}] Entry for {} was removed from {} by someone else, putting it back.",
+                    target.name, cidr, target.prefix_list_id
+                );
+                notifier.dispatch(&NotificationEvent::entry_removed_externally(
+                    target,
+                    cidr.net(),
+                    sync.prefix_list.version,
+                    latest.version,
+                ));
+            }
+            vec![]
+        }
+    };
+    let room = aws.make_room(&latest, &[cidr], &remove).await?;
+    remove.extend(room);
+    let modified = aws.modify_entries(&latest, vec![cidr], remove).await?;
+    sync.written_at = Some(Utc::now());
+    sync.prefix_list = aws
+        .wait_for_state(
+            &target.prefix_list_id,
+            PrefixListState::ModifyComplete,
+            modified.version,
+        )
+        .await?;
+    saw_version(state, target, sync.prefix_list.version, true);
+    Ok(())
+}
+
+/// Whether the expiry of the target's entry should be refreshed, as less than half of `ttl` seconds is left.
+///
+/// An entry doorman didn't write since starting, or which someone else's stands in for, is left to
+/// [`restore_entry`].
+fn refresh_due(ttl: Option<u64>, sync: &TargetSync) -> bool {
+    match (ttl, sync.written_at, &sync.foreign) {
+        (Some(ttl), Some(at), None) => Utc::now() - at >= chrono::Duration::seconds(ttl as i64 / 2),
+        _ => false,
+    }
+}
+
+/// Rewrites the target's entry for `cidr` with a new expiry, and waits for the change to complete.
+///
+/// Should the entry be gone, it's put back as by [`restore_entry`], which writes a new expiry too.
+async fn refresh_entry(
+    target: &TargetClient,
+    sync: &mut TargetSync,
+    cidr: &HostCidr,
+    notifier: &Dispatcher,
+    state: &mut State,
+) -> Result<()> {
+    let TargetClient {
+        target: config,
+        aws,
+        ..
+    } = target;
+    let latest = aws.get_prefix_list(&config.prefix_list_id).await?;
+    saw_version(state, config, latest.version, false);
+    if !aws.has_owned_entry(&latest, &cidr.net()).await? {
+        // Makes restoring look at the entries, even if the version didn't move
+        sync.prefix_list.version = None;
+        return restore_entry(target, sync, cidr, notifier, state).await;
+    }
+    debug!(
+        "[{}] Refreshing the expiry of the entry for {} in {}.",
+        config.name, cidr, config.prefix_list_id
+    );
+    // Adding the CIDR again only replaces the description of the entry
+    let modified = aws.modify_entries(&latest, vec![cidr], vec![]).await?;
+    sync.written_at = Some(Utc::now());
+    sync.prefix_list = aws
+        .wait_for_state(
+            &config.prefix_list_id,
+            PrefixListState::ModifyComplete,
+            modified.version,
+        )
+        .await?;
+    saw_version(state, config, sync.prefix_list.version, true);
+    Ok(())
+}
+
+/// What doorman does about someone else's entry for the IP it's about to add.
+enum Foreign {
+    /// There's none
+    Absent,
+    /// Doorman waits for it to go away before adding its own
+    Waiting,
+    /// Doorman replaces it with its own, removing it in the same modification
+    Adopted(Entry),
+}
+
+/// Looks for someone else's entry holding `cidr`, which keeps doorman from adding its own unless the target adopts it.
+/// The sync remembers it, so it's only reported when it shows up and goes away.
+async fn foreign_entry(
+    target: &Target,
+    aws: &AWSClient,
+    latest: &ManagedPrefixList,
+    sync: &mut TargetSync,
+    cidr: &HostCidr,
+    notifier: &Dispatcher,
+) -> Result<Foreign> {
+    let entry = aws.foreign_entry(latest, &cidr.net()).await?;
+    let foreign = &mut sync.foreign;
+    match (entry, foreign.take()) {
+        // It's for this very CIDR, so adopting never touches anyone else's access
+        (Some(entry), _) if sync.adopt => {
+            info!(
+                "[{}] Adopting the entry for {} in {}, replacing its description \"{}\" with doorman's.",
+                target.name, cidr, target.prefix_list_id, entry.description
+            );
+            Ok(Foreign::Adopted(entry))
+        }
+        (Some(entry), known) => {
+            if known.as_ref() != Some(&entry) {
+                info!(
+                    "[{}] {} already holds {} as \"{}\", adding doorman's entry once that one is gone.",
+                    target.name, target.prefix_list_id, cidr, entry.description
+                );
+                notifier.dispatch(&NotificationEvent::foreign_entry(
+                    target,
+                    cidr.net(),
+                    &entry.description,
+                ));
+            }
+            *foreign = Some(entry);
+            Ok(Foreign::Waiting)
+        }
+        (None, Some(known)) if known.cidr == cidr.net() => {
+            info!(
+                "[{}] The entry \"{}\" for {} is gone from {}, adding doorman's.",
+                target.name, known.description, cidr, target.prefix_list_id
+            );
+            Ok(Foreign::Absent)
+        }
+        (None, _) => Ok(Foreign::Absent),
+    }
+}
+
+/// Records the version of the target's prefix list, warning when it moved in a way doorman doesn't account for.
+pub fn saw_version(state: &mut State, target: &Target, version: Option<i64>, by_doorman: bool) {
+    let version = match version {
+        Some(version) => version,
+        None => return,
+    };
+    let last = match state.saw_version(target.prefix_list_id.as_str(), version, by_doorman) {
+        Some(last) => last,
+        None => return,
+    };
+    match by_doorman {
+        false => warn!(
+            "[{}] Prefix list {} version went {}→{} without doorman acting.",
+            target.name, target.prefix_list_id, last, version
+        ),
+        // Doorman's own change only accounts for one version
+        true if version > last + 1 => warn!(
+            "[{}] Prefix list {} version went {}→{}, doorman only made one change.",
+            target.name, target.prefix_list_id, last, version
+        ),
+        true => {}
+    }
+}
+
+/// Describes the current state for `systemctl status`.
+fn status(cidr: Option<HostCidr>, last_update: Option<DateTime<Local>>) -> String {
+    format!(
+        "IP {}, last updated {}",
+        cidr.map_or_else(|| "unknown".to_string(), |cidr| cidr.to_string()),
+        last_update.map_or_else(
+            || "never".to_string(),
+            |at| at.format("%Y-%m-%d %H:%M:%S").to_string()
+        )
+    )
+}
+
+/// Checks that each prefix list holds an entry for `cidr` owned by doorman, and isn't being changed or in a failed
+/// state. This returns what's wrong, if anything.
+async fn verify(targets: &[TargetClient], cidr: &HostCidr) -> Option<String> {
+    let mut problems = Vec::new();
+    for TargetClient { target, aws, .. } in targets {
+        let problem = match aws.get_prefix_list(&target.prefix_list_id).await {
+            Ok(prefix_list) => match prefix_list.state {
+                Some(
+                    PrefixListState::CreateComplete
+                    | PrefixListState::ModifyComplete
+                    | PrefixListState::RestoreComplete,
+                ) => match aws.has_owned_entry(&prefix_list, &cidr.net()).await {
+                    Ok(true) => None,
+                    Ok(false) => Some(format!("no entry for {}", cidr)),
+                    Err(err) => Some(format!("failed to list the entries: {:#}", err)),
+                },
+                state => Some(format!(
+                    "the prefix list is {}",
+                    state
+                        .as_ref()
+                        .map_or("in no known state", PrefixListState::as_str)
+                )),
+            },
+            Err(err) => Some(format!("failed to get the prefix list: {:#}", err)),
+        };
+        if let Some(problem) = problem {
+            problems.push(format!("{}: {}", target.name, problem));
+        }
+    }
+    match problems.is_empty() {
+        true => None,
+        false => Some(problems.join(", ")),
+    }
+}
+
+fn target_statuses(targets: &[TargetClient], syncs: &[TargetSync]) -> Vec<TargetStatus> {
+    targets
+        .iter()
+        .zip(syncs)
+        .map(|(target, sync)| TargetStatus {
+            name: target.target.name.clone(),
+            prefix_list_id: target.target.prefix_list_id.to_string(),
+            version: sync.prefix_list.version,
+            failures: sync.failures,
+        })
+        .collect()
+}
+
+/// Exits right away on the next signal, in case cleaning up hangs, e.g. because AWS can't be reached.
+fn exit_on_next_signal(mut signals: impl Shutdown) {
+    tokio::spawn(async move {
+        let signal = signals.recv().await;
+        warn!(
+            "Received {} again, exiting without cleaning up. Entries may be left in the prefix lists.",
+            signal
+        );
+        std::process::exit(Exit::Forced.code());
+    });
+}
+
+/// Recreates the target's deleted prefix list, which doorman manages from then on.
+async fn recreate(target: &mut TargetClient, sync: &mut TargetSync) -> Result<()> {
+    let (prefix_list_id, prefix_list) = target.aws.recreate_prefix_list(&sync.prefix_list).await?;
+    target.target.prefix_list_id = prefix_list_id;
+    warn!(
+        "[{}] Recreated the prefix list as {}, references to the old one must be updated.",
+        target.target.name, target.target.prefix_list_id
+    );
+    *sync = TargetSync::new(prefix_list);
+    Ok(())
+}
+
+/// Rewrites the target's entry for `cidr` whatever the prefix list holds, and waits for the change to complete.
+///
+/// Every entry doorman owns is removed, along with any other entry for `cidr`, e.g. one whose description was mangled
+/// by hand, and a fresh one is added in the same modification.
+async fn force_entry(
+    target: &TargetClient,
+    sync: &mut TargetSync,
+    cidr: HostCidr,
+    state: &mut State,
+) -> Result<()> {
+    let TargetClient { target, aws, .. } = target;
+    let latest = aws.get_prefix_list(&target.prefix_list_id).await?;
+    saw_version(state, target, latest.version, false);
+    let mut remove: Vec<IpNet> = aws
+        .entries(&latest, EntryFilter::Owned)
+        .await?
+        .into_iter()
+        .map(|entry| entry.cidr)
+        .collect();
+    if let Some(entry) = aws.foreign_entry(&latest, &cidr.net()).await? {
+        remove.push(entry.cidr);
+    }
+    info!(
+        "[{}] Forcing the update of {}: removing {}, adding {}.",
+        target.name,
+        target.prefix_list_id,
+        match remove.is_empty() {
+            true => "nothing".to_string(),
+            false => remove
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", "),
+        },
+        cidr
+    );
+    let room = aws.make_room(&latest, &[&cidr], &remove).await?;
+    remove.extend(room);
+    let modified = aws.modify_entries(&latest, vec![&cidr], remove).await?;
+    sync.cidr = Some(cidr);
+    sync.foreign = None;
+    sync.written_at = Some(Utc::now());
+    sync.prefix_list = aws
+        .wait_for_state(
+            &target.prefix_list_id,
+            PrefixListState::ModifyComplete,
+            modified.version,
+        )
+        .await?;
+    saw_version(state, target, sync.prefix_list.version, true);
+    info!(
+        "[{}] Forced the update of {} to {}.",
+        target.name, target.prefix_list_id, cidr
+    );
+    Ok(())
+}
+
+/// Replaces the target's entry with `cidr` and waits for the change to complete.
+async fn update_entry(
+    target: &TargetClient,
+    sync: &mut TargetSync,
+    cidr: HostCidr,
+    notifier: &Dispatcher,
+    state: &mut State,
+) -> Result<()> {
+    let TargetClient { target, aws, .. } = target;
+    // A failed attempt may have changed the version since
+    let latest = aws.get_prefix_list(&target.prefix_list_id).await?;
+    saw_version(state, target, latest.version, false);
+    let mut remove: Vec<IpNet> = sync.cidr.map(|old| old.net()).into_iter().collect();
+    match foreign_entry(target, aws, &latest, sync, &cidr, notifier).await? {
+        // Adding ours would fail. The previous entry is kept until it can be replaced, so the state still tells what
+        // to remove.
+        Foreign::Waiting => {
+            sync.prefix_list = latest;
+            return Ok(());
+        }
+        Foreign::Adopted(entry) => remove.push(entry.cidr),
+        Foreign::Absent => {}
+    }
+    let room = aws.make_room(&latest, &[&cidr], &remove).await?;
+    remove.extend(room);
+    let modified = aws.modify_entries(&latest, vec![&cidr], remove).await?;
+    // The change was accepted, even if it doesn't complete in time. Should waiting fail, the next check finds the
+    // entry through the new version.
+    sync.cidr = Some(cidr);
+    sync.written_at = Some(Utc::now());
+    sync.prefix_list = aws
+        .wait_for_state(
+            &target.prefix_list_id,
+            PrefixListState::ModifyComplete,
+            modified.version,
+        )
+        .await?;
+    saw_version(state, target, sync.prefix_list.version, true);
+    Ok(())
+}
+
+pub fn region_name(region: Option<&Region>) -> String {
+    region.map_or_else(|| "<no region>".to_string(), |region| region.to_string())
+}
+
+/// What cleaning up did for each target.
+pub struct CleanupReport {
+    lines: Vec<String>,
+    /// Names of the targets which couldn't be cleaned up
+    failed: Vec<String>,
+    /// The versions removing the entries led to, per target
+    versions: Vec<(Target, i64)>,
+}
+
+impl CleanupReport {
+    pub fn summary(&self) -> String {
+        self.lines.join("\n")
+    }
+
+    pub fn has_failures(&self) -> bool {
+        !self.failed.is_empty()
+    }
+
+    /// Records the versions in the state, and forgets the IP unless some of it may be left behind.
+    pub fn save(&self, state: &mut State, path: &Path) {
+        for (target, version) in &self.versions {
+            saw_version(state, target, Some(*version), true);
+        }
+        if !self.has_failures() {
+            state.cidr = None;
+        }
+        state.save(path);
+    }
+
+    pub fn into_result(self) -> Result<()> {
+        match self.failed.is_empty() {
+            true => Ok(()),
+            false => Err(eyre!(
+                "Failed to clean up target(s): {}",
+                self.failed.join(", ")
+            )),
+        }
+    }
+}
+
+/// Warns about and notifies the entries carrying the description for another IP than `ours`, the last one this instance
+/// wrote. They're likely from another doorman sharing the description, and the two would remove each other's when
+/// cleaning up.
+pub async fn find_conflicts(
+    targets: &[TargetClient],
+    syncs: &[TargetSync],
+    ours: &HostCidr,
+    notifier: &Dispatcher,
+) {
+    for (TargetClient { target, aws, .. }, sync) in targets.iter().zip(syncs) {
+        let entries = match aws.entries(&sync.prefix_list, EntryFilter::Owned).await {
+            Ok(entries) => entries,
+            Err(err) => {
+                warn!(
+                    "[{}] Failed to look for entries written by someone else: {:#}",
+                    target.name, err
+                );
+                continue;
+            }
+        };
+        for entry in entries.iter().filter(|entry| entry.cidr != ours.net()) {
+            warn!(
+                "[{}] {} in {} carries the description but wasn't written by this instance, another doorman may \
+                 share it. It's only cleaned up with --takeover.",
+                target.name, entry.cidr, target.prefix_list_id
+            );
+            notifier.dispatch(&NotificationEvent::ownership_conflict(
+                target,
+                entry.cidr,
+                "It's only cleaned up with --takeover.",
+            ));
+        }
+    }
+}
+
+/// Removes all but one of the entries carrying the description from each target, keeping the one for `keep` if there
+/// is one, otherwise the newest.
+///
+/// The entry kept becomes the one doorman replaces on the next IP change. Failures are only logged, the duplicates are
+/// harmless meanwhile. So is giving up on a target after `wait_timeout`, e.g. one stuck with a modification in
+/// progress, which would otherwise hold up everything else.
+pub async fn collapse_duplicates(
+    targets: &[TargetClient],
+    syncs: &mut [TargetSync],
+    keep: Option<HostCidr>,
+    state: &mut State,
+    wait_timeout: Duration,
+) {
+    for (TargetClient { target, aws, .. }, sync) in targets.iter().zip(syncs.iter_mut()) {
+        let collapsed = match timeout(
+            wait_timeout,
+            aws.collapse_owned(&target.prefix_list_id, keep.map(|cidr| cidr.net()).as_ref()),
+        )
+        .await
+        {
+            Ok(Ok(collapsed)) => collapsed,
+            Ok(Err(err)) => {
+                warn!(
+                    "[{}] Failed to remove duplicate entries: {:#}",
+                    target.name, err
+                );
+                continue;
+            }
+            Err(_) => {
+                warn!(
+                    "[{}] Gave up removing duplicate entries from {} after {} seconds.",
+                    target.name,
+                    target.prefix_list_id,
+                    wait_timeout.as_secs()
+                );
+                continue;
+            }
+        };
+        if collapsed.removed.is_empty() {
+            continue;
+        }
+        for cidr in &collapsed.removed {
+            warn!(
+                "[{}] Removed the duplicate entry {} from {}, keeping {}.",
+                target.name,
+                cidr,
+                target.prefix_list_id,
+                collapsed
+                    .kept
+                    .map_or_else(|| "none".to_string(), |cidr| cidr.to_string())
+            );
+        }
+        if let Some(kept) = collapsed
+            .kept
+            .and_then(|cidr| HostCidr::try_from(cidr).ok())
+        {
+            sync.cidr = Some(kept);
+        }
+        // The entries changed, so the next check looks at them
+        sync.prefix_list.version = None;
+        saw_version(state, target, collapsed.version, true);
+    }
+}
+
+/// Cleans up every target, even if some of them fail.
+///
+/// Unless `ours` is `None`, only the entries for these CIDRs are removed, others carrying the description are left
+/// in place and notified as conflicts.
+///
+/// Targets left once `budget` is spent are reported as failed, so doorman stops before being killed.
+pub async fn cleanup(
+    targets: &[TargetClient],
+    ours: Option<&[IpNet]>,
+    notifier: &Dispatcher,
+    budget: Duration,
+) -> CleanupReport {
+    let deadline = Instant::now() + budget;
+    let mut report = CleanupReport {
+        lines: Vec::with_capacity(targets.len()),
+        failed: Vec::new(),
+        versions: Vec::new(),
+    };
+    for TargetClient { target, aws, .. } in targets {
+        info!(
+            "[{}] Cleaning up prefix list {}...",
+            target.name, target.prefix_list_id
+        );
+        let step = Cell::new("starting");
+        let result = timeout_at(deadline, aws.cleanup(&target.prefix_list_id, ours, &step))
+            .await
+            .unwrap_or_else(|_| {
+                Err(eyre!(
+                    "gave up after {} seconds while {}, the entry may be left behind",
+                    budget.as_secs(),
+                    step.get()
+                ))
+            });
+        let line = match result {
+            Ok(cleaned) => {
+                if let Some(version) = cleaned.version {
+                    report.versions.push((target.clone(), version));
+                }
+                let mut line = match cleaned.removed.is_empty() {
+                    true => format!("nothing to remove from {}", target.prefix_list_id),
+                    false => {
+                        let removed: Vec<String> =
+                            cleaned.removed.iter().map(ToString::to_string).collect();
+                        format!(
+                            "removed {} from {}",
+                            removed.join(", "),
+                            target.prefix_list_id
+                        )
+                    }
+                };
+                if !cleaned.skipped.is_empty() {
+                    line.push_str(&format!(
+                        ", skipped {} malformed entr{}: {}",
+                        cleaned.skipped.len(),
+                        if cleaned.skipped.len() == 1 {
+                            "y"
+                        } else {
+                            "ies"
+                        },
+                        cleaned.skipped.join(", ")
+                    ));
+                }
+                for cidr in &cleaned.conflicts {
+                    warn!(
+                        "[{}] {} in {} carries the description but wasn't written by this instance, another doorman \
+                         may share it. Leaving it, run with --takeover to remove it.",
+                        target.name, cidr, target.prefix_list_id
+                    );
+                    notifier.dispatch(&NotificationEvent::ownership_conflict(
+                        target,
+                        *cidr,
+                        "Left it in place, run with --takeover to remove it.",
+                    ));
+                }
+                if !cleaned.conflicts.is_empty() {
+                    let conflicts: Vec<String> =
+                        cleaned.conflicts.iter().map(ToString::to_string).collect();
+                    line.push_str(&format!(
+                        ", left {} written by someone else",
+                        conflicts.join(", ")
+                    ));
+                }
+                line
+            }
+            Err(err) => {
+                error!("[{}] Failed to clean up: {:#}", target.name, err);
+                report.failed.push(target.name.clone());
+                format!("failed to clean up {}: {:#}", target.prefix_list_id, err)
+            }
+        };
+        report.lines.push(format!("{}: {}", target.name, line));
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aws::FakeApi;
+    use crate::client_builder;
+    use crate::metrics::Metrics;
+    use crate::notification::{Notifier, NotifyError};
+    use async_trait::async_trait;
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+    use tokio::time::sleep_until;
+
+    const ID: &str = "pl-12345678";
+
+    #[tokio::test(start_paused = true)]
+    async fn recreate_takes_the_new_id() {
+        let config = Config::parse_from(&["--prefix-list-id", ID, "--description", "office"]);
+        let api = FakeApi::default().with_prefix_list(ID, Some(10), &[]);
+        let stats = Stats::new(&config.targets);
+        let aws = client_builder(&config, &config.targets[0], None, &stats)
+            .api(api.clone())
+            .build()
+            .unwrap();
+        let deleted = aws.get_prefix_list(&ID.parse().unwrap()).await.unwrap();
+        api.delete(ID);
+        let mut target = TargetClient {
+            target: config.targets[0].clone(),
+            aws,
+            region: None,
+        };
+        let mut sync = TargetSync::new(deleted);
+
+        recreate(&mut target, &mut sync).await.unwrap();
+        assert_ne!(target.target.prefix_list_id.as_str(), ID);
+        assert_eq!(
+            sync.prefix_list.prefix_list_id.as_deref(),
+            Some(target.target.prefix_list_id.as_str())
+        );
+        assert_eq!(
+            sync.prefix_list.state,
+            Some(PrefixListState::CreateComplete)
+        );
+        assert_eq!(sync.prefix_list.max_entries, Some(10));
+    }
+
+    /// Looks up the IPs in turn, `None` standing for a failed lookup and an IPv6 address for a network without IPv4.
+    struct ScriptedIps {
+        ips: VecDeque<Option<&'static str>>,
+        /// How long the lookups take, in turn, the next ones being immediate
+        delays: VecDeque<Duration>,
+        /// When each lookup started, and how many AWS operations `api` was called for before
+        lookups: Arc<Mutex<Vec<(Instant, usize)>>>,
+        api: FakeApi,
+    }
+
+    fn ips(ips: &[Option<&'static str>]) -> ScriptedIps {
+        ScriptedIps {
+            ips: ips.iter().copied().collect(),
+            delays: VecDeque::new(),
+            lookups: Arc::default(),
+            api: FakeApi::default(),
+        }
+    }
+
+    #[async_trait(?Send)]
+    impl IpSource for ScriptedIps {
+        async fn addresses(&mut self) -> Result<Addresses> {
+            let calls = self.api.calls().len();
+            self.lookups.lock().unwrap().push((Instant::now(), calls));
+            if let Some(delay) = self.delays.pop_front() {
+                sleep(delay).await;
+            }
+            match self.ips.pop_front().expect("no IP left in the script") {
+                Some(ip) => Ok(match ip.parse().unwrap() {
+                    IpAddr::V4(v4) => Addresses {
+                        v4: Some(v4),
+                        v6: None,
+                    },
+                    IpAddr::V6(v6) => Addresses {
+                        v4: None,
+                        v6: Some(v6),
+                    },
+                }),
+                None => Err(eyre!("lookup failed")),
+            }
+        }
+    }
+
+    /// Keeps the kind of each event it's notified of.
+    #[derive(Clone, Default)]
+    struct Notified(Arc<Mutex<Vec<EventKind>>>);
+
+    impl Notified {
+        fn take(&self) -> Vec<EventKind> {
+            std::mem::take(&mut self.0.lock().unwrap())
+        }
+    }
+
+    #[async_trait]
+    impl Notifier for Notified {
+        fn name(&self) -> &'static str {
+            "webhook"
+        }
+
+        async fn notify(&self, event: &NotificationEvent) -> Result<(), NotifyError> {
+            self.0.lock().unwrap().push(event.kind);
+            Ok(())
+        }
+    }
+
+    /// Collects what's logged while it's set as the default subscriber.
+    #[derive(Clone, Default)]
+    struct Logged(Arc<Mutex<Vec<u8>>>);
+
+    impl Logged {
+        fn subscribe(&self) -> tracing::subscriber::DefaultGuard {
+            let writer = self.clone();
+            tracing::subscriber::set_default(
+                tracing_subscriber::fmt()
+                    .with_writer(move || writer.clone())
+                    .with_ansi(false)
+                    .finish(),
+            )
+        }
+
+        fn lines(&self) -> Vec<String> {
+            String::from_utf8(self.0.lock().unwrap().clone())
+                .unwrap()
+                .lines()
+                .map(str::to_string)
+                .collect()
+        }
+    }
+
+    impl std::io::Write for Logged {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// A doorman managing the fake's prefix list, as `work` would set it up.
+    async fn doorman(api: &FakeApi, ips: ScriptedIps, notified: &Notified) -> Doorman {
+        doorman_with(api, ips, notified, &[]).await
+    }
+
+    /// A doorman managing the fake's prefix lists, with `args` on top of the usual ones.
+    async fn doorman_with(
+        api: &FakeApi,
+        mut ips: ScriptedIps,
+        notified: &Notified,
+        args: &[&str],
+    ) -> Doorman {
+        ips.api = api.clone();
+        let mut all_args = vec![
+            "--prefix-list-id",
+            ID,
+            "--description",
+            "office",
+            "--interval",
+            "60",
+        ];
+        all_args.extend_from_slice(args);
+        let config = Config::parse_from(&all_args);
+        let stats = Stats::new(&config.targets);
+        let mut targets = Vec::new();
+        let mut syncs = Vec::new();
+        for target in &config.targets {
+            let target = TargetClient {
+                target: target.clone(),
+                aws: client_builder(&config, target, None, &stats)
+                    .api(api.clone())
+                    .build()
+                    .unwrap(),
+                region: None,
+            };
+            let prefix_list = target
+                .aws
+                .get_prefix_list(&target.target.prefix_list_id)
+                .await
+                .unwrap();
+            targets.push(target);
+            syncs.push(TargetSync::new(prefix_list));
+        }
+        Doorman::new(Parts {
+            notifier: Dispatcher::with_backends(&config.notify, vec![Box::new(notified.clone())]),
+            health: Health::new(Duration::from_secs(config.interval)),
+            pause: Pause::new(Vec::new()),
+            breaker: Breaker::new(
+                config.breaker_threshold,
+                Duration::from_secs(config.breaker_probe),
+            ),
+            schedule: Schedule::new(Duration::from_secs(config.interval), None),
+            targets,
+            syncs,
+            stats,
+            recheck: Recheck::default(),
+            reload: Reload::default(),
+            election: None,
+            role: None,
+            state: State::default(),
+            status_file: None,
+            systemd: Systemd::from_env(),
+            mqtt: None,
+            connectivity: None,
+            ip_source: Box::new(ips),
+            current_cidr: None,
+            last_update: None,
+            config,
+        })
+    }
+
+    /// Ticks once, returning the events notified meanwhile.
+    async fn tick(doorman: &mut Doorman, notified: &Notified) -> Vec<EventKind> {
+        doorman.tick().await.unwrap();
+        doorman.notifier.shutdown().await;
+        notified.take()
+    }
+
+    fn cidrs(api: &FakeApi) -> Vec<String> {
+        api.entries(ID).into_iter().map(|(cidr, _)| cidr).collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn tick_without_change_leaves_the_prefix_list_alone() {
+        let api = FakeApi::default().with_prefix_list(ID, None, &[]);
+        let notified = Notified::default();
+        let mut doorman = doorman(
+            &api,
+            ips(&[Some("192.0.2.1"), Some("192.0.2.1")]),
+            &notified,
+        )
+        .await;
+
+        assert_eq!(tick(&mut doorman, &notified).await, [EventKind::Updated]);
+        let version = api.version(ID);
+        assert_eq!(tick(&mut doorman, &notified).await, []);
+
+        assert_eq!(api.modifications().len(), 1);
+        assert_eq!(api.version(ID), version);
+        assert_eq!(cidrs(&api), ["192.0.2.1/32"]);
+        let stats = doorman.stats.snapshot();
+        assert_eq!(stats.ip_changes(), 1);
+        assert!(stats.targets.values().all(|target| target.in_sync));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn tick_with_a_new_ip_replaces_the_entry() {
+        let api = FakeApi::default().with_prefix_list(ID, None, &[("198.51.100.7/32", "someone")]);
+        let notified = Notified::default();
+        let mut doorman = doorman(
+            &api,
+            ips(&[Some("192.0.2.1"), Some("192.0.2.2")]),
+            &notified,
+        )
+        .await;
+
+        tick(&mut doorman, &notified).await;
+        assert_eq!(tick(&mut doorman, &notified).await, [EventKind::Updated]);
+
+        let modification = api.modifications().pop().unwrap();
+        assert_eq!(modification.remove, ["192.0.2.1/32"]);
+        assert_eq!(modification.add.len(), 1);
+        assert_eq!(modification.add[0].cidr.as_deref(), Some("192.0.2.2/32"));
+        assert_eq!(cidrs(&api), ["198.51.100.7/32", "192.0.2.2/32"]);
+        assert_eq!(
+            doorman.current_cidr.map(|cidr| cidr.to_string()).as_deref(),
+            Some("192.0.2.2/32")
+        );
+        assert_eq!(doorman.state.cidr, doorman.current_cidr);
+        assert_eq!(doorman.stats.snapshot().ip_changes(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn slow_updates_are_timed_and_warned_about() {
+        let api = FakeApi::default().with_prefix_list(ID, None, &[]);
+        let notified = Notified::default();
+        let mut doorman = doorman(
+            &api,
+            ips(&[Some("192.0.2.1"), Some("192.0.2.2")]),
+            &notified,
+        )
+        .await;
+        let logged = Logged::default();
+        let _guard = logged.subscribe();
+
+        api.slow_modifications(ID, Duration::from_secs(40));
+        tick(&mut doorman, &notified).await;
+        api.slow_modifications(ID, Duration::from_secs(5));
+        tick(&mut doorman, &notified).await;
+
+        let stats = doorman.stats.snapshot();
+        let (fastest, slowest) = (
+            stats.updates.quantile(0.).unwrap(),
+            stats.updates.quantile(1.).unwrap(),
+        );
+        assert!(fastest >= Duration::from_secs(5) && fastest < Duration::from_secs(30));
+        assert!(slowest >= Duration::from_secs(40));
+        // The operations are timed by the same spans
+        assert!(
+            stats.operations["modify_entries"].quantile(1.).unwrap() >= Duration::from_secs(40)
+        );
+        assert!(stats.operations.contains_key("get_prefix_list"));
+
+        // Only the update past the 30 seconds is warned about
+        let warnings: Vec<String> = logged
+            .lines()
+            .into_iter()
+            .filter(|line| line.contains("longer than 30 seconds"))
+            .collect();
+        assert_eq!(warnings.len(), 1, "{:?}", warnings);
+        assert!(warnings[0].contains("WARN"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn summaries_are_hourly_however_far_apart_the_checks_are() {
+        let api = FakeApi::default().with_prefix_list(ID, None, &[]);
+        let notified = Notified::default();
+        let mut doorman = doorman(&api, ips(&[Some("192.0.2.1"); 5]), &notified).await;
+        let logged = Logged::default();
+        let _guard = logged.subscribe();
+        let summaries = || {
+            logged
+                .lines()
+                .iter()
+                .filter(|line| line.contains(" Up "))
+                .count()
+        };
+
+        tick(&mut doorman, &notified).await;
+        assert_eq!(summaries(), 0);
+
+        // Checks stretched out by a backoff, counting them would summarize far less often
+        for _ in 0..4 {
+            tokio::time::advance(Duration::from_secs(40 * 60)).await;
+            tick(&mut doorman, &notified).await;
+        }
+        assert_eq!(summaries(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn tick_with_an_aws_failure_retries_and_recovers() {
+        let api = FakeApi::default().with_prefix_list(ID, None, &[]);
+        let notified = Notified::default();
+        let ips = ips(&[
+            Some("192.0.2.1"),
+            Some("192.0.2.2"),
+            Some("192.0.2.2"),
+            Some("192.0.2.2"),
+        ]);
+        let mut doorman = doorman(&api, ips, &notified).await;
+        tick(&mut doorman, &notified).await;
+
+        api.fail_next_modification("InternalError");
+        assert_eq!(
+            tick(&mut doorman, &notified).await,
+            [EventKind::UpdateFailed]
+        );
+        assert_eq!(cidrs(&api), ["192.0.2.1/32"]);
+        assert_eq!(doorman.syncs[0].failures, 1);
+        let stats = doorman.stats.snapshot();
+        assert_eq!(stats.update_failures(), 1);
+        assert!(!stats.targets.values().any(|target| target.in_sync));
+        // Still the IP the entry holds, so the next check tells the change apart
+        assert_eq!(
+            doorman.state.cidr.map(|cidr| cidr.to_string()).as_deref(),
+            Some("192.0.2.1/32")
+        );
+
+        // Retried on the next check, as the first failure only backs off for one
+        assert_eq!(
+            tick(&mut doorman, &notified).await,
+            [EventKind::Recovered, EventKind::Updated]
+        );
+        assert_eq!(cidrs(&api), ["192.0.2.2/32"]);
+        assert_eq!(doorman.syncs[0].failures, 0);
+        assert_eq!(doorman.stats.snapshot().ip_changes(), 2);
+
+        assert_eq!(tick(&mut doorman, &notified).await, []);
+        assert_eq!(api.modifications().len(), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn repeated_failures_open_the_circuit_until_a_probe_succeeds() {
+        let api = FakeApi::default().with_prefix_list(ID, None, &[]);
+        let notified = Notified::default();
+        let ips = ips(&[
+            Some("192.0.2.1"),
+            Some("192.0.2.2"),
+            Some("192.0.2.2"),
+            Some("192.0.2.2"),
+            Some("192.0.2.2"),
+        ]);
+        let mut doorman = doorman_with(
+            &api,
+            ips,
+            &notified,
+            &["--breaker-threshold", "2", "--breaker-probe", "900"],
+        )
+        .await;
+        tick(&mut doorman, &notified).await;
+
+        api.fail_next_modification("InternalError");
+        api.fail_next_modification("InternalError");
+        assert_eq!(
+            tick(&mut doorman, &notified).await,
+            [EventKind::UpdateFailed]
+        );
+        assert!(!doorman.breaker.is_open());
+        assert_eq!(
+            tick(&mut doorman, &notified).await,
+            [EventKind::CircuitOpen]
+        );
+        assert!(doorman.breaker.is_open());
+        assert!(doorman.stats.snapshot().circuit_open);
+        assert!(Metrics::new(doorman.stats.clone())
+            .render()
+            .contains("\ndoorman_circuit_open 1\n"));
+
+        // Left alone until the probe is due
+        let modifications = api.modifications().len();
+        assert_eq!(tick(&mut doorman, &notified).await, []);
+        assert_eq!(api.modifications().len(), modifications);
+
+        tokio::time::advance(Duration::from_secs(900)).await;
+        // The target's failure and the circuit both recover
+        assert_eq!(
+            tick(&mut doorman, &notified).await,
+            [
+                EventKind::Recovered,
+                EventKind::Updated,
+                EventKind::Recovered
+            ]
+        );
+        assert_eq!(cidrs(&api), ["192.0.2.2/32"]);
+        assert!(!doorman.breaker.is_open());
+        assert!(!doorman.stats.snapshot().circuit_open);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn an_ipv6_only_network_leaves_the_ipv4_entry_alone() {
+        let api = FakeApi::default().with_prefix_list(ID, None, &[]);
+        let notified = Notified::default();
+        let ips = ips(&[
+            Some("192.0.2.1"),
+            Some("2001:db8::1"),
+            Some("2001:db8::1"),
+            Some("192.0.2.1"),
+        ]);
+        let mut doorman = doorman(&api, ips, &notified).await;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("status.json");
+        doorman.status_file = Some(StatusFile::new(path.clone(), doorman.stats.clone()));
+        let status = || -> crate::status::Status {
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap()
+        };
+        let logged = Logged::default();
+        let _guard = logged.subscribe();
+        assert_eq!(tick(&mut doorman, &notified).await, [EventKind::Updated]);
+        let modifications = api.modifications().len();
+
+        // Neither a failure nor a reason to touch the entry
+        for _ in 0..2 {
+            assert_eq!(tick(&mut doorman, &notified).await, []);
+            assert_eq!(cidrs(&api), ["192.0.2.1/32"]);
+            let status = status();
+            assert_eq!(status.degraded.as_deref(), Some("v4 unavailable"));
+            assert_eq!(status.last_error, None);
+            assert_eq!(status.ip, Some("192.0.2.1".parse().unwrap()));
+        }
+        assert_eq!(doorman.stats.snapshot().ip_detection_failures, 0);
+        let warnings = logged
+            .lines()
+            .into_iter()
+            .filter(|line| line.contains("Only found the IPv6 address 2001:db8::1"))
+            .count();
+        assert_eq!(warnings, 1);
+        assert!(!logged
+            .lines()
+            .iter()
+            .any(|line| line.contains("None found")));
+
+        assert_eq!(tick(&mut doorman, &notified).await, []);
+        assert_eq!(status().degraded, None);
+        assert_eq!(api.modifications().len(), modifications);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn only_the_leader_modifies_the_prefix_lists() {
+        use aws_sdk_dynamodb::{Config as DynamoDBConfig, Credentials, Region};
+        use aws_smithy_client::test_connection::TestConnection;
+        use aws_smithy_http::body::SdkBody;
+
+        let api = FakeApi::default().with_prefix_list(ID, None, &[]);
+        let notified = Notified::default();
+        let ips = ips(&[Some("192.0.2.1"), Some("192.0.2.1"), Some("192.0.2.1")]);
+        let mut doorman = doorman(&api, ips, &notified).await;
+        // The lease is first held by the other doorman, then taken over
+        let held = r#"{"__type": "com.amazonaws.dynamodb.v20120810#ConditionalCheckFailedException", "message": "The conditional request failed"}"#;
+        let conn = TestConnection::new(
+            [(400, held), (200, "{}")]
+                .into_iter()
+                .map(|(status, body)| {
+                    (
+                        ::http::Request::new(SdkBody::empty()),
+                        ::http::Response::builder()
+                            .status(status)
+                            .body(body.to_string())
+                            .unwrap(),
+                    )
+                })
+                .collect(),
+        );
+        let conf = DynamoDBConfig::builder()
+            .region(Region::new("us-east-1"))
+            .credentials_provider(Credentials::new("test", "test", None, None, "test"))
+            .retry_config(crate::aws::retry_config(1))
+            .build();
+        let election = Election::new(
+            aws_sdk_dynamodb::Client::from_conf_conn(conf, conn),
+            "doorman".to_string(),
+            "office".to_string(),
+            Duration::from_secs(60),
+        );
+        doorman.election = Some(election.clone());
+
+        assert_eq!(election.renew().await, Role::Follower);
+        assert_eq!(
+            tick(&mut doorman, &notified).await,
+            [EventKind::LeadershipChanged]
+        );
+        assert!(api.modifications().is_empty());
+        assert_eq!(doorman.stats.snapshot().role, Some(Role::Follower));
+
+        assert_eq!(election.renew().await, Role::Leader);
+        assert_eq!(
+            tick(&mut doorman, &notified).await,
+            [EventKind::LeadershipChanged, EventKind::Updated]
+        );
+        assert_eq!(cidrs(&api), ["192.0.2.1/32"]);
+
+        // The lease wasn't renewed, so the other doorman may be taking over
+        tokio::time::advance(Duration::from_secs(30)).await;
+        assert_eq!(
+            tick(&mut doorman, &notified).await,
+            [EventKind::LeadershipChanged]
+        );
+        assert_eq!(doorman.stats.snapshot().role, Some(Role::Unknown));
+        assert_eq!(api.modifications().len(), 1);
+    }
+
+    /// A backend which can't deliver anything.
+    struct Failing;
+
+    #[async_trait]
+    impl Notifier for Failing {
+        fn name(&self) -> &'static str {
+            "webhook"
+        }
+
+        async fn notify(&self, _: &NotificationEvent) -> Result<(), NotifyError> {
+            Err(NotifyError::Transient("connection refused".to_string()))
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn tick_with_failing_notifications_still_updates() {
+        let api = FakeApi::default().with_prefix_list(ID, None, &[]);
+        let notified = Notified::default();
+        let ips = ips(&[Some("192.0.2.1"), Some("192.0.2.2")]);
+        let mut doorman = doorman(&api, ips, &notified).await;
+        doorman.notifier =
+            Dispatcher::with_backends(&doorman.config.notify, vec![Box::new(Failing)]);
+
+        doorman.tick().await.unwrap();
+        doorman.tick().await.unwrap();
+        doorman.notifier.shutdown().await;
+
+        assert_eq!(cidrs(&api), ["192.0.2.2/32"]);
+        assert_eq!(doorman.syncs[0].failures, 0);
+        assert_eq!(doorman.stats.snapshot().ip_changes(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn tick_with_repeated_aws_failures_tells_the_attempt() {
+        let api = FakeApi::default().with_prefix_list(ID, None, &[]);
+        let notified = Notified::default();
+        let ips = ips(&[Some("192.0.2.1"), Some("192.0.2.1")]);
+        let mut doorman = doorman(&api, ips, &notified).await;
+
+        api.fail_next_modification("InternalError");
+        tick(&mut doorman, &notified).await;
+        let first = doorman.syncs[0].last_error.clone().unwrap();
+        assert!(first.contains(&format!("on {}", ID)), "{}", first);
+        assert!(!first.contains("attempt"), "{}", first);
+
+        api.fail_next_modification("InternalError");
+        tick(&mut doorman, &notified).await;
+        let second = doorman.syncs[0].last_error.clone().unwrap();
+        assert!(second.contains("(attempt 2)"), "{}", second);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn forcing_a_target_in_backoff_tries_it_right_away() {
+        let api = FakeApi::default().with_prefix_list(ID, None, &[]);
+        let notified = Notified::default();
+        let mut doorman = doorman(&api, ips(&[Some("192.0.2.1"); 3]), &notified).await;
+
+        api.fail_next_modification("InternalError");
+        tick(&mut doorman, &notified).await;
+        api.fail_next_modification("InternalError");
+        tick(&mut doorman, &notified).await;
+        // The next check would leave it alone
+        assert_eq!(doorman.syncs[0].retry_at, doorman.ticks + 2);
+        let modifications = api.modifications().len();
+
+        doorman.recheck.force();
+        tick(&mut doorman, &notified).await;
+        assert_eq!(api.modifications().len(), modifications + 1);
+        assert_eq!(cidrs(&api), ["192.0.2.1/32"]);
+        assert_eq!(doorman.syncs[0].failures, 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn tick_with_a_failed_lookup_leaves_the_prefix_list_alone_and_recovers() {
+        let api = FakeApi::default().with_prefix_list(ID, None, &[]);
+        let notified = Notified::default();
+        let mut doorman = doorman(&api, ips(&[None, None, Some("192.0.2.1")]), &notified).await;
+
+        assert_eq!(
+            tick(&mut doorman, &notified).await,
+            [EventKind::IpLookupFailed]
+        );
+        // Repeated failures are only reminded of once in a while
+        assert_eq!(tick(&mut doorman, &notified).await, []);
+        assert!(api.modifications().is_empty());
+        assert_eq!(api.describes(), 1);
+        assert_eq!(doorman.stats.snapshot().ip_detection_failures, 2);
+        assert_eq!(doorman.current_cidr, None);
+
+        assert_eq!(
+            tick(&mut doorman, &notified).await,
+            [EventKind::Recovered, EventKind::Updated]
+        );
+        assert_eq!(cidrs(&api), ["192.0.2.1/32"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn once_fails_unless_the_prefix_lists_hold_the_ip() {
+        let api = FakeApi::default().with_prefix_list(ID, None, &[]);
+        let notified = Notified::default();
+
+        let mut updated = doorman(&api, ips(&[Some("192.0.2.1")]), &notified).await;
+        updated.once().await.unwrap();
+        assert_eq!(cidrs(&api), ["192.0.2.1/32"]);
+
+        let mut lookup_failed = doorman(&api, ips(&[None]), &notified).await;
+        let err = lookup_failed.once().await.unwrap_err();
+        assert!(err.to_string().contains("external IP"), "{}", err);
+
+        // As if the state file held the IP of a previous run
+        let mut restored = doorman(&api, ips(&[None]), &notified).await;
+        restored.current_cidr = Some(HostCidr::from("192.0.2.1".parse::<IpAddr>().unwrap()));
+        restored.state.cidr = restored.current_cidr;
+        let err = restored.once().await.unwrap_err();
+        assert!(err.to_string().contains("external IP"), "{}", err);
+
+        api.fail_next_modification("InternalError");
+        let mut update_failed = doorman(&api, ips(&[Some("192.0.2.2")]), &notified).await;
+        let err = update_failed.once().await.unwrap_err();
+        assert!(err.to_string().contains("InternalError"), "{}", err);
+        // Left for the next run
+        assert_eq!(cidrs(&api), ["192.0.2.1/32"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn tick_after_the_prefix_list_is_deleted_gives_up() {
+        let api = FakeApi::default().with_prefix_list(ID, None, &[]);
+        let notified = Notified::default();
+        let ips = ips(&[Some("192.0.2.1"), Some("192.0.2.1")]);
+        let mut doorman = doorman(&api, ips, &notified).await;
+        assert_eq!(tick(&mut doorman, &notified).await, [EventKind::Updated]);
+
+        api.delete(ID);
+        let err = doorman.tick().await.unwrap_err();
+        assert_eq!(Exit::of(&err), Exit::PrefixListNotFound);
+        assert!(err.to_string().contains("Giving up"), "{}", err);
+        // Told about once, rather than as a failed update
+        assert_eq!(notified.take(), [EventKind::PrefixListDeleted]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn tick_after_the_prefix_list_is_deleted_recreates_it_if_asked() {
+        let api = FakeApi::default().with_prefix_list(ID, Some(10), &[]);
+        let notified = Notified::default();
+        let ips = ips(&[Some("192.0.2.1"); 3]);
+        let mut doorman = doorman_with(&api, ips, &notified, &["--recreate-on-delete"]).await;
+        tick(&mut doorman, &notified).await;
+
+        api.delete(ID);
+        assert_eq!(
+            tick(&mut doorman, &notified).await,
+            [EventKind::PrefixListDeleted]
+        );
+        let recreated = doorman.targets[0].target.prefix_list_id.to_string();
+        assert_ne!(recreated, ID);
+        assert_eq!(
+            api.entries(&recreated),
+            [("192.0.2.1/32".to_string(), "office".to_string())]
+        );
+        assert_eq!(doorman.syncs[0].failures, 0);
+
+        // From then on, the new prefix list is the one managed
+        let modifications = api.modifications().len();
+        assert_eq!(tick(&mut doorman, &notified).await, []);
+        assert_eq!(api.modifications().len(), modifications);
+    }
+
+    /// Asks to shut down once, at `at`.
+    struct ShutdownAt(Option<Instant>);
+
+    #[async_trait]
+    impl Shutdown for ShutdownAt {
+        async fn recv(&mut self) -> ShutdownSignal {
+            match self.0 {
+                Some(at) => {
+                    // Only cleared once over, as the loop drops this whenever a check comes first
+                    sleep_until(at).await;
+                    self.0 = None;
+                    ShutdownSignal::Interrupt
+                }
+                None => std::future::pending().await,
+            }
+        }
+    }
+
+    const LOOKUP: &str = "lookup";
+    const DESCRIBE: &str = "DescribeManagedPrefixLists";
+    const LIST: &str = "GetManagedPrefixListEntries";
+    const MODIFY: &str = "ModifyManagedPrefixList";
+
+    /// The IP lookups and AWS operations since `start`, in order, each with the second its check started at.
+    fn timeline(
+        api: &FakeApi,
+        lookups: &Mutex<Vec<(Instant, usize)>>,
+        start: Instant,
+    ) -> Vec<(u64, &'static str)> {
+        let calls = api.calls();
+        let mut timeline = Vec::new();
+        let mut check = None;
+        let mut lookups = lookups.lock().unwrap().clone().into_iter().peekable();
+        for (index, (_, operation)) in calls.iter().enumerate() {
+            while let Some((at, _)) = lookups.next_if(|(_, before)| *before == index) {
+                let at = (at - start).as_secs();
+                check = Some(at);
+                timeline.push((at, LOOKUP));
+            }
+            // Calls made while setting up come before the first check
+            if let Some(check) = check {
+                timeline.push((check, *operation));
+            }
+        }
+        timeline.extend(lookups.map(|(at, _)| ((at - start).as_secs(), LOOKUP)));
+        timeline
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn run_checks_once_per_interval() {
+        let api = FakeApi::default().with_prefix_list(ID, None, &[]);
+        let notified = Notified::default();
+        let source = ips(&[
+            Some("192.0.2.1"),
+            Some("192.0.2.1"),
+            Some("192.0.2.2"),
+            Some("192.0.2.2"),
+        ]);
+        let lookups = source.lookups.clone();
+        let mut doorman = doorman(&api, source, &notified).await;
+        let start = Instant::now();
+
+        let shutdown = ShutdownAt(Some(start + Duration::from_secs(200)));
+        let signal = doorman.run(shutdown, &mut None).await.unwrap();
+        assert_eq!(signal, ShutdownSignal::Interrupt);
+        assert_eq!(Instant::now() - start, Duration::from_secs(200));
+
+        assert_eq!(
+            timeline(&api, &lookups, start),
+            [
+                // Adding the entry, then waiting for the modification in progress to complete
+                (0, LOOKUP),
+                (0, DESCRIBE),
+                (0, LIST),
+                (0, MODIFY),
+                (0, DESCRIBE),
+                (0, DESCRIBE),
+                // The version didn't change, so the entries aren't listed again
+                (60, LOOKUP),
+                (60, DESCRIBE),
+                (120, LOOKUP),
+                (120, DESCRIBE),
+                (120, LIST),
+                (120, MODIFY),
+                (120, DESCRIBE),
+                (120, DESCRIBE),
+                (180, LOOKUP),
+                (180, DESCRIBE),
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn run_skips_the_checks_missed_by_a_slow_one() {
+        let api = FakeApi::default().with_prefix_list(ID, None, &[]);
+        let notified = Notified::default();
+        let mut source = ips(&[Some("192.0.2.1"); 5]);
+        // The second lookup takes two and a half intervals
+        source.delays = [Duration::ZERO, Duration::from_secs(150)].into();
+        let lookups = source.lookups.clone();
+        let mut doorman = doorman(&api, source, &notified).await;
+        let start = Instant::now();
+        doorman.schedule = Schedule::starting_at(
+            start + Duration::from_secs(30),
+            Duration::from_secs(60),
+            None,
+        );
+
+        let shutdown = ShutdownAt(Some(start + Duration::from_secs(350)));
+        doorman.run(shutdown, &mut None).await.unwrap();
+
+        assert_eq!(
+            timeline(&api, &lookups, start),
+            [
+                (30, LOOKUP),
+                (30, DESCRIBE),
+                (30, LIST),
+                (30, MODIFY),
+                (30, DESCRIBE),
+                (30, DESCRIBE),
+                (90, LOOKUP),
+                (90, DESCRIBE),
+                // The check due at 150 runs as soon as the slow one is over, and the one due at 210 is skipped rather
+                // than run right after it
+                (240, LOOKUP),
+                (240, DESCRIBE),
+                (270, LOOKUP),
+                (270, DESCRIBE),
+                (330, LOOKUP),
+                (330, DESCRIBE),
+            ]
+        );
+    }
+
+    const OTHER: &str = "pl-87654321";
+
+    #[tokio::test(start_paused = true)]
+    async fn run_lets_a_slow_modification_finish_then_cleans_up() {
+        let api = FakeApi::default()
+            .with_prefix_list(ID, None, &[])
+            .with_prefix_list(OTHER, None, &[]);
+        api.slow_modifications(ID, Duration::from_secs(8));
+        let notified = Notified::default();
+        let args = ["--target", OTHER, "--shutdown-timeout", "10"];
+        let mut doorman = doorman_with(&api, ips(&[Some("192.0.2.1")]), &notified, &args).await;
+        let start = Instant::now();
+
+        // Asked to stop while the first prefix list is being modified
+        let shutdown = ShutdownAt(Some(start + Duration::from_secs(5)));
+        let signal = doorman.run(shutdown, &mut None).await.unwrap();
+        // The other prefix list was still updated, rather than left for a check which won't come
+        assert_eq!(cidrs(&api), ["192.0.2.1/32"]);
+        assert_eq!(api.entries(OTHER).len(), 1);
+        assert!(Instant::now() - start < Duration::from_secs(15));
+
+        let report = doorman.stop(signal).await;
+        assert!(report.failed.is_empty(), "{}", report.summary());
+        assert!(cidrs(&api).is_empty());
+        assert!(api.entries(OTHER).is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn run_doesnt_wait_past_the_shutdown_timeout() {
+        // The other prefix list comes first, the slow one never finishes in time
+        let api = FakeApi::default()
+            .with_prefix_list(OTHER, None, &[])
+            .with_prefix_list(ID, None, &[]);
+        api.slow_modifications(ID, Duration::from_secs(3600));
+        let notified = Notified::default();
+        let args = ["--target", OTHER, "--shutdown-timeout", "10"];
+        let mut doorman = doorman_with(&api, ips(&[Some("192.0.2.1")]), &notified, &args).await;
+        doorman.targets.reverse();
+        doorman.syncs.reverse();
+        let start = Instant::now();
+
+        let shutdown = ShutdownAt(Some(start + Duration::from_secs(5)));
+        let signal = doorman.run(shutdown, &mut None).await.unwrap();
+        assert_eq!(Instant::now() - start, Duration::from_secs(15));
+        assert_eq!(api.entries(OTHER).len(), 1);
+        // Given up on, so it never happened
+        assert!(cidrs(&api).is_empty());
+
+        // Only the entry which made it in is removed, and right away
+        api.slow_modifications(ID, Duration::ZERO);
+        let report = doorman.stop(signal).await;
+        assert!(report.failed.is_empty(), "{}", report.summary());
+        assert!(api.entries(OTHER).is_empty());
+        assert!(Instant::now() - start < Duration::from_secs(25));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn collapse_duplicates_replaces_the_newest_afterwards() {
+        let api = FakeApi::default().with_prefix_list(
+            ID,
+            None,
+            &[
+                ("192.0.2.1/32", "office @ 2022-09-01T10:00:00Z"),
+                ("192.0.2.2/32", "office @ 2022-09-04T10:00:00Z"),
+                ("192.0.2.3/32", "office @ 2022-09-03T10:00:00Z"),
+                ("192.0.2.4/32", "office"),
+                ("192.0.2.5/32", "office @ 2022-09-02T10:00:00Z"),
+            ],
+        );
+        let notified = Notified::default();
+        let mut doorman = doorman(&api, ips(&[Some("198.51.100.7")]), &notified).await;
+        let keep = "198.51.100.7".parse().ok();
+        collapse_duplicates(
+            &doorman.targets,
+            &mut doorman.syncs,
+            keep,
+            &mut doorman.state,
+            Duration::from_secs(30),
+        )
+        .await;
+        assert_eq!(cidrs(&api), ["192.0.2.2/32"]);
+        assert_eq!(doorman.syncs[0].cidr, "192.0.2.2".parse().ok());
+
+        // Replaced by the next check, rather than added next to it
+        doorman.tick().await.unwrap();
+        assert_eq!(cidrs(&api), ["198.51.100.7/32"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reloading_applies_the_new_notification_settings() {
+        let api = FakeApi::default().with_prefix_list(ID, None, &[]);
+        let notified = Notified::default();
+        let mut doorman = doorman(
+            &api,
+            ips(&[Some("198.51.100.7"), Some("198.51.100.8")]),
+            &notified,
+        )
+        .await;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("doorman.toml");
+        let target = format!(
+            "[[target]]\nname = \"default\"\nprefix_list_id = \"{}\"\ndescription = \"office\"\n",
+            ID
+        );
+        std::fs::write(
+            &path,
+            format!("{}\n[notify.webhook]\nmin_level = \"error\"\n", target),
+        )
+        .unwrap();
+        doorman.config.config_file = Some(path.clone());
+        let logged = Logged::default();
+        let _guard = logged.subscribe();
+
+        doorman.reload_config();
+        // Updates are changes, which the webhook no longer gets
+        assert_eq!(tick(&mut doorman, &notified).await, []);
+        assert!(!logged.lines().iter().any(|line| line.contains("WARN")));
+
+        // The settings in use are kept
+        std::fs::write(&path, "[[target]]\nname = ").unwrap();
+        doorman.reload_config();
+        assert!(logged.lines().iter().any(
+            |line| line.contains("Failed to reload the configuration, keeping the current one")
+        ));
+        assert_eq!(tick(&mut doorman, &notified).await, []);
+
+        // The targets only change when restarting
+        std::fs::write(&path, target.replace("office", "home")).unwrap();
+        doorman.reload_config();
+        assert!(logged
+            .lines()
+            .iter()
+            .any(|line| line.contains("changed, restart doorman to apply them")));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn collapse_duplicates_gives_up_on_a_list_stuck_in_progress() {
+        let api = FakeApi::default().with_prefix_list(
+            ID,
+            None,
+            &[("192.0.2.1/32", "office"), ("192.0.2.2/32", "office")],
+        );
+        let notified = Notified::default();
+        let mut doorman = doorman(&api, ips(&[Some("198.51.100.7")]), &notified).await;
+        api.script(ID, &vec![(PrefixListState::ModifyInProgress, 1); 1000]);
+        let logged = Logged::default();
+        let _guard = logged.subscribe();
+        let start = Instant::now();
+
+        collapse_duplicates(
+            &doorman.targets,
+            &mut doorman.syncs,
+            None,
+            &mut doorman.state,
+            Duration::from_secs(30),
+        )
+        .await;
+        assert_eq!(Instant::now() - start, Duration::from_secs(30));
+        assert!(api.modifications().is_empty());
+        assert!(logged
+            .lines()
+            .iter()
+            .any(|line| line.contains("Gave up removing duplicate entries from")));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cleanup_gives_up_at_the_deadline_naming_the_step() {
+        let api = FakeApi::default()
+            .with_prefix_list(ID, None, &[("192.0.2.1/32", "office")])
+            .with_prefix_list(OTHER, None, &[("192.0.2.1/32", "office")]);
+        api.slow_modifications(ID, Duration::from_secs(3600));
+        let notified = Notified::default();
+        let mut doorman = doorman_with(&api, ips(&[]), &notified, &["--target", OTHER]).await;
+        // The other one first, so it's cleaned up within the budget
+        doorman.targets.reverse();
+        let start = Instant::now();
+
+        let report = cleanup(
+            &doorman.targets,
+            None,
+            &doorman.notifier,
+            Duration::from_secs(10),
+        )
+        .await;
+        assert_eq!(Instant::now() - start, Duration::from_secs(10));
+        assert!(api.entries(OTHER).is_empty());
+        assert_eq!(report.failed, [doorman.targets[1].target.name.clone()]);
+        assert_eq!(
+            report.lines[1],
+            format!(
+                "{}: failed to clean up {}: gave up after 10 seconds while removing the entries, the entry may be \
+                 left behind",
+                doorman.targets[1].target.name, ID
+            )
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cleanup_gives_up_on_a_modification_which_never_ends() {
+        let api = FakeApi::default().with_prefix_list(ID, None, &[("192.0.2.1/32", "office")]);
+        let notified = Notified::default();
+        let doorman = doorman(&api, ips(&[]), &notified).await;
+        api.script(ID, &vec![(PrefixListState::ModifyInProgress, 2); 1000]);
+
+        let report = cleanup(
+            &doorman.targets,
+            None,
+            &doorman.notifier,
+            Duration::from_secs(30),
+        )
+        .await;
+        assert!(report.has_failures());
+        assert!(
+            report.lines[0].contains(
+                "gave up after 30 seconds while waiting for a modification in progress to end"
+            ),
+            "{}",
+            report.lines[0]
+        );
+    }
+
+    /// What the readiness probe answers, as its status code and JSON body.
+    async fn readiness(doorman: &Doorman) -> (u16, serde_json::Value) {
+        let request = hyper::Request::get("/readyz")
+            .body(hyper::Body::empty())
+            .unwrap();
+        let response = doorman.health.respond(&request);
+        let status = response.status().as_u16();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn verification_runs_every_few_checks() {
+        let api = FakeApi::default().with_prefix_list(ID, None, &[]);
+        let notified = Notified::default();
+        let mut doorman = doorman_with(
+            &api,
+            ips(&[Some("192.0.2.1"), Some("192.0.2.1"), Some("192.0.2.1")]),
+            &notified,
+            &["--verify-every", "2"],
+        )
+        .await;
+
+        tick(&mut doorman, &notified).await;
+        assert_eq!(
+            readiness(&doorman).await.1["last_verified"],
+            serde_json::Value::Null
+        );
+        tick(&mut doorman, &notified).await;
+        let (status, body) = readiness(&doorman).await;
+        assert_eq!(status, 200);
+        assert!(body["last_verified"].is_string());
+        assert_eq!(body["verification_error"], serde_json::Value::Null);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn verification_failing_twice_in_a_row_is_notified() {
+        let api = FakeApi::default().with_prefix_list(ID, None, &[]);
+        let notified = Notified::default();
+        let mut doorman = doorman(&api, ips(&[Some("192.0.2.1")]), &notified).await;
+        tick(&mut doorman, &notified).await;
+        let cidr: HostCidr = "192.0.2.1".parse().unwrap();
+
+        // Someone emptied the prefix list behind doorman's back
+        api.delete(ID);
+        let _ = api.clone().with_prefix_list(ID, None, &[]);
+        doorman.verify_entries(cidr).await;
+        doorman.notifier.shutdown().await;
+        assert_eq!(doorman.verify_failures, 1);
+        // Once may be a fluke
+        assert!(notified.take().is_empty());
+        let (status, body) = readiness(&doorman).await;
+        assert_eq!(status, 503);
+        assert_eq!(
+            body["verification_error"],
+            format!(
+                "{}: no entry for 192.0.2.1/32",
+                doorman.targets[0].target.name
+            )
+        );
+
+        doorman.verify_entries(cidr).await;
+        doorman.notifier.shutdown().await;
+        assert_eq!(notified.take(), [EventKind::VerificationFailed]);
+
+        let _ = api
+            .clone()
+            .with_prefix_list(ID, None, &[("192.0.2.1/32", "office")]);
+        // At a version doorman hasn't seen, as a real prefix list would be
+        for _ in 0..3 {
+            api.bump(ID);
+        }
+        doorman.verify_entries(cidr).await;
+        doorman.notifier.shutdown().await;
+        assert_eq!(doorman.verify_failures, 0);
+        assert_eq!(notified.take(), [EventKind::Recovered]);
+        assert_eq!(readiness(&doorman).await.0, 200);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn verification_fails_while_the_prefix_list_is_being_modified() {
+        let api = FakeApi::default().with_prefix_list(ID, None, &[("192.0.2.1/32", "office")]);
+        let notified = Notified::default();
+        let doorman = doorman(&api, ips(&[]), &notified).await;
+        api.script(ID, &[(PrefixListState::ModifyInProgress, 2)]);
+
+        let problem = verify(&doorman.targets, &"192.0.2.1".parse().unwrap()).await;
+        assert_eq!(
+            problem.unwrap(),
+            format!(
+                "{}: the prefix list is modify-in-progress",
+                doorman.targets[0].target.name
+            )
+        );
+        // Missing altogether
+        api.delete(ID);
+        let problem = verify(&doorman.targets, &"192.0.2.1".parse().unwrap()).await;
+        assert!(
+            problem
+                .as_deref()
+                .unwrap()
+                .contains("failed to get the prefix list"),
+            "{:?}",
+            problem
+        );
+    }
+
+    /// The versions of the prefix list in the state, and whether doorman made them.
+    fn history(doorman: &Doorman) -> Vec<(i64, bool)> {
+        doorman.state.history[ID]
+            .iter()
+            .map(|record| (record.version, record.by_doorman))
+            .collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn versions_record_who_made_them() {
+        let api = FakeApi::default().with_prefix_list(ID, None, &[]);
+        let notified = Notified::default();
+        let mut doorman = doorman(
+            &api,
+            ips(&[Some("192.0.2.1"), Some("192.0.2.1"), Some("192.0.2.1")]),
+            &notified,
+        )
+        .await;
+
+        // The version found first, then doorman's
+        tick(&mut doorman, &notified).await;
+        assert_eq!(history(&doorman), [(1, false), (2, true)]);
+
+        // Someone else's changes, skipping a few versions
+        api.bump(ID);
+        api.bump(ID);
+        api.bump(ID);
+        tick(&mut doorman, &notified).await;
+        assert_eq!(history(&doorman), [(1, false), (2, true), (5, false)]);
+        // Nothing new when nothing changed
+        tick(&mut doorman, &notified).await;
+        assert_eq!(history(&doorman).len(), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cleaning_up_records_its_version_and_forgets_the_ip() {
+        let api = FakeApi::default().with_prefix_list(ID, None, &[("192.0.2.1/32", "office")]);
+        let notified = Notified::default();
+        let mut doorman = doorman(&api, ips(&[]), &notified).await;
+        doorman.state.cidr = "192.0.2.1".parse().ok();
+        doorman.state.saw_version(ID, 1, true);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+
+        let report = cleanup(
+            &doorman.targets,
+            None,
+            &doorman.notifier,
+            Duration::from_secs(10),
+        )
+        .await;
+        report.save(&mut doorman.state, &path);
+        assert_eq!(history(&doorman), [(1, true), (2, true)]);
+        assert_eq!(doorman.state.cidr, None);
+        // So the next start doesn't report doorman's own cleanup as someone else's
+        let saved = State::load(&path);
+        assert_eq!(saved.history[ID].len(), 2);
+        assert!(saved.cidr.is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cleanup_reports_the_skipped_entries() {
+        let api = FakeApi::default().with_prefix_list(
+            ID,
+            None,
+            &[("192.0.2.1/32", "office"), ("not a cidr", "office")],
+        );
+        let notified = Notified::default();
+        let doorman = doorman(&api, ips(&[]), &notified).await;
+
+        let report = cleanup(
+            &doorman.targets,
+            None,
+            &doorman.notifier,
+            Duration::from_secs(10),
+        )
+        .await;
+        assert!(!report.has_failures());
+        let line = &report.lines[0];
+        assert!(
+            line.contains(&format!(
+                "removed 192.0.2.1/32 from {}, skipped 1 malformed entry: office (",
+                ID
+            )),
+            "{}",
+            line
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn hosts_sharing_a_prefix_list_each_own_one_entry() {
+        let api = FakeApi::default().with_prefix_list(
+            ID,
+            None,
+            &[("198.51.100.7/32", "office @ other-host")],
+        );
+        let notified = Notified::default();
+        let ips = ips(&[Some("192.0.2.1"), Some("192.0.2.2")]);
+        let mut doorman = doorman_with(&api, ips, &notified, &["--per-host"]).await;
+        let ours = doorman.config.targets[0].description.clone();
+
+        tick(&mut doorman, &notified).await;
+        tick(&mut doorman, &notified).await;
+        let mut entries = api.entries(ID);
+        entries.sort();
+        assert_eq!(
+            entries,
+            [
+                ("192.0.2.2/32".to_string(), ours.to_string()),
+                (
+                    "198.51.100.7/32".to_string(),
+                    "office @ other-host".to_string()
+                ),
+            ]
+        );
+
+        let report = cleanup(
+            &doorman.targets,
+            None,
+            &doorman.notifier,
+            Duration::from_secs(10),
+        )
+        .await;
+        assert!(!report.has_failures());
+        assert_eq!(
+            api.entries(ID),
+            [(
+                "198.51.100.7/32".to_string(),
+                "office @ other-host".to_string()
+            )]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn entry_expiries_are_refreshed_once_half_is_left() {
+        let api = FakeApi::default().with_prefix_list(ID, None, &[]);
+        let notified = Notified::default();
+        let ips = ips(&[Some("192.0.2.1"), Some("192.0.2.1"), Some("192.0.2.1")]);
+        let mut doorman = doorman_with(&api, ips, &notified, &["--entry-ttl", "3600"]).await;
+        tick(&mut doorman, &notified).await;
+        let (_, description) = api.entries(ID).pop().unwrap();
+        assert!(description.starts_with("office @ "), "{}", description);
+        let modifications = api.modifications().len();
+
+        // More than half left
+        doorman.syncs[0].written_at = Some(Utc::now() - chrono::Duration::minutes(29));
+        assert_eq!(tick(&mut doorman, &notified).await, []);
+        assert_eq!(api.modifications().len(), modifications);
+
+        let written_at = Utc::now() - chrono::Duration::minutes(31);
+        doorman.syncs[0].written_at = Some(written_at);
+        assert_eq!(tick(&mut doorman, &notified).await, []);
+        // Adding the CIDR again only rewrites its description
+        let refreshed = api.modifications().pop().unwrap();
+        assert_eq!(api.modifications().len(), modifications + 1);
+        assert!(refreshed.remove.is_empty());
+        assert_eq!(refreshed.add[0].cidr.as_deref(), Some("192.0.2.1/32"));
+        assert_eq!(cidrs(&api), ["192.0.2.1/32"]);
+        assert!(doorman.syncs[0].written_at.unwrap() > written_at);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn tick_waits_for_someone_elses_entry_for_the_ip_to_go() {
+        let api = FakeApi::default().with_prefix_list(ID, None, &[("192.0.2.1/32", "alice")]);
+        let notified = Notified::default();
+        let mut doorman = doorman(
+            &api,
+            ips(&[Some("192.0.2.1"), Some("192.0.2.1"), Some("192.0.2.1")]),
+            &notified,
+        )
+        .await;
+
+        // Told once, and left alone
+        assert_eq!(
+            tick(&mut doorman, &notified).await,
+            [EventKind::ForeignEntry]
+        );
+        assert!(tick(&mut doorman, &notified).await.is_empty());
+        assert!(api.modifications().is_empty());
+        assert_eq!(
+            api.entries(ID),
+            [("192.0.2.1/32".to_string(), "alice".to_string())]
+        );
+
+        // Once it's gone, doorman's own takes over
+        api.delete(ID);
+        let _ = api.clone().with_prefix_list(ID, None, &[]);
+        for _ in 0..3 {
+            api.bump(ID);
+        }
+        let events = tick(&mut doorman, &notified).await;
+        assert!(!events.contains(&EventKind::ForeignEntry), "{:?}", events);
+        assert_eq!(
+            api.entries(ID),
+            [("192.0.2.1/32".to_string(), "office".to_string())]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn tick_keeps_its_old_entry_while_someone_else_holds_the_new_ip() {
+        let api = FakeApi::default().with_prefix_list(
+            ID,
+            None,
+            &[("198.51.100.7/32", "office"), ("192.0.2.1/32", "alice")],
+        );
+        let notified = Notified::default();
+        let mut doorman = doorman(
+            &api,
+            ips(&[Some("198.51.100.7"), Some("192.0.2.1")]),
+            &notified,
+        )
+        .await;
+
+        tick(&mut doorman, &notified).await;
+        let modifications = api.modifications().len();
+        assert_eq!(
+            tick(&mut doorman, &notified).await,
+            [EventKind::ForeignEntry]
+        );
+        assert_eq!(api.modifications().len(), modifications);
+        let mut entries = api.entries(ID);
+        entries.sort();
+        assert_eq!(
+            entries,
+            [
+                ("192.0.2.1/32".to_string(), "alice".to_string()),
+                ("198.51.100.7/32".to_string(), "office".to_string())
+            ]
+        );
+        // Still known, so it's replaced once the IP is free
+        assert_eq!(doorman.state.cidr, "198.51.100.7".parse().ok());
+        assert!(
+            !NotificationEvent::foreign_entry(
+                &doorman.targets[0].target,
+                "192.0.2.1/32".parse().unwrap(),
+                "alice"
+            )
+            .urgent
+        );
+    }
+}
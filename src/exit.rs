@@ -0,0 +1,208 @@
+use crate::aws::AWSError;
+
+use color_eyre::Report;
+use std::error::Error;
+use std::fmt;
+
+/// Exit statuses, so supervisors can tell why doorman stopped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Exit {
+    Success,
+    /// Anything not covered below
+    Failure,
+    /// The command line or configuration file is invalid
+    Config,
+    /// AWS rejected the credentials or the permissions are missing
+    AwsAuth,
+    /// A prefix list doesn't exist
+    PrefixListNotFound,
+    /// AWS couldn't be reached or kept failing
+    AwsUnavailable,
+    /// A second signal cut the cleanup short
+    Forced,
+}
+
+impl Exit {
+    pub const ALL: [Self; 7] = [
+        Self::Success,
+        Self::Failure,
+        Self::Config,
+        Self::AwsAuth,
+        Self::PrefixListNotFound,
+        Self::AwsUnavailable,
+        Self::Forced,
+    ];
+
+    pub fn code(self) -> i32 {
+        match self {
+            Self::Success => 0,
+            Self::Failure => 1,
+            // This is what clap exits with on usage errors
+            Self::Config => 2,
+            Self::AwsAuth => 3,
+            Self::PrefixListNotFound => 4,
+            Self::AwsUnavailable => 5,
+            Self::Forced => 6,
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Self::Success => "success",
+            Self::Failure => "unexpected error",
+            Self::Config => "invalid command line or configuration file",
+            Self::AwsAuth => "AWS rejected the credentials or permissions are missing",
+            Self::PrefixListNotFound => "a prefix list doesn't exist",
+            Self::AwsUnavailable => "AWS couldn't be reached or its API kept failing",
+            Self::Forced => "shutdown was forced before cleaning up, entries may be left behind",
+        }
+    }
+
+    /// Classifies an error by the first AWS error or [`InvalidConfig`] in its chain.
+    pub fn of(report: &Report) -> Self {
+        match report
+            .chain()
+            .find_map(|err| err.downcast_ref::<AWSError>())
+        {
+            Some(err) => Self::of_aws(err),
+            // Attached with `wrap_err`, which the chain doesn't tell apart
+            None if report.downcast_ref::<InvalidConfig>().is_some() => Self::Config,
+            None => Self::Failure,
+        }
+    }
+
+    fn of_aws(err: &AWSError) -> Self {
+        match err {
+            AWSError::NotFound { .. } => Self::PrefixListNotFound,
+            AWSError::Service {
+                code: Some(code), ..
+            } if code.starts_with("InvalidPrefixListID") => Self::PrefixListNotFound,
+            AWSError::Service {
+                code: Some(code), ..
+            } if AUTH_ERROR_CODES.contains(&code.as_str()) => Self::AwsAuth,
+            AWSError::Timeout { .. } | AWSError::Dispatch { .. } | AWSError::Service { .. } => {
+                Self::AwsUnavailable
+            }
+            AWSError::Full { .. } | AWSError::Other { .. } => Self::Failure,
+        }
+    }
+
+    /// The table printed by the `exit-codes` subcommand.
+    pub fn table() -> String {
+        Self::ALL
+            .iter()
+            .map(|exit| format!("{}  {}", exit.code(), exit.description()))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// Marks an error found while setting up from the configuration, e.g. an unreadable certificate file, so doorman exits
+/// with [`Exit::Config`].
+#[derive(Debug)]
+pub struct InvalidConfig;
+
+impl Error for InvalidConfig {}
+
+impl fmt::Display for InvalidConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid configuration")
+    }
+}
+
+/// EC2 error codes meaning the caller isn't allowed in.
+const AUTH_ERROR_CODES: [&str; 7] = [
+    "AuthFailure",
+    "UnauthorizedOperation",
+    "InvalidClientTokenId",
+    "ExpiredToken",
+    "RequestExpired",
+    "SignatureDoesNotMatch",
+    "OptInRequired",
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aws::RequestIds;
+    use color_eyre::eyre::eyre;
+
+    fn service(code: &str) -> AWSError {
+        AWSError::Service {
+            operation: "modify_entries",
+            code: Some(code.to_string()),
+            message: "failed".to_string(),
+            request: RequestIds::default(),
+        }
+    }
+
+    /// The code doorman exits with for `err`, wrapped like errors coming out of `work`.
+    fn code(err: impl Into<Report>) -> i32 {
+        let report: Report = err.into();
+        Exit::of(&report.wrap_err("[office] Giving up")).code()
+    }
+
+    #[test]
+    fn not_found_exits_with_4() {
+        let not_found = AWSError::NotFound {
+            prefix_list_id: "pl-12345678".to_string(),
+        };
+        assert_eq!(code(not_found), 4);
+        assert_eq!(code(service("InvalidPrefixListID.NotFound")), 4);
+    }
+
+    #[test]
+    fn auth_exits_with_3() {
+        for auth in AUTH_ERROR_CODES {
+            assert_eq!(code(service(auth)), 3, "{}", auth);
+        }
+    }
+
+    #[test]
+    fn full_exits_with_1() {
+        let full = AWSError::Full {
+            prefix_list_id: "pl-12345678".to_string(),
+            max_entries: 10,
+            foreign: 10,
+        };
+        assert_eq!(code(full), 1);
+    }
+
+    #[test]
+    fn timeout_exits_with_5() {
+        let timeout = AWSError::Timeout {
+            operation: "modify_entries",
+        };
+        assert_eq!(code(timeout), 5);
+    }
+
+    #[test]
+    fn dispatch_exits_with_5() {
+        let dispatch = AWSError::Dispatch {
+            operation: "modify_entries",
+            message: "connection refused".to_string(),
+        };
+        assert_eq!(code(dispatch), 5);
+        assert_eq!(code(service("InternalError")), 5);
+    }
+
+    #[test]
+    fn config_exits_with_2() {
+        let invalid = eyre!("Failed to read /etc/doorman/ca.pem").wrap_err(InvalidConfig);
+        assert_eq!(code(invalid), 2);
+    }
+
+    #[test]
+    fn anything_else_exits_with_1() {
+        assert_eq!(code(eyre!("something broke")), 1);
+    }
+
+    #[test]
+    fn codes_are_distinct() {
+        let mut codes: Vec<i32> = Exit::ALL.iter().map(|exit| exit.code()).collect();
+        codes.sort_unstable();
+        codes.dedup();
+        assert_eq!(codes.len(), Exit::ALL.len());
+        assert_eq!(Exit::table().lines().count(), Exit::ALL.len());
+    }
+}
@@ -0,0 +1,173 @@
+use crate::audit::{block_on, client};
+use crate::aws::{AWSClient, EntryFilter, PrefixListApi};
+use crate::config::{Description, PrefixListId};
+use crate::exit::Exit;
+
+use aws_sdk_ec2::model::PrefixListState;
+use chrono::{DateTime, Duration, Utc};
+use color_eyre::Result;
+
+/// Runs the `cleanup-expired` subcommand, telling how doorman should exit.
+///
+/// Entries are only removed once their expiry is more than `skew` seconds in the past, which leaves room for the
+/// clocks of the hosts writing them to drift. Entries without an expiry are reported, but left alone.
+pub fn run(
+    prefix_list_ids: Vec<PrefixListId>,
+    description: Description,
+    region: Option<String>,
+    skew: u64,
+) -> Exit {
+    block_on(async {
+        let deadline = Utc::now() - Duration::seconds(skew as i64);
+        cleanup_expired(client(region).await, prefix_list_ids, description, deadline).await?;
+        Ok(Exit::Success)
+    })
+}
+
+/// Removes the entries owned by `description` which expired before `deadline`, returning how many were per prefix
+/// list.
+async fn cleanup_expired(
+    api: impl PrefixListApi + 'static,
+    prefix_list_ids: Vec<PrefixListId>,
+    description: Description,
+    deadline: DateTime<Utc>,
+) -> Result<Vec<usize>> {
+    let aws = AWSClient::builder()
+        .api(api)
+        .description(description.clone())
+        .build()?;
+    let mut removed = Vec::new();
+    for prefix_list_id in prefix_list_ids {
+        // Removing the entries fails if they changed since they were listed
+        let latest = aws.get_prefix_list(&prefix_list_id).await?;
+        let mut expired = Vec::new();
+        for entry in aws.entries(&latest, EntryFilter::Owned).await? {
+            match description.embedded_timestamp(&entry) {
+                Some(at) if at < deadline => {
+                    println!(
+                        "{}: {} (\"{}\") expired at {}",
+                        prefix_list_id, entry.cidr, entry.description, at
+                    );
+                    expired.push(entry.cidr);
+                }
+                Some(_) => {}
+                None => eprintln!(
+                    "{}: {} (\"{}\") has no expiry, leaving it",
+                    prefix_list_id, entry.cidr, entry.description
+                ),
+            }
+        }
+        removed.push(expired.len());
+        if expired.is_empty() {
+            eprintln!("{}: nothing expired", prefix_list_id);
+            continue;
+        }
+
+        let count = expired.len();
+        let modified = aws.modify_entries(&latest, vec![], expired).await?;
+        let modified = aws
+            .wait_for_state(
+                &prefix_list_id,
+                PrefixListState::ModifyComplete,
+                modified.version,
+            )
+            .await?;
+        println!(
+            "{}: removed {} expired entries, now at version {}",
+            prefix_list_id,
+            count,
+            modified
+                .version
+                .map_or_else(|| "?".to_string(), |version| version.to_string())
+        );
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aws::FakeApi;
+
+    const ID: &str = "pl-12345678";
+    const OTHER: &str = "pl-87654321";
+
+    fn at(timestamp: &str) -> DateTime<Utc> {
+        timestamp.parse().unwrap()
+    }
+
+    /// Cleans up the prefix lists as if it were 10:00, with 5 minutes of skew allowed.
+    async fn cleaned(api: &FakeApi, prefix_list_ids: &[&str]) -> Vec<usize> {
+        cleanup_expired(
+            api.clone(),
+            prefix_list_ids
+                .iter()
+                .map(|id| id.parse().unwrap())
+                .collect(),
+            "office".parse().unwrap(),
+            at("2022-09-01T09:55:00Z"),
+        )
+        .await
+        .unwrap()
+    }
+
+    fn cidrs(api: &FakeApi, prefix_list_id: &str) -> Vec<String> {
+        api.entries(prefix_list_id)
+            .into_iter()
+            .map(|(cidr, _)| cidr)
+            .collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn expired_entries_are_removed_whichever_host_wrote_them() {
+        let api = FakeApi::default().with_prefix_list(
+            ID,
+            None,
+            &[
+                ("192.0.2.1/32", "office @ 2022-09-01T09:00:00Z"),
+                ("192.0.2.2/32", "office @ laptop @ 2022-09-01T08:00:00Z"),
+                ("192.0.2.3/32", "office @ desktop @ 2022-09-01T11:00:00Z"),
+                // Within the skew, the writer's clock may be behind
+                ("192.0.2.4/32", "office @ 2022-09-01T09:58:00Z"),
+                ("192.0.2.5/32", "office @ laptop"),
+                ("192.0.2.6/32", "office"),
+                ("192.0.2.7/32", "officer @ 2022-09-01T09:00:00Z"),
+            ],
+        );
+        assert_eq!(cleaned(&api, &[ID]).await, [2]);
+        assert_eq!(
+            cidrs(&api, ID),
+            [
+                "192.0.2.3/32",
+                "192.0.2.4/32",
+                "192.0.2.5/32",
+                "192.0.2.6/32",
+                "192.0.2.7/32"
+            ]
+        );
+        assert_eq!(api.modifications().len(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn prefix_lists_without_expired_entries_are_left_alone() {
+        let api = FakeApi::default()
+            .with_prefix_list(
+                ID,
+                None,
+                &[
+                    ("192.0.2.1/32", "office @ 2022-09-01T11:00:00Z"),
+                    ("192.0.2.2/32", "office"),
+                ],
+            )
+            .with_prefix_list(
+                OTHER,
+                None,
+                &[("192.0.2.1/32", "office @ 2022-09-01T09:00:00Z")],
+            );
+        assert_eq!(cleaned(&api, &[ID, OTHER]).await, [0, 1]);
+        assert_eq!(cidrs(&api, ID).len(), 2);
+        assert!(cidrs(&api, OTHER).is_empty());
+        assert_eq!(api.modifications().len(), 1);
+        assert_eq!(api.version(ID), 1);
+    }
+}
@@ -0,0 +1,115 @@
+//! What the fuzz targets in `fuzz/` run, each on whatever bytes the fuzzer came up with.
+//!
+//! These only look for panics, so what the parsers return is thrown away. The tests replay the corpus of each target,
+//! which keeps checking the inputs that once found a panic without running a fuzzer.
+
+use crate::aws::{all_entries, describe_response, Entry, RequestIds};
+use crate::cidr::HostCidr;
+use crate::config::{Description, HeaderSpec, PauseWindow, PrefixListId, Since, TargetSpec};
+use crate::logging::LogTemplate;
+use crate::simulate::{FixtureApi, FixtureIps, IpSource};
+
+use aws_sdk_ec2::model::PrefixListEntry;
+use futures::executor::block_on;
+use hyper::{HeaderMap, StatusCode};
+
+/// An entry as listed from a prefix list, its CIDR and description being separated by the first newline.
+pub fn entry(data: &[u8]) {
+    let data = String::from_utf8_lossy(data);
+    let (cidr, description) = data.split_once('\n').unwrap_or((&data, ""));
+    let entry = PrefixListEntry::builder()
+        .cidr(cidr)
+        .description(description)
+        .build();
+    owned(&entry);
+}
+
+/// Goes through what doorman does with an entry listed from AWS.
+fn owned(entry: &PrefixListEntry) {
+    let entry = match Entry::try_from(entry) {
+        Ok(entry) => entry,
+        Err(_) => return,
+    };
+    let _ = HostCidr::try_from(entry.cidr);
+    let description: Description = "office".parse().unwrap();
+    description.matches_owned_entry(&entry);
+    description.host(&entry);
+    description.embedded_timestamp(&entry);
+    let _ = description.for_host(&entry.description);
+}
+
+/// The body of an error response, which may come from AWS or from anything in between, like a proxy.
+pub fn error_body(data: &[u8]) {
+    RequestIds::from_response(&HeaderMap::new(), Some(data));
+    describe_response(StatusCode::BAD_GATEWAY, Some(data));
+}
+
+/// The IPs and prefix lists of a `--simulate` fixture, a JSON file each.
+pub fn fixture(data: &[u8]) {
+    let data = String::from_utf8_lossy(data);
+    if let Ok(mut ips) = FixtureIps::from_json(&data) {
+        let _ = block_on(ips.addresses());
+    }
+    if let Ok(api) = FixtureApi::from_json(&data) {
+        let id: PrefixListId = "pl-12345678".parse().unwrap();
+        for entry in block_on(all_entries(&api, &id)).unwrap_or_default() {
+            owned(&entry);
+        }
+    }
+}
+
+/// The values given on the command line or in the configuration file.
+pub fn values(data: &[u8]) {
+    let data = String::from_utf8_lossy(data);
+    let _ = data.parse::<PrefixListId>();
+    let _ = data.parse::<TargetSpec>();
+    let _ = data.parse::<Description>();
+    let _ = data.parse::<HeaderSpec>();
+    let _ = data.parse::<PauseWindow>();
+    let _ = data.parse::<Since>();
+    let _ = data.parse::<LogTemplate>();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+
+    /// Runs `target` on every input of its corpus.
+    fn replay(name: &str, target: fn(&[u8])) {
+        let corpus = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("fuzz/corpus")
+            .join(name);
+        let mut inputs = 0;
+        for input in fs::read_dir(&corpus).unwrap() {
+            let path = input.unwrap().path();
+            let data = fs::read(&path).unwrap();
+            // The input is printed by the test harness along with the panic
+            eprintln!("Replaying {}", path.display());
+            target(&data);
+            inputs += 1;
+        }
+        assert!(inputs > 0, "{} is empty", corpus.display());
+    }
+
+    #[test]
+    fn entry_corpus() {
+        replay("entry", entry);
+    }
+
+    #[test]
+    fn error_body_corpus() {
+        replay("error_body", error_body);
+    }
+
+    #[test]
+    fn fixture_corpus() {
+        replay("fixture", fixture);
+    }
+
+    #[test]
+    fn values_corpus() {
+        replay("values", values);
+    }
+}
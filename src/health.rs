@@ -0,0 +1,168 @@
+use chrono::{DateTime, Utc};
+use hyper::header::{HeaderValue, CONTENT_TYPE};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use serde::Serialize;
+use std::fmt::Display;
+use std::sync::{Arc, Mutex};
+use tokio::time::{Duration, Instant};
+use tracing::{info, warn};
+
+struct State {
+    /// When the main loop last started a check, or doorman started
+    last_tick: Instant,
+    last_success: Option<(Instant, DateTime<Utc>)>,
+    last_error: Option<String>,
+    /// When the prefix lists were last found to hold the IP, if verification is enabled
+    last_verified: Option<DateTime<Utc>>,
+    /// What the last verification found wrong
+    verification_error: Option<String>,
+    /// What readiness was last reported as, to log its changes
+    ready: bool,
+    /// Whether the network is down, which isn't doorman failing
+    offline: bool,
+}
+
+/// What the probes get.
+#[derive(Serialize)]
+struct Status<'a> {
+    status: &'static str,
+    last_success: Option<String>,
+    last_error: Option<&'a str>,
+    last_verified: Option<String>,
+    verification_error: Option<&'a str>,
+}
+
+/// Whether doorman is alive and keeping the prefix lists up to date, for liveness and readiness probes.
+///
+/// This only reports what the main loop recorded, it never calls AWS itself.
+#[derive(Clone)]
+pub struct Health {
+    state: Arc<Mutex<State>>,
+    /// Doorman is considered stuck, or not ready, past this long without a tick, or a success
+    max_age: Duration,
+}
+
+impl Health {
+    /// `interval` is how often the main loop checks the IP.
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(State {
+                last_tick: Instant::now(),
+                last_success: None,
+                last_error: None,
+                last_verified: None,
+                verification_error: None,
+                ready: false,
+                offline: false,
+            })),
+            max_age: interval * 2,
+        }
+    }
+
+    /// The main loop started a check.
+    pub fn tick(&self) {
+        self.state.lock().unwrap().last_tick = Instant::now();
+    }
+
+    /// Records how a check went, `failure` being its last error if any.
+    pub fn checked(&self, failure: Option<String>) {
+        match failure {
+            Some(error) => self.failed(error),
+            None => self.succeeded(),
+        }
+    }
+
+    /// Every prefix list holds the current IP.
+    fn succeeded(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.last_success = Some((Instant::now(), Utc::now()));
+        state.last_error = None;
+        self.update_readiness(&mut state);
+    }
+
+    pub fn failed(&self, error: impl Display) {
+        let mut state = self.state.lock().unwrap();
+        state.last_error = Some(error.to_string());
+        self.update_readiness(&mut state);
+    }
+
+    /// The network went down or came back.
+    pub fn offline(&self, offline: bool) {
+        self.state.lock().unwrap().offline = offline;
+    }
+
+    /// Records a verification pass, `problem` being what it found wrong if anything.
+    pub fn verified(&self, problem: Option<String>) {
+        let mut state = self.state.lock().unwrap();
+        if problem.is_none() {
+            state.last_verified = Some(Utc::now());
+        }
+        state.verification_error = problem;
+        self.update_readiness(&mut state);
+    }
+
+    /// Whether the last success is recent enough, and the last verification passed, logging when that changes.
+    fn update_readiness(&self, state: &mut State) -> bool {
+        let ready = state
+            .last_success
+            .is_some_and(|(at, _)| at.elapsed() <= self.max_age)
+            && state.verification_error.is_none();
+        if ready != state.ready {
+            match ready {
+                true => info!("Ready: the prefix lists are up to date."),
+                false => warn!(
+                    "Not ready: no successful check within {} seconds, or failed verification. Last error: {}",
+                    self.max_age.as_secs(),
+                    state
+                        .verification_error
+                        .as_deref()
+                        .or(state.last_error.as_deref())
+                        .unwrap_or("none")
+                ),
+            }
+            state.ready = ready;
+        }
+        ready
+    }
+
+    /// Answers `GET /healthz` and `GET /readyz`.
+    pub fn respond(&self, request: &Request<Body>) -> Response<Body> {
+        let mut state = self.state.lock().unwrap();
+        let healthy = match (request.method(), request.uri().path()) {
+            (&Method::GET, "/healthz") => state.last_tick.elapsed() <= self.max_age,
+            (&Method::GET, "/readyz") => self.update_readiness(&mut state),
+            _ => {
+                let mut response = Response::default();
+                *response.status_mut() = StatusCode::NOT_FOUND;
+                return response;
+            }
+        };
+
+        // Nothing can be done while offline, which is reported apart from failing
+        let (healthy, status) = match (healthy, state.offline) {
+            (_, true) => (true, "degraded: offline"),
+            (true, false) => (true, "ok"),
+            (false, false) => (false, "unavailable"),
+        };
+        let status = Status {
+            status,
+            last_success: state
+                .last_success
+                .map(|(_, at)| at.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)),
+            last_error: state.last_error.as_deref(),
+            last_verified: state
+                .last_verified
+                .map(|at| at.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)),
+            verification_error: state.verification_error.as_deref(),
+        };
+        // Serializing strings can't fail
+        let mut response = Response::new(Body::from(serde_json::to_string(&status).unwrap()));
+        if !healthy {
+            *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+        }
+        response
+            .headers_mut()
+            .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        response
+    }
+}
@@ -0,0 +1,54 @@
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+/// A small HTTP server running in the background until shut down.
+///
+/// Requests are answered by `handler` from state doorman already has, so they never wait on anything.
+pub struct HttpServer {
+    stop: oneshot::Sender<()>,
+    handle: JoinHandle<()>,
+}
+
+impl HttpServer {
+    /// Fails right away if `addr` can't be listened on. `name` tells the servers apart in the logs.
+    pub fn start<F>(addr: SocketAddr, name: &'static str, handler: F) -> hyper::Result<Self>
+    where
+        F: Fn(&Request<Body>) -> Response<Body> + Clone + Send + Sync + 'static,
+    {
+        let make_service = make_service_fn(move |_| {
+            let handler = handler.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    let response = handler(&request);
+                    async move { Ok::<_, Infallible>(response) }
+                }))
+            }
+        });
+
+        let (stop, stopped) = oneshot::channel();
+        let server = Server::try_bind(&addr)?
+            .serve(make_service)
+            .with_graceful_shutdown(async {
+                let _ = stopped.await;
+            });
+        info!("Serving {} on http://{}", name, addr);
+        let handle = tokio::spawn(async move {
+            if let Err(err) = server.await {
+                error!("The {} server failed: {}", name, err);
+            }
+        });
+
+        Ok(Self { stop, handle })
+    }
+
+    /// Stops accepting connections and waits for the ongoing requests to finish.
+    pub async fn shutdown(self) {
+        let _ = self.stop.send(());
+        let _ = self.handle.await;
+    }
+}
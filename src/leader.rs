@@ -0,0 +1,381 @@
+use crate::aws::AWSError;
+use crate::recheck::Recheck;
+
+use aws_sdk_dynamodb::model::AttributeValue;
+use aws_sdk_dynamodb::types::SdkError;
+use aws_sdk_dynamodb::Client;
+use chrono::Utc;
+use std::sync::{Arc, Mutex};
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, Duration, Instant};
+use tracing::{debug, warn};
+
+/// The attributes of the lock item. `expires` can be used as the table's TTL attribute.
+const KEY: &str = "lock_id";
+const OWNER: &str = "owner";
+const EXPIRES: &str = "expires";
+
+/// What this doorman does in a redundant pair.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    /// Holds the lease, and modifies the prefix lists
+    Leader,
+    /// Another doorman holds the lease, this one stays ready to take over
+    Follower,
+    /// The lease couldn't be read or renewed, so this doorman can't tell and leaves the prefix lists alone
+    Unknown,
+}
+
+impl Role {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Leader => "leader",
+            Self::Follower => "follower",
+            Self::Unknown => "unknown",
+        }
+    }
+}
+
+/// Decides which doorman of a redundant pair modifies the prefix lists, through a lease in a DynamoDB item.
+///
+/// The item tells who holds the lease and when it expires. Taking or renewing it is a conditional write which only
+/// goes through while the lease is free, expired or already ours, so at most one doorman leads. The lease is renewed
+/// every third of its duration, but only trusted for half of it, so the clocks may be off by up to half the lease,
+/// less how long a check takes.
+/// Whenever the item can't be written, doorman stops acting once what it knows of its lease runs out: two writers
+/// are worse than none.
+#[derive(Clone)]
+pub struct Election(Arc<Inner>);
+
+struct Inner {
+    client: Client,
+    table: String,
+    key: String,
+    /// Who this doorman is in the item, the hostname and PID
+    owner: String,
+    lease: Duration,
+    state: Mutex<Lease>,
+    renewing: Mutex<Option<JoinHandle<()>>>,
+}
+
+struct Lease {
+    role: Role,
+    /// Until when the lease is trusted to be ours, by the local clock
+    until: Option<Instant>,
+}
+
+impl Election {
+    pub fn new(client: Client, table: String, key: String, lease: Duration) -> Self {
+        let hostname = hostname::get()
+            .map(|hostname| hostname.to_string_lossy().into_owned())
+            .unwrap_or_else(|_| "unknown".to_string());
+        Self(Arc::new(Inner {
+            client,
+            table,
+            key,
+            owner: format!("{}/{}", hostname, std::process::id()),
+            lease,
+            state: Mutex::new(Lease {
+                role: Role::Unknown,
+                until: None,
+            }),
+            renewing: Mutex::new(None),
+        }))
+    }
+
+    pub fn owner(&self) -> &str {
+        &self.0.owner
+    }
+
+    /// The role as of now. A lease which couldn't be renewed in time makes it unknown.
+    pub fn role(&self) -> Role {
+        let state = self.0.state.lock().unwrap();
+        match (state.role, state.until) {
+            (Role::Leader, Some(until)) if Instant::now() < until => Role::Leader,
+            (Role::Leader, _) => Role::Unknown,
+            (role, _) => role,
+        }
+    }
+
+    /// Keeps renewing the lease in the background, triggering a check whenever the role changes.
+    pub fn start(&self, recheck: Recheck) {
+        let election = self.clone();
+        let handle = tokio::spawn(async move {
+            loop {
+                sleep(election.0.lease / 3).await;
+                let before = election.role();
+                if election.renew().await != before {
+                    recheck.trigger();
+                }
+            }
+        });
+        *self.0.renewing.lock().unwrap() = Some(handle);
+    }
+
+    /// Takes or renews the lease, returning the role it leads to.
+    pub async fn renew(&self) -> Role {
+        let Inner {
+            client,
+            table,
+            key,
+            owner,
+            lease,
+            ..
+        } = &*self.0;
+        let started = Instant::now();
+        let now = Utc::now().timestamp();
+        let result = client
+            .put_item()
+            .table_name(table)
+            .item(KEY, AttributeValue::S(key.clone()))
+            .item(OWNER, AttributeValue::S(owner.clone()))
+            .item(
+                EXPIRES,
+                AttributeValue::N((now + lease.as_secs() as i64).to_string()),
+            )
+            .condition_expression(
+                "attribute_not_exists(#key) OR #owner = :owner OR #expires < :now",
+            )
+            .expression_attribute_names("#key", KEY)
+            .expression_attribute_names("#owner", OWNER)
+            .expression_attribute_names("#expires", EXPIRES)
+            .expression_attribute_values(":owner", AttributeValue::S(owner.clone()))
+            .expression_attribute_values(":now", AttributeValue::N(now.to_string()))
+            .send()
+            .await;
+        let mut state = self.0.state.lock().unwrap();
+        match result {
+            Ok(_) => {
+                debug!("Holding the leader lease {} in {}.", key, table);
+                state.role = Role::Leader;
+                state.until = Some(started + *lease / 2);
+            }
+            Err(SdkError::ServiceError { err, .. })
+                if err.is_conditional_check_failed_exception() =>
+            {
+                debug!(
+                    "Another doorman holds the leader lease {} in {}.",
+                    key, table
+                );
+                state.role = Role::Follower;
+                state.until = None;
+            }
+            Err(err) => {
+                warn!(
+                    "Failed to renew the leader lease {} in {}: {}",
+                    key,
+                    table,
+                    AWSError::from_sdk("PutItem", err)
+                );
+                // A lease still believed to be ours stays so until it runs out, see `role`
+                if state.role != Role::Leader {
+                    state.role = Role::Unknown;
+                }
+            }
+        }
+        drop(state);
+        self.role()
+    }
+
+    /// Stops renewing and gives the lease up if it's ours, so the other doorman takes over right away.
+    pub async fn release(&self) {
+        if let Some(handle) = self.0.renewing.lock().unwrap().take() {
+            handle.abort();
+        }
+        let Inner {
+            client,
+            table,
+            key,
+            owner,
+            ..
+        } = &*self.0;
+        let result = client
+            .delete_item()
+            .table_name(table)
+            .key(KEY, AttributeValue::S(key.clone()))
+            .condition_expression("#owner = :owner")
+            .expression_attribute_names("#owner", OWNER)
+            .expression_attribute_values(":owner", AttributeValue::S(owner.clone()))
+            .send()
+            .await;
+        match result {
+            Ok(_) => debug!("Released the leader lease {} in {}.", key, table),
+            Err(SdkError::ServiceError { err, .. }) if err.is_conditional_check_failed_exception() => {}
+            Err(err) => warn!(
+                "Failed to release the leader lease {} in {}, the other doorman takes over once it expires: {}",
+                key,
+                table,
+                AWSError::from_sdk("DeleteItem", err)
+            ),
+        }
+        let mut state = self.0.state.lock().unwrap();
+        state.role = Role::Unknown;
+        state.until = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aws::retry_config;
+    use aws_sdk_dynamodb::{Config, Credentials, Region};
+    use aws_smithy_client::test_connection::TestConnection;
+    use aws_smithy_http::body::SdkBody;
+    use serde_json::Value;
+    use tokio::time::advance;
+
+    const LEASE: Duration = Duration::from_secs(60);
+
+    fn response(status: u16, body: &str) -> http::Response<String> {
+        http::Response::builder()
+            .status(status)
+            .body(body.to_string())
+            .unwrap()
+    }
+
+    fn written() -> http::Response<String> {
+        response(200, "{}")
+    }
+
+    /// Someone else holds the lease.
+    fn held() -> http::Response<String> {
+        response(
+            400,
+            r#"{"__type": "com.amazonaws.dynamodb.v20120810#ConditionalCheckFailedException", "message": "The conditional request failed"}"#,
+        )
+    }
+
+    fn broken() -> http::Response<String> {
+        response(
+            500,
+            r#"{"__type": "com.amazonaws.dynamodb.v20120810#InternalServerError", "message": "Internal server error"}"#,
+        )
+    }
+
+    /// An election answered with each of `responses` in turn, without retries.
+    fn election(responses: Vec<http::Response<String>>) -> (Election, TestConnection<String>) {
+        // Requests are checked once sent rather than against expected ones
+        let events = responses
+            .into_iter()
+            .map(|response| (http::Request::new(SdkBody::empty()), response))
+            .collect();
+        let conn = TestConnection::new(events);
+        let conf = Config::builder()
+            .region(Region::new("us-east-1"))
+            .credentials_provider(Credentials::new("test", "test", None, None, "test"))
+            .retry_config(retry_config(1))
+            .build();
+        let election = Election::new(
+            Client::from_conf_conn(conf, conn.clone()),
+            "doorman".to_string(),
+            "office".to_string(),
+            LEASE,
+        );
+        (election, conn)
+    }
+
+    /// The operation and JSON body of the `n`th request.
+    fn request(conn: &TestConnection<String>, n: usize) -> (String, Value) {
+        let requests = conn.requests();
+        let request = &requests[n].actual;
+        (
+            request.headers()["x-amz-target"]
+                .to_str()
+                .unwrap()
+                .to_string(),
+            serde_json::from_slice(request.body().bytes().unwrap()).unwrap(),
+        )
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn taking_the_lease_leads_for_half_of_it() {
+        let (election, conn) = election(vec![written()]);
+        assert_eq!(election.role(), Role::Unknown);
+        assert_eq!(election.renew().await, Role::Leader);
+
+        let (operation, body) = request(&conn, 0);
+        assert_eq!(operation, "DynamoDB_20120810.PutItem");
+        assert_eq!(body["TableName"], "doorman");
+        assert_eq!(body["Item"]["lock_id"]["S"], "office");
+        assert_eq!(body["Item"]["owner"]["S"], election.owner());
+        let expires: i64 = body["Item"]["expires"]["N"]
+            .as_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!((expires - Utc::now().timestamp() - 60).abs() <= 1);
+        // Only written while free, expired or ours
+        assert_eq!(
+            body["ConditionExpression"],
+            "attribute_not_exists(#key) OR #owner = :owner OR #expires < :now"
+        );
+        assert_eq!(
+            body["ExpressionAttributeValues"][":owner"]["S"],
+            election.owner()
+        );
+
+        advance(LEASE / 2 - Duration::from_secs(1)).await;
+        assert_eq!(election.role(), Role::Leader);
+        advance(Duration::from_secs(1)).await;
+        assert_eq!(election.role(), Role::Unknown);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_lease_held_by_another_makes_a_follower_until_it_expires() {
+        let (election, _conn) = election(vec![held(), held(), written()]);
+        assert_eq!(election.renew().await, Role::Follower);
+        // Following doesn't run out
+        advance(LEASE).await;
+        assert_eq!(election.role(), Role::Follower);
+        assert_eq!(election.renew().await, Role::Follower);
+        assert_eq!(election.renew().await, Role::Leader);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_lease_which_cant_be_read_leads_nowhere() {
+        let (election, _conn) = election(vec![broken(), held(), broken()]);
+        assert_eq!(election.renew().await, Role::Unknown);
+        assert_eq!(election.renew().await, Role::Follower);
+        // A follower can't tell either whether the lease is still held
+        assert_eq!(election.renew().await, Role::Unknown);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_lease_which_cant_be_renewed_is_only_trusted_until_it_runs_out() {
+        let (election, _conn) = election(vec![written(), broken(), broken()]);
+        assert_eq!(election.renew().await, Role::Leader);
+        advance(LEASE / 3).await;
+        assert_eq!(election.renew().await, Role::Leader);
+        advance(LEASE / 6).await;
+        // The other doorman may take over once the lease expires, so this one stops before
+        assert_eq!(election.role(), Role::Unknown);
+        assert_eq!(election.renew().await, Role::Unknown);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn releasing_gives_up_only_our_lease() {
+        let (election, conn) = election(vec![written(), written()]);
+        election.renew().await;
+        election.release().await;
+        assert_eq!(election.role(), Role::Unknown);
+
+        let (operation, body) = request(&conn, 1);
+        assert_eq!(operation, "DynamoDB_20120810.DeleteItem");
+        assert_eq!(body["TableName"], "doorman");
+        assert_eq!(body["Key"]["lock_id"]["S"], "office");
+        assert_eq!(body["ConditionExpression"], "#owner = :owner");
+        assert_eq!(
+            body["ExpressionAttributeValues"][":owner"]["S"],
+            election.owner()
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn releasing_someone_elses_lease_is_quiet() {
+        let (election, conn) = election(vec![held(), held()]);
+        assert_eq!(election.renew().await, Role::Follower);
+        election.release().await;
+        assert_eq!(conn.requests().len(), 2);
+        assert_eq!(election.role(), Role::Unknown);
+    }
+}
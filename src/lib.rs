@@ -0,0 +1,40 @@
+//! Doorman is a binary, this library is only built for the fuzz targets in `fuzz/`, which need one to link against.
+//!
+//! It's empty unless cargo-fuzz builds it, so the modules aren't built twice otherwise.
+#![cfg(fuzzing)]
+// Most of doorman is only used by the binary
+#![allow(dead_code, unused_imports)]
+
+mod audit;
+mod audit_log;
+mod aws;
+mod breaker;
+mod cidr;
+mod config;
+mod connectivity;
+mod control;
+#[cfg(unix)]
+mod daemon;
+mod doorman;
+mod exit;
+mod expired;
+pub mod fuzz;
+mod health;
+mod http;
+mod leader;
+mod lock;
+mod logging;
+mod metrics;
+mod mqtt;
+mod notification;
+mod pause;
+mod plan;
+mod recheck;
+mod reload;
+mod schedule;
+mod shutdown;
+mod simulate;
+mod state;
+mod stats;
+mod status;
+mod systemd;
@@ -0,0 +1,96 @@
+use crate::config::Target;
+
+use color_eyre::{eyre::eyre, eyre::WrapErr, Result};
+use fs2::FileExt;
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use tracing::debug;
+
+/// Keeps other doorman processes away from the same entries, which they would otherwise keep replacing.
+///
+/// There is one lock per prefix list and description. The locks are held by the open files, so the system releases
+/// them however doorman stops, even when killed. The files themselves are left in place, removing them could let two
+/// processes hold the "same" lock.
+pub struct InstanceLock {
+    _files: Vec<File>,
+}
+
+impl InstanceLock {
+    pub fn acquire(targets: &[Target]) -> Result<Self> {
+        let dir = lock_dir();
+        fs::create_dir_all(&dir)
+            .wrap_err_with(|| format!("Failed to create the lock directory {}", dir.display()))?;
+
+        let mut files = Vec::with_capacity(targets.len());
+        let mut paths = Vec::with_capacity(targets.len());
+        for target in targets {
+            let path = dir.join(format!(
+                "{}-{}.lock",
+                target.prefix_list_id.as_str(),
+                sanitize(target.description.as_str())
+            ));
+            // Locking the same file twice would conflict with ourselves
+            if paths.contains(&path) {
+                continue;
+            }
+            let mut file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                // The PID of whoever holds the lock must stay until we hold it
+                .truncate(false)
+                .open(&path)
+                .wrap_err_with(|| format!("Failed to open the lock file {}", path.display()))?;
+
+            if file.try_lock_exclusive().is_err() {
+                let mut pid = String::new();
+                let _ = file.read_to_string(&mut pid);
+                let pid = match pid.trim() {
+                    "" => "unknown PID".to_string(),
+                    pid => format!("PID {}", pid),
+                };
+                return Err(eyre!(
+                    "Another doorman ({}) already manages prefix list {} with description \"{}\". \
+                    Pass --allow-multiple if this is intended.",
+                    pid,
+                    target.prefix_list_id,
+                    target.description
+                ));
+            }
+
+            file.set_len(0)?;
+            file.seek(SeekFrom::Start(0))?;
+            write!(file, "{}", std::process::id())?;
+            file.flush()?;
+            debug!("[{}] Locked {}.", target.name, path.display());
+            files.push(file);
+            paths.push(path);
+        }
+
+        Ok(Self { _files: files })
+    }
+}
+
+/// The runtime directory if there is one, as it's cleared on reboot, else the temporary directory.
+fn lock_dir() -> PathBuf {
+    env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .unwrap_or_else(env::temp_dir)
+        .join("aws_doorman")
+}
+
+/// Descriptions may hold anything, only keep what's safe in a file name.
+fn sanitize(description: &str) -> String {
+    description
+        .chars()
+        .map(
+            |c| match c.is_ascii_alphanumeric() || c == '-' || c == '.' {
+                true => c,
+                false => '_',
+            },
+        )
+        .collect()
+}
@@ -0,0 +1,171 @@
+use std::ffi::OsStr;
+use std::fmt::{self, Write};
+use std::io;
+use std::os::windows::ffi::OsStrExt;
+use std::ptr::{null, null_mut};
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_log::NormalizeEvent;
+use tracing_subscriber::layer::{Context, Layer};
+use windows_sys::Win32::Foundation::{ERROR_SUCCESS, HANDLE};
+use windows_sys::Win32::System::EventLog::{
+    DeregisterEventSource, RegisterEventSourceW, ReportEventW, EVENTLOG_ERROR_TYPE,
+    EVENTLOG_INFORMATION_TYPE, EVENTLOG_WARNING_TYPE,
+};
+use windows_sys::Win32::System::Registry::{
+    RegCloseKey, RegCreateKeyExW, RegSetValueExW, HKEY, HKEY_LOCAL_MACHINE, KEY_SET_VALUE,
+    REG_DWORD, REG_EXPAND_SZ, REG_OPTION_NON_VOLATILE,
+};
+
+/// Name of the event source in the Application log.
+pub const SOURCE: &str = "aws_doorman";
+
+/// Ships with the .NET Framework, and renders the text it's given as is, so doorman doesn't need a message file.
+const MESSAGE_FILE: &str =
+    r"%SystemRoot%\Microsoft.NET\Framework64\v4.0.30319\EventLogMessages.dll";
+/// Errors, warnings and information
+const TYPES_SUPPORTED: u32 = 7;
+
+/// Writes events to the Windows Application log.
+pub struct EventLog {
+    handle: HANDLE,
+}
+
+impl EventLog {
+    /// Registers the event source, which needs administrator rights the first time.
+    pub fn register() -> io::Result<Self> {
+        register_source()?;
+        let handle = unsafe { RegisterEventSourceW(null(), wide(SOURCE).as_ptr()) };
+        if handle == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { handle })
+    }
+
+    fn report(&self, level: &Level, message: &str) {
+        let event_type = match *level {
+            Level::ERROR => EVENTLOG_ERROR_TYPE,
+            Level::WARN => EVENTLOG_WARNING_TYPE,
+            _ => EVENTLOG_INFORMATION_TYPE,
+        };
+        let message = wide(message);
+        let strings = [message.as_ptr()];
+        // Failing to log has nowhere to be reported
+        unsafe {
+            ReportEventW(
+                self.handle,
+                event_type,
+                0,
+                0,
+                null_mut(),
+                1,
+                0,
+                strings.as_ptr(),
+                null(),
+            );
+        }
+    }
+}
+
+impl Drop for EventLog {
+    fn drop(&mut self) {
+        unsafe {
+            DeregisterEventSource(self.handle);
+        }
+    }
+}
+
+impl<S: Subscriber> Layer<S> for EventLog {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        // Records from crates still using `log` carry their actual level in their fields
+        let normalized = event.normalized_metadata();
+        let metadata = normalized.as_ref().unwrap_or_else(|| event.metadata());
+        let mut message = Message::default();
+        event.record(&mut message);
+        self.report(
+            metadata.level(),
+            &format!("[{}] {}", metadata.target(), message.0),
+        );
+    }
+}
+
+/// The message of an event, followed by its other fields.
+#[derive(Default)]
+struct Message(String);
+
+impl Visit for Message {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name().starts_with("log.") {
+            return;
+        }
+        if !self.0.is_empty() {
+            self.0.push(' ');
+        }
+        // Writing to a String can't fail
+        let _ = match field.name() {
+            "message" => write!(self.0, "{:?}", value),
+            name => write!(self.0, "{}={:?}", name, value),
+        };
+    }
+}
+
+/// Creates the registry key of the event source, so the Event Viewer can show its messages.
+fn register_source() -> io::Result<()> {
+    let path = wide(&format!(
+        r"SYSTEM\CurrentControlSet\Services\EventLog\Application\{}",
+        SOURCE
+    ));
+    let mut key: HKEY = 0;
+    let status = unsafe {
+        RegCreateKeyExW(
+            HKEY_LOCAL_MACHINE,
+            path.as_ptr(),
+            0,
+            null(),
+            REG_OPTION_NON_VOLATILE,
+            KEY_SET_VALUE,
+            null(),
+            &mut key,
+            null_mut(),
+        )
+    };
+    if status != ERROR_SUCCESS {
+        return Err(io::Error::from_raw_os_error(status as i32));
+    }
+
+    let message_file = wide(MESSAGE_FILE);
+    let mut status = unsafe {
+        RegSetValueExW(
+            key,
+            wide("EventMessageFile").as_ptr(),
+            0,
+            REG_EXPAND_SZ,
+            message_file.as_ptr().cast(),
+            (message_file.len() * 2) as u32,
+        )
+    };
+    if status == ERROR_SUCCESS {
+        status = unsafe {
+            RegSetValueExW(
+                key,
+                wide("TypesSupported").as_ptr(),
+                0,
+                REG_DWORD,
+                (&TYPES_SUPPORTED as *const u32).cast(),
+                4,
+            )
+        };
+    }
+    unsafe {
+        RegCloseKey(key);
+    }
+    match status {
+        ERROR_SUCCESS => Ok(()),
+        status => Err(io::Error::from_raw_os_error(status as i32)),
+    }
+}
+
+/// A null-terminated UTF-16 string, as the Windows API expects.
+fn wide(s: &str) -> Vec<u16> {
+    OsStr::new(s).encode_wide().chain(Some(0)).collect()
+}
@@ -0,0 +1,510 @@
+#[cfg(all(windows, feature = "eventlog"))]
+mod eventlog;
+
+use chrono::{Local, SecondsFormat, Utc};
+use reqwest::Url;
+use std::fmt::{self, Display, Write};
+use std::fs::OpenOptions;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Mutex;
+use tracing::{warn, Event, Level, Subscriber};
+use tracing_log::NormalizeEvent;
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::fmt::format::{FormatEvent, FormatFields, Writer};
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::fmt::{FmtContext, FormattedFields};
+use tracing_subscriber::layer::{Layer, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::EnvFilter;
+
+/// How log lines are timestamped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogTimestamp {
+    Utc,
+    Local,
+    Off,
+}
+
+impl LogTimestamp {
+    /// RFC 3339, with milliseconds.
+    fn now(self) -> String {
+        match self {
+            Self::Utc => Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            Self::Local => Local::now().to_rfc3339_opts(SecondsFormat::Millis, false),
+            Self::Off => String::new(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Placeholder {
+    Timestamp,
+    Level,
+    Target,
+    Spans,
+    Message,
+}
+
+impl Placeholder {
+    const NAMES: [(&'static str, Self); 5] = [
+        ("timestamp", Self::Timestamp),
+        ("level", Self::Level),
+        ("target", Self::Target),
+        ("spans", Self::Spans),
+        ("message", Self::Message),
+    ];
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    Placeholder(Placeholder),
+}
+
+/// Layout of a log line, with placeholders such as `{message}`.
+///
+/// Braces are written `{{` and `}}`. The level and target are padded, so the messages line up.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LogTemplate(Vec<Part>);
+
+impl FromStr for LogTemplate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut chars = s.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(format!("unterminated placeholder '{{{}'", name)),
+                        }
+                    }
+                    let placeholder = Placeholder::NAMES
+                        .iter()
+                        .find(|(known, _)| *known == name)
+                        .map(|(_, placeholder)| *placeholder)
+                        .ok_or_else(|| {
+                            let known: Vec<&str> =
+                                Placeholder::NAMES.iter().map(|(name, _)| *name).collect();
+                            format!(
+                                "unknown placeholder '{{{}}}', expected one of {}",
+                                name,
+                                known.join(", ")
+                            )
+                        })?;
+                    if !text.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(Part::Placeholder(placeholder));
+                }
+                '}' => return Err("unmatched '}', write '}}' for a literal brace".to_string()),
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+        if !parts.contains(&Part::Placeholder(Placeholder::Message)) {
+            return Err("the template must contain '{message}'".to_string());
+        }
+        Ok(Self(parts))
+    }
+}
+
+impl Display for LogTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for part in &self.0 {
+            match part {
+                Part::Text(text) => write!(f, "{}", text.replace('{', "{{").replace('}', "}}"))?,
+                Part::Placeholder(placeholder) => {
+                    let name = Placeholder::NAMES
+                        .iter()
+                        .find(|(_, known)| known == placeholder)
+                        .map_or("", |(name, _)| name);
+                    write!(f, "{{{}}}", name)?
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl LogTemplate {
+    /// The layout used unless one is given. Debug logs also show the spans, e.g. which check and AWS call they're from.
+    pub fn default_for(timestamp: LogTimestamp, verbose: bool) -> Self {
+        let template = match (timestamp, verbose) {
+            (LogTimestamp::Off, false) => "[ {level} ][ {target} ] {message}",
+            (LogTimestamp::Off, true) => "[ {level} ][ {target} ] {spans}{message}",
+            (_, false) => "[ {timestamp} ][ {level} ][ {target} ] {message}",
+            (_, true) => "[ {timestamp} ][ {level} ][ {target} ] {spans}{message}",
+        };
+        // The default templates are known to be valid
+        template.parse().unwrap()
+    }
+
+    /// Renders a record. Each line of a multi-line message gets its own prefix, so every line can be parsed alone.
+    fn render(
+        &self,
+        timestamp: &str,
+        level: &Level,
+        target: &str,
+        spans: &str,
+        message: &str,
+    ) -> String {
+        let mut rendered = String::new();
+        for (index, line) in message.split('\n').enumerate() {
+            if index > 0 {
+                rendered.push('\n');
+            }
+            for part in &self.0 {
+                // Writing to a String can't fail
+                let _ = match part {
+                    Part::Text(text) => write!(rendered, "{}", text),
+                    Part::Placeholder(Placeholder::Timestamp) => write!(rendered, "{}", timestamp),
+                    Part::Placeholder(Placeholder::Level) => write!(rendered, "{:5}", level),
+                    Part::Placeholder(Placeholder::Target) => write!(rendered, "{:15}", target),
+                    Part::Placeholder(Placeholder::Spans) => write!(rendered, "{}", spans),
+                    Part::Placeholder(Placeholder::Message) => write!(rendered, "{}", line),
+                };
+            }
+        }
+        rendered
+    }
+}
+
+/// Formats events along the template.
+struct LogFormat {
+    timestamp: LogTimestamp,
+    template: LogTemplate,
+}
+
+impl<S, N> FormatEvent<S, N> for LogFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        // Records from crates still using `log` carry their actual target in their fields
+        let normalized = event.normalized_metadata();
+        let metadata = normalized.as_ref().unwrap_or_else(|| event.metadata());
+
+        // Only the subscriber can make a writer over a String, which its formatted fields are
+        let mut message = FormattedFields::<N>::new(String::new());
+        ctx.format_fields(message.as_writer(), event)?;
+
+        let mut spans = String::new();
+        if let Some(scope) = ctx.event_scope() {
+            for span in scope.from_root() {
+                spans.push_str(span.name());
+                let extensions = span.extensions();
+                if let Some(fields) = extensions.get::<FormattedFields<N>>() {
+                    if !fields.is_empty() {
+                        write!(spans, "{{{}}}", fields)?;
+                    }
+                }
+                spans.push_str(": ");
+            }
+        }
+
+        writeln!(
+            writer,
+            "{}",
+            self.template.render(
+                &self.timestamp.now(),
+                metadata.level(),
+                metadata.target(),
+                &spans,
+                &message.fields
+            )
+        )
+    }
+}
+
+/// Logs to `log_file` or stdout, and to the Windows Event Log from `event_log` up if given. Doorman's spans are exported to
+/// `otlp_endpoint` if given.
+///
+/// Only warnings from other crates are shown, unless `verbose` is set. `RUST_LOG` takes precedence, e.g.
+/// `RUST_LOG=info,aws_config=debug`. It doesn't apply to the exported spans, which include every AWS call.
+///
+/// Failing to register with the Event Log, e.g. without administrator rights, only logs a warning.
+pub fn setup_logger(
+    verbose: bool,
+    timestamp: LogTimestamp,
+    template: LogTemplate,
+    otlp_endpoint: Option<&Url>,
+    event_log: Option<Level>,
+    log_file: Option<&Path>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let directives = match verbose {
+        true => "debug",
+        false => "warn,aws_doorman=info",
+    };
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(directives));
+
+    let writer = match log_file {
+        Some(path) => BoxMakeWriter::new(Mutex::new(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|err| format!("failed to open {}: {}", path.display(), err))?,
+        )),
+        None => BoxMakeWriter::new(std::io::stdout),
+    };
+
+    let otlp = otlp_endpoint
+        .map(otlp_layer)
+        .transpose()?
+        .map(|layer| layer.with_filter(Targets::new().with_target("aws_doorman", Level::DEBUG)));
+
+    #[cfg(all(windows, feature = "eventlog"))]
+    let (event_log, event_log_error) = match event_log.map(|level| {
+        eventlog::EventLog::register().map(|layer| {
+            layer.with_filter(tracing_subscriber::filter::LevelFilter::from_level(level))
+        })
+    }) {
+        Some(Ok(layer)) => (Some(layer), None),
+        Some(Err(err)) => (None, Some(err)),
+        None => (None, None),
+    };
+    #[cfg(not(all(windows, feature = "eventlog")))]
+    let (event_log, event_log_error): (
+        Option<tracing_subscriber::layer::Identity>,
+        Option<std::io::Error>,
+    ) = match event_log {
+        Some(_) => return Err("doorman was built without Windows Event Log support".into()),
+        None => (None, None),
+    };
+
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::fmt::layer()
+                .event_format(LogFormat {
+                    timestamp,
+                    template,
+                })
+                .with_writer(writer)
+                // The span fields would otherwise be colored, unlike the messages, even in log files
+                .with_ansi(false)
+                .with_filter(filter),
+        )
+        .with(otlp)
+        .with(event_log)
+        .try_init()?;
+
+    if let Some(err) = event_log_error {
+        warn!(
+            "Failed to register with the Windows Event Log, not logging there: {}",
+            err
+        );
+    }
+    Ok(())
+}
+
+/// Exports spans in batches from a thread of its own, so the main loop never waits for the collector.
+#[cfg(feature = "otlp")]
+fn otlp_layer<S>(
+    endpoint: &Url,
+) -> Result<
+    tracing_opentelemetry::OpenTelemetryLayer<S, opentelemetry::sdk::trace::Tracer>,
+    Box<dyn std::error::Error + Send + Sync>,
+>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    use opentelemetry::sdk::{trace, Resource};
+    use opentelemetry::KeyValue;
+    use opentelemetry_otlp::WithExportConfig;
+
+    // When the collector can't be reached, spans are dropped rather than cluttering the logs
+    let _ = opentelemetry::global::set_error_handler(|_| {});
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(endpoint.as_str())
+                .with_timeout(std::time::Duration::from_secs(5)),
+        )
+        .with_trace_config(
+            trace::config().with_resource(Resource::new(vec![KeyValue::new(
+                "service.name",
+                "aws_doorman",
+            )])),
+        )
+        .install_batch(opentelemetry::runtime::TokioCurrentThread)?;
+    Ok(tracing_opentelemetry::layer().with_tracer(tracer))
+}
+
+#[cfg(not(feature = "otlp"))]
+fn otlp_layer(
+    _endpoint: &Url,
+) -> Result<tracing_subscriber::layer::Identity, Box<dyn std::error::Error + Send + Sync>> {
+    Err("doorman was built without OpenTelemetry support".into())
+}
+
+/// Sends the spans not exported yet.
+pub fn shutdown() {
+    #[cfg(feature = "otlp")]
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use std::sync::Arc;
+    use tracing::{info, info_span, warn};
+
+    /// Collects what the layer writes.
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// What the layer writes for the events `log` emits.
+    fn logged(timestamp: LogTimestamp, template: &str, log: impl FnOnce()) -> String {
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let subscriber = tracing_subscriber::registry().with(
+            tracing_subscriber::fmt::layer()
+                .event_format(LogFormat {
+                    timestamp,
+                    template: template.parse().unwrap(),
+                })
+                .with_writer(move || writer.clone())
+                .with_ansi(false),
+        );
+        tracing::subscriber::with_default(subscriber, log);
+        let logged = buffer.0.lock().unwrap().clone();
+        String::from_utf8(logged).unwrap()
+    }
+
+    #[test]
+    fn default_layout_pads_the_level_and_target() {
+        let template = LogTemplate::default_for(LogTimestamp::Off, false).to_string();
+        let logged = logged(LogTimestamp::Off, &template, || {
+            info!(target: "doorman", "Updated to 192.0.2.1");
+            warn!(target: "doorman", "Retrying");
+        });
+        assert_eq!(
+            logged,
+            "[ INFO  ][ doorman         ] Updated to 192.0.2.1\n\
+             [ WARN  ][ doorman         ] Retrying\n"
+        );
+    }
+
+    #[test]
+    fn every_line_of_a_message_gets_the_prefix() {
+        let template = LogTemplate::default_for(LogTimestamp::Off, false).to_string();
+        let logged = logged(LogTimestamp::Off, &template, || {
+            info!(target: "doorman", "Prefix lists:\n  pl-1\n  pl-2");
+        });
+        assert_eq!(
+            logged,
+            "[ INFO  ][ doorman         ] Prefix lists:\n\
+             [ INFO  ][ doorman         ]   pl-1\n\
+             [ INFO  ][ doorman         ]   pl-2\n"
+        );
+    }
+
+    #[test]
+    fn verbose_layout_shows_the_spans() {
+        let template = LogTemplate::default_for(LogTimestamp::Off, true).to_string();
+        let logged = logged(LogTimestamp::Off, &template, || {
+            let _check = info_span!("check", tick = 3).entered();
+            let _call = info_span!("get_prefix_list").entered();
+            info!(target: "doorman", "Found it");
+        });
+        assert_eq!(
+            logged,
+            "[ INFO  ][ doorman         ] check{tick=3}: get_prefix_list: Found it\n"
+        );
+    }
+
+    #[test]
+    fn custom_template() {
+        let logged = logged(
+            LogTimestamp::Off,
+            "{level}|{target}|{{literal}}|{message}",
+            || {
+                info!(target: "doorman", answer = 42, "Done");
+            },
+        );
+        assert_eq!(logged, "INFO |doorman        |{literal}|Done answer=42\n");
+    }
+
+    #[test]
+    fn utc_timestamps_are_rfc3339() {
+        let logged = logged(LogTimestamp::Utc, "{timestamp} {message}", || {
+            info!("Done");
+        });
+        let (timestamp, message) = logged.split_once(' ').unwrap();
+        assert_eq!(message, "Done\n");
+        assert!(timestamp.ends_with('Z'), "{}", timestamp);
+        assert!(chrono::DateTime::parse_from_rfc3339(timestamp).is_ok());
+    }
+
+    #[test]
+    fn templates_round_trip() {
+        for template in [
+            "[ {timestamp} ][ {level} ][ {target} ] {spans}{message}",
+            "{{{level}}} {message}",
+        ] {
+            assert_eq!(
+                template.parse::<LogTemplate>().unwrap().to_string(),
+                template
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_templates() {
+        assert_eq!(
+            "{level}".parse::<LogTemplate>().unwrap_err(),
+            "the template must contain '{message}'"
+        );
+        assert_eq!(
+            "{message} {host}".parse::<LogTemplate>().unwrap_err(),
+            "unknown placeholder '{host}', expected one of timestamp, level, target, spans, message"
+        );
+        assert_eq!(
+            "{message".parse::<LogTemplate>().unwrap_err(),
+            "unterminated placeholder '{message'"
+        );
+        assert_eq!(
+            "{message} }".parse::<LogTemplate>().unwrap_err(),
+            "unmatched '}', write '}}' for a literal brace"
+        );
+    }
+}
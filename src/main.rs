@@ -1,133 +1,626 @@
+mod audit;
+mod audit_log;
 mod aws;
+mod breaker;
+mod cidr;
 mod config;
+mod connectivity;
+mod control;
+#[cfg(unix)]
+mod daemon;
+mod doorman;
+mod exit;
+mod expired;
+#[cfg(test)]
+mod fuzz;
+mod health;
+mod http;
+mod leader;
+mod lock;
+mod logging;
+mod metrics;
+mod mqtt;
 mod notification;
+mod pause;
+mod plan;
+mod recheck;
+mod reload;
+mod schedule;
+mod shutdown;
+mod simulate;
+mod state;
+mod stats;
+mod status;
+mod systemd;
 
-use crate::aws::AWSClient;
-use crate::config::Config;
-use crate::notification::notify;
+use crate::audit_log::AuditLog;
+use crate::aws::{
+    retry_config, timeout_config, AWSClient, AWSClientBuilder, AWSError, CallerIdentity,
+    EC2Clients, EntryFilter, PollBackoff,
+};
+use crate::breaker::Breaker;
+use crate::cidr::HostCidr;
+use crate::config::{show_config, Config, Target};
+use crate::connectivity::Connectivity;
+use crate::control::ControlServer;
+use crate::doorman::{
+    cleanup, collapse_duplicates, find_conflicts, region_name, saw_version, Doorman, Parts,
+    TargetClient, TargetSync,
+};
+use crate::exit::{Exit, InvalidConfig};
+use crate::health::Health;
+use crate::http::HttpServer;
+use crate::leader::{Election, Role};
+use crate::lock::InstanceLock;
+use crate::logging::setup_logger;
+use crate::metrics::Metrics;
+use crate::mqtt::MqttPublisher;
+use crate::notification::{Dispatcher, NotificationEvent};
+use crate::pause::Pause;
+use crate::recheck::Recheck;
+use crate::reload::Reload;
+use crate::schedule::Schedule;
+use crate::shutdown::ShutdownSignals;
+use crate::simulate::{
+    FixedIp, FixtureApi, FixtureIps, IpSource, LiveIps, Recorder, RecordingApi, RecordingIps,
+};
+use crate::state::State;
+use crate::stats::Stats;
+use crate::status::StatusFile;
+use crate::systemd::Systemd;
 
-use aws_sdk_ec2::client::Client;
-use aws_sdk_ec2::model::{ManagedPrefixList, PrefixListState};
-use color_eyre::{Report, Result};
+use aws_sdk_ec2::Region;
+use chrono::{DateTime, Local};
+use color_eyre::{eyre::eyre, eyre::WrapErr, Result};
 use ipnet::IpNet;
-use log::{debug, error, info, LevelFilter};
-use query_external_ip::Consensus;
-use tokio::signal::ctrl_c;
-use tokio::time::{interval, Duration, MissedTickBehavior};
+use tokio::time::Duration;
+use tracing::{error, info, warn};
 
-#[tokio::main(flavor = "current_thread")]
-async fn main() -> Result<()> {
+fn main() -> Result<()> {
     color_eyre::install()?;
     let config = Config::from_args();
 
-    let log_level = match config.verbose {
-        true => LevelFilter::Debug,
-        false => LevelFilter::Info,
-    };
-    setup_logger(log_level).unwrap();
+    // Forking is only safe before the runtime starts
+    if let (true, Some(log_file)) = (config.daemon, &config.log_file) {
+        #[cfg(unix)]
+        let result = daemon::daemonize(log_file, config.pid_file.as_deref());
+        #[cfg(not(unix))]
+        let result: Result<()> = Err(eyre!(
+            "running in the background isn't supported on this platform, {} wasn't used",
+            log_file.display()
+        ));
+        if let Err(err) = result {
+            eprintln!("Error: {:?}", err);
+            std::process::exit(Exit::Config.code());
+        }
+    }
+
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?
+        .block_on(run(config))
+}
+
+async fn run(config: Config) -> Result<()> {
+    if config.show_config {
+        return show_config(&config).await;
+    }
+
+    if let Err(err) = setup_logger(
+        config.verbose,
+        config.log_timestamp,
+        config.log_template.clone(),
+        config.otlp_endpoint.as_ref(),
+        config.event_log,
+        config.log_file.as_deref(),
+    ) {
+        eprintln!("Error: failed to set up logging: {}", err);
+        std::process::exit(Exit::Config.code());
+    }
+
+    if config.wait_timeout_adjusted {
+        warn!(
+            "Shortened the wait timeout to {} seconds so it fits in the {} seconds interval.",
+            config.wait_timeout, config.interval
+        );
+    }
 
-    work(config).await?;
+    let result = work(config).await;
+    logging::shutdown();
+    if let Err(err) = result {
+        let exit = Exit::of(&err);
+        eprintln!("Error: {:?}", err);
+        std::process::exit(exit.code());
+    }
     Ok(())
 }
 
+/// The target's client as configured, only missing the API to reach AWS with.
+fn client_builder(
+    config: &Config,
+    target: &Target,
+    audit_log: Option<&AuditLog>,
+    stats: &Stats,
+) -> AWSClientBuilder {
+    let builder = AWSClient::builder()
+        .description(target.description.clone())
+        .wait_timeout(config.wait_timeout)
+        .stats(stats.clone());
+    let builder = match audit_log {
+        Some(audit_log) => builder.audit_log(audit_log.clone()),
+        None => builder,
+    };
+    match config.entry_ttl {
+        Some(ttl) => builder.entry_ttl(chrono::Duration::seconds(ttl as i64)),
+        None => builder,
+    }
+}
+
+/// Whether this run must be the only one managing its entries.
+///
+/// A simulation doesn't touch the prefix lists, and a cleanup is meant to run alongside the doorman whose entries it
+/// removes.
+fn needs_lock(config: &Config) -> bool {
+    !(config.allow_multiple || config.simulate.is_some() || config.cleanup)
+}
+
 async fn work(config: Config) -> Result<()> {
-    let shared_config = aws_config::load_from_env().await;
-    let ec2_client = Client::new(&shared_config);
-    let aws_client = AWSClient::new(ec2_client, &config.description);
+    // Released when returning
+    let _lock = match needs_lock(&config) {
+        true => Some(InstanceLock::acquire(&config.targets)?),
+        false => None,
+    };
+
+    let timeout_config = timeout_config(
+        Duration::from_secs(config.aws_connect_timeout),
+        Duration::from_secs(config.aws_timeout),
+    );
+    // A call still failing after these attempts fails the target's update, which doorman retries on later checks with
+    // its own backoff. The two don't multiply: doorman never retries a call right away.
+    let retry_config = retry_config(config.aws_max_attempts);
+    let mut clients = EC2Clients::new(config.region.clone(), timeout_config, retry_config)
+        .endpoint(config.endpoint_url.clone());
+
+    let fixture = config
+        .simulate
+        .as_deref()
+        .map(FixtureApi::load)
+        .transpose()?;
+    let recorder = config.record.as_deref().map(Recorder::new).transpose()?;
+    let ip_source: Box<dyn IpSource> = match (&config.simulate, &recorder, config.ip) {
+        (Some(dir), _, _) => Box::new(FixtureIps::load(dir)?),
+        (None, Some(recorder), _) => Box::new(RecordingIps(recorder.clone())),
+        (None, None, Some(ip)) => Box::new(FixedIp(ip)),
+        (None, None, None) => Box::new(LiveIps),
+    };
+
+    let audit_log = config.audit_log.as_deref().map(AuditLog::new);
+    let stats = Stats::new(&config.targets);
+    let mut targets = Vec::with_capacity(config.targets.len());
+    for target in &config.targets {
+        let builder = client_builder(&config, target, audit_log.as_ref(), &stats);
+        let (builder, region) = match (&fixture, &recorder) {
+            // Resolving the region could reach out to AWS
+            // The fixture's changes are over right away, so there's no need to wait long for them
+            (Some(fixture), _) => (
+                builder.api(fixture.clone()).poll_backoff(PollBackoff {
+                    initial: Duration::from_millis(10),
+                    max: Duration::from_millis(10),
+                }),
+                target
+                    .region
+                    .clone()
+                    .or_else(|| config.region.clone())
+                    .map(Region::new),
+            ),
+            (None, Some(recorder)) => {
+                let (ec2_client, region) = clients.get(target.region.as_deref()).await;
+                (
+                    builder.api(RecordingApi::new(ec2_client, recorder.clone())),
+                    region,
+                )
+            }
+            (None, None) => {
+                let (ec2_client, region) = clients.get(target.region.as_deref()).await;
+                (builder.api(ec2_client), region)
+            }
+        };
+        targets.push(TargetClient {
+            target: target.clone(),
+            aws: builder.build()?,
+            region,
+        });
+    }
+
+    let recheck = Recheck::new()?;
+    if config.force_update {
+        recheck.force();
+    }
+    let notifier = Dispatcher::new(&config.notify, &recheck).wrap_err(InvalidConfig)?;
+
+    let identity = match fixture {
+        Some(_) => Ok(CallerIdentity {
+            account: "<simulated>".to_string(),
+            arn: "<simulated>".to_string(),
+        }),
+        None => clients.caller_identity().await,
+    };
+    let banner = banner(&config, &targets, identity, &notifier);
+    for line in &banner {
+        info!("{}", line);
+    }
 
     if config.cleanup {
         info!("Running in cleanup mode...");
-        aws_client.cleanup(&config.prefix_list_id).await?;
+        let mut state = config.state_file.as_deref().map(State::load);
+        if let Some(state) = &state {
+            notifier.restore(&state.notifications).await;
+        }
+        // Only the state file tells which entries this instance wrote, without it they're all removed
+        let ours: Option<Vec<IpNet>> = match (&state, config.takeover || config.all_hosts) {
+            (Some(state), false) => Some(state.cidr.iter().map(HostCidr::net).collect()),
+            _ => None,
+        };
+        if config.all_hosts {
+            warn!("Removing the entries of every host sharing the description.");
+        }
+        let report = cleanup(
+            &targets,
+            ours.as_deref(),
+            &notifier,
+            Duration::from_secs(config.cleanup_timeout),
+        )
+        .await;
+        notifier.dispatch(&NotificationEvent::cleaned_up(
+            report.summary(),
+            report.has_failures(),
+        ));
+        notifier.shutdown().await;
+        if let (Some(state), Some(path)) = (&mut state, &config.state_file) {
+            state.queued(notifier.pending());
+            report.save(state, path);
+        }
+        report.into_result()?;
         info!("Done!");
         return Ok(());
     }
 
-    let mut timer = interval(Duration::from_secs(config.interval));
-    timer.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    let signals = ShutdownSignals::new()?;
+    let reload = Reload::new()?;
+    let systemd = Systemd::from_env();
+    systemd.spawn_watchdog();
+    let mqtt = config
+        .mqtt
+        .as_ref()
+        .map(|mqtt| MqttPublisher::new(&mqtt.url, &mqtt.topic))
+        .transpose()
+        .wrap_err(InvalidConfig)?;
+    let mut servers = Vec::new();
+    if let Some(addr) = config.metrics_listen {
+        let metrics = Metrics::new(stats.clone());
+        servers.push(HttpServer::start(addr, "metrics", move |request| {
+            metrics.respond(request)
+        })?);
+    }
+    let pause = Pause::new(config.pause_windows.clone());
+    let breaker = Breaker::new(
+        config.breaker_threshold,
+        Duration::from_secs(config.breaker_probe),
+    );
+    let mut control = config
+        .control_socket
+        .as_deref()
+        .map(|path| {
+            ControlServer::start(
+                path,
+                stats.clone(),
+                recheck.clone(),
+                pause.clone(),
+                breaker.clone(),
+            )
+        })
+        .transpose()?;
+    // Doorman isn't stuck as long as it ticks within the longest interval
+    let health = Health::new(Duration::from_secs(
+        config.max_interval.unwrap_or(config.interval),
+    ));
+    if let Some(addr) = config.health_listen {
+        let health = health.clone();
+        servers.push(HttpServer::start(addr, "health checks", move |request| {
+            health.respond(request)
+        })?);
+    }
 
-    info!(
-        "Sleeping {} seconds between external IP checks.",
-        config.interval
+    let schedule = Schedule::new(
+        Duration::from_secs(config.interval),
+        config.max_interval.map(Duration::from_secs),
     );
+    stats.interval(schedule.current());
 
-    let mut current_cidr: Option<IpNet> = None;
-    let mut current_prefix_list: ManagedPrefixList =
-        aws_client.get_prefix_list(&config.prefix_list_id).await?;
-
-    loop {
-        tokio::select! {
-            _ = timer.tick() => {
-                match Consensus::get().await.map_err(Report::from) {
-                    Err(err) => {
-                        error!("Failed to retrieve external IP: {}", err);
-                        notify("Failed to retrieve external IP.", "", true)?;
-                        continue;
-                    }
-                    Ok(consensus) => {
-                        let new_ip = consensus.v4();
-                        if new_ip.is_none() {
-                            error!("Failed to retrieve external IP. None found...");
-                            notify("Failed to retrieve external IP.", "No IP found...", true)?;
-                            continue;
-                        }
-
-                        // This works because we know that `new_ip` is a valid IpV4
-                        let new_cidr = new_ip.map(|ip| {format!("{}/32", ip).parse::<IpNet>().unwrap()});
-
-                        if new_cidr == current_cidr {
-                            debug!("External IP didn't change.");
-                            continue;
-                        }
-
-                        let add = new_cidr.iter().collect();
-                        let remove = current_cidr.iter().collect();
-                        match aws_client.modify_entries(&current_prefix_list, add, remove).await {
-                            Err(err) => error!("Failed to modify prefix list: {:#?}", err),
-                            Ok(mpl) => {
-                                let new_prefix_list = aws_client.wait_for_state(&mpl.prefix_list_id.unwrap(), PrefixListState::ModifyComplete, None).await?;
-                                info!("Updated prefix list IP to {}", new_cidr.unwrap());
-                                notify("Updated prefix list", &format!("New IP: {}", new_cidr.unwrap()), false)?;
-                                current_prefix_list = new_prefix_list;
-                            }
-                        }
+    // A target that can't be reached at startup, e.g. because of a mistyped region, is dropped without affecting
+    // the others.
+    let mut current_cidr: Option<HostCidr> = None;
+    let last_update: Option<DateTime<Local>> = None;
+    let mut syncs = Vec::with_capacity(targets.len());
+    let mut reachable_targets = Vec::with_capacity(targets.len());
+    let mut last_error = None;
+    for target in targets {
+        match target
+            .aws
+            .get_prefix_list(&target.target.prefix_list_id)
+            .await
+        {
+            Ok(prefix_list) => {
+                let mut sync = TargetSync::new(prefix_list);
+                sync.adopt = config.adopt;
+                syncs.push(sync);
+                reachable_targets.push(target);
+            }
+            Err(err) => {
+                error!(
+                    "[{}] Ignoring target, failed to get prefix list {} in {}: {:#}",
+                    target.target.name,
+                    target.target.prefix_list_id,
+                    region_name(target.region.as_ref()),
+                    err
+                );
+                last_error = Some(err);
+            }
+        }
+    }
+    if reachable_targets.is_empty() {
+        // Keeping the cause tells why doorman exits
+        let err = last_error.unwrap_or_else(|| eyre!("No targets."));
+        return Err(err.wrap_err("None of the targets could be reached."));
+    }
+    let targets = reachable_targets;
 
-                        current_cidr = new_cidr;
+    // Remembering the last IP lets doorman replace it, even if it stopped without cleaning up. The entries may have
+    // changed since, so the first check looks at them whatever the version of the prefix lists.
+    let mut state = config
+        .state_file
+        .as_deref()
+        .map(State::load)
+        .unwrap_or_default();
+    state.started(banner);
+    // Kept in the state until delivered, in case doorman stops again before
+    notifier.restore(&state.notifications).await;
+    for (target, sync) in targets.iter().zip(&syncs) {
+        saw_version(&mut state, &target.target, sync.prefix_list.version, false);
+    }
+    if let (true, Some(path)) = (state.changed(), &config.state_file) {
+        state.save(path);
+    }
+    if let Some(cidr) = state.cidr {
+        info!("The last known IP was {}.", cidr);
+        current_cidr = Some(cidr);
+        for sync in &mut syncs {
+            sync.cidr = Some(cidr);
+            sync.prefix_list.version = None;
+        }
+    }
+    // Decided before the startup changes, which only the leader makes
+    let election = match &config.leader_table {
+        Some(table) => {
+            let election = Election::new(
+                clients.dynamodb().await,
+                table.clone(),
+                config.leader_key.clone(),
+                Duration::from_secs(config.leader_lease),
+            );
+            let role = election.renew().await;
+            info!(
+                "Running as the {} of a redundant pair, known as {} in the DynamoDB table {}.",
+                role.as_str(),
+                election.owner(),
+                table
+            );
+            stats.role(role);
+            election.start(recheck.clone());
+            Some(election)
+        }
+        None => None,
+    };
+    let role = election.as_ref().map(Election::role);
+    // The leader's entries carry the description too, so they're none of a follower's business
+    if role.is_none_or(|role| role == Role::Leader) {
+        if config.takeover {
+            // Entries left over by earlier runs, which would otherwise be taken for other instances'
+            let last = state.cidr;
+            collapse_duplicates(
+                &targets,
+                &mut syncs,
+                last,
+                &mut state,
+                Duration::from_secs(config.wait_timeout),
+            )
+            .await;
+        } else if let Some(cidr) = state.cidr {
+            find_conflicts(&targets, &syncs, &cidr, &notifier).await;
+        } else {
+            // Without a last known IP, nothing would ever remove these
+            for (TargetClient { target, aws, .. }, sync) in targets.iter().zip(&syncs) {
+                match aws.entries(&sync.prefix_list, EntryFilter::Owned).await {
+                    Ok(entries) if entries.is_empty() => {}
+                    Ok(entries) => {
+                        let cidrs: Vec<String> =
+                            entries.iter().map(|entry| entry.cidr.to_string()).collect();
+                        warn!(
+                            "[{}] {} holds entries from an earlier run: {}. Run with {} to remove them.",
+                            target.name,
+                            target.prefix_list_id,
+                            cidrs.join(", "),
+                            // The state file doesn't know them either, so they'd be taken for someone else's
+                            match config.state_file {
+                                Some(_) => "--cleanup --takeover",
+                                None => "--cleanup",
+                            }
+                        );
                     }
+                    Err(err) => warn!(
+                        "[{}] Failed to look for entries from an earlier run: {:#}",
+                        target.name, err
+                    ),
                 }
             }
-            _ = ctrl_c() => {
-                info!("Received ^C. Cleaning up...");
-                aws_client.cleanup(&config.prefix_list_id).await?;
-                break;
-            }
         }
     }
 
-    Ok(())
-}
+    let status_file = config
+        .status_file
+        .clone()
+        .map(|path| StatusFile::new(path, stats.clone()));
+    let connectivity = config.offline_check.clone().map(Connectivity::new);
 
-fn setup_logger(level: LevelFilter) -> Result<(), fern::InitError> {
-    let default_level = if level == LevelFilter::Debug {
-        level
-    } else {
-        LevelFilter::Warn
+    let once = config.once;
+    let mut doorman = Doorman::new(Parts {
+        config,
+        targets,
+        syncs,
+        notifier,
+        stats,
+        health,
+        recheck,
+        reload,
+        pause,
+        breaker,
+        election,
+        role,
+        schedule,
+        state,
+        status_file,
+        systemd,
+        mqtt,
+        connectivity,
+        ip_source,
+        current_cidr,
+        last_update,
+    });
+    let report = match once {
+        true => {
+            doorman.once().await?;
+            None
+        }
+        false => {
+            let signal = doorman.run(signals, &mut control).await?;
+            Some(doorman.stop(signal).await)
+        }
     };
+    for server in servers {
+        server.shutdown().await;
+    }
+    if let Some(control) = control {
+        control.shutdown();
+    }
+    if let Some(report) = report {
+        report.into_result()?;
+    }
+    info!("Done!");
 
-    fern::Dispatch::new()
-        .format(move |out, message, record| {
-            out.finish(format_args!(
-                // "[ {} ][ {:5} ][ {:15} ] {}",
-                // chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
-                "[ {:5} ][ {:15} ] {}",
-                record.level(),
-                record.target(),
-                message
-            ))
-        })
-        .level(default_level)
-        .level_for("aws_doorman", level)
-        .chain(std::io::stdout())
-        //        .chain(fern::log_file("output.log")?)
-        .apply()?;
     Ok(())
 }
+
+/// Tells which credentials, prefix lists and notification backends doorman uses, as logged at startup.
+///
+/// Some restrictive roles can't call STS, so failing to get the caller identity is only a warning.
+fn banner(
+    config: &Config,
+    targets: &[TargetClient],
+    identity: Result<CallerIdentity, AWSError>,
+    notifier: &Dispatcher,
+) -> Vec<String> {
+    let mut lines = vec![format!("aws_doorman {}", env!("CARGO_PKG_VERSION"))];
+    match identity {
+        Ok(identity) => lines.push(format!(
+            "Running as {} in account {}",
+            identity.arn, identity.account
+        )),
+        Err(err) => {
+            warn!("Failed to get the caller identity: {}", err);
+            lines.push("Running as an unknown identity".to_string());
+        }
+    }
+    lines.push(format!("Managing {} target(s):", targets.len()));
+    for TargetClient { target, region, .. } in targets {
+        lines.push(format!(
+            "  {}: prefix list {} in {}, description \"{}\"",
+            target.name,
+            target.prefix_list_id,
+            region_name(region.as_ref()),
+            target.description
+        ));
+    }
+    lines.push(format!(
+        "Checking the external IP every {} seconds",
+        config.interval
+    ));
+    lines.push(format!(
+        "AWS calls time out after {} seconds ({} to connect), with up to {} attempt(s) in standard retry mode",
+        config.aws_timeout, config.aws_connect_timeout, config.aws_max_attempts
+    ));
+    if let Some(endpoint) = &config.endpoint_url {
+        lines.push(format!("Sending the AWS calls to {}", endpoint));
+    }
+    if let Some(ip) = config.ip {
+        lines.push(format!(
+            "Using {} as the external IP, without looking it up",
+            ip
+        ));
+    }
+    match (&config.simulate, &config.record) {
+        (Some(dir), _) => lines.push(format!(
+            "Simulating with the fixture in {}, AWS isn't called",
+            dir.display()
+        )),
+        (None, Some(dir)) => lines.push(format!("Recording a fixture into {}", dir.display())),
+        (None, None) => {}
+    }
+    lines.push(format!(
+        "Notifying through {}",
+        notifier.backend_names().join(", ")
+    ));
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aws::FakeApi;
+    use std::net::IpAddr;
+
+    const ID: &str = "pl-12345678";
+
+    #[tokio::test]
+    async fn entries_carry_the_configured_description() {
+        let config = Config::parse_from(&["--prefix-list-id", ID, "--description", "vpn-office"]);
+        let api = FakeApi::default().with_prefix_list(ID, None, &[]);
+        let stats = Stats::new(&config.targets);
+        let aws = client_builder(&config, &config.targets[0], None, &stats)
+            .api(api.clone())
+            .build()
+            .unwrap();
+        let pl = aws.get_prefix_list(&ID.parse().unwrap()).await.unwrap();
+        let cidr = HostCidr::from("192.0.2.1".parse::<IpAddr>().unwrap());
+        aws.modify_entries(&pl, vec![&cidr], vec![]).await.unwrap();
+
+        let modification = &api.modifications()[0];
+        assert_eq!(modification.add.len(), 1);
+        assert_eq!(
+            modification.add[0].description.as_deref(),
+            Some("vpn-office")
+        );
+    }
+
+    #[test]
+    fn cleanup_runs_alongside_the_daemon() {
+        let base = ["--prefix-list-id", ID, "--description", "office"];
+        let config = |extra: &[&str]| {
+            let mut args = base.to_vec();
+            args.extend_from_slice(extra);
+            Config::parse_from(&args)
+        };
+        assert!(needs_lock(&config(&[])));
+        assert!(!needs_lock(&config(&["--cleanup"])));
+        assert!(!needs_lock(&config(&["--allow-multiple"])));
+    }
+}
@@ -0,0 +1,360 @@
+use crate::leader::Role;
+use crate::stats::{Latency, Stats, TargetStats, LATENCY_WINDOW};
+
+use hyper::header::{HeaderValue, CONTENT_TYPE};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use std::fmt::Write;
+
+/// A per-target metric: its name, type, help, and value for a target if it has one.
+type Family = (
+    &'static str,
+    &'static str,
+    &'static str,
+    fn(&TargetStats) -> Option<String>,
+);
+
+/// What doorman did so far, exposed to Prometheus.
+#[derive(Clone)]
+pub struct Metrics(Stats);
+
+impl Metrics {
+    pub fn new(stats: Stats) -> Self {
+        Self(stats)
+    }
+
+    /// Renders the metrics in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let state = self.0.snapshot();
+        let mut out = String::new();
+
+        // Writing to a String can't fail
+        let _ = writeln!(
+            out,
+            "# HELP doorman_ip_detection_failures_total Failed attempts to retrieve the external IP.\n\
+             # TYPE doorman_ip_detection_failures_total counter\n\
+             doorman_ip_detection_failures_total {}",
+            state.ip_detection_failures
+        );
+        let _ = writeln!(
+            out,
+            "# HELP doorman_paused Whether the prefix lists are left alone, during a maintenance window or on request.\n\
+             # TYPE doorman_paused gauge\n\
+             doorman_paused {}",
+            u8::from(state.paused)
+        );
+        let _ = writeln!(
+            out,
+            "# HELP doorman_circuit_open Whether the prefix lists are only tried once per probe interval, after failing repeatedly.\n\
+             # TYPE doorman_circuit_open gauge\n\
+             doorman_circuit_open {}",
+            u8::from(state.circuit_open)
+        );
+        if let Some(current) = state.role {
+            let _ = writeln!(
+                out,
+                "# HELP doorman_role This doorman's role in a redundant pair, only the leader modifies the prefix lists.\n\
+                 # TYPE doorman_role gauge"
+            );
+            for role in [Role::Leader, Role::Follower, Role::Unknown] {
+                let _ = writeln!(
+                    out,
+                    "doorman_role{{role=\"{}\"}} {}",
+                    role.as_str(),
+                    u8::from(role == current)
+                );
+            }
+        }
+        let _ = writeln!(
+            out,
+            "# HELP doorman_entry_cache_lookups_total Times the entries of a prefix list were needed, by whether they could be reused.\n\
+             # TYPE doorman_entry_cache_lookups_total counter\n\
+             doorman_entry_cache_lookups_total{{result=\"hit\"}} {}\n\
+             doorman_entry_cache_lookups_total{{result=\"miss\"}} {}",
+            state.entry_cache_hits, state.entry_cache_misses
+        );
+
+        let _ = writeln!(
+            out,
+            "# HELP doorman_aws_operation_duration_seconds How long AWS operations took, over the last {} of each.\n\
+             # TYPE doorman_aws_operation_duration_seconds gauge",
+            LATENCY_WINDOW
+        );
+        for (operation, latency) in &state.operations {
+            write_quantiles(
+                &mut out,
+                "doorman_aws_operation_duration_seconds",
+                &format!("operation=\"{}\",", operation),
+                latency,
+            );
+        }
+        let _ = writeln!(
+            out,
+            "# HELP doorman_update_duration_seconds How long updates took, from looking up the IP to the prefix list \
+             holding it, over the last {}.\n\
+             # TYPE doorman_update_duration_seconds gauge",
+            LATENCY_WINDOW
+        );
+        write_quantiles(
+            &mut out,
+            "doorman_update_duration_seconds",
+            "",
+            &state.updates,
+        );
+
+        let families: [Family; 4] = [
+            (
+                "doorman_ip_changes_total",
+                "counter",
+                "Times the prefix list was updated to a new IP.",
+                |metrics| Some(metrics.ip_changes.to_string()),
+            ),
+            (
+                "doorman_update_failures_total",
+                "counter",
+                "Failed attempts to modify the prefix list.",
+                |metrics| Some(metrics.update_failures.to_string()),
+            ),
+            (
+                "doorman_last_success_timestamp_seconds",
+                "gauge",
+                "When the prefix list was last found or made to hold the current IP.",
+                |metrics| {
+                    metrics
+                        .last_success
+                        .map(|seconds| format!("{:.3}", seconds))
+                },
+            ),
+            (
+                "doorman_in_sync",
+                "gauge",
+                "Whether the prefix list holds the current IP.",
+                |metrics| Some(u8::from(metrics.in_sync).to_string()),
+            ),
+        ];
+        for (name, kind, help, value) in families {
+            let _ = writeln!(out, "# HELP {} {}\n# TYPE {} {}", name, help, name, kind);
+            for (target, metrics) in &state.targets {
+                if let Some(value) = value(metrics) {
+                    let _ = writeln!(
+                        out,
+                        "{}{{target=\"{}\",prefix_list_id=\"{}\"}} {}",
+                        name,
+                        escape_label(target),
+                        escape_label(&metrics.prefix_list_id),
+                        value
+                    );
+                }
+            }
+        }
+
+        out
+    }
+
+    /// Answers `GET /metrics`.
+    pub fn respond(&self, request: &Request<Body>) -> Response<Body> {
+        let mut response = Response::default();
+        match (request.method(), request.uri().path()) {
+            (&Method::GET, "/metrics") => {
+                *response.body_mut() = Body::from(self.render());
+                response.headers_mut().insert(
+                    CONTENT_TYPE,
+                    HeaderValue::from_static("text/plain; version=0.0.4"),
+                );
+            }
+            _ => *response.status_mut() = StatusCode::NOT_FOUND,
+        }
+        response
+    }
+}
+
+/// The median and 95th percentile of `latency`, if it has samples. `labels` are put first, each followed by a comma.
+fn write_quantiles(out: &mut String, name: &str, labels: &str, latency: &Latency) {
+    for quantile in [0.5, 0.95] {
+        if let Some(took) = latency.quantile(quantile) {
+            let _ = writeln!(
+                out,
+                "{}{{{}quantile=\"{}\"}} {:.3}",
+                name,
+                labels,
+                quantile,
+                took.as_secs_f64()
+            );
+        }
+    }
+}
+
+/// Escapes a label value as required by the text format.
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Target;
+    use crate::http::HttpServer;
+    use std::net::{SocketAddr, TcpListener};
+    use std::time::Duration;
+
+    fn target(name: &str, prefix_list_id: &str) -> Target {
+        Target {
+            name: name.to_string(),
+            prefix_list_id: prefix_list_id.parse().unwrap(),
+            description: "office".parse().unwrap(),
+            region: None,
+            notify: true,
+        }
+    }
+
+    /// An address nothing listens on.
+    fn free_addr() -> SocketAddr {
+        TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+    }
+
+    /// The samples, without the help and type lines.
+    fn samples(rendered: &str) -> Vec<&str> {
+        rendered
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn scraping_after_some_events() {
+        let office = target("office", "pl-12345678");
+        let home = target("home", "pl-87654321");
+        let stats = Stats::new([&office, &home]);
+        stats.ip_detection_failed();
+        stats.ip_changed(&office, Some(3));
+        stats.in_sync(&home, Some(7));
+        stats.update_failed(&home);
+
+        let addr = free_addr();
+        let metrics = Metrics::new(stats.clone());
+        let server =
+            HttpServer::start(addr, "metrics", move |request| metrics.respond(request)).unwrap();
+        let response = reqwest::get(format!("http://{}/metrics", addr))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        assert_eq!(
+            response.headers()[reqwest::header::CONTENT_TYPE],
+            "text/plain; version=0.0.4"
+        );
+        let scraped = response.text().await.unwrap();
+        let first = samples(&scraped);
+        for expected in [
+            "doorman_ip_detection_failures_total 1",
+            "doorman_paused 0",
+            "doorman_ip_changes_total{target=\"home\",prefix_list_id=\"pl-87654321\"} 0",
+            "doorman_ip_changes_total{target=\"office\",prefix_list_id=\"pl-12345678\"} 1",
+            "doorman_update_failures_total{target=\"home\",prefix_list_id=\"pl-87654321\"} 1",
+            "doorman_update_failures_total{target=\"office\",prefix_list_id=\"pl-12345678\"} 0",
+            "doorman_in_sync{target=\"home\",prefix_list_id=\"pl-87654321\"} 0",
+            "doorman_in_sync{target=\"office\",prefix_list_id=\"pl-12345678\"} 1",
+        ] {
+            assert!(first.contains(&expected), "{} in\n{}", expected, scraped);
+        }
+        // Both targets succeeded once
+        assert_eq!(
+            first
+                .iter()
+                .filter(|sample| sample.starts_with("doorman_last_success_timestamp_seconds{"))
+                .count(),
+            2
+        );
+
+        // Later events show up in the next scrape
+        stats.ip_detection_failed();
+        let scraped = reqwest::get(format!("http://{}/metrics", addr))
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert!(samples(&scraped).contains(&"doorman_ip_detection_failures_total 2"));
+
+        let response = reqwest::get(format!("http://{}/other", addr))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+
+        server.shutdown().await;
+        assert!(reqwest::get(format!("http://{}/metrics", addr))
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn targets_without_a_success_have_no_timestamp() {
+        let office = target("office", "pl-12345678");
+        let rendered = Metrics::new(Stats::new([&office])).render();
+        assert!(rendered.contains(
+            "# TYPE doorman_last_success_timestamp_seconds gauge\n# HELP doorman_in_sync"
+        ));
+    }
+
+    #[test]
+    fn latencies_are_exposed_as_quantiles() {
+        let stats = Stats::new([&target("office", "pl-12345678")]);
+        let rendered = Metrics::new(stats.clone()).render();
+        // Only the help and type lines until there are samples
+        assert!(rendered.contains("# TYPE doorman_update_duration_seconds gauge"));
+        assert!(!samples(&rendered)
+            .iter()
+            .any(|sample| sample.contains("_duration_seconds")));
+
+        for millis in 1..=20 {
+            stats.operation("modify_entries", Duration::from_millis(millis * 100));
+        }
+        stats.operation("get_prefix_list", Duration::from_millis(250));
+        stats.update_took(Duration::from_secs(12));
+        let rendered = Metrics::new(stats).render();
+        let rendered = samples(&rendered);
+        for expected in [
+            "doorman_aws_operation_duration_seconds{operation=\"get_prefix_list\",quantile=\"0.5\"} 0.250",
+            "doorman_aws_operation_duration_seconds{operation=\"get_prefix_list\",quantile=\"0.95\"} 0.250",
+            "doorman_aws_operation_duration_seconds{operation=\"modify_entries\",quantile=\"0.5\"} 1.000",
+            "doorman_aws_operation_duration_seconds{operation=\"modify_entries\",quantile=\"0.95\"} 1.900",
+            "doorman_update_duration_seconds{quantile=\"0.5\"} 12.000",
+            "doorman_update_duration_seconds{quantile=\"0.95\"} 12.000",
+        ] {
+            assert!(rendered.contains(&expected), "{} in\n{:?}", expected, rendered);
+        }
+    }
+
+    #[test]
+    fn the_role_is_only_exposed_in_a_pair() {
+        let stats = Stats::new([&target("office", "pl-12345678")]);
+        assert!(!Metrics::new(stats.clone())
+            .render()
+            .contains("doorman_role"));
+
+        stats.role(Role::Follower);
+        let rendered = Metrics::new(stats).render();
+        let rendered = samples(&rendered);
+        for expected in [
+            "doorman_role{role=\"leader\"} 0",
+            "doorman_role{role=\"follower\"} 1",
+            "doorman_role{role=\"unknown\"} 0",
+        ] {
+            assert!(
+                rendered.contains(&expected),
+                "{} in\n{:?}",
+                expected,
+                rendered
+            );
+        }
+    }
+
+    #[test]
+    fn labels_are_escaped() {
+        assert_eq!(escape_label("a \"b\"\\c\nd"), "a \\\"b\\\"\\\\c\\nd");
+    }
+}
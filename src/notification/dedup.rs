@@ -0,0 +1,207 @@
+use super::{EventKind, NotificationEvent};
+use crate::config::PrefixListId;
+
+use ipnet::IpNet;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// How many distinct events are remembered.
+const MAX_ENTRIES: usize = 256;
+
+/// What makes two events identical. Timings and the rendered body don't count.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct Key {
+    kind: EventKind,
+    target: Option<String>,
+    prefix_list_id: Option<PrefixListId>,
+    old_cidr: Option<IpNet>,
+    new_cidr: Option<IpNet>,
+    version: Option<i64>,
+    error: Option<String>,
+    detail: Option<String>,
+    recovered_from: Option<EventKind>,
+}
+
+impl From<&NotificationEvent> for Key {
+    fn from(event: &NotificationEvent) -> Self {
+        Self {
+            kind: event.kind,
+            target: event.target.clone(),
+            prefix_list_id: event.prefix_list_id.clone(),
+            old_cidr: event.old_cidr,
+            new_cidr: event.new_cidr,
+            version: event.version,
+            error: event.error.clone(),
+            detail: event.detail.clone(),
+            recovered_from: event.recovered_from,
+        }
+    }
+}
+
+struct Seen {
+    at: Instant,
+    duplicates: u32,
+}
+
+/// Drops events identical to one sent within the window.
+///
+/// The same event may come from several places within seconds. The next time it's sent, it says how many duplicates
+/// were dropped.
+pub struct Deduplicator {
+    window: Duration,
+    seen: Mutex<HashMap<Key, Seen>>,
+}
+
+impl Deduplicator {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            seen: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the event to send, if it isn't a duplicate.
+    pub fn check(&self, event: &NotificationEvent) -> Option<NotificationEvent> {
+        if self.window.is_zero() {
+            return Some(event.clone());
+        }
+
+        let key = Key::from(event);
+        let mut seen = self.seen.lock().unwrap();
+        let previous_duplicates = match seen.get_mut(&key) {
+            Some(entry) if entry.at.elapsed() < self.window => {
+                entry.duplicates += 1;
+                return None;
+            }
+            Some(entry) => entry.duplicates,
+            None => 0,
+        };
+
+        // Keeps the cache bounded, forgetting about expired entries first
+        if seen.len() >= MAX_ENTRIES {
+            let window = self.window;
+            seen.retain(|_, entry| entry.at.elapsed() < window || entry.duplicates > 0);
+        }
+        if seen.len() >= MAX_ENTRIES {
+            if let Some(oldest) = seen
+                .iter()
+                .min_by_key(|(_, entry)| entry.at)
+                .map(|(key, _)| key.clone())
+            {
+                seen.remove(&oldest);
+            }
+        }
+        seen.insert(
+            key,
+            Seen {
+                at: Instant::now(),
+                duplicates: 0,
+            },
+        );
+
+        Some(NotificationEvent {
+            suppressed: event.suppressed + previous_duplicates,
+            ..event.clone()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::sleep;
+
+    const WINDOW: Duration = Duration::from_secs(60);
+
+    fn restored(detail: &str) -> NotificationEvent {
+        NotificationEvent::shutdown(detail)
+    }
+
+    fn size(deduplicator: &Deduplicator) -> usize {
+        deduplicator.seen.lock().unwrap().len()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn duplicates_within_the_window_are_dropped() {
+        let deduplicator = Deduplicator::new(WINDOW);
+        let sent = deduplicator.check(&restored("entry restored")).unwrap();
+        assert_eq!(sent.suppressed, 0);
+
+        sleep(Duration::from_secs(5)).await;
+        assert!(deduplicator.check(&restored("entry restored")).is_none());
+        sleep(Duration::from_secs(50)).await;
+        assert!(deduplicator.check(&restored("entry restored")).is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn the_next_one_tells_how_many_were_dropped() {
+        let deduplicator = Deduplicator::new(WINDOW);
+        deduplicator.check(&restored("entry restored")).unwrap();
+        deduplicator.check(&restored("entry restored"));
+        deduplicator.check(&restored("entry restored"));
+
+        sleep(WINDOW).await;
+        let sent = deduplicator.check(&restored("entry restored")).unwrap();
+        assert_eq!(sent.suppressed, 2);
+        assert!(sent
+            .body()
+            .ends_with("(2 similar notification(s) suppressed)"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn events_differing_in_payload_are_all_sent() {
+        let deduplicator = Deduplicator::new(WINDOW);
+        assert!(deduplicator.check(&restored("entry restored")).is_some());
+        assert!(deduplicator.check(&restored("entry removed")).is_some());
+        assert!(deduplicator
+            .check(&NotificationEvent::ip_lookup_failed("timed out"))
+            .is_some());
+        assert!(deduplicator
+            .check(&NotificationEvent::ip_lookup_failed("refused"))
+            .is_some());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn an_empty_window_sends_everything() {
+        let deduplicator = Deduplicator::new(Duration::ZERO);
+        for _ in 0..3 {
+            let sent = deduplicator.check(&restored("entry restored")).unwrap();
+            assert_eq!(sent.suppressed, 0);
+        }
+        assert_eq!(size(&deduplicator), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn the_cache_is_bounded() {
+        let deduplicator = Deduplicator::new(WINDOW);
+        for i in 0..MAX_ENTRIES + 10 {
+            assert!(deduplicator.check(&restored(&i.to_string())).is_some());
+            sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(size(&deduplicator), MAX_ENTRIES);
+        // The oldest were forgotten, the newest weren't
+        assert!(deduplicator.check(&restored("0")).is_some());
+        assert!(deduplicator
+            .check(&restored(&(MAX_ENTRIES + 9).to_string()))
+            .is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn expired_entries_are_forgotten_before_counted_duplicates() {
+        let deduplicator = Deduplicator::new(WINDOW);
+        deduplicator.check(&restored("duplicated")).unwrap();
+        deduplicator.check(&restored("duplicated"));
+        sleep(Duration::from_secs(1)).await;
+        for i in 1..MAX_ENTRIES {
+            deduplicator.check(&restored(&i.to_string())).unwrap();
+        }
+
+        sleep(WINDOW).await;
+        deduplicator.check(&restored("new")).unwrap();
+        // The duplicate count survived the cleanup, although its entry was the oldest
+        let sent = deduplicator.check(&restored("duplicated")).unwrap();
+        assert_eq!(sent.suppressed, 1);
+    }
+}
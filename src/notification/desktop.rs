@@ -0,0 +1,194 @@
+use super::{NotificationEvent, Notifier, NotifyError};
+use crate::recheck::Recheck;
+
+use async_trait::async_trait;
+use color_eyre::Result;
+use notify_rust::Notification;
+
+/// Shows notifications on the desktop.
+///
+/// Where the platform supports it, failures come with a button to check again right away.
+pub struct Desktop {
+    recheck: Recheck,
+}
+
+impl Desktop {
+    pub fn new(recheck: Recheck) -> Self {
+        Self { recheck }
+    }
+
+    /// Checks whether notifications can be shown at all.
+    pub fn probe() -> Result<()> {
+        platform::probe()
+    }
+}
+
+#[async_trait]
+impl Notifier for Desktop {
+    fn name(&self) -> &'static str {
+        "desktop"
+    }
+
+    async fn notify(&self, event: &NotificationEvent) -> Result<(), NotifyError> {
+        let mut notification = Notification::new();
+        notification.summary(event.summary()).body(&event.body());
+        platform::set_urgency(&mut notification, event.urgent);
+        let retry = event.kind.is_failure().then(|| self.recheck.clone());
+        // The notification server went away, or never was there when the backend was asked for explicitly
+        platform::show(notification, retry)
+            .map_err(|err| NotifyError::Unavailable(format!("{:#}", err)))
+    }
+}
+
+/// Linux and the BSDs, which go through D-Bus.
+#[cfg(all(unix, not(target_os = "macos")))]
+mod platform {
+    use crate::recheck::Recheck;
+
+    use color_eyre::Result;
+    use notify_rust::{Notification, Urgency};
+    use tracing::warn;
+
+    const RETRY_ACTION: &str = "retry";
+
+    /// This needs a notification server on the session bus, which headless machines don't have.
+    pub fn probe() -> Result<()> {
+        notify_rust::get_server_information()?;
+        Ok(())
+    }
+
+    pub fn set_urgency(notification: &mut Notification, urgent: bool) {
+        notification.urgency(match urgent {
+            true => Urgency::Critical,
+            false => Urgency::Low,
+        });
+    }
+
+    pub fn show(mut notification: Notification, retry: Option<Recheck>) -> Result<()> {
+        let recheck = match retry {
+            Some(recheck) => recheck,
+            None => {
+                notification.show()?;
+                return Ok(());
+            }
+        };
+
+        notification.action(RETRY_ACTION, "Retry now");
+        // Waiting for the action blocks until the notification is closed
+        tokio::task::spawn_blocking(move || match notification.show() {
+            Ok(handle) => handle.wait_for_action(|action| {
+                if action == RETRY_ACTION {
+                    recheck.trigger();
+                }
+            }),
+            Err(err) => warn!("Failed to show desktop notification: {}", err),
+        });
+        Ok(())
+    }
+}
+
+/// Windows, which shows toasts.
+#[cfg(windows)]
+mod platform {
+    use crate::recheck::Recheck;
+
+    use color_eyre::{eyre::eyre, Result};
+    use notify_rust::{Notification, Timeout};
+    use std::env;
+    use std::ffi::OsString;
+    use std::path::PathBuf;
+
+    /// Server Core has neither a shell nor the toast APIs, which is best told by the missing Explorer.
+    pub fn probe() -> Result<()> {
+        probe_shell(env::var_os("SystemRoot"))
+    }
+
+    /// Looks for Explorer in `system_root`, the Windows directory.
+    pub(super) fn probe_shell(system_root: Option<OsString>) -> Result<()> {
+        let explorer = system_root
+            .map(|root| PathBuf::from(root).join("explorer.exe"))
+            .ok_or_else(|| eyre!("SystemRoot isn't set"))?;
+        match explorer.exists() {
+            true => Ok(()),
+            false => Err(eyre!("there's no desktop shell, as on Server Core")),
+        }
+    }
+
+    /// Urgent toasts stay up for longer, routine ones use the default duration.
+    pub fn set_urgency(notification: &mut Notification, urgent: bool) {
+        if urgent {
+            notification.timeout(Timeout::Never);
+        }
+    }
+
+    /// Toasts can't report actions back through notify-rust, so there's no retry button.
+    pub fn show(notification: Notification, _retry: Option<Recheck>) -> Result<()> {
+        notification.show()?;
+        Ok(())
+    }
+}
+
+/// MacOS, which has no notion of urgency.
+#[cfg(target_os = "macos")]
+mod platform {
+    use crate::recheck::Recheck;
+
+    use color_eyre::Result;
+    use notify_rust::Notification;
+
+    /// Native notifications are attributed to an application bundle, which has to exist.
+    pub fn probe() -> Result<()> {
+        notify_rust::set_application("com.apple.Terminal")?;
+        Ok(())
+    }
+
+    pub fn set_urgency(_notification: &mut Notification, _urgent: bool) {}
+
+    pub fn show(notification: Notification, _retry: Option<Recheck>) -> Result<()> {
+        notification.show()?;
+        Ok(())
+    }
+}
+
+// MacOS has no notion of urgency, so there is nothing to check there
+#[cfg(all(test, not(target_os = "macos")))]
+mod tests {
+    use super::*;
+
+    #[cfg(all(unix, not(target_os = "macos")))]
+    #[test]
+    fn urgent_notifications_are_critical() {
+        use notify_rust::{Hint, Urgency};
+
+        for (urgent, urgency) in [(true, Urgency::Critical), (false, Urgency::Low)] {
+            let mut notification = Notification::new();
+            platform::set_urgency(&mut notification, urgent);
+            assert!(notification.hints.contains(&Hint::Urgency(urgency)));
+        }
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn urgent_toasts_stay_up() {
+        use notify_rust::Timeout;
+
+        let mut notification = Notification::new();
+        platform::set_urgency(&mut notification, true);
+        assert_eq!(notification.timeout, Timeout::Never);
+        let mut notification = Notification::new();
+        platform::set_urgency(&mut notification, false);
+        assert_eq!(notification.timeout, Timeout::Default);
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn server_core_has_no_toasts() {
+        let root = tempfile::tempdir().unwrap();
+        let system_root = Some(root.path().as_os_str().to_owned());
+        assert!(platform::probe_shell(system_root.clone()).is_err());
+
+        std::fs::write(root.path().join("explorer.exe"), "").unwrap();
+        assert!(platform::probe_shell(system_root).is_ok());
+        assert!(platform::probe_shell(None).is_err());
+    }
+}
@@ -0,0 +1,239 @@
+use super::{NotificationEvent, Notifier, NotifyError};
+
+use async_trait::async_trait;
+use chrono::Utc;
+use reqwest::{Client, StatusCode, Url};
+use serde::Serialize;
+use tokio::time::{sleep, Duration};
+use tracing::debug;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+const ATTEMPTS: u32 = 3;
+/// Upper bound on how long we're willing to wait when Discord asks us to slow down.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(30);
+const RATE_LIMIT_RESET_AFTER: &str = "X-RateLimit-Reset-After";
+
+// Discord rejects embeds exceeding these
+const MAX_TITLE_LENGTH: usize = 256;
+const MAX_DESCRIPTION_LENGTH: usize = 4096;
+
+const COLOR_SUCCESS: u32 = 0x2e_cc_71;
+const COLOR_FAILURE: u32 = 0xe7_4c_3c;
+
+#[derive(Debug, Serialize)]
+struct DiscordMessage {
+    embeds: Vec<Embed>,
+}
+
+#[derive(Debug, Serialize)]
+struct Embed {
+    title: String,
+    description: String,
+    color: u32,
+    timestamp: String,
+    fields: Vec<EmbedField>,
+}
+
+#[derive(Debug, Serialize)]
+struct EmbedField {
+    name: &'static str,
+    value: String,
+    inline: bool,
+}
+
+impl DiscordMessage {
+    fn new(event: &NotificationEvent) -> Self {
+        let (kind, color) = match event.urgent {
+            true => ("Failure", COLOR_FAILURE),
+            false => ("Update", COLOR_SUCCESS),
+        };
+        let mut fields = vec![EmbedField {
+            name: "Event",
+            value: kind.to_string(),
+            inline: true,
+        }];
+        if let Some(target) = &event.target {
+            fields.push(EmbedField {
+                name: "Target",
+                value: target.clone(),
+                inline: true,
+            });
+        }
+        if let Some(new_cidr) = &event.new_cidr {
+            fields.push(EmbedField {
+                name: "Old IP",
+                value: event
+                    .old_cidr
+                    .map_or_else(|| "none".to_string(), |cidr| cidr.to_string()),
+                inline: true,
+            });
+            fields.push(EmbedField {
+                name: "New IP",
+                value: new_cidr.to_string(),
+                inline: true,
+            });
+        }
+        Self {
+            embeds: vec![Embed {
+                title: truncate(event.summary(), MAX_TITLE_LENGTH),
+                description: truncate(&event.body(), MAX_DESCRIPTION_LENGTH),
+                color,
+                timestamp: Utc::now().to_rfc3339(),
+                fields,
+            }],
+        }
+    }
+}
+
+/// Truncates to at most `max` characters, marking the cut with an ellipsis.
+fn truncate(text: &str, max: usize) -> String {
+    if text.chars().count() <= max {
+        return text.to_string();
+    }
+    let mut truncated: String = text.chars().take(max - 1).collect();
+    truncated.push('…');
+    truncated
+}
+
+/// Posts notifications as embeds to a Discord webhook.
+pub struct Discord {
+    client: Client,
+    url: Url,
+}
+
+impl Discord {
+    pub fn new(url: &Url) -> reqwest::Result<Self> {
+        Ok(Self {
+            client: Client::builder().timeout(TIMEOUT).build()?,
+            url: url.clone(),
+        })
+    }
+
+    pub async fn send(&self, event: &NotificationEvent) -> reqwest::Result<()> {
+        let message = DiscordMessage::new(event);
+        let mut attempt = 1;
+        loop {
+            let response = self
+                .client
+                .post(self.url.clone())
+                .json(&message)
+                .send()
+                .await?;
+            if response.status() != StatusCode::TOO_MANY_REQUESTS || attempt == ATTEMPTS {
+                response.error_for_status()?;
+                return Ok(());
+            }
+
+            // Discord gives the delay in seconds, possibly fractional
+            let retry_after = response
+                .headers()
+                .get(RATE_LIMIT_RESET_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse::<f64>().ok())
+                .filter(|secs| secs.is_finite() && *secs >= 0.)
+                .map_or(Duration::from_secs(1), Duration::from_secs_f64)
+                .min(MAX_RETRY_AFTER);
+            debug!(
+                "Rate limited by Discord, retrying in {:.1} seconds.",
+                retry_after.as_secs_f64()
+            );
+            sleep(retry_after).await;
+            attempt += 1;
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for Discord {
+    fn name(&self) -> &'static str {
+        "Discord"
+    }
+
+    async fn notify(&self, event: &NotificationEvent) -> Result<(), NotifyError> {
+        self.send(event).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Target;
+    use serde_json::json;
+
+    fn target() -> Target {
+        Target {
+            name: "office".to_string(),
+            prefix_list_id: "pl-12345678".parse().unwrap(),
+            description: "office".parse().unwrap(),
+            region: None,
+            notify: true,
+        }
+    }
+
+    /// The message as posted, without the timestamp which changes every time.
+    fn posted(event: &NotificationEvent) -> serde_json::Value {
+        let mut message = serde_json::to_value(DiscordMessage::new(event)).unwrap();
+        let timestamp = message["embeds"][0]
+            .as_object_mut()
+            .unwrap()
+            .remove("timestamp")
+            .unwrap();
+        assert!(chrono::DateTime::parse_from_rfc3339(timestamp.as_str().unwrap()).is_ok());
+        message
+    }
+
+    #[test]
+    fn embed_of_an_update() {
+        let event = NotificationEvent::updated(
+            &target(),
+            Some("192.0.2.1/32".parse().unwrap()),
+            "192.0.2.2/32".parse().unwrap(),
+            Some(3),
+            Duration::from_millis(1500),
+        );
+        assert_eq!(
+            posted(&event),
+            json!({
+                "embeds": [{
+                    "title": "Updated prefix list",
+                    "description": "office: 192.0.2.1/32 -> 192.0.2.2/32 in pl-12345678 version 3, took 1.5s",
+                    "color": COLOR_SUCCESS,
+                    "fields": [
+                        {"name": "Event", "value": "Update", "inline": true},
+                        {"name": "Target", "value": "office", "inline": true},
+                        {"name": "Old IP", "value": "192.0.2.1/32", "inline": true},
+                        {"name": "New IP", "value": "192.0.2.2/32", "inline": true},
+                    ],
+                }],
+            })
+        );
+    }
+
+    #[test]
+    fn embed_of_a_failure() {
+        let event = NotificationEvent::ip_lookup_failed("No IP found...");
+        assert_eq!(
+            posted(&event),
+            json!({
+                "embeds": [{
+                    "title": "Failed to retrieve external IP.",
+                    "description": "No IP found...",
+                    "color": COLOR_FAILURE,
+                    "fields": [{"name": "Event", "value": "Failure", "inline": true}],
+                }],
+            })
+        );
+    }
+
+    #[test]
+    fn long_descriptions_are_truncated() {
+        let event = NotificationEvent::ip_lookup_failed("x".repeat(5000));
+        let description = posted(&event)["embeds"][0]["description"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        assert_eq!(description.chars().count(), MAX_DESCRIPTION_LENGTH);
+        assert!(description.ends_with('…'));
+    }
+}
@@ -0,0 +1,54 @@
+use reqwest::StatusCode;
+use std::error::Error;
+use std::fmt;
+
+/// Why a notification wasn't delivered, which decides what the dispatcher does about it.
+#[derive(Debug)]
+pub enum NotifyError {
+    /// The backend can't work on this machine, e.g. there's no notification server on a headless one. It's disabled
+    /// until doorman restarts.
+    Unavailable(String),
+    /// The notification or its payload was refused as it is, which is a bug or a misconfiguration. Sending it again
+    /// won't help.
+    Malformed(String),
+    /// The backend couldn't be reached or took too long. This may go away, so it's worth retrying.
+    Transient(String),
+}
+
+impl NotifyError {
+    pub fn is_transient(&self) -> bool {
+        matches!(self, Self::Transient(_))
+    }
+}
+
+impl From<reqwest::Error> for NotifyError {
+    fn from(err: reqwest::Error) -> Self {
+        let retry = |status: StatusCode| {
+            status == StatusCode::TOO_MANY_REQUESTS
+                || status == StatusCode::REQUEST_TIMEOUT
+                || status.is_server_error()
+        };
+        let message = err.to_string();
+        match err.status() {
+            Some(status) if retry(status) => Self::Transient(message),
+            Some(_) => Self::Malformed(message),
+            None if err.is_builder() || err.is_body() || err.is_decode() => {
+                Self::Malformed(message)
+            }
+            // Timeouts, unreachable servers and the connection breaking
+            None => Self::Transient(message),
+        }
+    }
+}
+
+impl Error for NotifyError {}
+
+impl fmt::Display for NotifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unavailable(message) => write!(f, "unavailable: {}", message),
+            Self::Malformed(message) => write!(f, "rejected: {}", message),
+            Self::Transient(message) => write!(f, "{}", message),
+        }
+    }
+}
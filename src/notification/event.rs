@@ -0,0 +1,429 @@
+use crate::config::{PrefixListId, Target};
+use crate::leader::Role;
+
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    /// A prefix list now holds the new IP
+    Updated,
+    /// The external IP couldn't be determined
+    IpLookupFailed,
+    /// A prefix list couldn't be modified
+    UpdateFailed,
+    /// A failure reported earlier went away
+    Recovered,
+    /// Doorman is stopping
+    Shutdown,
+    /// Someone else removed our entry from a prefix list
+    EntryRemovedExternally,
+    /// Owned entries were removed on request
+    CleanedUp,
+    /// A prefix list doorman manages was deleted
+    PrefixListDeleted,
+    /// The prefix lists didn't hold the IP when checked again
+    VerificationFailed,
+    /// An entry carries our description but wasn't written by us, likely by another doorman sharing it
+    OwnershipConflict,
+    /// Someone else's entry already holds the IP, so doorman doesn't add its own
+    ForeignEntry,
+    /// Modifying the prefix lists kept failing the same way, so doorman only tries once in a while
+    CircuitOpen,
+    /// This doorman became the leader of a redundant pair, stopped being it, or can't tell anymore
+    LeadershipChanged,
+}
+
+/// How much an event matters, used to filter what each backend gets.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Info,
+    Change,
+    Error,
+}
+
+impl Severity {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Info => "info",
+            Self::Change => "change",
+            Self::Error => "error",
+        }
+    }
+}
+
+impl EventKind {
+    pub fn severity(self) -> Severity {
+        match self {
+            Self::Recovered | Self::Shutdown | Self::CleanedUp | Self::ForeignEntry => {
+                Severity::Info
+            }
+            Self::Updated | Self::LeadershipChanged => Severity::Change,
+            Self::IpLookupFailed
+            | Self::UpdateFailed
+            | Self::EntryRemovedExternally
+            | Self::PrefixListDeleted
+            | Self::VerificationFailed
+            | Self::OwnershipConflict
+            | Self::CircuitOpen => Severity::Error,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Updated => "updated",
+            Self::IpLookupFailed => "ip_lookup_failed",
+            Self::UpdateFailed => "update_failed",
+            Self::Recovered => "recovered",
+            Self::Shutdown => "shutdown",
+            Self::EntryRemovedExternally => "entry_removed_externally",
+            Self::CleanedUp => "cleaned_up",
+            Self::PrefixListDeleted => "prefix_list_deleted",
+            Self::VerificationFailed => "verification_failed",
+            Self::OwnershipConflict => "ownership_conflict",
+            Self::ForeignEntry => "foreign_entry",
+            Self::CircuitOpen => "circuit_open",
+            Self::LeadershipChanged => "leadership_changed",
+        }
+    }
+
+    /// Whether this reports a failure, which may repeat until it's fixed.
+    pub fn is_failure(self) -> bool {
+        matches!(
+            self,
+            Self::IpLookupFailed
+                | Self::UpdateFailed
+                | Self::VerificationFailed
+                | Self::CircuitOpen
+        )
+    }
+}
+
+/// Something worth telling the user about.
+///
+/// Backends get the whole event, so they can render it as they see fit. Those which only handle text can rely on
+/// [`NotificationEvent::summary`] and [`NotificationEvent::body`].
+///
+/// Events still queued for delivery are kept in the state file, so they survive a restart.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct NotificationEvent {
+    pub kind: EventKind,
+    /// Name of the target concerned, if any
+    pub target: Option<String>,
+    pub prefix_list_id: Option<PrefixListId>,
+    pub old_cidr: Option<IpNet>,
+    pub new_cidr: Option<IpNet>,
+    /// Version of the prefix list after the update
+    pub version: Option<i64>,
+    /// How long the update took, including waiting for it to complete
+    pub duration: Option<Duration>,
+    pub error: Option<String>,
+    pub urgent: bool,
+    /// How many identical failures were left out since the last notification
+    pub suppressed: u32,
+    /// For recoveries, what went wrong
+    pub recovered_from: Option<EventKind>,
+    /// For recoveries, how many times it went wrong
+    pub failures: u32,
+    /// Anything else worth knowing, such as why doorman stops
+    pub detail: Option<String>,
+    /// Replaces the default body, rendered from the backend's template
+    pub body_override: Option<String>,
+}
+
+impl NotificationEvent {
+    fn new(kind: EventKind, target: Option<&Target>, urgent: bool) -> Self {
+        Self {
+            kind,
+            target: target.map(|target| target.name.clone()),
+            prefix_list_id: target.map(|target| target.prefix_list_id.clone()),
+            old_cidr: None,
+            new_cidr: None,
+            version: None,
+            duration: None,
+            error: None,
+            urgent,
+            suppressed: 0,
+            recovered_from: None,
+            failures: 0,
+            detail: None,
+            body_override: None,
+        }
+    }
+
+    pub fn updated(
+        target: &Target,
+        old_cidr: Option<IpNet>,
+        new_cidr: IpNet,
+        version: Option<i64>,
+        duration: Duration,
+    ) -> Self {
+        Self {
+            old_cidr,
+            new_cidr: Some(new_cidr),
+            version,
+            duration: Some(duration),
+            ..Self::new(EventKind::Updated, Some(target), false)
+        }
+    }
+
+    pub fn ip_lookup_failed(error: impl Display) -> Self {
+        Self {
+            error: Some(error.to_string()),
+            ..Self::new(EventKind::IpLookupFailed, None, true)
+        }
+    }
+
+    pub fn update_failed(target: &Target, error: impl Display) -> Self {
+        Self {
+            // All of the chain, an AWS error's context alone doesn't tell what went wrong
+            error: Some(format!("{:#}", error)),
+            ..Self::new(EventKind::UpdateFailed, Some(target), true)
+        }
+    }
+
+    /// The failure reported by `failed` went away after happening `failures` times.
+    pub fn recovered(failed: &Self, failures: u32) -> Self {
+        Self {
+            target: failed.target.clone(),
+            prefix_list_id: failed.prefix_list_id.clone(),
+            recovered_from: Some(failed.kind),
+            failures,
+            ..Self::new(EventKind::Recovered, None, false)
+        }
+    }
+
+    /// `cidr` vanished from the target's prefix list, which went from `old_version` to `new_version`.
+    pub fn entry_removed_externally(
+        target: &Target,
+        cidr: IpNet,
+        old_version: Option<i64>,
+        new_version: Option<i64>,
+    ) -> Self {
+        let version =
+            |version: Option<i64>| version.map_or_else(|| "?".to_string(), |v| v.to_string());
+        Self {
+            old_cidr: Some(cidr),
+            version: new_version,
+            detail: Some(format!(
+                "version {} -> {}",
+                version(old_version),
+                version(new_version)
+            )),
+            ..Self::new(EventKind::EntryRemovedExternally, Some(target), true)
+        }
+    }
+
+    /// The target's prefix list was deleted, and recreated as `recreated` if doorman was asked to.
+    pub fn prefix_list_deleted(target: &Target, recreated: Option<&PrefixListId>) -> Self {
+        Self {
+            detail: Some(match recreated {
+                Some(id) => format!(
+                    "recreated it as {}, references to the old one must be updated",
+                    id
+                ),
+                None => "stopping".to_string(),
+            }),
+            ..Self::new(EventKind::PrefixListDeleted, Some(target), true)
+        }
+    }
+
+    /// `failures` verification passes in a row found `problem`.
+    pub fn verification_failed(problem: impl Display, failures: u32) -> Self {
+        Self {
+            error: Some(problem.to_string()),
+            failures,
+            ..Self::new(EventKind::VerificationFailed, None, true)
+        }
+    }
+
+    /// `cidr` in the target's prefix list carries our description, but this instance didn't write it. `action` tells
+    /// what doorman does about it.
+    pub fn ownership_conflict(target: &Target, cidr: IpNet, action: impl Display) -> Self {
+        Self {
+            old_cidr: Some(cidr),
+            detail: Some(action.to_string()),
+            ..Self::new(EventKind::OwnershipConflict, Some(target), true)
+        }
+    }
+
+    /// The target's prefix list already holds `cidr` in an entry described as `description`, which isn't ours.
+    pub fn foreign_entry(target: &Target, cidr: IpNet, description: &str) -> Self {
+        Self {
+            new_cidr: Some(cidr),
+            detail: Some(description.to_string()),
+            ..Self::new(EventKind::ForeignEntry, Some(target), false)
+        }
+    }
+
+    /// `failures` checks in a row failed to modify the prefix lists, the last one with `error`, so they're only tried
+    /// every `probe_every`.
+    pub fn circuit_open(error: impl Display, failures: u32, probe_every: Duration) -> Self {
+        Self {
+            error: Some(error.to_string()),
+            failures,
+            detail: Some(format!(
+                "trying again every {} seconds",
+                probe_every.as_secs()
+            )),
+            ..Self::new(EventKind::CircuitOpen, None, true)
+        }
+    }
+
+    /// This doorman, known as `owner` in the lease, is now in `role`. Not knowing is urgent, as nothing gets updated.
+    pub fn leadership_changed(owner: &str, role: Role) -> Self {
+        Self {
+            detail: Some(format!(
+                "{} {}",
+                owner,
+                match role {
+                    Role::Leader => "is now the leader, and modifies the prefix lists",
+                    Role::Follower =>
+                        "is now a follower, the other doorman modifies the prefix lists",
+                    Role::Unknown => "can't read the leader lease, leaving the prefix lists alone",
+                }
+            )),
+            ..Self::new(EventKind::LeadershipChanged, None, role == Role::Unknown)
+        }
+    }
+
+    /// `summary` tells what was done for each target, the event is urgent if any of them failed.
+    pub fn cleaned_up(summary: impl Display, failed: bool) -> Self {
+        Self {
+            detail: Some(summary.to_string()),
+            ..Self::new(EventKind::CleanedUp, None, failed)
+        }
+    }
+
+    pub fn shutdown(reason: impl Display) -> Self {
+        Self {
+            detail: Some(reason.to_string()),
+            ..Self::new(EventKind::Shutdown, None, false)
+        }
+    }
+
+    pub fn summary(&self) -> &'static str {
+        match self.kind {
+            EventKind::Updated => "Updated prefix list",
+            EventKind::IpLookupFailed => "Failed to retrieve external IP.",
+            EventKind::UpdateFailed => "Failed to modify prefix list.",
+            EventKind::Recovered => match self.recovered_from {
+                Some(EventKind::IpLookupFailed) => "Retrieved external IP again.",
+                Some(EventKind::VerificationFailed) => "Verified prefix lists again.",
+                Some(EventKind::CircuitOpen) => "Modifying prefix lists again.",
+                _ => "Modified prefix list again.",
+            },
+            EventKind::Shutdown => "Shutting down",
+            EventKind::EntryRemovedExternally => "Entry removed from prefix list by someone else",
+            EventKind::PrefixListDeleted => "Prefix list deleted",
+            EventKind::VerificationFailed => "Prefix lists don't hold the IP",
+            EventKind::OwnershipConflict => "Entry written by another doorman",
+            EventKind::ForeignEntry => "IP already in prefix list",
+            EventKind::CircuitOpen => "Stopped retrying prefix lists",
+            EventKind::LeadershipChanged => "Leadership changed",
+            EventKind::CleanedUp => match self.urgent {
+                true => "Failed to clean up some prefix lists",
+                false => "Cleaned up prefix lists",
+            },
+        }
+    }
+
+    pub fn body(&self) -> String {
+        let body = match &self.body_override {
+            Some(body) => body.clone(),
+            None => self.default_body(),
+        };
+        match self.suppressed {
+            0 => body,
+            suppressed => format!(
+                "{} ({} similar notification(s) suppressed)",
+                body, suppressed
+            ),
+        }
+    }
+
+    fn default_body(&self) -> String {
+        let target = self.target.as_deref().unwrap_or_default();
+        let error = self.error.as_deref().unwrap_or_default();
+        match self.kind {
+            EventKind::Updated => format!(
+                "{}: {} -> {} in {}{}{}",
+                target,
+                self.old_cidr
+                    .map_or_else(|| "none".to_string(), |cidr| cidr.to_string()),
+                self.new_cidr
+                    .map_or_else(|| "none".to_string(), |cidr| cidr.to_string()),
+                self.prefix_list_id
+                    .as_ref()
+                    .map_or("", PrefixListId::as_str),
+                self.version
+                    .map_or_else(String::new, |version| format!(" version {}", version)),
+                self.duration.map_or_else(String::new, |duration| format!(
+                    ", took {:.1}s",
+                    duration.as_secs_f64()
+                )),
+            ),
+            EventKind::IpLookupFailed => error.to_string(),
+            EventKind::UpdateFailed => format!("{}: {}", target, error),
+            EventKind::Recovered => match &self.target {
+                Some(target) => format!("{}: recovered after {} failure(s)", target, self.failures),
+                None => format!("Recovered after {} failure(s)", self.failures),
+            },
+            EventKind::Shutdown | EventKind::CleanedUp | EventKind::LeadershipChanged => {
+                self.detail.clone().unwrap_or_default()
+            }
+            EventKind::EntryRemovedExternally => format!(
+                "{}: {} was removed from {} ({}), putting it back",
+                target,
+                self.old_cidr
+                    .map_or_else(|| "none".to_string(), |cidr| cidr.to_string()),
+                self.prefix_list_id
+                    .as_ref()
+                    .map_or("", PrefixListId::as_str),
+                self.detail.as_deref().unwrap_or_default(),
+            ),
+            EventKind::VerificationFailed => {
+                format!("{} (failed {} times in a row)", error, self.failures)
+            }
+            EventKind::CircuitOpen => format!(
+                "{} (failed {} checks in a row), {}",
+                error,
+                self.failures,
+                self.detail.as_deref().unwrap_or_default(),
+            ),
+            EventKind::OwnershipConflict => format!(
+                "{}: {} in {} carries doorman's description but wasn't written by this instance, another one may \
+                 share the description. {}",
+                target,
+                self.old_cidr
+                    .map_or_else(|| "none".to_string(), |cidr| cidr.to_string()),
+                self.prefix_list_id
+                    .as_ref()
+                    .map_or("", PrefixListId::as_str),
+                self.detail.as_deref().unwrap_or_default(),
+            ),
+            EventKind::ForeignEntry => format!(
+                "{}: {} is already in {} as \"{}\", doorman adds its own entry once that one is gone",
+                target,
+                self.new_cidr
+                    .map_or_else(|| "none".to_string(), |cidr| cidr.to_string()),
+                self.prefix_list_id
+                    .as_ref()
+                    .map_or("", PrefixListId::as_str),
+                self.detail.as_deref().unwrap_or_default(),
+            ),
+            EventKind::PrefixListDeleted => format!(
+                "{}: {} was deleted, {}",
+                target,
+                self.prefix_list_id
+                    .as_ref()
+                    .map_or("", PrefixListId::as_str),
+                self.detail.as_deref().unwrap_or_default(),
+            ),
+        }
+    }
+}
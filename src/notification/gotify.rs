@@ -0,0 +1,76 @@
+use super::{NotificationEvent, Notifier, NotifyError};
+
+use async_trait::async_trait;
+use reqwest::{Certificate, Client, Url};
+use serde::Serialize;
+use tokio::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+// Gotify clients usually pop up notifications from priority 8
+const PRIORITY_URGENT: u8 = 8;
+const PRIORITY_ROUTINE: u8 = 2;
+
+#[derive(Debug, Serialize)]
+struct GotifyMessage<'a> {
+    title: &'a str,
+    message: &'a str,
+    priority: u8,
+}
+
+/// Sends notifications to a Gotify server.
+pub struct Gotify {
+    client: Client,
+    message_url: Url,
+    token: String,
+}
+
+impl Gotify {
+    pub fn new(
+        server_url: &Url,
+        token: &str,
+        ca_cert: Option<&Certificate>,
+    ) -> color_eyre::Result<Self> {
+        let mut builder = Client::builder().timeout(TIMEOUT);
+        if let Some(ca_cert) = ca_cert {
+            builder = builder.add_root_certificate(ca_cert.clone());
+        }
+        Ok(Self {
+            client: builder.build()?,
+            message_url: server_url.join("message")?,
+            token: token.to_string(),
+        })
+    }
+
+    pub async fn send(&self, summary: &str, body: &str, urgent: bool) -> reqwest::Result<()> {
+        let message = GotifyMessage {
+            title: summary,
+            message: body,
+            priority: match urgent {
+                true => PRIORITY_URGENT,
+                false => PRIORITY_ROUTINE,
+            },
+        };
+        self.client
+            .post(self.message_url.clone())
+            .header("X-Gotify-Key", &self.token)
+            .json(&message)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Notifier for Gotify {
+    fn name(&self) -> &'static str {
+        "Gotify"
+    }
+
+    async fn notify(&self, event: &NotificationEvent) -> Result<(), NotifyError> {
+        self.send(event.summary(), &event.body(), event.urgent)
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,104 @@
+use super::{EventKind, NotificationEvent, Notifier, NotifyError};
+use crate::config::HookConfig;
+
+use async_trait::async_trait;
+use ipnet::IpNet;
+use std::path::PathBuf;
+use std::process::Stdio;
+use tokio::process::Command;
+use tokio::time::{timeout, Duration};
+use tracing::{info, warn};
+
+/// Runs user commands when the IP changes or updates fail.
+///
+/// Commands run in the background, so a slow one doesn't hold up doorman. They get the details of the event in
+/// `DOORMAN_*` environment variables.
+pub struct Hook {
+    on_change: Option<PathBuf>,
+    on_failure: Option<PathBuf>,
+    timeout: Duration,
+}
+
+impl Hook {
+    pub fn new(config: &HookConfig) -> Self {
+        Self {
+            on_change: config.on_change.clone(),
+            on_failure: config.on_failure.clone(),
+            timeout: Duration::from_secs(config.timeout),
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for Hook {
+    fn name(&self) -> &'static str {
+        "hook"
+    }
+
+    async fn notify(&self, event: &NotificationEvent) -> Result<(), NotifyError> {
+        let program = match event.kind {
+            EventKind::Updated => &self.on_change,
+            EventKind::UpdateFailed => &self.on_failure,
+            _ => &None,
+        };
+        let program = match program {
+            Some(program) => program,
+            None => return Ok(()),
+        };
+
+        let ip = |cidr: Option<IpNet>| cidr.map_or_else(String::new, |cidr| cidr.to_string());
+        let mut command = Command::new(program);
+        command
+            .env("DOORMAN_EVENT", event.kind.as_str())
+            .env(
+                "DOORMAN_TARGET",
+                event.target.as_deref().unwrap_or_default(),
+            )
+            .env("DOORMAN_OLD_IP", ip(event.old_cidr))
+            .env("DOORMAN_NEW_IP", ip(event.new_cidr))
+            .env(
+                "DOORMAN_PREFIX_LIST_ID",
+                event
+                    .prefix_list_id
+                    .as_ref()
+                    .map_or_else(String::new, ToString::to_string),
+            )
+            .env("DOORMAN_ERROR", event.error.as_deref().unwrap_or_default())
+            .env("DOORMAN_MESSAGE", event.body())
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            // Kills the command if it times out
+            .kill_on_drop(true);
+        // Spawning fails right away if the command doesn't exist, which should be reported as such. It's a
+        // misconfiguration, so there's no point in retrying.
+        let child = command.spawn().map_err(|err| {
+            NotifyError::Malformed(format!("failed to run {}: {}", program.display(), err))
+        })?;
+
+        let program = program.display().to_string();
+        let limit = self.timeout;
+        tokio::spawn(async move {
+            match timeout(limit, child.wait_with_output()).await {
+                Err(_) => warn!(
+                    "Hook {} didn't finish within {} seconds and was killed.",
+                    program,
+                    limit.as_secs()
+                ),
+                Ok(Err(err)) => warn!("Failed to run hook {}: {}", program, err),
+                Ok(Ok(output)) => {
+                    for line in String::from_utf8_lossy(&output.stdout).lines() {
+                        info!("[{}] {}", program, line);
+                    }
+                    for line in String::from_utf8_lossy(&output.stderr).lines() {
+                        warn!("[{}] {}", program, line);
+                    }
+                    if !output.status.success() {
+                        warn!("Hook {} exited with {}", program, output.status);
+                    }
+                }
+            }
+        });
+        Ok(())
+    }
+}
@@ -0,0 +1,22 @@
+use super::{NotificationEvent, Notifier, NotifyError};
+
+use async_trait::async_trait;
+use tracing::{info, warn};
+
+/// Writes notifications to the log.
+pub struct LogNotifier;
+
+#[async_trait]
+impl Notifier for LogNotifier {
+    fn name(&self) -> &'static str {
+        "log"
+    }
+
+    async fn notify(&self, event: &NotificationEvent) -> Result<(), NotifyError> {
+        match event.urgent {
+            true => warn!("Notification: {} {}", event.summary(), event.body()),
+            false => info!("Notification: {} {}", event.summary(), event.body()),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,935 @@
+use crate::config::{DesktopBackend, NotifyConfig};
+use crate::recheck::Recheck;
+
+use async_trait::async_trait;
+use color_eyre::{eyre::WrapErr, Result};
+use futures::future::join_all;
+use reqwest::Certificate;
+use std::borrow::Cow;
+use std::collections::hash_map::{Entry, HashMap};
+use std::collections::BTreeMap;
+use std::fs;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tokio::task::JoinHandle;
+use tokio::time::{timeout, Instant};
+use tracing::{debug, error, info, warn};
+
+mod dedup;
+#[cfg(feature = "desktop")]
+mod desktop;
+mod discord;
+mod error;
+mod event;
+mod gotify;
+mod hook;
+mod logger;
+mod ntfy;
+mod osascript;
+mod pushover;
+mod queue;
+mod slack;
+mod smtp;
+mod telegram;
+mod template;
+mod webhook;
+
+pub use self::error::NotifyError;
+pub use self::event::{EventKind, NotificationEvent, Severity};
+pub use self::template::TemplateSet;
+
+use self::dedup::Deduplicator;
+#[cfg(feature = "desktop")]
+use self::desktop::Desktop;
+use self::discord::Discord;
+use self::gotify::Gotify;
+use self::hook::Hook;
+use self::logger::LogNotifier;
+use self::ntfy::Ntfy;
+use self::osascript::Osascript;
+use self::pushover::Pushover;
+use self::queue::Queued;
+use self::slack::Slack;
+use self::smtp::Smtp;
+use self::telegram::Telegram;
+use self::webhook::Webhook;
+
+/// Names of the backends, as used to configure their templates.
+pub const BACKEND_NAMES: [&str; 11] = [
+    "desktop", "log", "webhook", "slack", "discord", "telegram", "smtp", "ntfy", "gotify",
+    "pushover", "hook",
+];
+
+/// A way of delivering notifications.
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    /// Names the backend in the logs. Lowercased, it's one of [`BACKEND_NAMES`].
+    fn name(&self) -> &'static str;
+
+    /// The kind of error decides whether the notification is retried or the backend is given up on, see
+    /// [`NotifyError`].
+    async fn notify(&self, event: &NotificationEvent) -> Result<(), NotifyError>;
+
+    /// Waits for notifications accepted by `notify` but not delivered yet.
+    async fn flush(&self) {}
+
+    /// The notifications accepted by `notify` but not delivered yet, oldest first.
+    fn pending(&self) -> Vec<NotificationEvent> {
+        Vec::new()
+    }
+}
+
+/// Picks how desktop notifications are shown, if at all.
+///
+/// This is checked once at startup, so a headless machine doesn't get an error for every notification.
+fn desktop(choice: DesktopBackend, recheck: &Recheck) -> Result<Option<Box<dyn Notifier>>> {
+    pick_desktop(choice, |probe| native(probe, recheck))
+}
+
+/// Like [`desktop`], `native` building the native backend, after checking that it works if asked to.
+fn pick_desktop(
+    choice: DesktopBackend,
+    native: impl Fn(bool) -> Result<Box<dyn Notifier>>,
+) -> Result<Option<Box<dyn Notifier>>> {
+    match choice {
+        DesktopBackend::Log => Ok(None),
+        DesktopBackend::Osascript => Ok(Some(Box::new(Osascript))),
+        // Asked for explicitly, so it's used even if it doesn't look like it would work
+        DesktopBackend::Native => native(false).map(Some),
+        DesktopBackend::Auto => match native(true) {
+            Ok(native) => Ok(Some(native)),
+            Err(err) if cfg!(target_os = "macos") => {
+                info!(
+                    "Native desktop notifications are unavailable, going through osascript instead: {}",
+                    err
+                );
+                Ok(Some(Box::new(Osascript)))
+            }
+            Err(err) => {
+                info!(
+                    "Desktop notifications are unavailable, writing notifications to the log instead: {}",
+                    err
+                );
+                Ok(None)
+            }
+        },
+    }
+}
+
+#[cfg(feature = "desktop")]
+fn native(probe: bool, recheck: &Recheck) -> Result<Box<dyn Notifier>> {
+    if probe {
+        Desktop::probe()?;
+    }
+    Ok(Box::new(Desktop::new(recheck.clone())))
+}
+
+#[cfg(not(feature = "desktop"))]
+fn native(_probe: bool, _recheck: &Recheck) -> Result<Box<dyn Notifier>> {
+    Err(color_eyre::eyre::eyre!(
+        "doorman was built without native desktop notifications"
+    ))
+}
+
+/// Network backends may be down for a while, so their notifications are queued until they're back.
+fn queued(backend: impl Notifier + 'static) -> Box<dyn Notifier> {
+    Box::new(Queued::new(Box::new(backend)))
+}
+
+/// How many notifications may be delivered at the same time.
+const MAX_CONCURRENT_DELIVERIES: usize = 8;
+/// How long shutting down waits for notifications still being delivered.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(5);
+
+/// A failure which keeps happening.
+struct Suppression {
+    /// The first occurrence, which was notified
+    event: NotificationEvent,
+    last_sent: Instant,
+    suppressed: u32,
+    failures: u32,
+}
+
+/// Sends notifications to every configured backend at once.
+///
+/// Desktop notifications are sent whenever possible, the other backends only when configured. If there's no desktop
+/// to notify, notifications are written to the log instead.
+///
+/// A failure that keeps happening, such as during an ISP outage, is only notified again every `reminder_interval`.
+/// Failures are tracked per kind and target, so they don't hide each other.
+///
+/// Notifications are delivered in the background, so a slow backend holds up neither the next check nor shutting down.
+///
+/// A backend which turns out to be unavailable, such as the desktop on a headless server, is disabled until doorman
+/// restarts. Once none is left, notifications are written to the log instead.
+pub struct Dispatcher {
+    delivery: Arc<Delivery>,
+    permits: Arc<Semaphore>,
+    in_flight: Mutex<Vec<JoinHandle<()>>>,
+    deduplicator: Deduplicator,
+    reminder_interval: Duration,
+    suppressions: Mutex<HashMap<(EventKind, Option<String>), Suppression>>,
+}
+
+/// What's needed to deliver a notification to the backends.
+struct Delivery {
+    backends: Vec<Box<dyn Notifier>>,
+    /// Whether each backend, in the same order, was disabled for being unavailable
+    disabled: Vec<AtomicBool>,
+    /// Body templates, per backend name, which reloading the configuration file replaces
+    templates: RwLock<HashMap<String, TemplateSet>>,
+    /// Least severe events sent, per backend name, which reloading the configuration file replaces
+    min_levels: RwLock<HashMap<String, Severity>>,
+    hostname: String,
+}
+
+impl Dispatcher {
+    /// Desktop notifications about failures may offer to retry, which goes through `recheck`.
+    pub fn new(config: &NotifyConfig, recheck: &Recheck) -> Result<Self> {
+        let mut backends: Vec<Box<dyn Notifier>> = Vec::new();
+        if let Some(desktop) = desktop(config.desktop, recheck)? {
+            backends.push(desktop);
+        }
+        if config.log || backends.is_empty() {
+            backends.push(Box::new(LogNotifier));
+        }
+        if let Some(webhook) = &config.webhook {
+            backends.push(queued(Webhook::new(webhook)?));
+        }
+        if let Some(url) = &config.slack_url {
+            backends.push(queued(Slack::new(url)?));
+        }
+        if let Some(url) = &config.discord_url {
+            backends.push(queued(Discord::new(url)?));
+        }
+        if let Some(telegram) = &config.telegram {
+            backends.push(queued(Telegram::new(&telegram.token, &telegram.chat_id)?));
+        }
+        if let Some(smtp) = &config.smtp {
+            backends.push(Box::new(Smtp::new(smtp)?));
+        }
+
+        let ca_cert = match &config.ca_cert {
+            Some(path) => {
+                let pem = fs::read(path)
+                    .wrap_err_with(|| format!("Failed to read {}", path.display()))?;
+                Some(Certificate::from_pem(&pem)?)
+            }
+            None => None,
+        };
+        if let Some(url) = &config.ntfy_url {
+            backends.push(queued(Ntfy::new(url, ca_cert.as_ref())?));
+        }
+        if let Some(gotify) = &config.gotify {
+            backends.push(queued(Gotify::new(
+                &gotify.url,
+                &gotify.token,
+                ca_cert.as_ref(),
+            )?));
+        }
+
+        if let Some(pushover) = &config.pushover {
+            backends.push(queued(Pushover::new(pushover)?));
+        }
+
+        if let Some(hook) = &config.hook {
+            backends.push(Box::new(Hook::new(hook)));
+        }
+
+        Ok(Self::with_backends(config, backends))
+    }
+
+    /// Notifies `backends`, whatever the backends `config` asks for.
+    pub fn with_backends(config: &NotifyConfig, backends: Vec<Box<dyn Notifier>>) -> Self {
+        let hostname = hostname::get()
+            .map(|hostname| hostname.to_string_lossy().into_owned())
+            .unwrap_or_else(|err| {
+                warn!("Failed to get the hostname for notifications: {}", err);
+                "unknown".to_string()
+            });
+
+        Self {
+            delivery: Arc::new(Delivery {
+                disabled: backends.iter().map(|_| AtomicBool::new(false)).collect(),
+                backends,
+                templates: RwLock::new(config.templates.clone()),
+                min_levels: RwLock::new(config.min_levels.clone()),
+                hostname,
+            }),
+            permits: Arc::new(Semaphore::new(MAX_CONCURRENT_DELIVERIES)),
+            in_flight: Mutex::new(Vec::new()),
+            deduplicator: Deduplicator::new(Duration::from_secs(config.dedup_window)),
+            reminder_interval: Duration::from_secs(config.reminder_interval),
+            suppressions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Names the backends in use, in the order they're notified.
+    pub fn backend_names(&self) -> Vec<&'static str> {
+        self.delivery
+            .backends
+            .iter()
+            .map(|backend| backend.name())
+            .collect()
+    }
+
+    /// Replaces the templates and least severe events sent per backend, the backends themselves staying the same.
+    pub fn reload(
+        &self,
+        templates: HashMap<String, TemplateSet>,
+        min_levels: HashMap<String, Severity>,
+    ) {
+        *self.delivery.templates.write().unwrap() = templates;
+        *self.delivery.min_levels.write().unwrap() = min_levels;
+    }
+
+    /// Sends the event to every backend concurrently, in the background.
+    ///
+    /// Failures are only logged. Notifications are mostly sent when something already went wrong, so failing to send
+    /// one must not stop doorman from retrying.
+    pub fn dispatch(&self, event: &NotificationEvent) {
+        let event = match self.deduplicator.check(event) {
+            Some(event) => event,
+            None => {
+                debug!("Dropped duplicate notification: {}", event.summary());
+                return;
+            }
+        };
+        match self.suppress(&event) {
+            Some(event) => self.spawn(event),
+            None => debug!("Suppressed repeated notification: {}", event.summary()),
+        }
+    }
+
+    /// Notifies that a failure went away, if it was notified in the first place.
+    pub fn recovered(&self, kind: EventKind, target: Option<&str>) {
+        let suppression = self
+            .suppressions
+            .lock()
+            .unwrap()
+            .remove(&(kind, target.map(ToString::to_string)));
+        if let Some(suppression) = suppression {
+            self.spawn(NotificationEvent::recovered(
+                &suppression.event,
+                suppression.failures,
+            ));
+        }
+    }
+
+    /// The notifications waiting for delivery, per backend name, so they can be saved across restarts.
+    pub fn pending(&self) -> BTreeMap<String, Vec<NotificationEvent>> {
+        self.delivery
+            .backends
+            .iter()
+            .map(|backend| (backend.name().to_lowercase(), backend.pending()))
+            .filter(|(_, pending)| !pending.is_empty())
+            .collect()
+    }
+
+    /// Queues again the notifications [`Dispatcher::pending`] returned before doorman restarted.
+    ///
+    /// They were already rendered and filtered for their backend, so they go straight to it. Those for a backend no
+    /// longer configured are dropped.
+    pub async fn restore(&self, pending: &BTreeMap<String, Vec<NotificationEvent>>) {
+        for (name, events) in pending {
+            let backend = match self
+                .delivery
+                .backends
+                .iter()
+                .find(|backend| backend.name().to_lowercase() == *name)
+            {
+                Some(backend) => backend,
+                None => {
+                    warn!(
+                        "Dropping {} pending {} notification(s), the backend isn't configured anymore.",
+                        events.len(),
+                        name
+                    );
+                    continue;
+                }
+            };
+            info!(
+                "Sending {} {} notification(s) left over from the previous run.",
+                events.len(),
+                backend.name()
+            );
+            for event in events {
+                if let Err(err) = backend.notify(event).await {
+                    warn!("Failed to send {} notification: {}", backend.name(), err);
+                }
+            }
+        }
+    }
+
+    /// Waits for the notifications being delivered, for a little while.
+    pub async fn shutdown(&self) {
+        let in_flight = std::mem::take(&mut *self.in_flight.lock().unwrap());
+        let delivered = async {
+            join_all(in_flight).await;
+            join_all(self.delivery.backends.iter().map(|backend| backend.flush())).await;
+        };
+        if timeout(SHUTDOWN_GRACE, delivered).await.is_err() {
+            warn!(
+                "Some notifications weren't delivered within {} seconds of shutting down.",
+                SHUTDOWN_GRACE.as_secs()
+            );
+        }
+    }
+
+    fn spawn(&self, event: NotificationEvent) {
+        let delivery = self.delivery.clone();
+        let permits = self.permits.clone();
+        let handle = tokio::spawn(async move {
+            // The semaphore is never closed
+            let _permit = permits.acquire_owned().await.unwrap();
+            delivery.send(&event).await;
+        });
+
+        let mut in_flight = self.in_flight.lock().unwrap();
+        in_flight.retain(|handle| !handle.is_finished());
+        in_flight.push(handle);
+    }
+
+    /// Returns the event to send, if any, keeping track of repeated failures.
+    fn suppress(&self, event: &NotificationEvent) -> Option<NotificationEvent> {
+        if !event.kind.is_failure() {
+            return Some(event.clone());
+        }
+
+        let mut suppressions = self.suppressions.lock().unwrap();
+        match suppressions.entry((event.kind, event.target.clone())) {
+            Entry::Vacant(entry) => {
+                entry.insert(Suppression {
+                    event: event.clone(),
+                    last_sent: Instant::now(),
+                    suppressed: 0,
+                    failures: 1,
+                });
+                Some(event.clone())
+            }
+            Entry::Occupied(mut entry) => {
+                let suppression = entry.get_mut();
+                suppression.failures += 1;
+                if suppression.last_sent.elapsed() < self.reminder_interval {
+                    suppression.suppressed += 1;
+                    return None;
+                }
+                let reminder = NotificationEvent {
+                    suppressed: suppression.suppressed,
+                    ..event.clone()
+                };
+                suppression.suppressed = 0;
+                suppression.last_sent = Instant::now();
+                Some(reminder)
+            }
+        }
+    }
+}
+
+impl Delivery {
+    async fn send(&self, event: &NotificationEvent) {
+        let severity = event.kind.severity();
+        let enabled: Vec<(&AtomicBool, &dyn Notifier)> = self
+            .disabled
+            .iter()
+            .zip(&self.backends)
+            .filter(|(disabled, _)| !disabled.load(Ordering::SeqCst))
+            .map(|(disabled, backend)| (disabled, backend.as_ref()))
+            .collect();
+        if enabled.is_empty() {
+            self.fall_back(event).await;
+            return;
+        }
+
+        let (backends, events): (Vec<_>, Vec<Cow<NotificationEvent>>) = enabled
+            .into_iter()
+            .filter(|(_, backend)| {
+                self.min_levels
+                    .read()
+                    .unwrap()
+                    .get(&backend.name().to_lowercase())
+                    .is_none_or(|min_level| severity >= *min_level)
+            })
+            .map(|(disabled, backend)| ((disabled, backend), self.render(backend.name(), event)))
+            .unzip();
+        let results = join_all(
+            backends
+                .iter()
+                .zip(&events)
+                .map(|((_, backend), event)| backend.notify(event)),
+        )
+        .await;
+
+        let mut lost = false;
+        for ((disabled, backend), result) in backends.iter().zip(results) {
+            match result {
+                Ok(()) => {}
+                Err(err @ NotifyError::Unavailable(_)) => {
+                    warn!(
+                        "{} notifications are {}, they're disabled until doorman restarts.",
+                        backend.name(),
+                        err
+                    );
+                    disabled.store(true, Ordering::SeqCst);
+                    lost = true;
+                }
+                Err(err @ NotifyError::Malformed(_)) => {
+                    error!("The {} notification was {}", backend.name(), err);
+                }
+                // Network backends are queued and retry by themselves
+                Err(err @ NotifyError::Transient(_)) => {
+                    warn!("Failed to send {} notification: {}", backend.name(), err);
+                }
+            }
+        }
+        if lost
+            && self
+                .disabled
+                .iter()
+                .all(|disabled| disabled.load(Ordering::SeqCst))
+        {
+            self.fall_back(event).await;
+        }
+    }
+
+    /// Writes the notification to the log, once no backend is left to send it.
+    async fn fall_back(&self, event: &NotificationEvent) {
+        // Writing to the log can't fail
+        let _ = LogNotifier.notify(&self.render("log", event)).await;
+    }
+
+    /// Applies the backend's template, if it has one for this kind of event.
+    fn render<'a>(
+        &self,
+        backend: &str,
+        event: &'a NotificationEvent,
+    ) -> Cow<'a, NotificationEvent> {
+        match self
+            .templates
+            .read()
+            .unwrap()
+            .get(&backend.to_lowercase())
+            .and_then(|templates| templates.get(event.kind))
+        {
+            Some(template) => Cow::Owned(NotificationEvent {
+                body_override: Some(template.render(event, &self.hostname)),
+                ..event.clone()
+            }),
+            None => Cow::Borrowed(event),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, Target};
+    use std::sync::atomic::AtomicU32;
+    use tokio::time::sleep;
+
+    fn config() -> NotifyConfig {
+        Config::parse_from(&["--prefix-list-id", "pl-12345678", "--description", "office"]).notify
+    }
+
+    /// A backend answering each notification with what `outcome` returns for the attempt, counting from 1.
+    #[derive(Clone)]
+    struct Stub {
+        name: &'static str,
+        calls: Arc<AtomicU32>,
+        outcome: fn(u32) -> Result<(), NotifyError>,
+    }
+
+    impl Stub {
+        fn new(name: &'static str, outcome: fn(u32) -> Result<(), NotifyError>) -> Self {
+            Self {
+                name,
+                calls: Arc::default(),
+                outcome,
+            }
+        }
+
+        fn calls(&self) -> u32 {
+            self.calls.load(Ordering::SeqCst)
+        }
+    }
+
+    #[async_trait]
+    impl Notifier for Stub {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        async fn notify(&self, _: &NotificationEvent) -> Result<(), NotifyError> {
+            let call = self.calls.fetch_add(1, Ordering::SeqCst) + 1;
+            (self.outcome)(call)
+        }
+    }
+
+    fn ok(_: u32) -> Result<(), NotifyError> {
+        Ok(())
+    }
+
+    fn unavailable(_: u32) -> Result<(), NotifyError> {
+        Err(NotifyError::Unavailable(
+            "no notification server".to_string(),
+        ))
+    }
+
+    fn malformed(_: u32) -> Result<(), NotifyError> {
+        Err(NotifyError::Malformed("400 Bad Request".to_string()))
+    }
+
+    fn transient_once(call: u32) -> Result<(), NotifyError> {
+        match call {
+            1 => Err(NotifyError::Transient("connection refused".to_string())),
+            _ => Ok(()),
+        }
+    }
+
+    /// A backend which never answers, as if the server hung.
+    struct Hanging;
+
+    #[async_trait]
+    impl Notifier for Hanging {
+        fn name(&self) -> &'static str {
+            "webhook"
+        }
+
+        async fn notify(&self, _: &NotificationEvent) -> Result<(), NotifyError> {
+            std::future::pending().await
+        }
+    }
+
+    /// A backend keeping what it's sent.
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<NotificationEvent>>>);
+
+    impl Recorder {
+        fn events(&self) -> Vec<NotificationEvent> {
+            self.0.lock().unwrap().clone()
+        }
+    }
+
+    #[async_trait]
+    impl Notifier for Recorder {
+        fn name(&self) -> &'static str {
+            "log"
+        }
+
+        async fn notify(&self, event: &NotificationEvent) -> Result<(), NotifyError> {
+            self.0.lock().unwrap().push(event.clone());
+            Ok(())
+        }
+    }
+
+    fn recording() -> (Recorder, Dispatcher) {
+        let recorder = Recorder::default();
+        let dispatcher = Dispatcher::with_backends(&config(), vec![Box::new(recorder.clone())]);
+        (recorder, dispatcher)
+    }
+
+    fn target(name: &str) -> Target {
+        Target {
+            name: name.to_string(),
+            prefix_list_id: "pl-12345678".parse().unwrap(),
+            description: "office".parse().unwrap(),
+            region: None,
+            notify: true,
+        }
+    }
+
+    /// Stands in for the native desktop backend, whose probe fails unless `works`.
+    fn native(works: bool) -> impl Fn(bool) -> Result<Box<dyn Notifier>> {
+        move |probe| match probe && !works {
+            true => Err(color_eyre::eyre::eyre!("no notification server")),
+            false => Ok(Box::new(Stub::new("native", ok))),
+        }
+    }
+
+    fn picked(choice: DesktopBackend, works: bool) -> Option<&'static str> {
+        pick_desktop(choice, native(works))
+            .unwrap()
+            .map(|backend| backend.name())
+    }
+
+    /// Distinct events which are neither deduplicated nor suppressed.
+    fn event(detail: &str) -> NotificationEvent {
+        NotificationEvent::shutdown(detail)
+    }
+
+    #[test]
+    fn auto_picks_native_desktop_notifications_when_they_work() {
+        assert_eq!(picked(DesktopBackend::Auto, true), Some("native"));
+    }
+
+    #[test]
+    fn native_desktop_notifications_are_used_if_asked_for_even_if_they_look_broken() {
+        assert_eq!(picked(DesktopBackend::Native, false), Some("native"));
+    }
+
+    #[test]
+    fn no_desktop_notifications_with_the_log_backend() {
+        assert_eq!(picked(DesktopBackend::Log, true), None);
+    }
+
+    /// Such as a Linux server without a session bus, or Windows Server Core.
+    #[cfg(not(target_os = "macos"))]
+    #[test]
+    fn auto_falls_back_to_the_log_without_a_desktop() {
+        assert_eq!(picked(DesktopBackend::Auto, false), None);
+    }
+
+    /// Such as when running from a terminal, outside of an application bundle.
+    #[cfg(target_os = "macos")]
+    #[test]
+    fn auto_falls_back_to_osascript_without_native_notifications() {
+        assert_eq!(picked(DesktopBackend::Auto, false), Some("desktop"));
+    }
+
+    #[test]
+    fn osascript_is_used_if_asked_for_even_if_native_notifications_work() {
+        assert_eq!(picked(DesktopBackend::Osascript, true), Some("desktop"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn failing_backends_dont_fail_dispatching() {
+        let failing = Stub::new("webhook", malformed);
+        let working = Stub::new("log", ok);
+        let dispatcher = Dispatcher::with_backends(
+            &config(),
+            vec![Box::new(failing.clone()), Box::new(working.clone())],
+        );
+
+        dispatcher.dispatch(&event("first"));
+        dispatcher.dispatch(&event("second"));
+        dispatcher.shutdown().await;
+
+        assert_eq!(failing.calls(), 2);
+        assert_eq!(working.calls(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn hanging_backends_dont_block_dispatching() {
+        let working = Stub::new("log", ok);
+        let dispatcher = Dispatcher::with_backends(
+            &config(),
+            vec![Box::new(Hanging), Box::new(working.clone())],
+        );
+
+        let started = Instant::now();
+        dispatcher.dispatch(&event("first"));
+        assert_eq!(started.elapsed(), Duration::ZERO);
+        // Shutting down only waits so long for it
+        dispatcher.shutdown().await;
+        assert_eq!(started.elapsed(), SHUTDOWN_GRACE);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn unavailable_backends_are_disabled() {
+        let unavailable = Stub::new("desktop", unavailable);
+        let working = Stub::new("webhook", ok);
+        let dispatcher = Dispatcher::with_backends(
+            &config(),
+            vec![Box::new(unavailable.clone()), Box::new(working.clone())],
+        );
+
+        dispatcher.dispatch(&event("first"));
+        dispatcher.shutdown().await;
+        dispatcher.dispatch(&event("second"));
+        dispatcher.shutdown().await;
+
+        assert_eq!(unavailable.calls(), 1);
+        assert_eq!(working.calls(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn malformed_notifications_are_dropped_without_retrying() {
+        let malformed = Stub::new("webhook", malformed);
+        let backend = queued(malformed.clone());
+
+        backend.notify(&event("first")).await.unwrap();
+        backend.flush().await;
+        assert_eq!(malformed.calls(), 1);
+        assert!(backend.pending().is_empty());
+
+        // The backend is kept for the next ones
+        backend.notify(&event("second")).await.unwrap();
+        backend.flush().await;
+        assert_eq!(malformed.calls(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn transient_failures_are_retried() {
+        let flaky = Stub::new("webhook", transient_once);
+        let backend = queued(flaky.clone());
+
+        backend.notify(&event("first")).await.unwrap();
+        backend.flush().await;
+        assert_eq!(flaky.calls(), 2);
+        assert!(backend.pending().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn pending_notifications_are_restored_to_their_backend() {
+        let webhook = Stub::new("webhook", ok);
+        let slack = Stub::new("Slack", ok);
+        let dispatcher = Dispatcher::with_backends(
+            &config(),
+            vec![Box::new(webhook.clone()), Box::new(slack.clone())],
+        );
+
+        dispatcher
+            .restore(&BTreeMap::from([
+                ("slack".to_string(), vec![event("first"), event("second")]),
+                ("discord".to_string(), vec![event("third")]),
+            ]))
+            .await;
+
+        assert_eq!(slack.calls(), 2);
+        assert_eq!(webhook.calls(), 0);
+        sleep(Duration::from_secs(1)).await;
+        assert!(dispatcher.pending().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn repeated_failures_are_reminded_hourly_with_a_count() {
+        let (recorder, dispatcher) = recording();
+        let office = target("office");
+
+        // Every five minutes for two hours, as during an outage
+        for _ in 0..24 {
+            dispatcher.dispatch(&NotificationEvent::update_failed(&office, "unreachable"));
+            dispatcher.shutdown().await;
+            sleep(Duration::from_secs(300)).await;
+        }
+
+        let events = recorder.events();
+        assert_eq!(
+            events
+                .iter()
+                .map(|event| event.suppressed)
+                .collect::<Vec<_>>(),
+            [0, 11]
+        );
+        assert!(events[1]
+            .body()
+            .ends_with("(11 similar notification(s) suppressed)"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn the_reminder_interval_is_configurable() {
+        let recorder = Recorder::default();
+        let mut config = config();
+        config.reminder_interval = 600;
+        let dispatcher = Dispatcher::with_backends(&config, vec![Box::new(recorder.clone())]);
+
+        for _ in 0..5 {
+            dispatcher.dispatch(&NotificationEvent::ip_lookup_failed("timed out"));
+            dispatcher.shutdown().await;
+            sleep(Duration::from_secs(300)).await;
+        }
+
+        let suppressed: Vec<_> = recorder
+            .events()
+            .iter()
+            .map(|event| event.suppressed)
+            .collect();
+        assert_eq!(suppressed, [0, 1, 1]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn recovering_is_notified_right_away() {
+        let (recorder, dispatcher) = recording();
+        let office = target("office");
+
+        for _ in 0..3 {
+            dispatcher.dispatch(&NotificationEvent::update_failed(&office, "unreachable"));
+            sleep(Duration::from_secs(300)).await;
+        }
+        dispatcher.recovered(EventKind::UpdateFailed, Some("office"));
+        dispatcher.shutdown().await;
+
+        let events = recorder.events();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[1].kind, EventKind::Recovered);
+        assert_eq!(events[1].recovered_from, Some(EventKind::UpdateFailed));
+        assert_eq!(events[1].failures, 3);
+        assert_eq!(events[1].body(), "office: recovered after 3 failure(s)");
+
+        // Only once, and a failure afterwards is new again
+        dispatcher.recovered(EventKind::UpdateFailed, Some("office"));
+        dispatcher.dispatch(&NotificationEvent::update_failed(&office, "unreachable"));
+        dispatcher.shutdown().await;
+        let events = recorder.events();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[2].kind, EventKind::UpdateFailed);
+        assert_eq!(events[2].suppressed, 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn nothing_to_recover_from_isnt_notified() {
+        let (recorder, dispatcher) = recording();
+        dispatcher.recovered(EventKind::IpLookupFailed, None);
+        dispatcher.shutdown().await;
+        assert!(recorder.events().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn failures_are_tracked_per_kind_and_target() {
+        let (recorder, dispatcher) = recording();
+        let (office, home) = (target("office"), target("home"));
+
+        for _ in 0..2 {
+            dispatcher.dispatch(&NotificationEvent::ip_lookup_failed("timed out"));
+            dispatcher.dispatch(&NotificationEvent::update_failed(&office, "unreachable"));
+            dispatcher.dispatch(&NotificationEvent::update_failed(&home, "unreachable"));
+            sleep(Duration::from_secs(300)).await;
+        }
+        // The lookup recovering says nothing about the prefix lists
+        dispatcher.recovered(EventKind::IpLookupFailed, None);
+        dispatcher.dispatch(&NotificationEvent::update_failed(&office, "unreachable"));
+        dispatcher.shutdown().await;
+
+        let sent: Vec<_> = recorder
+            .events()
+            .iter()
+            .map(|event| (event.kind, event.target.clone()))
+            .collect();
+        assert_eq!(sent.len(), 4);
+        assert!(sent.contains(&(EventKind::IpLookupFailed, None)));
+        assert!(sent.contains(&(EventKind::UpdateFailed, Some("office".to_string()))));
+        assert!(sent.contains(&(EventKind::UpdateFailed, Some("home".to_string()))));
+        assert!(sent.contains(&(EventKind::Recovered, None)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn duplicates_are_dropped_whatever_the_backends_answer() {
+        let failing = Stub::new("webhook", malformed);
+        let unavailable = Stub::new("desktop", unavailable);
+        let recorder = Recorder::default();
+        let dispatcher = Dispatcher::with_backends(
+            &config(),
+            vec![
+                Box::new(failing.clone()),
+                Box::new(unavailable.clone()),
+                Box::new(recorder.clone()),
+            ],
+        );
+
+        dispatcher.dispatch(&event("entry restored"));
+        dispatcher.shutdown().await;
+        dispatcher.dispatch(&event("entry restored"));
+        dispatcher.shutdown().await;
+        assert_eq!(failing.calls(), 1);
+        assert_eq!(unavailable.calls(), 1);
+        assert_eq!(recorder.events().len(), 1);
+
+        sleep(Duration::from_secs(60)).await;
+        dispatcher.dispatch(&event("entry restored"));
+        dispatcher.shutdown().await;
+        assert_eq!(failing.calls(), 2);
+        assert_eq!(recorder.events()[1].suppressed, 1);
+    }
+}
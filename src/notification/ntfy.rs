@@ -0,0 +1,60 @@
+use super::{NotificationEvent, Notifier, NotifyError};
+
+use async_trait::async_trait;
+use reqwest::{Certificate, Client, Url};
+use tokio::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+// See https://docs.ntfy.sh/publish/#message-priority
+const PRIORITY_URGENT: &str = "5";
+const PRIORITY_ROUTINE: &str = "2";
+
+/// Publishes notifications to an ntfy topic.
+pub struct Ntfy {
+    client: Client,
+    topic_url: Url,
+}
+
+impl Ntfy {
+    pub fn new(topic_url: &Url, ca_cert: Option<&Certificate>) -> reqwest::Result<Self> {
+        let mut builder = Client::builder().timeout(TIMEOUT);
+        if let Some(ca_cert) = ca_cert {
+            builder = builder.add_root_certificate(ca_cert.clone());
+        }
+        Ok(Self {
+            client: builder.build()?,
+            topic_url: topic_url.clone(),
+        })
+    }
+
+    pub async fn send(&self, summary: &str, body: &str, urgent: bool) -> reqwest::Result<()> {
+        let (priority, tags) = match urgent {
+            true => (PRIORITY_URGENT, "warning"),
+            false => (PRIORITY_ROUTINE, "white_check_mark"),
+        };
+        self.client
+            .post(self.topic_url.clone())
+            .header("X-Title", summary)
+            .header("X-Priority", priority)
+            .header("X-Tags", tags)
+            .body(body.to_string())
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Notifier for Ntfy {
+    fn name(&self) -> &'static str {
+        "ntfy"
+    }
+
+    async fn notify(&self, event: &NotificationEvent) -> Result<(), NotifyError> {
+        self.send(event.summary(), &event.body(), event.urgent)
+            .await?;
+        Ok(())
+    }
+}
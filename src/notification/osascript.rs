@@ -0,0 +1,74 @@
+use super::{NotificationEvent, Notifier, NotifyError};
+
+use async_trait::async_trait;
+use std::io::ErrorKind;
+use tokio::process::Command;
+
+/// Shows notifications on macOS through AppleScript.
+///
+/// This works without the application bundle native notifications need, for instance when running from a terminal.
+pub struct Osascript;
+
+/// Quotes a string for AppleScript.
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[async_trait]
+impl Notifier for Osascript {
+    fn name(&self) -> &'static str {
+        "desktop"
+    }
+
+    async fn notify(&self, event: &NotificationEvent) -> Result<(), NotifyError> {
+        let mut script = format!(
+            "display notification {} with title {}",
+            quote(&event.body()),
+            quote(event.summary())
+        );
+        if event.urgent {
+            script.push_str(" sound name \"Basso\"");
+        }
+        let output = Command::new("osascript")
+            .arg("-e")
+            .arg(script)
+            .output()
+            .await
+            .map_err(|err| match err.kind() {
+                ErrorKind::NotFound => {
+                    NotifyError::Unavailable(format!("osascript wasn't found: {}", err))
+                }
+                _ => NotifyError::Transient(format!("failed to run osascript: {}", err)),
+            })?;
+        match output.status.success() {
+            true => Ok(()),
+            // The script is built here, so it being refused is a bug
+            false => Err(NotifyError::Malformed(format!(
+                "osascript failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_text_is_only_wrapped_in_quotes() {
+        assert_eq!(quote("office"), r#""office""#);
+    }
+
+    #[test]
+    fn quotes_dont_end_the_string() {
+        assert_eq!(quote(r#"say "hi""#), r#""say \"hi\"""#);
+    }
+
+    #[test]
+    fn backslashes_are_escaped_first() {
+        assert_eq!(quote(r"C:\dir"), r#""C:\\dir""#);
+        // Otherwise the backslash escaping the quote would be doubled too
+        assert_eq!(quote(r#"\""#), r#""\\\"""#);
+    }
+}
@@ -0,0 +1,182 @@
+use super::{NotificationEvent, Notifier, NotifyError};
+use crate::config::PushoverConfig;
+
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use tokio::time::Duration;
+use tracing::warn;
+
+const API_URL: &str = "https://api.pushover.net/1/messages.json";
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+// Emergency notifications are repeated until acknowledged, the routine ones don't make a sound
+const PRIORITY_URGENT: i8 = 2;
+const PRIORITY_ROUTINE: i8 = -1;
+/// How often an unacknowledged urgent notification is repeated, in seconds. Pushover requires at least 30.
+const URGENT_RETRY: u32 = 60;
+/// How long an urgent notification keeps being repeated, in seconds.
+const URGENT_EXPIRE: u32 = 3600;
+
+#[derive(Debug, Serialize)]
+struct PushoverMessage<'a> {
+    token: &'a str,
+    user: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    device: Option<&'a str>,
+    title: &'a str,
+    message: &'a str,
+    priority: i8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    retry: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expire: Option<u32>,
+}
+
+/// What Pushover tells about a rejected message.
+#[derive(Debug, Default, Deserialize)]
+struct PushoverErrors {
+    #[serde(default)]
+    errors: Vec<String>,
+}
+
+/// Sends notifications through Pushover.
+pub struct Pushover {
+    client: Client,
+    token: String,
+    user: String,
+    device: Option<String>,
+}
+
+impl Pushover {
+    pub fn new(config: &PushoverConfig) -> reqwest::Result<Self> {
+        Ok(Self {
+            client: Client::builder().timeout(TIMEOUT).build()?,
+            token: config.token.clone(),
+            user: config.user.clone(),
+            device: config.device.clone(),
+        })
+    }
+
+    fn message<'a>(&'a self, summary: &'a str, body: &'a str, urgent: bool) -> PushoverMessage<'a> {
+        PushoverMessage {
+            token: &self.token,
+            user: &self.user,
+            device: self.device.as_deref(),
+            title: summary,
+            message: body,
+            priority: match urgent {
+                true => PRIORITY_URGENT,
+                false => PRIORITY_ROUTINE,
+            },
+            retry: urgent.then_some(URGENT_RETRY),
+            expire: urgent.then_some(URGENT_EXPIRE),
+        }
+    }
+
+    /// Server errors are returned so the delivery is retried. Messages Pushover rejects, for instance because of a
+    /// wrong token, would be rejected again, so they're only logged.
+    pub async fn send(&self, summary: &str, body: &str, urgent: bool) -> reqwest::Result<()> {
+        // The token is sent in the form, so it doesn't show up in errors
+        let response = self
+            .client
+            .post(API_URL)
+            .form(&self.message(summary, body, urgent))
+            .send()
+            .await?;
+
+        if response.status().is_client_error() {
+            let status = response.status();
+            let errors = response
+                .json::<PushoverErrors>()
+                .await
+                .unwrap_or_default()
+                .errors;
+            warn!(
+                "Pushover rejected the notification ({}), not retrying: {}",
+                status,
+                errors.join(", ")
+            );
+            return Ok(());
+        }
+        response.error_for_status()?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Notifier for Pushover {
+    fn name(&self) -> &'static str {
+        "Pushover"
+    }
+
+    async fn notify(&self, event: &NotificationEvent) -> Result<(), NotifyError> {
+        self.send(event.summary(), &event.body(), event.urgent)
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pushover(device: Option<&str>) -> Pushover {
+        Pushover::new(&PushoverConfig {
+            token: "app-token".to_string(),
+            user: "user-key".to_string(),
+            device: device.map(String::from),
+        })
+        .unwrap()
+    }
+
+    /// The form as `send` posts it.
+    fn form(pushover: &Pushover, summary: &str, body: &str, urgent: bool) -> String {
+        let request = pushover
+            .client
+            .post(API_URL)
+            .form(&pushover.message(summary, body, urgent))
+            .build()
+            .unwrap();
+        String::from_utf8(request.body().unwrap().as_bytes().unwrap().to_vec()).unwrap()
+    }
+
+    #[test]
+    fn routine_notifications_are_silent() {
+        assert_eq!(
+            form(
+                &pushover(None),
+                "Updated prefix list",
+                "office: 192.0.2.1/32 -> 192.0.2.2/32",
+                false
+            ),
+            "token=app-token&user=user-key&title=Updated+prefix+list\
+             &message=office%3A+192.0.2.1%2F32+-%3E+192.0.2.2%2F32&priority=-1"
+        );
+    }
+
+    #[test]
+    fn urgent_notifications_repeat_until_acknowledged() {
+        assert_eq!(
+            form(
+                &pushover(Some("phone")),
+                "Failed to modify prefix list.",
+                "office: InternalError",
+                true
+            ),
+            "token=app-token&user=user-key&device=phone&title=Failed+to+modify+prefix+list.\
+             &message=office%3A+InternalError&priority=2&retry=60&expire=3600"
+        );
+    }
+
+    #[test]
+    fn rejections_list_the_errors() {
+        let errors: PushoverErrors = serde_json::from_str(
+            r#"{"user": "invalid", "errors": ["user identifier is invalid"], "status": 0}"#,
+        )
+        .unwrap();
+        assert_eq!(errors.errors, ["user identifier is invalid"]);
+        let errors: PushoverErrors = serde_json::from_str("{}").unwrap();
+        assert!(errors.errors.is_empty());
+    }
+}
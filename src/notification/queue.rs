@@ -0,0 +1,221 @@
+use super::{NotificationEvent, Notifier, NotifyError};
+
+use async_trait::async_trait;
+use std::cmp::min;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+use tokio::time::{sleep, Duration};
+use tracing::{debug, warn};
+
+/// How many notifications may wait for delivery.
+const CAPACITY: usize = 50;
+/// How often flushing checks whether everything was delivered.
+const FLUSH_POLL: Duration = Duration::from_millis(100);
+/// How many times delivering a notification is attempted before giving up on it.
+const ATTEMPTS: u32 = 10;
+const MIN_BACKOFF: Duration = Duration::from_secs(5);
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+/// Delivers notifications to a network backend in the background, retrying until it's reachable again.
+///
+/// Only [transient](NotifyError::Transient) failures are retried, anything else won't go away by waiting.
+///
+/// Notifications are delivered in order. When the queue is full, the oldest routine notification is dropped first, so
+/// the latest failures and recoveries are kept.
+///
+/// What's still pending when doorman stops goes to the state file, and is queued again on the next start.
+pub struct Queued {
+    name: &'static str,
+    queue: Arc<Mutex<VecDeque<NotificationEvent>>>,
+    wake: Arc<Notify>,
+    /// The notification being delivered, if any
+    delivering: Arc<Mutex<Option<NotificationEvent>>>,
+}
+
+impl Queued {
+    pub fn new(backend: Box<dyn Notifier>) -> Self {
+        let name = backend.name();
+        let queue = Arc::new(Mutex::new(VecDeque::with_capacity(CAPACITY)));
+        let wake = Arc::new(Notify::new());
+        let delivering = Arc::new(Mutex::new(None));
+        tokio::spawn(deliver(
+            backend,
+            queue.clone(),
+            wake.clone(),
+            delivering.clone(),
+        ));
+        Self {
+            name,
+            queue,
+            wake,
+            delivering,
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for Queued {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    async fn notify(&self, event: &NotificationEvent) -> Result<(), NotifyError> {
+        {
+            let mut queue = self.queue.lock().unwrap();
+            if queue.len() >= CAPACITY {
+                let routine = queue.iter().position(|queued| !queued.urgent);
+                let dropped = match (routine, event.urgent) {
+                    (Some(position), _) => queue.remove(position),
+                    // Everything queued matters more than this one
+                    (None, false) => Some(event.clone()),
+                    (None, true) => queue.pop_front(),
+                };
+                if let Some(dropped) = dropped {
+                    warn!(
+                        "Too many pending {} notifications, dropping: {}",
+                        self.name,
+                        dropped.summary()
+                    );
+                }
+                if routine.is_none() && !event.urgent {
+                    return Ok(());
+                }
+            }
+            queue.push_back(event.clone());
+        }
+        self.wake.notify_one();
+        Ok(())
+    }
+
+    async fn flush(&self) {
+        while self.delivering.lock().unwrap().is_some() || !self.queue.lock().unwrap().is_empty() {
+            sleep(FLUSH_POLL).await;
+        }
+    }
+
+    fn pending(&self) -> Vec<NotificationEvent> {
+        let delivering = self.delivering.lock().unwrap().clone();
+        let queue = self.queue.lock().unwrap();
+        delivering
+            .into_iter()
+            .chain(queue.iter().cloned())
+            .collect()
+    }
+}
+
+async fn deliver(
+    backend: Box<dyn Notifier>,
+    queue: Arc<Mutex<VecDeque<NotificationEvent>>>,
+    wake: Arc<Notify>,
+    delivering: Arc<Mutex<Option<NotificationEvent>>>,
+) {
+    loop {
+        let event = {
+            // Taking the event and marking it as being delivered at once, so flushing can't miss it
+            let mut queue = queue.lock().unwrap();
+            let event = queue.pop_front();
+            delivering.lock().unwrap().clone_from(&event);
+            event
+        };
+        let event = match event {
+            Some(event) => event,
+            None => {
+                wake.notified().await;
+                continue;
+            }
+        };
+
+        let mut backoff = MIN_BACKOFF;
+        for attempt in 1..=ATTEMPTS {
+            match backend.notify(&event).await {
+                Ok(()) => break,
+                Err(err) if !err.is_transient() => {
+                    warn!("Giving up on {} notification: {}", backend.name(), err);
+                    break;
+                }
+                Err(err) if attempt == ATTEMPTS => warn!(
+                    "Giving up on {} notification after {} attempts: {}",
+                    backend.name(),
+                    ATTEMPTS,
+                    err
+                ),
+                Err(err) => {
+                    debug!(
+                        "Failed to send {} notification, retrying in {} seconds: {}",
+                        backend.name(),
+                        backoff.as_secs(),
+                        err
+                    );
+                    sleep(backoff).await;
+                    backoff = min(backoff * 2, MAX_BACKOFF);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    /// A backend that can't be reached, counting the attempts.
+    #[derive(Clone, Default)]
+    struct Down(Arc<AtomicU32>);
+
+    #[async_trait]
+    impl Notifier for Down {
+        fn name(&self) -> &'static str {
+            "webhook"
+        }
+
+        async fn notify(&self, _: &NotificationEvent) -> Result<(), NotifyError> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Err(NotifyError::Transient("connection refused".to_string()))
+        }
+    }
+
+    fn failed(error: &str) -> NotificationEvent {
+        NotificationEvent::ip_lookup_failed(error)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn pending_keeps_what_the_backend_didnt_take() {
+        let down = Down::default();
+        let queued = Queued::new(Box::new(down.clone()));
+        queued.notify(&failed("first")).await.unwrap();
+        queued.notify(&failed("second")).await.unwrap();
+        // Let the first delivery start and fail
+        sleep(Duration::from_secs(1)).await;
+
+        assert_eq!(down.0.load(Ordering::SeqCst), 1);
+        let pending = queued.pending();
+        let errors: Vec<_> = pending.iter().map(|event| event.error.as_deref()).collect();
+        // The one being retried comes first
+        assert_eq!(errors, [Some("first"), Some("second")]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn pending_is_empty_once_given_up() {
+        let down = Down::default();
+        let queued = Queued::new(Box::new(down.clone()));
+        queued.notify(&failed("first")).await.unwrap();
+        queued.flush().await;
+
+        assert_eq!(down.0.load(Ordering::SeqCst), ATTEMPTS);
+        assert!(queued.pending().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn pending_survives_a_round_trip_through_json() {
+        let queued = Queued::new(Box::new(Down::default()));
+        queued.notify(&failed("first")).await.unwrap();
+        sleep(Duration::from_secs(1)).await;
+
+        let pending = queued.pending();
+        let saved = serde_json::to_string(&pending).unwrap();
+        let restored: Vec<NotificationEvent> = serde_json::from_str(&saved).unwrap();
+        assert_eq!(restored, pending);
+    }
+}
@@ -0,0 +1,109 @@
+use super::{NotificationEvent, Notifier, NotifyError};
+
+use async_trait::async_trait;
+use reqwest::header::RETRY_AFTER;
+use reqwest::{Client, StatusCode, Url};
+use serde::Serialize;
+use std::sync::Mutex;
+use std::time::Instant;
+use tokio::time::{sleep, Duration};
+use tracing::debug;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+const ATTEMPTS: u32 = 3;
+/// Upper bound on how long we're willing to wait when Slack asks us to slow down.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(30);
+/// The same failure message isn't sent again before this.
+const FAILURE_REPEAT_INTERVAL: Duration = Duration::from_secs(3600);
+
+#[derive(Serialize)]
+struct SlackMessage<'a> {
+    text: &'a str,
+}
+
+/// Posts notifications to a Slack incoming webhook.
+pub struct Slack {
+    client: Client,
+    url: Url,
+    last_failure: Mutex<Option<(String, Instant)>>,
+}
+
+impl Slack {
+    pub fn new(url: &Url) -> reqwest::Result<Self> {
+        Ok(Self {
+            client: Client::builder().timeout(TIMEOUT).build()?,
+            url: url.clone(),
+            last_failure: Mutex::new(None),
+        })
+    }
+
+    pub async fn send(&self, summary: &str, body: &str, urgent: bool) -> reqwest::Result<()> {
+        let text = match urgent {
+            true => format!(":warning: *{}*\n{}", summary, body),
+            false => format!("{}\n{}", summary, body),
+        };
+
+        if urgent && self.is_repeated_failure(&text) {
+            debug!("Not sending repeated failure to Slack: {}", summary);
+            return Ok(());
+        }
+
+        let message = SlackMessage { text: &text };
+        let mut attempt = 1;
+        loop {
+            let response = self
+                .client
+                .post(self.url.clone())
+                .json(&message)
+                .send()
+                .await?;
+            if response.status() != StatusCode::TOO_MANY_REQUESTS || attempt == ATTEMPTS {
+                response.error_for_status()?;
+                return Ok(());
+            }
+
+            let retry_after = response
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse().ok())
+                .map_or(Duration::from_secs(1), Duration::from_secs)
+                .min(MAX_RETRY_AFTER);
+            debug!(
+                "Rate limited by Slack, retrying in {} seconds.",
+                retry_after.as_secs()
+            );
+            sleep(retry_after).await;
+            attempt += 1;
+        }
+    }
+
+    /// Whether the same failure was already sent recently. If not, it's recorded as the last one.
+    fn is_repeated_failure(&self, text: &str) -> bool {
+        let mut last_failure = self.last_failure.lock().unwrap();
+        match &*last_failure {
+            Some((last_text, sent_at))
+                if last_text == text && sent_at.elapsed() < FAILURE_REPEAT_INTERVAL =>
+            {
+                true
+            }
+            _ => {
+                *last_failure = Some((text.to_string(), Instant::now()));
+                false
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for Slack {
+    fn name(&self) -> &'static str {
+        "Slack"
+    }
+
+    async fn notify(&self, event: &NotificationEvent) -> Result<(), NotifyError> {
+        self.send(event.summary(), &event.body(), event.urgent)
+            .await?;
+        Ok(())
+    }
+}
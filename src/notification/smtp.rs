@@ -0,0 +1,168 @@
+use super::{NotificationEvent, Notifier, NotifyError};
+use crate::config::{SmtpConfig, SmtpTls};
+
+use async_trait::async_trait;
+use color_eyre::Result;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use std::cmp::min;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::time::{sleep, Duration};
+use tracing::{debug, warn};
+
+/// How many mails may wait for the delivery task before new ones get dropped.
+const QUEUE_SIZE: usize = 32;
+/// How many undelivered mails are kept while the server is unreachable. Older ones are dropped.
+const MAX_PENDING: usize = 100;
+const INITIAL_BACKOFF: Duration = Duration::from_secs(5);
+const MAX_BACKOFF: Duration = Duration::from_secs(600);
+
+struct Mail {
+    subject: String,
+    body: String,
+}
+
+/// Sends a plain-text mail per notification.
+///
+/// Delivery happens in a background task. If the server is unreachable, mails are kept and retried with backoff,
+/// then sent as a single digest once it's back, so an outage doesn't end in a flood of mails.
+pub struct Smtp {
+    sender: mpsc::Sender<Mail>,
+}
+
+impl Smtp {
+    pub fn new(config: &SmtpConfig) -> Result<Self> {
+        let mut builder = match config.tls {
+            SmtpTls::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.host),
+            SmtpTls::StartTls => {
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host)?
+            }
+            SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host)?,
+        };
+        if let Some(port) = config.port {
+            builder = builder.port(port);
+        }
+        if let Some((username, password)) = &config.credentials {
+            builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+        let transport = builder.build();
+
+        let (sender, receiver) = mpsc::channel(QUEUE_SIZE);
+        tokio::spawn(deliver(
+            transport,
+            config.from.clone(),
+            config.to.clone(),
+            receiver,
+        ));
+        Ok(Self { sender })
+    }
+
+    /// Queues the mail for delivery. This only fails if the queue is full or the delivery task is gone.
+    pub fn send(&self, summary: &str, body: &str, urgent: bool) -> Result<(), NotifyError> {
+        let subject = match urgent {
+            true => format!("[URGENT] {}", summary),
+            false => summary.to_string(),
+        };
+        let mail = Mail {
+            subject,
+            body: body.to_string(),
+        };
+        self.sender.try_send(mail).map_err(|err| match err {
+            TrySendError::Full(_) => NotifyError::Transient("the mail queue is full".to_string()),
+            TrySendError::Closed(_) => {
+                NotifyError::Unavailable("the mail delivery task stopped".to_string())
+            }
+        })
+    }
+}
+
+async fn deliver(
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+    to: Vec<Mailbox>,
+    mut receiver: mpsc::Receiver<Mail>,
+) {
+    let mut pending: Vec<Mail> = Vec::new();
+    let mut backoff = INITIAL_BACKOFF;
+
+    loop {
+        if pending.is_empty() {
+            match receiver.recv().await {
+                Some(mail) => pending.push(mail),
+                None => return,
+            }
+        }
+        while let Ok(mail) = receiver.try_recv() {
+            pending.push(mail);
+        }
+        if pending.len() > MAX_PENDING {
+            let dropped = pending.len() - MAX_PENDING;
+            warn!("Dropping {} undelivered mail(s).", dropped);
+            pending.drain(..dropped);
+        }
+
+        let mail = coalesce(&pending);
+        match send(&transport, &from, &to, &mail).await {
+            Ok(()) => {
+                debug!("Sent mail: {}", mail.subject);
+                pending.clear();
+                backoff = INITIAL_BACKOFF;
+            }
+            Err(err) => {
+                warn!(
+                    "Failed to send mail, retrying in {} seconds: {}",
+                    backoff.as_secs(),
+                    err
+                );
+                sleep(backoff).await;
+                backoff = min(backoff * 2, MAX_BACKOFF);
+            }
+        }
+    }
+}
+
+/// Merges pending mails into one.
+fn coalesce(pending: &[Mail]) -> Mail {
+    match pending {
+        [mail] => Mail {
+            subject: mail.subject.clone(),
+            body: mail.body.clone(),
+        },
+        mails => Mail {
+            subject: format!("{} doorman notifications", mails.len()),
+            body: mails
+                .iter()
+                .map(|mail| format!("{}\n{}", mail.subject, mail.body))
+                .collect::<Vec<_>>()
+                .join("\n\n"),
+        },
+    }
+}
+
+async fn send(
+    transport: &AsyncSmtpTransport<Tokio1Executor>,
+    from: &Mailbox,
+    to: &[Mailbox],
+    mail: &Mail,
+) -> Result<()> {
+    let mut builder = Message::builder().from(from.clone()).subject(&mail.subject);
+    for recipient in to {
+        builder = builder.to(recipient.clone());
+    }
+    let message = builder.body(mail.body.clone())?;
+    transport.send(message).await?;
+    Ok(())
+}
+
+#[async_trait]
+impl Notifier for Smtp {
+    fn name(&self) -> &'static str {
+        "SMTP"
+    }
+
+    async fn notify(&self, event: &NotificationEvent) -> Result<(), NotifyError> {
+        // Only queues, delivery happens in the background
+        self.send(event.summary(), &event.body(), event.urgent)
+    }
+}
@@ -0,0 +1,164 @@
+use super::{NotificationEvent, Notifier, NotifyError};
+
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::Serialize;
+use tokio::time::{sleep, Duration};
+use tracing::debug;
+
+const API_URL: &str = "https://api.telegram.org";
+const TIMEOUT: Duration = Duration::from_secs(5);
+const ATTEMPTS: u32 = 2;
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Characters which must be escaped in MarkdownV2 text.
+const MARKDOWN_V2_SPECIAL: &str = "\\_*[]()~`>#+-=|{}.!";
+
+#[derive(Debug, Serialize)]
+struct SendMessage<'a> {
+    chat_id: &'a str,
+    text: String,
+    parse_mode: &'static str,
+    disable_notification: bool,
+}
+
+/// Escapes text so Telegram shows it verbatim when parsing MarkdownV2.
+fn escape_markdown_v2(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if MARKDOWN_V2_SPECIAL.contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Sends notifications through the Telegram Bot API.
+pub struct Telegram {
+    client: Client,
+    token: String,
+    chat_id: String,
+}
+
+impl Telegram {
+    pub fn new(token: &str, chat_id: &str) -> reqwest::Result<Self> {
+        Ok(Self {
+            client: Client::builder().timeout(TIMEOUT).build()?,
+            token: token.to_string(),
+            chat_id: chat_id.to_string(),
+        })
+    }
+
+    /// Urgent notifications ring, the others are delivered silently.
+    pub async fn send(&self, summary: &str, body: &str, urgent: bool) -> reqwest::Result<()> {
+        let message = SendMessage {
+            chat_id: &self.chat_id,
+            text: format!(
+                "*{}*\n{}",
+                escape_markdown_v2(summary),
+                escape_markdown_v2(body)
+            ),
+            parse_mode: "MarkdownV2",
+            disable_notification: !urgent,
+        };
+
+        let mut attempt = 1;
+        loop {
+            match self.try_send(&message).await {
+                Err(err) if attempt < ATTEMPTS => {
+                    debug!("Telegram delivery failed, retrying: {}", err);
+                    sleep(RETRY_DELAY).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn try_send(&self, message: &SendMessage<'_>) -> reqwest::Result<()> {
+        let url = format!("{}/bot{}/sendMessage", API_URL, self.token);
+        // The URL contains the token, so it must not end up in the logs
+        self.client
+            .post(url)
+            .json(message)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|err| err.without_url())?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Notifier for Telegram {
+    fn name(&self) -> &'static str {
+        "Telegram"
+    }
+
+    async fn notify(&self, event: &NotificationEvent) -> Result<(), NotifyError> {
+        self.send(event.summary(), &event.body(), event.urgent)
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// How Telegram reads escaped MarkdownV2 text: a backslash makes the next character literal.
+    fn unescape(text: &str) -> String {
+        let mut unescaped = String::with_capacity(text.len());
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => unescaped.extend(chars.next()),
+                c => unescaped.push(c),
+            }
+        }
+        unescaped
+    }
+
+    #[test]
+    fn escapes_the_dots_of_an_ip() {
+        assert_eq!(escape_markdown_v2("192.0.2.1/32"), "192\\.0\\.2\\.1/32");
+    }
+
+    #[test]
+    fn escapes_dashes_and_parentheses() {
+        assert_eq!(
+            escape_markdown_v2("pl-12345678 (office)"),
+            "pl\\-12345678 \\(office\\)"
+        );
+    }
+
+    #[test]
+    fn escapes_formatting_characters() {
+        assert_eq!(
+            escape_markdown_v2("*bold* _it_ `code` ~x~ ||s||"),
+            "\\*bold\\* \\_it\\_ \\`code\\` \\~x\\~ \\|\\|s\\|\\|"
+        );
+        assert_eq!(escape_markdown_v2("a\\b"), "a\\\\b");
+    }
+
+    #[test]
+    fn leaves_plain_text_alone() {
+        assert_eq!(
+            escape_markdown_v2("Updated prefix list IP"),
+            "Updated prefix list IP"
+        );
+    }
+
+    #[test]
+    fn every_reserved_character_round_trips() {
+        let reserved = "_*[]()~`>#+-=|{}.!\\";
+        let escaped = escape_markdown_v2(reserved);
+        assert_eq!(escaped.len(), reserved.len() * 2);
+        assert!(escaped.chars().step_by(2).all(|escape| escape == '\\'));
+        assert_eq!(unescape(&escaped), reserved);
+
+        let text = format!("IP: 2001:db8::1, {} 🚪", reserved);
+        assert_eq!(unescape(&escape_markdown_v2(&text)), text);
+    }
+}
@@ -0,0 +1,264 @@
+use super::{EventKind, NotificationEvent};
+
+use ipnet::IpNet;
+use serde::Deserialize;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Placeholder {
+    Target,
+    OldIp,
+    NewIp,
+    PrefixList,
+    Hostname,
+    Error,
+}
+
+impl Placeholder {
+    const NAMES: [(&'static str, Self); 6] = [
+        ("target", Self::Target),
+        ("old_ip", Self::OldIp),
+        ("new_ip", Self::NewIp),
+        ("prefix_list", Self::PrefixList),
+        ("hostname", Self::Hostname),
+        ("error", Self::Error),
+    ];
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    Placeholder(Placeholder),
+}
+
+/// Notification body with placeholders such as `{new_ip}`.
+///
+/// Braces are written `{{` and `}}`. Unknown placeholders are rejected when parsing, so they're caught at startup.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Template(Vec<Part>);
+
+impl FromStr for Template {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut chars = s.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(format!("unterminated placeholder '{{{}'", name)),
+                        }
+                    }
+                    let placeholder = Placeholder::NAMES
+                        .iter()
+                        .find(|(known, _)| *known == name)
+                        .map(|(_, placeholder)| *placeholder)
+                        .ok_or_else(|| {
+                            let known: Vec<&str> =
+                                Placeholder::NAMES.iter().map(|(name, _)| *name).collect();
+                            format!(
+                                "unknown placeholder '{{{}}}', expected one of {}",
+                                name,
+                                known.join(", ")
+                            )
+                        })?;
+                    if !text.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(Part::Placeholder(placeholder));
+                }
+                '}' => return Err("unmatched '}', write '}}' for a literal brace".to_string()),
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+        Ok(Self(parts))
+    }
+}
+
+impl TryFrom<String> for Template {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl Template {
+    /// Renders the event. Absent IPs are shown as `none`, other absent values as nothing.
+    pub fn render(&self, event: &NotificationEvent, hostname: &str) -> String {
+        let ip =
+            |cidr: Option<IpNet>| cidr.map_or_else(|| "none".to_string(), |cidr| cidr.to_string());
+        self.0
+            .iter()
+            .map(|part| match part {
+                Part::Text(text) => text.clone(),
+                Part::Placeholder(Placeholder::Target) => event.target.clone().unwrap_or_default(),
+                Part::Placeholder(Placeholder::OldIp) => ip(event.old_cidr),
+                Part::Placeholder(Placeholder::NewIp) => ip(event.new_cidr),
+                Part::Placeholder(Placeholder::PrefixList) => event
+                    .prefix_list_id
+                    .as_ref()
+                    .map_or_else(String::new, ToString::to_string),
+                Part::Placeholder(Placeholder::Hostname) => hostname.to_string(),
+                Part::Placeholder(Placeholder::Error) => event.error.clone().unwrap_or_default(),
+            })
+            .collect()
+    }
+}
+
+/// Body templates of a backend, per kind of event.
+///
+/// Events without a template keep the default body.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TemplateSet {
+    updated: Option<Template>,
+    ip_lookup_failed: Option<Template>,
+    update_failed: Option<Template>,
+    recovered: Option<Template>,
+    shutdown: Option<Template>,
+    entry_removed_externally: Option<Template>,
+    cleaned_up: Option<Template>,
+    prefix_list_deleted: Option<Template>,
+    verification_failed: Option<Template>,
+    ownership_conflict: Option<Template>,
+    foreign_entry: Option<Template>,
+    circuit_open: Option<Template>,
+    leadership_changed: Option<Template>,
+}
+
+impl TemplateSet {
+    pub fn get(&self, kind: EventKind) -> Option<&Template> {
+        match kind {
+            EventKind::Updated => self.updated.as_ref(),
+            EventKind::IpLookupFailed => self.ip_lookup_failed.as_ref(),
+            EventKind::UpdateFailed => self.update_failed.as_ref(),
+            EventKind::Recovered => self.recovered.as_ref(),
+            EventKind::Shutdown => self.shutdown.as_ref(),
+            EventKind::EntryRemovedExternally => self.entry_removed_externally.as_ref(),
+            EventKind::CleanedUp => self.cleaned_up.as_ref(),
+            EventKind::PrefixListDeleted => self.prefix_list_deleted.as_ref(),
+            EventKind::VerificationFailed => self.verification_failed.as_ref(),
+            EventKind::OwnershipConflict => self.ownership_conflict.as_ref(),
+            EventKind::ForeignEntry => self.foreign_entry.as_ref(),
+            EventKind::CircuitOpen => self.circuit_open.as_ref(),
+            EventKind::LeadershipChanged => self.leadership_changed.as_ref(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Target;
+    use std::time::Duration;
+
+    fn target() -> Target {
+        Target {
+            name: "office".to_string(),
+            prefix_list_id: "pl-12345678".parse().unwrap(),
+            description: "office".parse().unwrap(),
+            region: None,
+            notify: true,
+        }
+    }
+
+    fn updated(old_cidr: Option<&str>) -> NotificationEvent {
+        NotificationEvent::updated(
+            &target(),
+            old_cidr.map(|cidr| cidr.parse().unwrap()),
+            "192.0.2.2/32".parse().unwrap(),
+            Some(3),
+            Duration::from_secs(1),
+        )
+    }
+
+    fn render(template: &str, event: &NotificationEvent) -> String {
+        template
+            .parse::<Template>()
+            .unwrap()
+            .render(event, "laptop")
+    }
+
+    #[test]
+    fn renders_each_placeholder() {
+        let event = updated(Some("192.0.2.1/32"));
+        assert_eq!(render("{target}", &event), "office");
+        assert_eq!(render("{old_ip}", &event), "192.0.2.1/32");
+        assert_eq!(render("{new_ip}", &event), "192.0.2.2/32");
+        assert_eq!(render("{prefix_list}", &event), "pl-12345678");
+        assert_eq!(render("{hostname}", &event), "laptop");
+        let failed = NotificationEvent::update_failed(&target(), "InternalError");
+        assert_eq!(render("{error}", &failed), "InternalError");
+    }
+
+    #[test]
+    fn renders_text_around_placeholders() {
+        assert_eq!(
+            render(
+                "{hostname}: {target} is now {new_ip}, was {old_ip}.",
+                &updated(Some("192.0.2.1/32"))
+            ),
+            "laptop: office is now 192.0.2.2/32, was 192.0.2.1/32."
+        );
+    }
+
+    #[test]
+    fn renders_a_missing_old_ip_as_none() {
+        assert_eq!(
+            render("{old_ip} -> {new_ip}", &updated(None)),
+            "none -> 192.0.2.2/32"
+        );
+    }
+
+    #[test]
+    fn renders_other_missing_values_as_nothing() {
+        let event = NotificationEvent::ip_lookup_failed("timed out");
+        assert_eq!(render("[{target}|{prefix_list}]", &event), "[|]");
+        assert_eq!(render("[{error}]", &updated(None)), "[]");
+    }
+
+    #[test]
+    fn doubled_braces_are_literal() {
+        assert_eq!(render("{{{new_ip}}}", &updated(None)), "{192.0.2.2/32}");
+    }
+
+    #[test]
+    fn rejects_unknown_placeholders() {
+        let err = "IP is {ip}".parse::<Template>().unwrap_err();
+        assert!(err.starts_with("unknown placeholder '{ip}'"), "{}", err);
+        assert!(err.contains("new_ip"), "{}", err);
+    }
+
+    #[test]
+    fn rejects_unbalanced_braces() {
+        assert!("{new_ip".parse::<Template>().is_err());
+        assert!("new_ip}".parse::<Template>().is_err());
+    }
+
+    #[test]
+    fn sets_only_apply_to_their_kinds() {
+        let set: TemplateSet = toml::from_str("updated = \"{new_ip}\"").unwrap();
+        assert!(set.get(EventKind::Updated).is_some());
+        assert!(set.get(EventKind::UpdateFailed).is_none());
+        assert!(toml::from_str::<TemplateSet>("updates = \"{new_ip}\"").is_err());
+    }
+}
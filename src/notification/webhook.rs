@@ -0,0 +1,196 @@
+use super::{EventKind, NotificationEvent, Notifier, NotifyError};
+use crate::config::WebhookConfig;
+
+use async_trait::async_trait;
+use ipnet::IpNet;
+use reqwest::{Client, Url};
+use serde::{Deserialize, Serialize};
+use tokio::time::{sleep, Duration};
+use tracing::debug;
+
+/// How many times a failed delivery is retried.
+const RETRIES: u32 = 2;
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// JSON body POSTed to the webhook.
+///
+/// Receivers rely on this, so fields must not be renamed or removed.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct WebhookPayload {
+    pub summary: String,
+    pub body: String,
+    pub urgent: bool,
+    pub kind: EventKind,
+    pub target: Option<String>,
+    pub old_cidr: Option<IpNet>,
+    pub new_cidr: Option<IpNet>,
+    pub prefix_list_id: Option<String>,
+    pub version: Option<i64>,
+    pub duration_secs: Option<f64>,
+    pub error: Option<String>,
+    pub suppressed: u32,
+    pub recovered_from: Option<EventKind>,
+}
+
+impl From<&NotificationEvent> for WebhookPayload {
+    fn from(event: &NotificationEvent) -> Self {
+        Self {
+            summary: event.summary().to_string(),
+            body: event.body(),
+            urgent: event.urgent,
+            kind: event.kind,
+            target: event.target.clone(),
+            old_cidr: event.old_cidr,
+            new_cidr: event.new_cidr,
+            prefix_list_id: event.prefix_list_id.as_ref().map(ToString::to_string),
+            version: event.version,
+            duration_secs: event.duration.map(|duration| duration.as_secs_f64()),
+            error: event.error.clone(),
+            suppressed: event.suppressed,
+            recovered_from: event.recovered_from,
+        }
+    }
+}
+
+pub struct Webhook {
+    client: Client,
+    url: Url,
+    header: Option<(String, String)>,
+}
+
+impl Webhook {
+    pub fn new(config: &WebhookConfig) -> reqwest::Result<Self> {
+        let client = Client::builder()
+            .timeout(Duration::from_secs(config.timeout))
+            .build()?;
+        Ok(Self {
+            client,
+            url: config.url.clone(),
+            header: config
+                .header
+                .as_ref()
+                .map(|header| (header.name.clone(), header.value.clone())),
+        })
+    }
+
+    pub async fn send(&self, payload: &WebhookPayload) -> reqwest::Result<()> {
+        let mut attempt = 0;
+        loop {
+            match self.try_send(payload).await {
+                Err(err) if attempt < RETRIES => {
+                    attempt += 1;
+                    debug!(
+                        "Webhook delivery failed, retrying ({}/{}): {}",
+                        attempt, RETRIES, err
+                    );
+                    sleep(RETRY_DELAY).await;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn try_send(&self, payload: &WebhookPayload) -> reqwest::Result<()> {
+        let mut request = self.client.post(self.url.clone()).json(payload);
+        if let Some((name, value)) = &self.header {
+            request = request.header(name, value);
+        }
+        request.send().await?.error_for_status()?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Notifier for Webhook {
+    fn name(&self) -> &'static str {
+        "webhook"
+    }
+
+    async fn notify(&self, event: &NotificationEvent) -> Result<(), NotifyError> {
+        self.send(&WebhookPayload::from(event)).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Target;
+    use serde_json::json;
+    use std::time::Duration;
+
+    fn target() -> Target {
+        Target {
+            name: "office".to_string(),
+            prefix_list_id: "pl-12345678".parse().unwrap(),
+            description: "office".parse().unwrap(),
+            region: None,
+            notify: true,
+        }
+    }
+
+    #[test]
+    fn payload_of_an_update() {
+        let event = NotificationEvent::updated(
+            &target(),
+            Some("192.0.2.1/32".parse().unwrap()),
+            "192.0.2.2/32".parse().unwrap(),
+            Some(3),
+            Duration::from_millis(1500),
+        );
+        assert_eq!(
+            serde_json::to_value(WebhookPayload::from(&event)).unwrap(),
+            json!({
+                "summary": "Updated prefix list",
+                "body": "office: 192.0.2.1/32 -> 192.0.2.2/32 in pl-12345678 version 3, took 1.5s",
+                "urgent": false,
+                "kind": "updated",
+                "target": "office",
+                "old_cidr": "192.0.2.1/32",
+                "new_cidr": "192.0.2.2/32",
+                "prefix_list_id": "pl-12345678",
+                "version": 3,
+                "duration_secs": 1.5,
+                "error": null,
+                "suppressed": 0,
+                "recovered_from": null,
+            })
+        );
+    }
+
+    #[test]
+    fn payload_of_a_failure() {
+        let event = NotificationEvent::update_failed(&target(), "InternalError");
+        assert_eq!(
+            serde_json::to_value(WebhookPayload::from(&event)).unwrap(),
+            json!({
+                "summary": "Failed to modify prefix list.",
+                "body": "office: InternalError",
+                "urgent": true,
+                "kind": "update_failed",
+                "target": "office",
+                "old_cidr": null,
+                "new_cidr": null,
+                "prefix_list_id": "pl-12345678",
+                "version": null,
+                "duration_secs": null,
+                "error": "InternalError",
+                "suppressed": 0,
+                "recovered_from": null,
+            })
+        );
+    }
+
+    #[test]
+    fn payload_round_trips() {
+        let event = NotificationEvent::recovered(
+            &NotificationEvent::update_failed(&target(), "InternalError"),
+            2,
+        );
+        let payload = WebhookPayload::from(&event);
+        let parsed: WebhookPayload =
+            serde_json::from_str(&serde_json::to_string(&payload).unwrap()).unwrap();
+        assert_eq!(parsed, payload);
+        assert_eq!(parsed.recovered_from, Some(EventKind::UpdateFailed));
+    }
+}
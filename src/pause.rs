@@ -0,0 +1,47 @@
+use crate::config::PauseWindow;
+
+use chrono::Utc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::time::Duration;
+
+/// Whether doorman may modify the prefix lists, as decided by the maintenance windows and the control socket.
+///
+/// While paused, the IP is still checked and logged, but AWS is left alone.
+#[derive(Clone)]
+pub struct Pause {
+    windows: Vec<PauseWindow>,
+    /// Set with `ctl pause`, until `ctl resume`
+    manual: Arc<AtomicBool>,
+}
+
+impl Pause {
+    pub fn new(windows: Vec<PauseWindow>) -> Self {
+        Self {
+            windows,
+            manual: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn set_manual(&self, paused: bool) {
+        self.manual.store(paused, Ordering::Relaxed);
+    }
+
+    /// Why modifications are paused right now, if they are, along with when a window ends.
+    ///
+    /// A manual pause has no end, it lasts until resumed.
+    pub fn reason(&self) -> Option<(String, Option<Duration>)> {
+        if self.manual.load(Ordering::Relaxed) {
+            return Some(("paused on the control socket".to_string(), None));
+        }
+        let now = Utc::now();
+        self.windows.iter().find_map(|window| {
+            window.remaining(now).map(|remaining| {
+                (
+                    format!("in the maintenance window {}", window),
+                    remaining.to_std().ok(),
+                )
+            })
+        })
+    }
+}
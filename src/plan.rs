@@ -0,0 +1,420 @@
+use crate::audit::{block_on, client};
+use crate::aws::{AWSClient, EntryFilter, PrefixListApi};
+use crate::cidr::HostCidr;
+use crate::config::{Description, PrefixListId};
+use crate::exit::Exit;
+
+use aws_sdk_ec2::model::PrefixListState;
+use chrono::{DateTime, Utc};
+use color_eyre::{eyre::eyre, eyre::WrapErr, Report, Result};
+use ipnet::IpNet;
+use query_external_ip::Consensus;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+
+/// Bumped whenever the layout changes in a way older versions can't read.
+const SCHEMA_VERSION: u32 = 1;
+
+/// What `apply` does, as decided by `plan`.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Plan {
+    pub version: u32,
+    pub created_at: DateTime<Utc>,
+    /// The external IP when planning
+    pub ip: HostCidr,
+    pub region: Option<String>,
+    pub targets: Vec<TargetPlan>,
+}
+
+/// The changes to a single prefix list.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TargetPlan {
+    pub prefix_list_id: PrefixListId,
+    pub description: Description,
+    /// The version the changes were computed against, applying them is refused once it moved
+    pub prefix_list_version: Option<i64>,
+    pub add: Vec<HostCidr>,
+    pub remove: Vec<IpNet>,
+    /// Why the IP isn't added, if it isn't
+    pub note: Option<String>,
+}
+
+impl TargetPlan {
+    fn is_empty(&self) -> bool {
+        self.add.is_empty() && self.remove.is_empty()
+    }
+
+    fn describe(&self) -> String {
+        let cidrs = |sign: &str, cidrs: Vec<String>| {
+            cidrs
+                .into_iter()
+                .map(|cidr| format!("\n  {} {}", sign, cidr))
+                .collect::<String>()
+        };
+        let mut lines = format!(
+            "{} (version {}):",
+            self.prefix_list_id,
+            self.prefix_list_version
+                .map_or_else(|| "?".to_string(), |version| version.to_string())
+        );
+        lines.push_str(&cidrs(
+            "+",
+            self.add.iter().map(ToString::to_string).collect(),
+        ));
+        lines.push_str(&cidrs(
+            "-",
+            self.remove.iter().map(ToString::to_string).collect(),
+        ));
+        if self.is_empty() {
+            lines.push_str("\n  no changes");
+        }
+        if let Some(note) = &self.note {
+            lines.push_str(&format!("\n  note: {}", note));
+        }
+        lines
+    }
+}
+
+/// Runs the `plan` subcommand, telling how doorman should exit.
+pub fn run_plan(
+    prefix_list_ids: Vec<PrefixListId>,
+    description: Description,
+    region: Option<String>,
+    path: &Path,
+) -> Exit {
+    block_on(async {
+        let plan = plan(prefix_list_ids, description, region).await?;
+        for target in &plan.targets {
+            println!("{}", target.describe());
+        }
+        // Serializing what was just built can't fail
+        fs::write(path, serde_json::to_string_pretty(&plan).unwrap())
+            .wrap_err_with(|| format!("Failed to write the plan to {}", path.display()))?;
+        eprintln!("Wrote the plan for {} to {}.", plan.ip, path.display());
+        Ok(Exit::Success)
+    })
+}
+
+/// Runs the `apply` subcommand, telling how doorman should exit.
+pub fn run_apply(path: &Path) -> Exit {
+    block_on(async {
+        let content = fs::read_to_string(path)
+            .wrap_err_with(|| format!("Failed to read the plan from {}", path.display()))?;
+        let plan: Plan = serde_json::from_str(&content)
+            .wrap_err_with(|| format!("Failed to parse the plan in {}", path.display()))?;
+        if plan.version != SCHEMA_VERSION {
+            return Err(eyre!(
+                "Plan version {} isn't supported, expected {}",
+                plan.version,
+                SCHEMA_VERSION
+            ));
+        }
+        apply(&plan).await?;
+        Ok(Exit::Success)
+    })
+}
+
+/// Computes what it takes for each prefix list to hold the external IP, and nothing else carrying the description.
+async fn plan(
+    prefix_list_ids: Vec<PrefixListId>,
+    description: Description,
+    region: Option<String>,
+) -> Result<Plan> {
+    let ip = Consensus::get()
+        .await
+        .map_err(Report::from)?
+        .v4()
+        .ok_or_else(|| eyre!("Failed to retrieve external IP: none found"))?;
+    let ip = HostCidr::from(IpAddr::V4(ip));
+    let api = client(region.clone()).await;
+    Ok(Plan {
+        version: SCHEMA_VERSION,
+        created_at: Utc::now(),
+        ip,
+        region,
+        targets: plan_targets(api, prefix_list_ids, description, ip).await?,
+    })
+}
+
+/// The changes each prefix list needs to hold `ip`, as [`plan`] computes them.
+async fn plan_targets(
+    api: impl PrefixListApi + 'static,
+    prefix_list_ids: Vec<PrefixListId>,
+    description: Description,
+    ip: HostCidr,
+) -> Result<Vec<TargetPlan>> {
+    let aws = AWSClient::builder()
+        .api(api)
+        .description(description.clone())
+        .build()?;
+    let mut targets = Vec::with_capacity(prefix_list_ids.len());
+    for prefix_list_id in prefix_list_ids {
+        let prefix_list = aws.get_prefix_list(&prefix_list_id).await?;
+        let owned = aws.entries(&prefix_list, EntryFilter::Owned).await?;
+        let foreign = aws.foreign_entry(&prefix_list, &ip.net()).await?;
+        let (add, note) = match (&foreign, owned.iter().any(|entry| entry.cidr == ip.net())) {
+            (_, true) => (vec![], None),
+            (Some(entry), false) => (
+                vec![],
+                Some(format!(
+                    "{} is already held by the entry \"{}\"",
+                    ip, entry.description
+                )),
+            ),
+            (None, false) => (vec![ip], None),
+        };
+        targets.push(TargetPlan {
+            prefix_list_id,
+            description: description.clone(),
+            prefix_list_version: prefix_list.version,
+            add,
+            remove: owned
+                .iter()
+                .map(|entry| entry.cidr)
+                .filter(|cidr| *cidr != ip.net())
+                .collect(),
+            note,
+        });
+    }
+    Ok(targets)
+}
+
+/// Makes exactly the planned changes, stopping at the first prefix list which moved since.
+///
+/// Prefix lists are changed one by one, so those before a failure stay changed.
+async fn apply(plan: &Plan) -> Result<()> {
+    apply_with(client(plan.region.clone()).await, plan).await
+}
+
+async fn apply_with(api: impl PrefixListApi + Clone + 'static, plan: &Plan) -> Result<()> {
+    for target in &plan.targets {
+        if target.is_empty() {
+            eprintln!("{}: nothing to do", target.prefix_list_id);
+            continue;
+        }
+        let aws = AWSClient::builder()
+            .api(api.clone())
+            .description(target.description.clone())
+            .build()?;
+        // Checked right before modifying, as the plan may be old
+        let latest = aws.get_prefix_list(&target.prefix_list_id).await?;
+        if latest.version != target.prefix_list_version {
+            return Err(eyre!(
+                "{} moved from version {} to {} since the plan was made, make a new one",
+                target.prefix_list_id,
+                target
+                    .prefix_list_version
+                    .map_or_else(|| "?".to_string(), |version| version.to_string()),
+                latest
+                    .version
+                    .map_or_else(|| "?".to_string(), |version| version.to_string())
+            ));
+        }
+        match latest.state {
+            Some(
+                PrefixListState::CreateComplete
+                | PrefixListState::ModifyComplete
+                | PrefixListState::RestoreComplete,
+            ) => {}
+            state => {
+                return Err(eyre!(
+                    "{} can't be modified while it's {}",
+                    target.prefix_list_id,
+                    state
+                        .as_ref()
+                        .map_or("in an unknown state", |state| state.as_str())
+                ))
+            }
+        }
+
+        let modified = aws
+            .modify_entries(&latest, target.add.iter().collect(), target.remove.clone())
+            .await?;
+        let modified = aws
+            .wait_for_state(
+                &target.prefix_list_id,
+                PrefixListState::ModifyComplete,
+                modified.version,
+            )
+            .await?;
+        println!("{}", target.describe());
+        println!(
+            "  applied, now at version {}",
+            modified
+                .version
+                .map_or_else(|| "?".to_string(), |version| version.to_string())
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aws::FakeApi;
+
+    const ID: &str = "pl-12345678";
+    const OTHER: &str = "pl-87654321";
+
+    fn ip() -> HostCidr {
+        "192.0.2.1".parse().unwrap()
+    }
+
+    async fn planned(api: &FakeApi, prefix_list_ids: &[&str]) -> Plan {
+        let prefix_list_ids = prefix_list_ids
+            .iter()
+            .map(|id| id.parse().unwrap())
+            .collect();
+        Plan {
+            version: SCHEMA_VERSION,
+            created_at: Utc::now(),
+            ip: ip(),
+            region: None,
+            targets: plan_targets(
+                api.clone(),
+                prefix_list_ids,
+                "office".parse().unwrap(),
+                ip(),
+            )
+            .await
+            .unwrap(),
+        }
+    }
+
+    #[tokio::test]
+    async fn plan_replaces_stale_entries_with_the_ip() {
+        let api = FakeApi::default().with_prefix_list(
+            ID,
+            None,
+            &[("198.51.100.7/32", "office"), ("192.0.2.9/32", "someone")],
+        );
+        let plan = planned(&api, &[ID]).await;
+        let target = &plan.targets[0];
+        assert_eq!(target.prefix_list_version, Some(1));
+        assert_eq!(target.add, [ip()]);
+        assert_eq!(target.remove, ["198.51.100.7/32".parse::<IpNet>().unwrap()]);
+        assert_eq!(target.note, None);
+        assert_eq!(
+            target.describe(),
+            "pl-12345678 (version 1):\n  + 192.0.2.1/32\n  - 198.51.100.7/32"
+        );
+        // Planning changes nothing
+        assert!(api.modifications().is_empty());
+    }
+
+    #[tokio::test]
+    async fn plan_leaves_a_prefix_list_already_holding_the_ip() {
+        let api = FakeApi::default().with_prefix_list(ID, None, &[("192.0.2.1/32", "office")]);
+        let plan = planned(&api, &[ID]).await;
+        assert!(plan.targets[0].is_empty());
+        assert_eq!(
+            plan.targets[0].describe(),
+            "pl-12345678 (version 1):\n  no changes"
+        );
+    }
+
+    #[tokio::test]
+    async fn plan_doesnt_add_an_ip_someone_else_holds() {
+        let api = FakeApi::default().with_prefix_list(ID, None, &[("192.0.2.1/32", "alice")]);
+        let plan = planned(&api, &[ID]).await;
+        assert!(plan.targets[0].is_empty());
+        assert_eq!(
+            plan.targets[0].note.as_deref(),
+            Some("192.0.2.1/32 is already held by the entry \"alice\"")
+        );
+    }
+
+    #[tokio::test]
+    async fn plan_files_survive_a_round_trip() {
+        let api = FakeApi::default().with_prefix_list(ID, None, &[("198.51.100.7/32", "office")]);
+        let plan = planned(&api, &[ID]).await;
+        let json = serde_json::to_string_pretty(&plan).unwrap();
+
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["version"], SCHEMA_VERSION);
+        assert_eq!(value["ip"], "192.0.2.1/32");
+        assert_eq!(value["targets"][0]["prefix_list_version"], 1);
+        assert_eq!(
+            value["targets"][0]["add"],
+            serde_json::json!(["192.0.2.1/32"])
+        );
+        assert_eq!(
+            value["targets"][0]["remove"],
+            serde_json::json!(["198.51.100.7/32"])
+        );
+
+        let read: Plan = serde_json::from_str(&json).unwrap();
+        assert_eq!(read.ip, plan.ip);
+        assert_eq!(read.targets[0].description, "office");
+        assert_eq!(read.targets[0].add, plan.targets[0].add);
+        assert_eq!(read.targets[0].remove, plan.targets[0].remove);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn apply_makes_exactly_the_planned_changes() {
+        let api = FakeApi::default().with_prefix_list(
+            ID,
+            None,
+            &[("198.51.100.7/32", "office"), ("192.0.2.9/32", "someone")],
+        );
+        let plan = planned(&api, &[ID]).await;
+        apply_with(api.clone(), &plan).await.unwrap();
+
+        let modifications = api.modifications();
+        assert_eq!(modifications.len(), 1);
+        assert_eq!(modifications[0].current_version, Some(1));
+        assert_eq!(modifications[0].remove, ["198.51.100.7/32"]);
+        assert_eq!(api.version(ID), 2);
+        assert_eq!(
+            api.entries(ID),
+            [
+                ("192.0.2.9/32".to_string(), "someone".to_string()),
+                ("192.0.2.1/32".to_string(), "office".to_string())
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn apply_refuses_a_prefix_list_which_moved() {
+        let api = FakeApi::default()
+            .with_prefix_list(ID, None, &[])
+            .with_prefix_list(OTHER, None, &[]);
+        let plan = planned(&api, &[ID, OTHER]).await;
+        api.bump(OTHER);
+
+        let err = apply_with(api.clone(), &plan).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "pl-87654321 moved from version 1 to 2 since the plan was made, make a new one"
+        );
+        // The ones before it stay changed
+        assert_eq!(api.entries(ID).len(), 1);
+        assert!(api.entries(OTHER).is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn apply_refuses_a_prefix_list_being_modified() {
+        let api = FakeApi::default().with_prefix_list(ID, None, &[]);
+        let plan = planned(&api, &[ID]).await;
+        api.script(ID, &[(PrefixListState::ModifyInProgress, 1)]);
+
+        let err = apply_with(api.clone(), &plan).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "pl-12345678 can't be modified while it's modify-in-progress"
+        );
+        assert!(api.modifications().is_empty());
+    }
+
+    #[tokio::test]
+    async fn apply_skips_prefix_lists_without_changes() {
+        let api = FakeApi::default().with_prefix_list(ID, None, &[("192.0.2.1/32", "office")]);
+        let plan = planned(&api, &[ID]).await;
+        api.bump(ID);
+        // Nothing to do, so it doesn't matter that it moved
+        apply_with(api.clone(), &plan).await.unwrap();
+        assert!(api.modifications().is_empty());
+    }
+}
@@ -0,0 +1,61 @@
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::sync::Notify;
+use tracing::info;
+
+/// Asks the main loop to check the external IP right away, instead of waiting for the next tick.
+///
+/// On Unix, `SIGUSR1` does the same.
+#[derive(Clone, Default)]
+pub struct Recheck(Arc<Inner>);
+
+#[derive(Default)]
+struct Inner {
+    notify: Notify,
+    /// Whether the next check rewrites the entries, even if they look fine
+    force: AtomicBool,
+}
+
+impl Recheck {
+    pub fn new() -> io::Result<Self> {
+        let recheck = Self::default();
+        #[cfg(unix)]
+        recheck.listen_sigusr1()?;
+        Ok(recheck)
+    }
+
+    pub fn trigger(&self) {
+        self.0.notify.notify_one();
+    }
+
+    /// Makes the next check rewrite the entries, without bringing it forward.
+    pub fn force(&self) {
+        self.0.force.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether the entries should be rewritten, which only holds once per request.
+    pub fn take_force(&self) -> bool {
+        self.0.force.swap(false, Ordering::Relaxed)
+    }
+
+    /// Resolves once a check was requested.
+    pub async fn requested(&self) {
+        self.0.notify.notified().await;
+    }
+
+    #[cfg(unix)]
+    fn listen_sigusr1(&self) -> io::Result<()> {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut sigusr1 = signal(SignalKind::user_defined1())?;
+        let recheck = self.clone();
+        tokio::spawn(async move {
+            while sigusr1.recv().await.is_some() {
+                info!("Received SIGUSR1.");
+                recheck.trigger();
+            }
+        });
+        Ok(())
+    }
+}
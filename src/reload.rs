@@ -0,0 +1,44 @@
+use std::io;
+use std::sync::Arc;
+use tokio::sync::Notify;
+use tracing::info;
+
+/// Asks the main loop to reload the configuration file, once the check in progress is over.
+///
+/// On Unix, `SIGHUP` does the same. Listening for it also keeps its default action from killing doorman without
+/// cleaning up, e.g. when the terminal it was started from goes away.
+#[derive(Clone, Default)]
+pub struct Reload(Arc<Notify>);
+
+impl Reload {
+    pub fn new() -> io::Result<Self> {
+        let reload = Self::default();
+        #[cfg(unix)]
+        reload.listen_sighup()?;
+        Ok(reload)
+    }
+
+    pub fn trigger(&self) {
+        self.0.notify_one();
+    }
+
+    /// Resolves once a reload was requested.
+    pub async fn requested(&self) {
+        self.0.notified().await;
+    }
+
+    #[cfg(unix)]
+    fn listen_sighup(&self) -> io::Result<()> {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut sighup = signal(SignalKind::hangup())?;
+        let reload = self.clone();
+        tokio::spawn(async move {
+            while sighup.recv().await.is_some() {
+                info!("Received SIGHUP.");
+                reload.trigger();
+            }
+        });
+        Ok(())
+    }
+}
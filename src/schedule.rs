@@ -0,0 +1,202 @@
+use std::cmp::min;
+use tokio::time::{interval_at, Duration, Instant, Interval, MissedTickBehavior};
+use tracing::info;
+
+/// How long the IP must stay the same before the adaptive interval doubles.
+pub const STABLE_STRETCH: Duration = Duration::from_secs(60 * 60);
+
+/// How a check went, as far as scheduling the next one goes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// The IP didn't change, and the prefix lists hold it
+    Stable,
+    /// The IP changed
+    Changed,
+    Failed,
+}
+
+/// When the main loop checks the IP.
+///
+/// With a maximum interval, the interval doubles each time the IP stays the same for [`STABLE_STRETCH`], up to that
+/// maximum. Any change or failure brings it back to the base interval right away.
+pub struct Schedule {
+    timer: Interval,
+    base: Duration,
+    /// Only set in adaptive mode
+    max: Option<Duration>,
+    current: Duration,
+    /// Since when the IP has been stable at the current interval
+    stable_since: Instant,
+}
+
+impl Schedule {
+    /// The first tick is immediate.
+    pub fn new(base: Duration, max: Option<Duration>) -> Self {
+        Self::starting_at(Instant::now(), base, max)
+    }
+
+    /// The first tick is at `start`, the next ones every `base` from then on.
+    pub fn starting_at(start: Instant, base: Duration, max: Option<Duration>) -> Self {
+        Self {
+            timer: timer(start, base),
+            base,
+            max,
+            current: base,
+            stable_since: start,
+        }
+    }
+
+    /// Resolves when the next check is due.
+    pub async fn tick(&mut self) {
+        self.timer.tick().await;
+    }
+
+    /// The interval currently in effect.
+    pub fn current(&self) -> Duration {
+        self.current
+    }
+
+    /// Adapts the interval to how the last check went.
+    pub fn checked(&mut self, outcome: Outcome) {
+        let max = match self.max {
+            Some(max) => max,
+            None => return,
+        };
+        let now = Instant::now();
+        let next = match outcome {
+            Outcome::Changed | Outcome::Failed => {
+                self.stable_since = now;
+                self.base
+            }
+            Outcome::Stable if now - self.stable_since >= STABLE_STRETCH => {
+                self.stable_since = now;
+                min(self.current * 2, max)
+            }
+            Outcome::Stable => return,
+        };
+        if next == self.current {
+            return;
+        }
+
+        info!(
+            "Checking the external IP every {} seconds from now on.",
+            next.as_secs()
+        );
+        self.current = next;
+        self.timer = timer(now + next, next);
+    }
+}
+
+fn timer(start: Instant, period: Duration) -> Interval {
+    let mut timer = interval_at(start, period);
+    timer.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    timer
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::advance;
+
+    const BASE: Duration = Duration::from_secs(300);
+    const MAX: Duration = Duration::from_secs(1800);
+
+    /// Ticks, returning how long it took.
+    async fn tick(schedule: &mut Schedule) -> Duration {
+        let start = Instant::now();
+        schedule.tick().await;
+        Instant::now() - start
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn fixed_interval_never_changes() {
+        let mut schedule = Schedule::new(BASE, None);
+        assert_eq!(tick(&mut schedule).await, Duration::ZERO);
+        advance(STABLE_STRETCH * 2).await;
+        schedule.checked(Outcome::Stable);
+        assert_eq!(schedule.current(), BASE);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn doubles_once_stable_for_the_whole_stretch() {
+        let mut schedule = Schedule::new(BASE, Some(MAX));
+        advance(STABLE_STRETCH - Duration::from_secs(1)).await;
+        schedule.checked(Outcome::Stable);
+        assert_eq!(schedule.current(), BASE);
+
+        advance(Duration::from_secs(1)).await;
+        schedule.checked(Outcome::Stable);
+        assert_eq!(schedule.current(), BASE * 2);
+
+        // The next stretch starts from the change
+        advance(STABLE_STRETCH - Duration::from_secs(1)).await;
+        schedule.checked(Outcome::Stable);
+        assert_eq!(schedule.current(), BASE * 2);
+        advance(Duration::from_secs(1)).await;
+        schedule.checked(Outcome::Stable);
+        assert_eq!(schedule.current(), BASE * 4);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stops_at_the_maximum() {
+        let mut schedule = Schedule::new(BASE, Some(MAX));
+        for expected in [BASE * 2, BASE * 4, MAX, MAX] {
+            advance(STABLE_STRETCH).await;
+            schedule.checked(Outcome::Stable);
+            assert_eq!(schedule.current(), expected);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn changes_and_failures_snap_back() {
+        for outcome in [Outcome::Changed, Outcome::Failed] {
+            let mut schedule = Schedule::new(BASE, Some(MAX));
+            advance(STABLE_STRETCH).await;
+            schedule.checked(Outcome::Stable);
+            assert_eq!(schedule.current(), BASE * 2);
+
+            schedule.checked(outcome);
+            assert_eq!(schedule.current(), BASE);
+
+            // Being stable again takes a whole stretch
+            advance(STABLE_STRETCH - Duration::from_secs(1)).await;
+            schedule.checked(Outcome::Stable);
+            assert_eq!(schedule.current(), BASE);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn ticks_follow_the_interval_in_effect() {
+        let mut schedule = Schedule::new(BASE, Some(MAX));
+        assert_eq!(tick(&mut schedule).await, Duration::ZERO);
+        assert_eq!(tick(&mut schedule).await, BASE);
+
+        // Doubling waits the whole new interval from the check
+        advance(STABLE_STRETCH).await;
+        schedule.checked(Outcome::Stable);
+        assert_eq!(tick(&mut schedule).await, BASE * 2);
+        assert_eq!(tick(&mut schedule).await, BASE * 2);
+
+        // Snapping back doesn't wait out the longer interval
+        schedule.checked(Outcome::Changed);
+        assert_eq!(tick(&mut schedule).await, BASE);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn late_checks_skip_the_missed_ticks() {
+        let mut schedule = Schedule::new(BASE, None);
+        assert_eq!(tick(&mut schedule).await, Duration::ZERO);
+        advance(BASE * 3 + Duration::from_secs(10)).await;
+        // The missed tick is due right away, then the schedule goes on from the original start
+        assert_eq!(tick(&mut schedule).await, Duration::ZERO);
+        assert_eq!(tick(&mut schedule).await, BASE - Duration::from_secs(10));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn starting_later() {
+        let start = Instant::now() + Duration::from_secs(30);
+        let mut schedule = Schedule::starting_at(start, BASE, None);
+        assert_eq!(tick(&mut schedule).await, Duration::from_secs(30));
+        assert_eq!(tick(&mut schedule).await, BASE);
+    }
+}
@@ -0,0 +1,114 @@
+use async_trait::async_trait;
+use std::fmt;
+use std::io;
+
+#[cfg(unix)]
+use tokio::signal::unix::{signal, Signal, SignalKind};
+#[cfg(windows)]
+use tokio::signal::windows::{ctrl_break, ctrl_c, ctrl_close, CtrlBreak, CtrlC, CtrlClose};
+
+/// The signal which asked doorman to shut down, or a request on the control socket.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShutdownSignal {
+    Interrupt,
+    ControlSocket,
+    #[cfg(unix)]
+    Terminate,
+    #[cfg(unix)]
+    Quit,
+    #[cfg(windows)]
+    CtrlBreak,
+    #[cfg(windows)]
+    ConsoleClose,
+}
+
+impl fmt::Display for ShutdownSignal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Interrupt => "^C",
+            Self::ControlSocket => "a cleanup request on the control socket",
+            #[cfg(unix)]
+            Self::Terminate => "SIGTERM",
+            #[cfg(unix)]
+            Self::Quit => "SIGQUIT",
+            #[cfg(windows)]
+            Self::CtrlBreak => "Ctrl-Break",
+            #[cfg(windows)]
+            Self::ConsoleClose => "console close",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Where the requests to shut down come from.
+#[async_trait]
+pub trait Shutdown: Send + 'static {
+    /// Waits for the next request to shut down.
+    async fn recv(&mut self) -> ShutdownSignal;
+}
+
+/// Listens for every signal that should trigger a graceful shutdown.
+///
+/// The listeners are registered when this is built and kept for its whole life, so signals received while doorman
+/// is busy aren't lost.
+pub struct ShutdownSignals {
+    #[cfg(unix)]
+    interrupt: Signal,
+    #[cfg(unix)]
+    terminate: Signal,
+    #[cfg(unix)]
+    quit: Signal,
+    #[cfg(windows)]
+    ctrl_c: CtrlC,
+    #[cfg(windows)]
+    ctrl_break: CtrlBreak,
+    #[cfg(windows)]
+    ctrl_close: CtrlClose,
+}
+
+impl ShutdownSignals {
+    #[cfg(unix)]
+    pub fn new() -> io::Result<Self> {
+        Ok(Self {
+            interrupt: signal(SignalKind::interrupt())?,
+            terminate: signal(SignalKind::terminate())?,
+            quit: signal(SignalKind::quit())?,
+        })
+    }
+
+    #[cfg(windows)]
+    pub fn new() -> io::Result<Self> {
+        Ok(Self {
+            ctrl_c: ctrl_c()?,
+            ctrl_break: ctrl_break()?,
+            ctrl_close: ctrl_close()?,
+        })
+    }
+
+    /// Waits for the next shutdown signal.
+    #[cfg(unix)]
+    pub async fn recv(&mut self) -> ShutdownSignal {
+        tokio::select! {
+            _ = self.interrupt.recv() => ShutdownSignal::Interrupt,
+            _ = self.terminate.recv() => ShutdownSignal::Terminate,
+            _ = self.quit.recv() => ShutdownSignal::Quit,
+        }
+    }
+
+    /// Waits for the next shutdown signal.
+    #[cfg(windows)]
+    pub async fn recv(&mut self) -> ShutdownSignal {
+        tokio::select! {
+            _ = self.ctrl_c.recv() => ShutdownSignal::Interrupt,
+            _ = self.ctrl_break.recv() => ShutdownSignal::CtrlBreak,
+            _ = self.ctrl_close.recv() => ShutdownSignal::ConsoleClose,
+        }
+    }
+}
+
+#[async_trait]
+impl Shutdown for ShutdownSignals {
+    async fn recv(&mut self) -> ShutdownSignal {
+        ShutdownSignals::recv(self).await
+    }
+}
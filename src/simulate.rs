@@ -0,0 +1,443 @@
+use crate::aws::{all_entries, AWSError, Page, PrefixListApi};
+use crate::config::PrefixListId;
+
+use async_trait::async_trait;
+use aws_sdk_ec2::model::{
+    AddPrefixListEntry, ManagedPrefixList, PrefixListEntry, PrefixListState, RemovePrefixListEntry,
+};
+use color_eyre::{eyre::eyre, eyre::WrapErr, Report, Result};
+use query_external_ip::Consensus;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tracing::{debug, info, warn};
+
+/// The external IPs found, one per check, `null` when none was.
+const IPS_FILE: &str = "ips.json";
+/// The prefix lists as they were first seen, with their entries.
+const PREFIX_LISTS_FILE: &str = "prefix_lists.json";
+
+/// A prefix list in a fixture.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct FixturePrefixList {
+    prefix_list_id: String,
+    #[serde(default)]
+    name: Option<String>,
+    version: i64,
+    #[serde(default)]
+    max_entries: Option<i32>,
+    #[serde(default)]
+    entries: Vec<FixtureEntry>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct FixtureEntry {
+    cidr: String,
+    #[serde(default)]
+    description: Option<String>,
+}
+
+impl FixturePrefixList {
+    fn from_sdk(prefix_list: &ManagedPrefixList, entries: &[PrefixListEntry]) -> Option<Self> {
+        Some(Self {
+            prefix_list_id: prefix_list.prefix_list_id.clone()?,
+            name: prefix_list.prefix_list_name.clone(),
+            version: prefix_list.version?,
+            max_entries: prefix_list.max_entries,
+            entries: entries
+                .iter()
+                .filter_map(|entry| {
+                    Some(FixtureEntry {
+                        cidr: entry.cidr.clone()?,
+                        description: entry.description.clone(),
+                    })
+                })
+                .collect(),
+        })
+    }
+
+    /// Changes are made right away, so the prefix list is always settled.
+    fn to_sdk(&self) -> ManagedPrefixList {
+        ManagedPrefixList::builder()
+            .prefix_list_id(&self.prefix_list_id)
+            .set_prefix_list_name(self.name.clone())
+            .version(self.version)
+            .set_max_entries(self.max_entries)
+            .address_family("IPv4")
+            .state(PrefixListState::ModifyComplete)
+            .build()
+    }
+}
+
+fn read<T>(dir: &Path, name: &str, parse: impl FnOnce(&str) -> Result<T>) -> Result<T> {
+    let path = dir.join(name);
+    let content = fs::read_to_string(&path)
+        .wrap_err_with(|| format!("Failed to read the fixture {}", path.display()))?;
+    parse(&content).wrap_err_with(|| format!("Failed to parse the fixture {}", path.display()))
+}
+
+/// Stands in for EC2, starting from the prefix lists of a fixture and making the changes in memory.
+///
+/// Clones share the same prefix lists, so targets on the same one see each other's changes.
+#[derive(Clone)]
+pub struct FixtureApi {
+    prefix_lists: Arc<Mutex<HashMap<String, FixturePrefixList>>>,
+}
+
+impl FixtureApi {
+    pub fn load(dir: &Path) -> Result<Self> {
+        read(dir, PREFIX_LISTS_FILE, Self::from_json)
+    }
+
+    /// The prefix lists of a fixture, as found in its `prefix_lists.json`.
+    pub fn from_json(content: &str) -> Result<Self> {
+        let prefix_lists: Vec<FixturePrefixList> = serde_json::from_str(content)?;
+        Ok(Self {
+            prefix_lists: Arc::new(Mutex::new(
+                prefix_lists
+                    .into_iter()
+                    .map(|prefix_list| (prefix_list.prefix_list_id.clone(), prefix_list))
+                    .collect(),
+            )),
+        })
+    }
+}
+
+#[async_trait]
+impl PrefixListApi for FixtureApi {
+    async fn describe_prefix_list(
+        &self,
+        prefix_list_id: &PrefixListId,
+    ) -> Result<Page<ManagedPrefixList>, AWSError> {
+        let prefix_lists = self.prefix_lists.lock().unwrap();
+        Ok((
+            prefix_lists
+                .get(prefix_list_id.as_str())
+                .map(FixturePrefixList::to_sdk)
+                .into_iter()
+                .collect(),
+            None,
+        ))
+    }
+
+    async fn get_entries(
+        &self,
+        prefix_list_id: &PrefixListId,
+        _next_token: Option<String>,
+    ) -> Result<Page<PrefixListEntry>, AWSError> {
+        let prefix_lists = self.prefix_lists.lock().unwrap();
+        let prefix_list =
+            prefix_lists
+                .get(prefix_list_id.as_str())
+                .ok_or_else(|| AWSError::NotFound {
+                    prefix_list_id: prefix_list_id.to_string(),
+                })?;
+        // Everything fits in a single page
+        Ok((
+            prefix_list
+                .entries
+                .iter()
+                .map(|entry| {
+                    PrefixListEntry::builder()
+                        .cidr(&entry.cidr)
+                        .set_description(entry.description.clone())
+                        .build()
+                })
+                .collect(),
+            None,
+        ))
+    }
+
+    async fn modify_entries(
+        &self,
+        prefix_list_id: &PrefixListId,
+        current_version: Option<i64>,
+        add: Vec<AddPrefixListEntry>,
+        remove: Vec<RemovePrefixListEntry>,
+    ) -> Result<Option<ManagedPrefixList>, AWSError> {
+        let mut prefix_lists = self.prefix_lists.lock().unwrap();
+        let prefix_list = prefix_lists
+            .get_mut(prefix_list_id.as_str())
+            .ok_or_else(|| AWSError::NotFound {
+                prefix_list_id: prefix_list_id.to_string(),
+            })?;
+        if current_version.is_some_and(|version| version != prefix_list.version) {
+            return Err(AWSError::Service {
+                operation: "ModifyManagedPrefixList",
+                code: Some("PrefixListVersionMismatch".to_string()),
+                message: format!(
+                    "the prefix list is at version {}, not {}",
+                    prefix_list.version,
+                    current_version.unwrap_or_default()
+                ),
+                request: Default::default(),
+            });
+        }
+
+        let removed: Vec<String> = remove.into_iter().filter_map(|entry| entry.cidr).collect();
+        prefix_list
+            .entries
+            .retain(|entry| !removed.contains(&entry.cidr));
+        let added: Vec<String> = add
+            .into_iter()
+            .filter_map(|entry| {
+                let cidr = entry.cidr?;
+                prefix_list.entries.retain(|existing| existing.cidr != cidr);
+                prefix_list.entries.push(FixtureEntry {
+                    cidr: cidr.clone(),
+                    description: entry.description,
+                });
+                Some(cidr)
+            })
+            .collect();
+        prefix_list.version += 1;
+        info!(
+            "Simulated the modification of {}: added [{}], removed [{}], now at version {}.",
+            prefix_list_id,
+            added.join(", "),
+            removed.join(", "),
+            prefix_list.version
+        );
+        Ok(Some(prefix_list.to_sdk()))
+    }
+
+    async fn create_prefix_list_like(
+        &self,
+        template: &ManagedPrefixList,
+    ) -> Result<Option<ManagedPrefixList>, AWSError> {
+        let mut prefix_lists = self.prefix_lists.lock().unwrap();
+        let prefix_list = FixturePrefixList {
+            prefix_list_id: format!("pl-simulated{:05}", prefix_lists.len()),
+            name: template.prefix_list_name.clone(),
+            version: 1,
+            max_entries: template.max_entries,
+            entries: Vec::new(),
+        };
+        info!("Simulated the creation of {}.", prefix_list.prefix_list_id);
+        let created = prefix_list.to_sdk();
+        prefix_lists.insert(prefix_list.prefix_list_id.clone(), prefix_list);
+        Ok(Some(created))
+    }
+}
+
+/// Writes down what a live run sees, in the format [`FixtureApi`] and [`IpSource::Fixture`] read.
+///
+/// Each prefix list is recorded as it was first seen, a simulation makes the changes itself from there.
+pub struct Recorder {
+    dir: PathBuf,
+    ips: Mutex<Vec<Option<Ipv4Addr>>>,
+    prefix_lists: Mutex<Vec<FixturePrefixList>>,
+}
+
+impl Recorder {
+    pub fn new(dir: &Path) -> Result<Arc<Self>> {
+        fs::create_dir_all(dir).wrap_err_with(|| format!("Failed to create {}", dir.display()))?;
+        Ok(Arc::new(Self {
+            dir: dir.to_path_buf(),
+            ips: Mutex::default(),
+            prefix_lists: Mutex::default(),
+        }))
+    }
+
+    fn ip(&self, ip: Option<Ipv4Addr>) {
+        let mut ips = self.ips.lock().unwrap();
+        ips.push(ip);
+        self.save(IPS_FILE, &*ips);
+    }
+
+    fn is_recorded(&self, prefix_list_id: &PrefixListId) -> bool {
+        self.prefix_lists
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|recorded| recorded.prefix_list_id == prefix_list_id.as_str())
+    }
+
+    fn prefix_list(&self, prefix_list: FixturePrefixList) {
+        let mut prefix_lists = self.prefix_lists.lock().unwrap();
+        debug!("Recorded {}.", prefix_list.prefix_list_id);
+        prefix_lists.push(prefix_list);
+        self.save(PREFIX_LISTS_FILE, &*prefix_lists);
+    }
+
+    /// Failing to record is only logged, the run itself goes on.
+    fn save(&self, name: &str, value: &impl Serialize) {
+        let path = self.dir.join(name);
+        let result = serde_json::to_vec_pretty(value)
+            .map_err(io::Error::from)
+            .and_then(|content| {
+                let temporary = path.with_extension("json.tmp");
+                fs::write(&temporary, content)?;
+                fs::rename(&temporary, &path)
+            });
+        if let Err(err) = result {
+            warn!("Failed to record to {}: {}", path.display(), err);
+        }
+    }
+}
+
+/// Goes through `inner`, recording each prefix list the first time it's described.
+pub struct RecordingApi<A> {
+    inner: A,
+    recorder: Arc<Recorder>,
+}
+
+impl<A> RecordingApi<A> {
+    pub fn new(inner: A, recorder: Arc<Recorder>) -> Self {
+        Self { inner, recorder }
+    }
+}
+
+#[async_trait]
+impl<A: PrefixListApi> PrefixListApi for RecordingApi<A> {
+    async fn describe_prefix_list(
+        &self,
+        prefix_list_id: &PrefixListId,
+    ) -> Result<Page<ManagedPrefixList>, AWSError> {
+        let page = self.inner.describe_prefix_list(prefix_list_id).await?;
+        if let (Some(prefix_list), false) =
+            (page.0.first(), self.recorder.is_recorded(prefix_list_id))
+        {
+            match all_entries(&self.inner, prefix_list_id).await {
+                Ok(entries) => match FixturePrefixList::from_sdk(prefix_list, &entries) {
+                    Some(recorded) => self.recorder.prefix_list(recorded),
+                    None => warn!(
+                        "Not recording {}, AWS left out its ID or version.",
+                        prefix_list_id
+                    ),
+                },
+                Err(err) => warn!(
+                    "Failed to record the entries of {}: {}",
+                    prefix_list_id, err
+                ),
+            }
+        }
+        Ok(page)
+    }
+
+    async fn get_entries(
+        &self,
+        prefix_list_id: &PrefixListId,
+        next_token: Option<String>,
+    ) -> Result<Page<PrefixListEntry>, AWSError> {
+        self.inner.get_entries(prefix_list_id, next_token).await
+    }
+
+    async fn modify_entries(
+        &self,
+        prefix_list_id: &PrefixListId,
+        current_version: Option<i64>,
+        add: Vec<AddPrefixListEntry>,
+        remove: Vec<RemovePrefixListEntry>,
+    ) -> Result<Option<ManagedPrefixList>, AWSError> {
+        self.inner
+            .modify_entries(prefix_list_id, current_version, add, remove)
+            .await
+    }
+
+    async fn create_prefix_list_like(
+        &self,
+        template: &ManagedPrefixList,
+    ) -> Result<Option<ManagedPrefixList>, AWSError> {
+        self.inner.create_prefix_list_like(template).await
+    }
+}
+
+/// Where the external IP comes from.
+///
+/// The lookups of `query_external_ip` can't be sent across threads, which the current thread runtime doesn't need.
+#[async_trait(?Send)]
+pub trait IpSource {
+    /// The external addresses found by a check.
+    async fn addresses(&mut self) -> Result<Addresses>;
+}
+
+/// The external addresses found by a check.
+pub struct Addresses {
+    pub v4: Option<Ipv4Addr>,
+    /// Only told about, doorman manages IPv4 entries alone
+    pub v6: Option<Ipv6Addr>,
+}
+
+/// The public services queried by `query_external_ip`.
+pub struct LiveIps;
+
+#[async_trait(?Send)]
+impl IpSource for LiveIps {
+    async fn addresses(&mut self) -> Result<Addresses> {
+        live().await
+    }
+}
+
+/// The IP given on the command line, whatever the external one is.
+pub struct FixedIp(pub Ipv4Addr);
+
+#[async_trait(?Send)]
+impl IpSource for FixedIp {
+    async fn addresses(&mut self) -> Result<Addresses> {
+        Ok(Addresses {
+            v4: Some(self.0),
+            v6: None,
+        })
+    }
+}
+
+/// One IP of a fixture per check, the last one repeating once they're used up.
+pub struct FixtureIps {
+    ips: VecDeque<Option<Ipv4Addr>>,
+    last: Option<Ipv4Addr>,
+}
+
+impl FixtureIps {
+    pub fn load(dir: &Path) -> Result<Self> {
+        read(dir, IPS_FILE, Self::from_json)
+    }
+
+    /// The IPs of a fixture, as found in its `ips.json`.
+    pub fn from_json(content: &str) -> Result<Self> {
+        let ips: VecDeque<Option<Ipv4Addr>> = serde_json::from_str(content)?;
+        if ips.is_empty() {
+            return Err(eyre!("There are no IPs"));
+        }
+        Ok(Self { ips, last: None })
+    }
+}
+
+/// Fixtures only hold v4 addresses.
+#[async_trait(?Send)]
+impl IpSource for FixtureIps {
+    async fn addresses(&mut self) -> Result<Addresses> {
+        match self.ips.pop_front() {
+            Some(ip) => self.last = ip,
+            None => debug!("No IPs left in the fixture, repeating the last one."),
+        }
+        Ok(Addresses {
+            v4: self.last,
+            v6: None,
+        })
+    }
+}
+
+/// Live, recording each IP found.
+pub struct RecordingIps(pub Arc<Recorder>);
+
+#[async_trait(?Send)]
+impl IpSource for RecordingIps {
+    async fn addresses(&mut self) -> Result<Addresses> {
+        let found = live().await;
+        self.0.ip(found.as_ref().ok().and_then(|found| found.v4));
+        found
+    }
+}
+
+async fn live() -> Result<Addresses> {
+    let consensus = Consensus::get().await.map_err(Report::from)?;
+    Ok(Addresses {
+        v4: consensus.v4(),
+        v6: consensus.v6(),
+    })
+}
@@ -0,0 +1,482 @@
+use crate::cidr::HostCidr;
+use crate::notification::NotificationEvent;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use tracing::{debug, warn};
+
+/// Bumped whenever the layout changes in a way older versions can't read.
+const SCHEMA_VERSION: u32 = 1;
+/// How many versions are remembered per prefix list.
+pub const MAX_HISTORY: usize = 50;
+
+/// What doorman needs to remember across restarts.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct State {
+    /// The IP last put in the prefix lists, so it can be replaced even if doorman didn't clean up before stopping
+    pub cidr: Option<HostCidr>,
+    /// The versions seen per prefix list ID, oldest first. Older files don't have it.
+    #[serde(default)]
+    pub history: BTreeMap<String, Vec<VersionRecord>>,
+    /// What doorman logged about its setup when it last started
+    #[serde(default)]
+    pub banner: Option<Banner>,
+    /// Notifications the network backends hadn't delivered yet, per backend name. Older files don't have it.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub notifications: BTreeMap<String, Vec<NotificationEvent>>,
+    /// Whether the history changed since the state was last saved
+    #[serde(skip)]
+    changed: bool,
+}
+
+/// A prefix list version doorman saw, telling who changed it, to answer who changed the allow-list.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct VersionRecord {
+    pub version: i64,
+    pub seen_at: DateTime<Utc>,
+    /// Whether doorman's own change led to this version
+    pub by_doorman: bool,
+}
+
+/// The startup banner, telling which credentials, prefix lists and notification backends were used.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Banner {
+    pub started_at: DateTime<Utc>,
+    pub lines: Vec<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct StateFile<S> {
+    version: u32,
+    #[serde(flatten)]
+    state: S,
+}
+
+/// Where the state is kept unless given on the command line, following the XDG base directory spec.
+pub fn default_path() -> Option<PathBuf> {
+    let state_home = env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local/state")))?;
+    Some(state_home.join("aws_doorman").join("state.json"))
+}
+
+impl State {
+    /// Loads the state, starting afresh if there is none.
+    ///
+    /// A file which can't be read, e.g. because it's corrupt or was written by a newer doorman, is moved aside so it
+    /// can be looked at, but doesn't stop doorman.
+    pub fn load(path: &Path) -> Self {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                debug!("No state in {}, starting afresh.", path.display());
+                return Self::default();
+            }
+            Err(err) => {
+                warn!("Failed to read the state from {}: {}", path.display(), err);
+                return Self::default();
+            }
+        };
+
+        let problem = match parse(&content) {
+            Ok(state) => return state,
+            Err(problem) => problem,
+        };
+        let backup = path.with_extension("json.bak");
+        match fs::rename(path, &backup) {
+            Ok(()) => warn!(
+                "Ignoring the state in {} ({}), moved it to {}.",
+                path.display(),
+                problem,
+                backup.display()
+            ),
+            Err(err) => warn!(
+                "Ignoring the state in {} ({}), and failed to move it aside: {}",
+                path.display(),
+                problem,
+                err
+            ),
+        }
+        Self::default()
+    }
+
+    /// Reads the state without ever touching the file, for looking at it while doorman runs.
+    pub fn read(path: &Path) -> Result<Self, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|err| format!("failed to read {}: {}", path.display(), err))?;
+        parse(&content).map_err(|problem| format!("ignoring {}: {}", path.display(), problem))
+    }
+
+    /// Records that a prefix list is at `version`, returning the version it was last seen at if that's different.
+    ///
+    /// Only changes are recorded, and only the last [`MAX_HISTORY`] of them are kept.
+    pub fn saw_version(
+        &mut self,
+        prefix_list_id: &str,
+        version: i64,
+        by_doorman: bool,
+    ) -> Option<i64> {
+        let history = self.history.entry(prefix_list_id.to_string()).or_default();
+        let last = history.last().map(|record| record.version);
+        if last == Some(version) {
+            return None;
+        }
+        history.push(VersionRecord {
+            version,
+            seen_at: Utc::now(),
+            by_doorman,
+        });
+        if history.len() > MAX_HISTORY {
+            history.drain(..history.len() - MAX_HISTORY);
+        }
+        self.changed = true;
+        last
+    }
+
+    /// Records the banner doorman just logged.
+    pub fn started(&mut self, lines: Vec<String>) {
+        self.banner = Some(Banner {
+            started_at: Utc::now(),
+            lines,
+        });
+        self.changed = true;
+    }
+
+    /// Records the notifications still waiting for delivery, as returned by `Dispatcher::pending`.
+    pub fn queued(&mut self, pending: BTreeMap<String, Vec<NotificationEvent>>) {
+        if pending != self.notifications {
+            self.notifications = pending;
+            self.changed = true;
+        }
+    }
+
+    /// Whether there is something new to save.
+    pub fn changed(&self) -> bool {
+        self.changed
+    }
+
+    /// Describes the state for the `status` subcommand.
+    pub fn describe(&self) -> String {
+        let mut lines = Vec::new();
+        if let Some(banner) = &self.banner {
+            lines.push(format!("Started at {}:", banner.started_at.to_rfc3339()));
+            lines.extend(banner.lines.iter().map(|line| format!("  {}", line)));
+        }
+        lines.push(format!(
+            "Last IP: {}",
+            self.cidr
+                .map_or_else(|| "none".to_string(), |cidr| cidr.to_string())
+        ));
+        for (prefix_list_id, history) in &self.history {
+            lines.push(format!("Versions of {}:", prefix_list_id));
+            for record in history {
+                lines.push(format!(
+                    "  v{} seen at {}, {}",
+                    record.version,
+                    record.seen_at.to_rfc3339(),
+                    match record.by_doorman {
+                        true => "changed by doorman",
+                        false => "changed by someone else",
+                    }
+                ));
+            }
+        }
+        let pending: Vec<String> = self
+            .notifications
+            .iter()
+            .map(|(backend, events)| format!("{}: {}", backend, events.len()))
+            .collect();
+        lines.push(match pending.is_empty() {
+            true => "Pending notifications: none".to_string(),
+            false => format!(
+                "Pending notifications: {} ({})",
+                self.notifications.values().map(Vec::len).sum::<usize>(),
+                pending.join(", ")
+            ),
+        });
+        lines.join("\n")
+    }
+
+    /// Writes the state to a temporary file first, so a crash never leaves a partial one behind.
+    ///
+    /// Failing to save is only logged, doorman works without it.
+    pub fn save(&mut self, path: &Path) {
+        match self.try_save(path) {
+            Ok(()) => self.changed = false,
+            Err(err) => warn!("Failed to save the state to {}: {}", path.display(), err),
+        }
+    }
+
+    fn try_save(&self, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = StateFile {
+            version: SCHEMA_VERSION,
+            state: self,
+        };
+        let temporary = path.with_extension("json.tmp");
+        fs::write(&temporary, serde_json::to_vec_pretty(&file)?)?;
+        fs::rename(&temporary, path)
+    }
+}
+
+fn parse(content: &str) -> Result<State, String> {
+    match serde_json::from_str::<StateFile<State>>(content) {
+        Ok(file) if file.version == SCHEMA_VERSION => Ok(file.state),
+        Ok(file) => Err(format!(
+            "version {} isn't supported, expected {}",
+            file.version, SCHEMA_VERSION
+        )),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn saved(state: &mut State) -> (TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        state.save(&path);
+        (dir, path)
+    }
+
+    #[test]
+    fn state_survives_a_round_trip() {
+        let mut state = State {
+            cidr: Some(HostCidr::from(
+                "192.0.2.1".parse::<std::net::IpAddr>().unwrap(),
+            )),
+            ..State::default()
+        };
+        state.saw_version("pl-12345678", 3, false);
+        state.saw_version("pl-12345678", 4, true);
+        state.started(vec!["Prefix list: pl-12345678".to_string()]);
+        state.queued(BTreeMap::from([(
+            "webhook".to_string(),
+            vec![NotificationEvent::ip_lookup_failed("timed out")],
+        )]));
+        let (_dir, path) = saved(&mut state);
+        assert!(!state.changed());
+        assert!(!path.with_extension("json.tmp").exists());
+
+        let loaded = State::load(&path);
+        assert_eq!(loaded.cidr, state.cidr);
+        let versions: Vec<(i64, bool)> = loaded.history["pl-12345678"]
+            .iter()
+            .map(|record| (record.version, record.by_doorman))
+            .collect();
+        assert_eq!(versions, [(3, false), (4, true)]);
+        assert_eq!(
+            loaded.banner.as_ref().map(|banner| banner.lines.clone()),
+            Some(vec!["Prefix list: pl-12345678".to_string()])
+        );
+        assert_eq!(loaded.notifications, state.notifications);
+        assert!(!loaded.changed());
+    }
+
+    #[test]
+    fn missing_state_starts_afresh() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = State::load(&dir.path().join("state.json"));
+        assert!(state.cidr.is_none());
+        assert!(state.history.is_empty());
+    }
+
+    #[test]
+    fn unsupported_version_is_moved_aside() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        let content = r#"{"version": 2, "cidr": "192.0.2.1/32"}"#;
+        fs::write(&path, content).unwrap();
+
+        assert_eq!(
+            State::read(&path).unwrap_err(),
+            format!(
+                "ignoring {}: version 2 isn't supported, expected 1",
+                path.display()
+            )
+        );
+        let state = State::load(&path);
+        assert!(state.cidr.is_none());
+        assert!(!path.exists());
+        assert_eq!(
+            fs::read_to_string(path.with_extension("json.bak")).unwrap(),
+            content
+        );
+    }
+
+    #[test]
+    fn corrupt_state_is_moved_aside() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        fs::write(&path, "{\"version\": 1, \"cidr\":").unwrap();
+
+        let state = State::load(&path);
+        assert!(state.cidr.is_none());
+        assert!(!path.exists());
+        assert_eq!(
+            fs::read_to_string(path.with_extension("json.bak")).unwrap(),
+            "{\"version\": 1, \"cidr\":"
+        );
+
+        // Saving starts a new file, leaving the backup alone
+        let mut state = state;
+        state.saw_version("pl-12345678", 1, false);
+        state.save(&path);
+        assert!(State::read(&path).is_ok());
+        assert!(path.with_extension("json.bak").exists());
+    }
+
+    #[test]
+    fn older_state_without_history_or_banner_loads() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        fs::write(&path, r#"{"version": 1, "cidr": "192.0.2.1/32"}"#).unwrap();
+
+        let state = State::load(&path);
+        assert_eq!(
+            state.cidr.map(|cidr| cidr.to_string()),
+            Some("192.0.2.1/32".to_string())
+        );
+        assert!(state.history.is_empty());
+        assert!(state.banner.is_none());
+        assert!(state.notifications.is_empty());
+        assert!(path.exists());
+    }
+
+    #[test]
+    fn describe_counts_the_pending_notifications() {
+        let mut state = State::default();
+        assert!(state.describe().ends_with("Pending notifications: none"));
+
+        let event = NotificationEvent::ip_lookup_failed("timed out");
+        state.queued(BTreeMap::from([
+            ("slack".to_string(), vec![event.clone()]),
+            ("webhook".to_string(), vec![event.clone(), event]),
+        ]));
+        assert!(state.changed());
+        assert!(
+            state
+                .describe()
+                .ends_with("Pending notifications: 3 (slack: 1, webhook: 2)"),
+            "{}",
+            state.describe()
+        );
+    }
+
+    #[test]
+    fn pending_notifications_survive_a_round_trip() {
+        let mut state = State::default();
+        state.queued(BTreeMap::from([(
+            "webhook".to_string(),
+            vec![NotificationEvent::ip_lookup_failed("timed out")],
+        )]));
+        let file = StateFile {
+            version: SCHEMA_VERSION,
+            state: &state,
+        };
+        let loaded = parse(&serde_json::to_string(&file).unwrap()).unwrap();
+        assert_eq!(loaded.notifications, state.notifications);
+    }
+
+    #[test]
+    fn only_version_changes_are_recorded() {
+        let mut state = State::default();
+        assert_eq!(state.saw_version("pl-12345678", 14, false), None);
+        assert!(state.changed());
+        assert_eq!(state.saw_version("pl-12345678", 14, false), None);
+        assert_eq!(state.saw_version("pl-12345678", 17, false), Some(14));
+        assert_eq!(state.saw_version("pl-12345678", 18, true), Some(17));
+        // Each prefix list has its own
+        assert_eq!(state.saw_version("pl-87654321", 3, true), None);
+
+        let versions: Vec<(i64, bool)> = state.history["pl-12345678"]
+            .iter()
+            .map(|record| (record.version, record.by_doorman))
+            .collect();
+        assert_eq!(versions, [(14, false), (17, false), (18, true)]);
+        assert_eq!(state.history["pl-87654321"].len(), 1);
+    }
+
+    #[test]
+    fn history_is_bounded() {
+        let mut state = State::default();
+        for version in 1..=MAX_HISTORY as i64 + 10 {
+            state.saw_version("pl-12345678", version, true);
+        }
+        let history = &state.history["pl-12345678"];
+        assert_eq!(history.len(), MAX_HISTORY);
+        assert_eq!(history[0].version, 11);
+        assert_eq!(history[MAX_HISTORY - 1].version, MAX_HISTORY as i64 + 10);
+    }
+
+    #[test]
+    fn describe_lists_the_history() {
+        let mut state = State::default();
+        state.saw_version("pl-12345678", 14, false);
+        state.saw_version("pl-12345678", 15, true);
+        let records = &state.history["pl-12345678"];
+        assert_eq!(
+            state.describe(),
+            format!(
+                "Last IP: none\nVersions of pl-12345678:\n  v14 seen at {}, changed by someone else\n  v15 seen at {}, \
+                 changed by doorman\nPending notifications: none",
+                records[0].seen_at.to_rfc3339(),
+                records[1].seen_at.to_rfc3339()
+            )
+        );
+    }
+
+    #[test]
+    fn reading_leaves_the_file_alone() {
+        let mut state = State::default();
+        state.saw_version("pl-12345678", 3, false);
+        let (_dir, path) = saved(&mut state);
+        assert_eq!(State::read(&path).unwrap().history["pl-12345678"].len(), 1);
+
+        // Even when it can't be used, since doorman may be running
+        fs::write(&path, "{").unwrap();
+        let err = State::read(&path).unwrap_err();
+        assert!(
+            err.starts_with(&format!("ignoring {}: ", path.display())),
+            "{}",
+            err
+        );
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{");
+        assert_eq!(fs::read_dir(path.parent().unwrap()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn status_json_survives_a_round_trip() {
+        // As the `status` subcommand prints it
+        let mut state = State {
+            cidr: "192.0.2.1".parse().ok(),
+            ..State::default()
+        };
+        state.saw_version("pl-12345678", 3, true);
+        state.started(vec!["Prefix list: pl-12345678".to_string()]);
+        let json = serde_json::to_string_pretty(&state).unwrap();
+
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["cidr"], "192.0.2.1/32");
+        assert_eq!(value["history"]["pl-12345678"][0]["version"], 3);
+        assert_eq!(value["history"]["pl-12345678"][0]["by_doorman"], true);
+        assert!(value.get("notifications").is_none());
+        assert!(value.get("changed").is_none());
+
+        let read: State = serde_json::from_str(&json).unwrap();
+        assert_eq!(read.cidr, state.cidr);
+        assert_eq!(read.history["pl-12345678"].len(), 1);
+        assert_eq!(read.banner.unwrap().lines, ["Prefix list: pl-12345678"]);
+    }
+}
@@ -0,0 +1,429 @@
+use crate::config::Target;
+use crate::leader::Role;
+
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::time::{Duration, Instant};
+
+/// Counters for a single target.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct TargetStats {
+    pub prefix_list_id: String,
+    pub ip_changes: u64,
+    pub update_failures: u64,
+    /// Seconds since the epoch
+    pub last_success: Option<f64>,
+    pub in_sync: bool,
+    /// The prefix list version last seen
+    pub version: Option<i64>,
+}
+
+/// How many durations a [`Latency`] keeps, dropping the oldest ones.
+pub const LATENCY_WINDOW: usize = 100;
+
+/// The last durations of something, to tell how long it usually takes.
+#[derive(Clone, Default)]
+pub struct Latency(VecDeque<Duration>);
+
+impl Latency {
+    fn record(&mut self, took: Duration) {
+        if self.0.len() == LATENCY_WINDOW {
+            self.0.pop_front();
+        }
+        self.0.push_back(took);
+    }
+
+    /// The duration within which `quantile` of the samples are, e.g. `0.95` for the 95th percentile, if there are any.
+    pub fn quantile(&self, quantile: f64) -> Option<Duration> {
+        if self.0.is_empty() {
+            return None;
+        }
+        let mut sorted: Vec<Duration> = self.0.iter().copied().collect();
+        sorted.sort_unstable();
+        // The nearest rank, so this is always one of the samples
+        let rank = (quantile * sorted.len() as f64).ceil() as usize;
+        Some(sorted[rank.clamp(1, sorted.len()) - 1])
+    }
+}
+
+#[derive(Clone)]
+pub struct Snapshot {
+    pub started: Instant,
+    pub ip_detection_failures: u64,
+    /// Per target name, sorted so the output is stable
+    pub targets: BTreeMap<String, TargetStats>,
+    pub ip: Option<IpNet>,
+    pub last_verified: Option<Instant>,
+    /// Whether the last check left the prefix lists alone, see [`crate::pause::Pause`]
+    pub paused: bool,
+    /// Whether the prefix lists are only tried once per probe interval, see [`crate::breaker::Breaker`]
+    pub circuit_open: bool,
+    /// This doorman's role in a redundant pair, if it's part of one, see [`crate::leader::Election`]
+    pub role: Option<Role>,
+    /// The interval between checks currently in effect
+    pub interval: Duration,
+    /// Times the entries of a prefix list were reused rather than listed again
+    pub entry_cache_hits: u64,
+    pub entry_cache_misses: u64,
+    /// Per AWS operation, as named in the spans
+    pub operations: BTreeMap<&'static str, Latency>,
+    /// From looking up the IP to a prefix list holding the new one
+    pub updates: Latency,
+}
+
+impl Snapshot {
+    pub fn ip_changes(&self) -> u64 {
+        self.targets.values().map(|target| target.ip_changes).sum()
+    }
+
+    pub fn update_failures(&self) -> u64 {
+        self.targets
+            .values()
+            .map(|target| target.update_failures)
+            .sum()
+    }
+
+    /// One line telling how doorman has been doing, for skimming the logs.
+    pub fn summary(&self) -> String {
+        let versions: Vec<String> = self
+            .targets
+            .iter()
+            .map(|(name, target)| {
+                format!(
+                    "{} v{}",
+                    name,
+                    target
+                        .version
+                        .map_or_else(|| "?".to_string(), |version| version.to_string())
+                )
+            })
+            .collect();
+        let latencies = match (self.updates.quantile(0.5), self.updates.quantile(0.95)) {
+            (Some(p50), Some(p95)) => format!(
+                ", updates taking {} (p50) / {} (p95)",
+                seconds(p50),
+                seconds(p95)
+            ),
+            _ => String::new(),
+        };
+        let slowest = self
+            .operations
+            .iter()
+            .filter_map(|(name, latency)| Some((name, latency.quantile(0.95)?)))
+            .max_by_key(|(_, p95)| *p95)
+            .map_or_else(String::new, |(name, p95)| {
+                format!(", slowest AWS operation {} at {} (p95)", name, seconds(p95))
+            });
+        format!(
+            "Up {}, {} IP change(s), {} update failure(s), IP {}, {}, {}, checking every {}{}{}{}{}{}",
+            human(self.started.elapsed()),
+            self.ip_changes(),
+            self.update_failures(),
+            self.ip
+                .map_or_else(|| "unknown".to_string(), |ip| ip.addr().to_string()),
+            versions.join(" "),
+            self.last_verified.map_or_else(
+                || "not verified".to_string(),
+                |at| format!("verified {} ago", human(at.elapsed()))
+            ),
+            human(self.interval),
+            match self.paused {
+                true => ", paused",
+                false => "",
+            },
+            match self.circuit_open {
+                true => ", circuit open",
+                false => "",
+            },
+            self.role
+                .map_or_else(String::new, |role| format!(", {}", role.as_str())),
+            latencies,
+            slowest
+        )
+    }
+}
+
+/// What doorman did so far, shared by the main loop, the metrics, the status file and the periodic summary.
+#[derive(Clone)]
+pub struct Stats(Arc<Mutex<Snapshot>>);
+
+impl Stats {
+    /// The targets are listed from the start, so their counters exist before anything happens.
+    pub fn new<'a>(targets: impl IntoIterator<Item = &'a Target>) -> Self {
+        let targets = targets
+            .into_iter()
+            .map(|target| {
+                (
+                    target.name.clone(),
+                    TargetStats {
+                        prefix_list_id: target.prefix_list_id.to_string(),
+                        ..TargetStats::default()
+                    },
+                )
+            })
+            .collect();
+        Self(Arc::new(Mutex::new(Snapshot {
+            started: Instant::now(),
+            ip_detection_failures: 0,
+            targets,
+            ip: None,
+            last_verified: None,
+            paused: false,
+            circuit_open: false,
+            role: None,
+            interval: Duration::ZERO,
+            entry_cache_hits: 0,
+            entry_cache_misses: 0,
+            operations: BTreeMap::new(),
+            updates: Latency::default(),
+        })))
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        self.0.lock().unwrap().clone()
+    }
+
+    pub fn ip_detection_failed(&self) {
+        self.0.lock().unwrap().ip_detection_failures += 1;
+    }
+
+    /// The external IP was retrieved.
+    pub fn ip(&self, ip: IpNet) {
+        self.0.lock().unwrap().ip = Some(ip);
+    }
+
+    pub fn interval(&self, interval: Duration) {
+        self.0.lock().unwrap().interval = interval;
+    }
+
+    /// Whether the check left the prefix lists alone.
+    pub fn paused(&self, paused: bool) {
+        self.0.lock().unwrap().paused = paused;
+    }
+
+    pub fn circuit_open(&self, open: bool) {
+        self.0.lock().unwrap().circuit_open = open;
+    }
+
+    pub fn role(&self, role: Role) {
+        self.0.lock().unwrap().role = Some(role);
+    }
+
+    /// The entries of a prefix list were needed, and could be reused if `hit`.
+    pub fn entry_cache(&self, hit: bool) {
+        let mut snapshot = self.0.lock().unwrap();
+        match hit {
+            true => snapshot.entry_cache_hits += 1,
+            false => snapshot.entry_cache_misses += 1,
+        }
+    }
+
+    /// An AWS operation finished, whether it succeeded or not.
+    pub fn operation(&self, name: &'static str, took: Duration) {
+        self.0
+            .lock()
+            .unwrap()
+            .operations
+            .entry(name)
+            .or_default()
+            .record(took);
+    }
+
+    /// A prefix list was updated to a new IP, `took` after starting to look it up.
+    pub fn update_took(&self, took: Duration) {
+        self.0.lock().unwrap().updates.record(took);
+    }
+
+    /// Every prefix list was found holding the IP.
+    pub fn verified(&self) {
+        self.0.lock().unwrap().last_verified = Some(Instant::now());
+    }
+
+    /// The target's prefix list, now at `version`, holds the new IP.
+    pub fn ip_changed(&self, target: &Target, version: Option<i64>) {
+        self.update(target, |stats| {
+            stats.ip_changes += 1;
+            stats.last_success = Some(now());
+            stats.in_sync = true;
+            stats.version = version;
+        });
+    }
+
+    /// The target's prefix list, at `version`, was checked and holds the current IP.
+    pub fn in_sync(&self, target: &Target, version: Option<i64>) {
+        self.update(target, |stats| {
+            stats.last_success = Some(now());
+            stats.in_sync = true;
+            stats.version = version;
+        });
+    }
+
+    pub fn update_failed(&self, target: &Target) {
+        self.update(target, |stats| {
+            stats.update_failures += 1;
+            stats.in_sync = false;
+        });
+    }
+
+    fn update(&self, target: &Target, f: impl FnOnce(&mut TargetStats)) {
+        let mut snapshot = self.0.lock().unwrap();
+        let stats = snapshot
+            .targets
+            .entry(target.name.clone())
+            .or_insert_with(|| TargetStats {
+                prefix_list_id: target.prefix_list_id.to_string(),
+                ..TargetStats::default()
+            });
+        f(stats);
+    }
+}
+
+fn now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0., |elapsed| elapsed.as_secs_f64())
+}
+
+/// Such as `1.25s`, for durations too short for [`human`].
+fn seconds(duration: Duration) -> String {
+    format!("{:.2}s", duration.as_secs_f64())
+}
+
+/// Such as `3d 4h 12m`, only keeping the two largest units.
+fn human(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    let units = [
+        (seconds / 86400, "d"),
+        (seconds / 3600 % 24, "h"),
+        (seconds / 60 % 60, "m"),
+        (seconds % 60, "s"),
+    ];
+    let parts: Vec<String> = units
+        .iter()
+        .skip_while(|(value, _)| *value == 0)
+        .take(2)
+        .map(|(value, unit)| format!("{}{}", value, unit))
+        .collect();
+    match parts.is_empty() {
+        true => "0s".to_string(),
+        false => parts.join(" "),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::sleep;
+
+    fn target(name: &str, prefix_list_id: &str) -> Target {
+        Target {
+            name: name.to_string(),
+            prefix_list_id: prefix_list_id.parse().unwrap(),
+            description: "office".parse().unwrap(),
+            region: None,
+            notify: true,
+        }
+    }
+
+    #[test]
+    fn durations_for_humans() {
+        assert_eq!(human(Duration::ZERO), "0s");
+        assert_eq!(human(Duration::from_secs(59)), "59s");
+        assert_eq!(human(Duration::from_secs(3661)), "1h 1m");
+        assert_eq!(human(Duration::from_secs(3600)), "1h 0m");
+        assert_eq!(
+            human(Duration::from_secs(3 * 86400 + 4 * 3600 + 725)),
+            "3d 4h"
+        );
+        assert_eq!(seconds(Duration::from_millis(1250)), "1.25s");
+    }
+
+    #[test]
+    fn latency_quantiles_are_samples() {
+        let mut latency = Latency::default();
+        assert_eq!(latency.quantile(0.5), None);
+        for millis in 1..=10 {
+            latency.record(Duration::from_millis(millis * 100));
+        }
+        assert_eq!(latency.quantile(0.5), Some(Duration::from_millis(500)));
+        assert_eq!(latency.quantile(0.95), Some(Duration::from_secs(1)));
+        assert_eq!(latency.quantile(0.), Some(Duration::from_millis(100)));
+    }
+
+    #[test]
+    fn latency_only_keeps_the_last_samples() {
+        let mut latency = Latency::default();
+        latency.record(Duration::from_secs(60));
+        for _ in 0..LATENCY_WINDOW {
+            latency.record(Duration::from_secs(1));
+        }
+        assert_eq!(latency.0.len(), LATENCY_WINDOW);
+        assert_eq!(latency.quantile(1.), Some(Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn counters_are_per_target_and_summed() {
+        let (office, home) = (
+            target("office", "pl-12345678"),
+            target("home", "pl-87654321"),
+        );
+        let stats = Stats::new([&office]);
+        // Listed before anything happens
+        assert_eq!(
+            stats.snapshot().targets["office"].prefix_list_id,
+            "pl-12345678"
+        );
+
+        stats.ip_changed(&office, Some(2));
+        stats.ip_changed(&office, Some(3));
+        stats.update_failed(&office);
+        stats.ip_changed(&home, Some(7));
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.ip_changes(), 3);
+        assert_eq!(snapshot.update_failures(), 1);
+        assert!(!snapshot.targets["office"].in_sync);
+        assert_eq!(snapshot.targets["office"].version, Some(3));
+        // Added on the fly, although it wasn't known from the start
+        assert_eq!(snapshot.targets["home"].prefix_list_id, "pl-87654321");
+        assert!(snapshot.targets["home"].last_success.is_some());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn summary_of_a_quiet_start() {
+        let stats = Stats::new([&target("office", "pl-12345678")]);
+        stats.interval(Duration::from_secs(300));
+        assert_eq!(
+            stats.snapshot().summary(),
+            "Up 0s, 0 IP change(s), 0 update failure(s), IP unknown, office v?, not verified, checking every 5m 0s"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn summary_after_a_while() {
+        let office = target("office", "pl-12345678");
+        let stats = Stats::new([&office]);
+        stats.interval(Duration::from_secs(300));
+        stats.ip("192.0.2.1/32".parse().unwrap());
+        stats.ip_changed(&office, Some(4));
+        stats.update_failed(&office);
+        stats.update_took(Duration::from_millis(1500));
+        stats.update_took(Duration::from_millis(2500));
+        stats.operation("modify_entries", Duration::from_millis(800));
+        stats.operation("get_prefix_list", Duration::from_millis(200));
+        sleep(Duration::from_secs(2 * 3600)).await;
+        stats.verified();
+        sleep(Duration::from_secs(600)).await;
+        stats.paused(true);
+        stats.circuit_open(true);
+
+        assert_eq!(
+            stats.snapshot().summary(),
+            "Up 2h 10m, 1 IP change(s), 1 update failure(s), IP 192.0.2.1, office v4, verified 10m 0s ago, \
+             checking every 5m 0s, paused, circuit open, updates taking 1.50s (p50) / 2.50s (p95), slowest AWS \
+             operation modify_entries at 0.80s (p95)"
+        );
+    }
+}
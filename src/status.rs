@@ -0,0 +1,423 @@
+use crate::stats::Stats;
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+/// Bumped whenever the layout changes in a way readers would trip on.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// What doorman reports after every check, for monitoring that only needs to read a file.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Status {
+    pub version: u32,
+    pub started_at: DateTime<Utc>,
+    /// When this was written
+    pub checked_at: DateTime<Utc>,
+    /// The external IP, or the last known one if it couldn't be retrieved
+    pub ip: Option<IpAddr>,
+    /// The last check after which every prefix list held the IP
+    pub last_success: Option<DateTime<Utc>>,
+    /// The last time an entry was changed
+    pub last_update: Option<DateTime<Utc>>,
+    /// How many checks in a row failed
+    pub consecutive_failures: u32,
+    /// Since doorman started
+    pub ip_changes: u64,
+    /// Since doorman started
+    pub update_failures: u64,
+    pub last_error: Option<String>,
+    /// The last verification pass which found every prefix list holding the IP
+    pub last_verified: Option<DateTime<Utc>>,
+    /// What the last verification pass found wrong
+    pub verification_error: Option<String>,
+    /// Why doorman only works partly, e.g. `v4 unavailable` when only an IPv6 address was found
+    #[serde(default)]
+    pub degraded: Option<String>,
+    /// Whether the prefix lists are only tried once per probe interval, after failing repeatedly
+    #[serde(default)]
+    pub circuit_open: bool,
+    /// `leader`, `follower` or `unknown`, when running as a redundant pair
+    #[serde(default)]
+    pub role: Option<String>,
+    pub targets: Vec<TargetStatus>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TargetStatus {
+    pub name: String,
+    pub prefix_list_id: String,
+    /// The prefix list version doorman last saw, if it knows
+    pub version: Option<i64>,
+    /// How many updates in a row failed
+    pub failures: u32,
+}
+
+/// Whether the last success recorded in the status file is at most `max_age` old.
+///
+/// Either way, this returns a one-line explanation. Only the file is read, so this neither needs credentials nor
+/// takes long, as container health checks expect.
+pub fn healthcheck(path: &Path, max_age: Duration) -> Result<String, String> {
+    let content = fs::read_to_string(path)
+        .map_err(|err| format!("unhealthy: failed to read {}: {}", path.display(), err))?;
+    let status: Status = serde_json::from_str(&content)
+        .map_err(|err| format!("unhealthy: failed to parse {}: {}", path.display(), err))?;
+    if status.version != SCHEMA_VERSION {
+        return Err(format!(
+            "unhealthy: status version {} isn't supported, expected {}",
+            status.version, SCHEMA_VERSION
+        ));
+    }
+
+    let last_error = status.last_error.map_or_else(String::new, |error| {
+        format!(", last error: {}", error.replace('\n', " "))
+    });
+    let age = match status.last_success {
+        Some(at) => Utc::now() - at,
+        None => {
+            return Err(format!(
+                "unhealthy: no successful check since {}{}",
+                status.started_at.to_rfc3339(),
+                last_error
+            ))
+        }
+    };
+    match age <= max_age {
+        true => Ok(format!(
+            "healthy: last success {} seconds ago{}",
+            age.num_seconds(),
+            status
+                .degraded
+                .map_or_else(String::new, |reason| format!(", degraded: {}", reason))
+        )),
+        false => Err(format!(
+            "unhealthy: last success {} seconds ago, more than {}{}",
+            age.num_seconds(),
+            max_age.num_seconds(),
+            last_error
+        )),
+    }
+}
+
+/// Keeps the status file up to date.
+pub struct StatusFile {
+    path: PathBuf,
+    status: Status,
+    stats: Stats,
+}
+
+impl StatusFile {
+    pub fn new(path: PathBuf, stats: Stats) -> Self {
+        let now = Utc::now();
+        Self {
+            path,
+            status: Status {
+                version: SCHEMA_VERSION,
+                started_at: now,
+                checked_at: now,
+                ip: None,
+                last_success: None,
+                last_update: None,
+                consecutive_failures: 0,
+                ip_changes: 0,
+                update_failures: 0,
+                last_error: None,
+                last_verified: None,
+                verification_error: None,
+                degraded: None,
+                circuit_open: false,
+                role: None,
+                targets: Vec::new(),
+            },
+            stats,
+        }
+    }
+
+    /// Records a verification pass, `problem` being what it found wrong if anything. This is written with the check.
+    pub fn verified(&mut self, problem: Option<String>) {
+        if problem.is_none() {
+            self.status.last_verified = Some(Utc::now());
+        }
+        self.status.verification_error = problem;
+    }
+
+    /// Records why doorman only works partly, if it does. This is written with the check.
+    pub fn degraded(&mut self, reason: Option<String>) {
+        self.status.degraded = reason;
+    }
+
+    /// Records how a check went, `failure` being its last error if any, and rewrites the file.
+    ///
+    /// Failing to write is only logged, doorman works without it.
+    pub fn checked(
+        &mut self,
+        ip: Option<IpAddr>,
+        failure: Option<String>,
+        last_update: Option<DateTime<Utc>>,
+        targets: Vec<TargetStatus>,
+    ) {
+        let status = &mut self.status;
+        status.checked_at = Utc::now();
+        status.ip = ip;
+        status.last_update = last_update;
+        status.targets = targets;
+        let snapshot = self.stats.snapshot();
+        status.ip_changes = snapshot.ip_changes();
+        status.update_failures = snapshot.update_failures();
+        status.circuit_open = snapshot.circuit_open;
+        status.role = snapshot.role.map(|role| role.as_str().to_string());
+        match failure {
+            Some(error) => {
+                status.consecutive_failures += 1;
+                status.last_error = Some(error);
+            }
+            None => {
+                status.last_success = Some(status.checked_at);
+                status.consecutive_failures = 0;
+                status.last_error = None;
+            }
+        }
+
+        if let Err(err) = self.write() {
+            warn!(
+                "Failed to write the status to {}: {}",
+                self.path.display(),
+                err
+            );
+        }
+    }
+
+    /// Writes to a temporary file first, so readers never see a partial one.
+    fn write(&self) -> io::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let temporary = self.path.with_extension("json.tmp");
+        fs::write(&temporary, serde_json::to_vec_pretty(&self.status)?)?;
+        fs::rename(&temporary, &self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn status_file() -> (TempDir, StatusFile) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("status.json");
+        (dir, StatusFile::new(path, Stats::new([])))
+    }
+
+    fn read(status: &StatusFile) -> Status {
+        serde_json::from_str(&fs::read_to_string(&status.path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn status_survives_a_round_trip() {
+        let (_dir, mut status) = status_file();
+        let updated = Utc::now();
+        status.degraded(Some("v4 unavailable".to_string()));
+        status.checked(
+            Some("192.0.2.1".parse().unwrap()),
+            None,
+            Some(updated),
+            vec![TargetStatus {
+                name: "office".to_string(),
+                prefix_list_id: "pl-12345678".to_string(),
+                version: Some(3),
+                failures: 0,
+            }],
+        );
+        assert!(!status.path.with_extension("json.tmp").exists());
+
+        let read = read(&status);
+        assert_eq!(read.version, SCHEMA_VERSION);
+        assert_eq!(read.ip, Some("192.0.2.1".parse().unwrap()));
+        assert_eq!(read.last_update, Some(updated));
+        assert_eq!(read.last_success, Some(read.checked_at));
+        assert_eq!(read.degraded.as_deref(), Some("v4 unavailable"));
+        assert_eq!(read.targets.len(), 1);
+        assert_eq!(read.targets[0].prefix_list_id, "pl-12345678");
+        assert_eq!(read.targets[0].version, Some(3));
+    }
+
+    #[test]
+    fn failures_are_written_too() {
+        let (_dir, mut status) = status_file();
+        status.checked(None, None, None, Vec::new());
+        status.checked(None, Some("AWS timed out".to_string()), None, Vec::new());
+        status.checked(
+            None,
+            Some("AWS timed out again".to_string()),
+            None,
+            Vec::new(),
+        );
+
+        let read = read(&status);
+        assert_eq!(read.consecutive_failures, 2);
+        assert_eq!(read.last_error.as_deref(), Some("AWS timed out again"));
+        assert!(read.last_success.is_some());
+        assert!(healthcheck(&status.path, Duration::minutes(5)).is_ok());
+    }
+
+    #[test]
+    fn verification_is_written_with_the_check() {
+        let (_dir, mut status) = status_file();
+        status.verified(None);
+        status.checked(None, None, None, Vec::new());
+        let verified = read(&status).last_verified;
+        assert!(verified.is_some());
+
+        // A failed pass keeps when the last one passed
+        status.verified(Some("office: no entry for 192.0.2.1/32".to_string()));
+        status.checked(None, None, None, Vec::new());
+        let read = read(&status);
+        assert_eq!(read.last_verified, verified);
+        assert_eq!(
+            read.verification_error.as_deref(),
+            Some("office: no entry for 192.0.2.1/32")
+        );
+    }
+
+    #[test]
+    fn healthcheck_rejects_an_unsupported_version() {
+        let (_dir, mut status) = status_file();
+        status.checked(None, None, None, Vec::new());
+        let content = fs::read_to_string(&status.path)
+            .unwrap()
+            .replace("\"version\": 1", "\"version\": 2");
+        fs::write(&status.path, content).unwrap();
+
+        assert_eq!(
+            healthcheck(&status.path, Duration::minutes(5)).unwrap_err(),
+            "unhealthy: status version 2 isn't supported, expected 1"
+        );
+    }
+
+    #[test]
+    fn healthcheck_rejects_a_corrupt_file() {
+        let (_dir, status) = status_file();
+        fs::write(&status.path, "{\"version\": 1,").unwrap();
+
+        let err = healthcheck(&status.path, Duration::minutes(5)).unwrap_err();
+        assert!(
+            err.starts_with(&format!(
+                "unhealthy: failed to parse {}: ",
+                status.path.display()
+            )),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn healthcheck_reads_older_files() {
+        // Without `degraded`, `circuit_open` and `role`
+        let (_dir, status) = status_file();
+        let content = serde_json::json!({
+            "version": 1,
+            "started_at": Utc::now(),
+            "checked_at": Utc::now(),
+            "ip": "192.0.2.1",
+            "last_success": Utc::now(),
+            "last_update": null,
+            "consecutive_failures": 0,
+            "ip_changes": 1,
+            "update_failures": 0,
+            "last_error": null,
+            "last_verified": null,
+            "verification_error": null,
+            "targets": [],
+        });
+        fs::write(&status.path, content.to_string()).unwrap();
+
+        let read = read(&status);
+        assert!(read.degraded.is_none());
+        assert!(!read.circuit_open);
+        assert!(read.role.is_none());
+        assert!(healthcheck(&status.path, Duration::minutes(5))
+            .unwrap()
+            .starts_with("healthy: last success"));
+    }
+
+    #[test]
+    fn healthcheck_rejects_a_stale_success() {
+        let (_dir, mut status) = status_file();
+        status.checked(None, None, None, Vec::new());
+        status.checked(None, Some("AWS timed out".to_string()), None, Vec::new());
+
+        let err = healthcheck(&status.path, Duration::seconds(-1)).unwrap_err();
+        assert!(err.ends_with(", last error: AWS timed out"), "{}", err);
+    }
+
+    #[test]
+    fn healthcheck_accepts_a_recent_success() {
+        let (_dir, mut status) = status_file();
+        status.degraded(Some("v4 unavailable".to_string()));
+        status.checked(None, None, None, Vec::new());
+
+        let reason = healthcheck(&status.path, Duration::seconds(900)).unwrap();
+        assert_eq!(
+            reason,
+            "healthy: last success 0 seconds ago, degraded: v4 unavailable"
+        );
+    }
+
+    #[test]
+    fn healthcheck_rejects_a_missing_file() {
+        let (_dir, status) = status_file();
+        let err = healthcheck(&status.path, Duration::seconds(900)).unwrap_err();
+        assert!(
+            err.starts_with(&format!(
+                "unhealthy: failed to read {}: ",
+                status.path.display()
+            )),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn healthcheck_rejects_a_doorman_which_never_succeeded() {
+        let (_dir, mut status) = status_file();
+        status.checked(
+            None,
+            Some("AWS timed out\nwhile modifying".to_string()),
+            None,
+            Vec::new(),
+        );
+
+        let err = healthcheck(&status.path, Duration::seconds(900)).unwrap_err();
+        assert_eq!(
+            err,
+            format!(
+                "unhealthy: no successful check since {}, last error: AWS timed out while modifying",
+                status.status.started_at.to_rfc3339()
+            )
+        );
+        // One line, as `docker inspect` shows it
+        assert!(!err.contains('\n'));
+    }
+
+    #[test]
+    fn healthcheck_tells_how_stale_the_success_is() {
+        let (_dir, mut status) = status_file();
+        status.checked(None, None, None, Vec::new());
+        let content = fs::read_to_string(&status.path).unwrap().replace(
+            &serde_json::to_string(&status.status.last_success).unwrap(),
+            &serde_json::to_string(&(Utc::now() - Duration::seconds(1000))).unwrap(),
+        );
+        fs::write(&status.path, content).unwrap();
+
+        let err = healthcheck(&status.path, Duration::seconds(900)).unwrap_err();
+        assert_eq!(
+            err,
+            "unhealthy: last success 1000 seconds ago, more than 900"
+        );
+    }
+}
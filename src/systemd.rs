@@ -0,0 +1,308 @@
+use std::env;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::time::{interval, Duration, MissedTickBehavior};
+use tracing::{debug, warn};
+
+/// Talks to systemd when running as a `Type=notify` service.
+///
+/// Without `NOTIFY_SOCKET`, e.g. when not started by systemd, this does nothing.
+#[derive(Clone)]
+pub struct Systemd {
+    socket: Option<Arc<NotifySocket>>,
+    /// Whether readiness was reported
+    ready: Arc<AtomicBool>,
+}
+
+impl Systemd {
+    pub fn from_env() -> Self {
+        let socket = env::var_os("NOTIFY_SOCKET").and_then(|path| {
+            match NotifySocket::connect(&path.to_string_lossy()) {
+                Ok(socket) => Some(Arc::new(socket)),
+                Err(err) => {
+                    warn!(
+                        "Failed to connect to the systemd notification socket: {}",
+                        err
+                    );
+                    None
+                }
+            }
+        });
+        Self {
+            socket,
+            ready: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// A check finished, `status` being shown by `systemctl status`.
+    ///
+    /// Doorman is ready once the first check is done, whatever its outcome, so it doesn't hold up the boot.
+    pub fn checked(&self, status: &str) {
+        let status = format!("STATUS={}", status.replace('\n', " "));
+        match self.ready.swap(true, Ordering::Relaxed) {
+            true => self.send(&status),
+            false => self.send(&format!("READY=1\n{}", status)),
+        }
+    }
+
+    /// The configuration is being reloaded, which [`Systemd::reloaded`] tells is over.
+    pub fn reloading(&self) {
+        self.send("RELOADING=1");
+    }
+
+    /// Reports being ready again after a reload, along with `status`.
+    pub fn reloaded(&self, status: &str) {
+        self.ready.store(true, Ordering::Relaxed);
+        self.send(&format!("READY=1\nSTATUS={}", status.replace('\n', " ")));
+    }
+
+    pub fn stopping(&self) {
+        self.send("STOPPING=1");
+    }
+
+    /// Pings the watchdog in the background, if systemd asked for it with `WatchdogSec=`.
+    ///
+    /// Pings are sent twice as often as required. They come from the same runtime as the main loop, so systemd
+    /// notices when it hangs.
+    pub fn spawn_watchdog(&self) {
+        let timeout = match (&self.socket, watchdog_timeout()) {
+            (Some(_), Some(timeout)) => timeout,
+            _ => return,
+        };
+        self.ping_every(timeout / 2);
+    }
+
+    fn ping_every(&self, period: Duration) {
+        debug!(
+            "Pinging the systemd watchdog every {} ms.",
+            period.as_millis()
+        );
+        let systemd = self.clone();
+        tokio::spawn(async move {
+            let mut timer = interval(period);
+            timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                timer.tick().await;
+                systemd.send("WATCHDOG=1");
+            }
+        });
+    }
+
+    fn send(&self, state: &str) {
+        if let Some(socket) = &self.socket {
+            if let Err(err) = socket.send(state) {
+                debug!("Failed to notify systemd of {}: {}", state, err);
+            }
+        }
+    }
+}
+
+/// How often systemd expects the watchdog to be pinged, if it's meant for this process.
+fn watchdog_timeout() -> Option<Duration> {
+    parse_watchdog(
+        env::var("WATCHDOG_PID").ok().as_deref(),
+        env::var("WATCHDOG_USEC").ok().as_deref(),
+        std::process::id(),
+    )
+}
+
+/// The watchdog timeout from `WATCHDOG_PID` and `WATCHDOG_USEC`, if it's meant for the process `pid`.
+fn parse_watchdog(watchdog_pid: Option<&str>, usec: Option<&str>, pid: u32) -> Option<Duration> {
+    if let Some(watchdog_pid) = watchdog_pid {
+        if watchdog_pid.parse::<u32>().ok() != Some(pid) {
+            return None;
+        }
+    }
+    usec?
+        .parse::<u64>()
+        .ok()
+        .filter(|usec| *usec > 0)
+        .map(Duration::from_micros)
+}
+
+#[cfg(unix)]
+struct NotifySocket {
+    socket: std::os::unix::net::UnixDatagram,
+}
+
+#[cfg(unix)]
+impl NotifySocket {
+    /// `path` starting with `@` is an abstract socket, which only exists on Linux.
+    fn connect(path: &str) -> io::Result<Self> {
+        let socket = std::os::unix::net::UnixDatagram::unbound()?;
+        match path.strip_prefix('@') {
+            #[cfg(target_os = "linux")]
+            Some(name) => {
+                use std::os::linux::net::SocketAddrExt;
+                let addr = std::os::unix::net::SocketAddr::from_abstract_name(name.as_bytes())?;
+                socket.connect_addr(&addr)?;
+            }
+            #[cfg(not(target_os = "linux"))]
+            Some(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "abstract sockets are only supported on Linux",
+                ))
+            }
+            None => socket.connect(path)?,
+        }
+        socket.set_nonblocking(true)?;
+        Ok(Self { socket })
+    }
+
+    fn send(&self, state: &str) -> io::Result<()> {
+        self.socket.send(state.as_bytes())?;
+        Ok(())
+    }
+}
+
+#[cfg(not(unix))]
+struct NotifySocket;
+
+#[cfg(not(unix))]
+impl NotifySocket {
+    fn connect(_path: &str) -> io::Result<Self> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "systemd notifications are only supported on Unix",
+        ))
+    }
+
+    fn send(&self, _state: &str) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixDatagram;
+    use tempfile::TempDir;
+
+    /// Stands in for systemd, returning the socket it listens on and a `Systemd` talking to it.
+    fn fake_systemd() -> (TempDir, UnixDatagram, Systemd) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notify");
+        let listener = UnixDatagram::bind(&path).unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let socket = NotifySocket::connect(path.to_str().unwrap()).unwrap();
+        let systemd = Systemd {
+            socket: Some(Arc::new(socket)),
+            ready: Arc::new(AtomicBool::new(false)),
+        };
+        (dir, listener, systemd)
+    }
+
+    /// What systemd received so far, one message per datagram.
+    fn received(listener: &UnixDatagram) -> Vec<String> {
+        let mut messages = Vec::new();
+        let mut buf = [0; 1024];
+        while let Ok(len) = listener.recv(&mut buf) {
+            messages.push(String::from_utf8_lossy(&buf[..len]).into_owned());
+        }
+        messages
+    }
+
+    #[test]
+    fn ready_once_then_status_then_stopping() {
+        let (_dir, listener, systemd) = fake_systemd();
+        systemd.checked("IP 192.0.2.1/32");
+        systemd.checked("IP 192.0.2.2/32,\nupdated just now");
+        systemd.stopping();
+        assert_eq!(
+            received(&listener),
+            [
+                "READY=1\nSTATUS=IP 192.0.2.1/32",
+                "STATUS=IP 192.0.2.2/32, updated just now",
+                "STOPPING=1",
+            ]
+        );
+    }
+
+    #[test]
+    fn reloading_then_ready_again() {
+        let (_dir, listener, systemd) = fake_systemd();
+        systemd.checked("IP 192.0.2.1/32");
+        systemd.reloading();
+        systemd.reloaded("IP 192.0.2.1/32");
+        systemd.checked("IP 192.0.2.1/32");
+        assert_eq!(
+            received(&listener),
+            [
+                "READY=1\nSTATUS=IP 192.0.2.1/32",
+                "RELOADING=1",
+                "READY=1\nSTATUS=IP 192.0.2.1/32",
+                "STATUS=IP 192.0.2.1/32",
+            ]
+        );
+    }
+
+    #[test]
+    fn clones_share_readiness() {
+        let (_dir, listener, systemd) = fake_systemd();
+        systemd.clone().checked("first");
+        systemd.checked("second");
+        assert_eq!(
+            received(&listener),
+            ["READY=1\nSTATUS=first", "STATUS=second"]
+        );
+    }
+
+    #[test]
+    fn nothing_happens_without_a_socket() {
+        let systemd = Systemd {
+            socket: None,
+            ready: Arc::new(AtomicBool::new(false)),
+        };
+        systemd.checked("first");
+        systemd.stopping();
+    }
+
+    #[test]
+    fn a_missing_socket_fails_to_connect() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("missing");
+        assert!(NotifySocket::connect(path.to_str().unwrap()).is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn abstract_sockets() {
+        use std::os::linux::net::SocketAddrExt;
+
+        let name = format!("aws_doorman-test-{}", std::process::id());
+        let addr = std::os::unix::net::SocketAddr::from_abstract_name(name.as_bytes()).unwrap();
+        let listener = UnixDatagram::bind_addr(&addr).unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let socket = NotifySocket::connect(&format!("@{}", name)).unwrap();
+        socket.send("WATCHDOG=1").unwrap();
+        assert_eq!(received(&listener), ["WATCHDOG=1"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn watchdog_pings_every_period() {
+        let (_dir, listener, systemd) = fake_systemd();
+        systemd.ping_every(Duration::from_secs(5));
+        // The first ping is sent right away
+        tokio::time::sleep(Duration::from_secs(11)).await;
+        assert_eq!(received(&listener), ["WATCHDOG=1"; 3]);
+    }
+
+    #[test]
+    fn watchdog_timeout() {
+        assert_eq!(
+            parse_watchdog(None, Some("30000000"), 42),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            parse_watchdog(Some("42"), Some("30000000"), 42),
+            Some(Duration::from_secs(30))
+        );
+        // Meant for another process, such as the shell running doorman
+        assert_eq!(parse_watchdog(Some("41"), Some("30000000"), 42), None);
+        assert_eq!(parse_watchdog(None, None, 42), None);
+        assert_eq!(parse_watchdog(None, Some("0"), 42), None);
+        assert_eq!(parse_watchdog(None, Some("soon"), 42), None);
+    }
+}
@@ -0,0 +1,260 @@
+//! The command line as users and scripts see it: what doorman prints and which code it exits with.
+//!
+//! Nothing here reaches AWS. Runs that would are sent to a local listener with `--endpoint-url`.
+
+#![cfg(unix)]
+
+use assert_cmd::Command;
+use predicates::prelude::*;
+use std::io::{ErrorKind, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+const PREFIX_LIST_ID: &str = "pl-12345678";
+
+/// What runs need to manage a prefix list, without looking up the region.
+const TARGET: [&str; 6] = [
+    "--prefix-list-id",
+    PREFIX_LIST_ID,
+    "--description",
+    "office",
+    "--region",
+    "us-east-1",
+];
+
+/// Doorman with credentials, but none of the user's profiles nor IMDS.
+fn doorman(home: &Path) -> Command {
+    let mut cmd = Command::cargo_bin("aws_doorman").unwrap();
+    cmd.env_clear()
+        .env("HOME", home)
+        .env("AWS_ACCESS_KEY_ID", "test")
+        .env("AWS_SECRET_ACCESS_KEY", "test")
+        .env("AWS_EC2_METADATA_DISABLED", "true")
+        .timeout(Duration::from_secs(60));
+    cmd
+}
+
+/// Listens on a free port without ever accepting, so a test can tell whether anything connected.
+fn untouched_endpoint() -> (TcpListener, String) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    (listener, url)
+}
+
+fn assert_untouched(listener: &TcpListener) {
+    listener.set_nonblocking(true).unwrap();
+    match listener.accept() {
+        Err(err) if err.kind() == ErrorKind::WouldBlock => {}
+        Err(err) => panic!("failed to check the endpoint: {}", err),
+        Ok((_, peer)) => panic!("{} connected to the endpoint", peer),
+    }
+}
+
+/// An endpoint handing each connection to `handle`, in the background.
+fn endpoint(handle: fn(TcpStream)) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            handle(stream.unwrap());
+        }
+    });
+    url
+}
+
+#[test]
+fn invalid_prefix_list_ids_are_usage_errors() {
+    let home = tempfile::tempdir().unwrap();
+    doorman(home.path())
+        .args(["--prefix-list-id", "pl-1", "--description", "office"])
+        .assert()
+        .code(2)
+        .stdout("")
+        .stderr(predicate::str::contains(
+            "Invalid value \"pl-1\" for '--prefix-list-id <PREFIX LIST ID>': the expected format is 'pl-1234567890abcdef0'",
+        ));
+}
+
+#[test]
+fn invalid_descriptions_are_usage_errors() {
+    let home = tempfile::tempdir().unwrap();
+    doorman(home.path())
+        .args([
+            "--prefix-list-id",
+            PREFIX_LIST_ID,
+            "--description",
+            "office @ home",
+        ])
+        .assert()
+        .code(2)
+        .stdout("")
+        .stderr(predicate::str::contains(
+            "must not contain '@', which separates the description from its suffix",
+        ));
+}
+
+#[test]
+fn help_lists_every_option() {
+    let home = tempfile::tempdir().unwrap();
+    let help = doorman(home.path()).arg("--help").assert().success();
+    let help = String::from_utf8_lossy(&help.get_output().stdout).into_owned();
+
+    // The completions are generated from the same definition, so they know every option
+    let completions = doorman(home.path())
+        .args(["completions", "bash"])
+        .assert()
+        .success();
+    let completions = String::from_utf8_lossy(&completions.get_output().stdout).into_owned();
+    let options: Vec<_> = completions
+        .lines()
+        .find(|line| line.contains("opts=\"") && line.contains("--show-config"))
+        .unwrap()
+        .split(['"', ' '])
+        // Only supported on Windows, so hidden here
+        .filter(|word| word.starts_with("--") && *word != "--event-log")
+        .collect();
+
+    assert!(options.len() > 50, "{:?}", options);
+    for option in options {
+        assert!(help.contains(option), "--help doesn't list {}", option);
+    }
+}
+
+#[test]
+fn exclusive_options_are_rejected() {
+    let home = tempfile::tempdir().unwrap();
+    let cases: [(&[&str], &str); 4] = [
+        (
+            &["--cleanup", "--show-config"],
+            "The argument '--cleanup' cannot be used with '--show-config'",
+        ),
+        (&["--cleanup", "--force-update"], "cannot be used with"),
+        (
+            &["--cleanup", "--all-hosts"],
+            "The following required arguments were not provided:\n    --confirm-all-hosts",
+        ),
+        (&["--config", "doorman.toml"], "cannot be used with"),
+    ];
+
+    for (args, expected) in cases {
+        doorman(home.path())
+            .args(TARGET)
+            .args(args)
+            .assert()
+            .code(2)
+            .stdout("")
+            .stderr(predicate::str::contains(expected));
+    }
+}
+
+#[test]
+fn show_config_doesnt_touch_the_network() {
+    let home = tempfile::tempdir().unwrap();
+    let (listener, url) = untouched_endpoint();
+
+    let output = doorman(home.path())
+        .args(TARGET)
+        .args(["--endpoint-url", &url, "--show-config", "--output", "json"])
+        .assert()
+        .success();
+
+    // Only the configuration goes to stdout, so scripts can parse it
+    let config: serde_json::Value = serde_json::from_slice(&output.get_output().stdout).unwrap();
+    assert_eq!(config["targets"][0]["prefix_list_id"], PREFIX_LIST_ID);
+    assert_eq!(config["targets"][0]["region"], "us-east-1");
+    assert_untouched(&listener);
+}
+
+#[test]
+fn completions_dont_touch_the_network() {
+    let home = tempfile::tempdir().unwrap();
+    let (listener, _) = untouched_endpoint();
+
+    for shell in ["bash", "zsh", "fish"] {
+        doorman(home.path())
+            .args(["completions", shell])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("prefix-list-id"));
+    }
+    assert_untouched(&listener);
+}
+
+#[test]
+fn exit_codes_are_listed() {
+    let home = tempfile::tempdir().unwrap();
+    doorman(home.path())
+        .arg("exit-codes")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "5  AWS couldn't be reached or its API kept failing",
+        ));
+}
+
+#[test]
+fn an_unreachable_endpoint_exits_as_aws_unavailable() {
+    let home = tempfile::tempdir().unwrap();
+    let url = endpoint(drop);
+
+    doorman(home.path())
+        .args(TARGET)
+        .args(["--endpoint-url", &url, "--aws-max-attempts", "1"])
+        .args(["--once", "--ip", "192.0.2.1", "--notify-backend", "log"])
+        .arg("--state-file")
+        .arg(home.path().join("state.json"))
+        .assert()
+        .code(5)
+        .stderr(predicate::str::contains(
+            "DescribeManagedPrefixLists couldn't reach AWS",
+        ));
+}
+
+#[test]
+fn a_silent_endpoint_times_out_as_aws_unavailable() {
+    let home = tempfile::tempdir().unwrap();
+    // Holds the connection open without ever answering
+    let url = endpoint(|stream| {
+        thread::spawn(move || {
+            thread::sleep(Duration::from_secs(60));
+            drop(stream);
+        });
+    });
+
+    doorman(home.path())
+        .args(TARGET)
+        .args(["--endpoint-url", &url, "--aws-max-attempts", "1"])
+        .args(["--aws-timeout", "1"])
+        .args(["--once", "--ip", "192.0.2.1", "--notify-backend", "log"])
+        .arg("--state-file")
+        .arg(home.path().join("state.json"))
+        .assert()
+        .code(5)
+        .stderr(predicate::str::contains(
+            "DescribeManagedPrefixLists timed out",
+        ));
+}
+
+#[test]
+fn a_failed_cleanup_names_the_target() {
+    let home = tempfile::tempdir().unwrap();
+    let url = endpoint(|mut stream| {
+        // Not an EC2 response, so the SDK can't make sense of it
+        let _ =
+            stream.write_all(b"HTTP/1.1 500 Internal Server Error\r\ncontent-length: 0\r\n\r\n");
+    });
+
+    doorman(home.path())
+        .args(TARGET)
+        .args(["--endpoint-url", &url, "--aws-max-attempts", "1"])
+        .args(["--cleanup", "--notify-backend", "log"])
+        .arg("--state-file")
+        .arg(home.path().join("state.json"))
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains(
+            "Failed to clean up target(s): default",
+        ));
+}
@@ -0,0 +1,163 @@
+//! End-to-end runs of the doorman binary against LocalStack.
+//!
+//! These are ignored by default. With LocalStack running, point `AWS_ENDPOINT_URL` at it and run the ignored tests:
+//!
+//! ```sh
+//! docker run --rm -d -p 4566:4566 localstack/localstack
+//! AWS_ENDPOINT_URL=http://localhost:4566 cargo test --test localstack -- --ignored
+//! ```
+
+use aws_sdk_ec2::{Client, Credentials, Endpoint, Region};
+use std::env;
+use std::fs;
+use std::process::Output;
+use tokio::process::Command;
+
+const REGION: &str = "us-east-1";
+const DESCRIPTION: &str = "doorman-localstack";
+
+/// Where LocalStack listens, if the suite should run at all.
+fn endpoint() -> Option<String> {
+    let endpoint = env::var("AWS_ENDPOINT_URL").ok();
+    if endpoint.is_none() {
+        eprintln!("AWS_ENDPOINT_URL isn't set, skipping.");
+    }
+    endpoint
+}
+
+/// LocalStack takes any credentials.
+async fn client(endpoint: &str) -> Client {
+    let config = aws_config::from_env()
+        .region(Region::new(REGION))
+        .credentials_provider(Credentials::new("test", "test", None, None, "localstack"))
+        .endpoint_resolver(Endpoint::immutable(endpoint.parse().unwrap()))
+        .load()
+        .await;
+    Client::new(&config)
+}
+
+async fn create_prefix_list(client: &Client, name: &str) -> String {
+    client
+        .create_managed_prefix_list()
+        .prefix_list_name(name)
+        .max_entries(10)
+        .address_family("IPv4")
+        .send()
+        .await
+        .unwrap()
+        .prefix_list
+        .and_then(|prefix_list| prefix_list.prefix_list_id)
+        .unwrap()
+}
+
+/// The CIDRs and descriptions of the entries.
+async fn entries(client: &Client, prefix_list_id: &str) -> Vec<(String, Option<String>)> {
+    client
+        .get_managed_prefix_list_entries()
+        .prefix_list_id(prefix_list_id)
+        .send()
+        .await
+        .unwrap()
+        .entries
+        .unwrap_or_default()
+        .into_iter()
+        .map(|entry| (entry.cidr.unwrap_or_default(), entry.description))
+        .collect()
+}
+
+/// Runs doorman against LocalStack with `args`, on top of what every run needs.
+async fn doorman(endpoint: &str, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_aws_doorman"))
+        .args(["--endpoint-url", endpoint, "--region", REGION])
+        .args(["--notify-backend", "log", "--interval", "60"])
+        .args(args)
+        .env("AWS_ACCESS_KEY_ID", "test")
+        .env("AWS_SECRET_ACCESS_KEY", "test")
+        .output()
+        .await
+        .unwrap()
+}
+
+fn assert_success(output: &Output) {
+    assert!(
+        output.status.success(),
+        "doorman exited with {}:\n{}{}",
+        output.status,
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[tokio::test]
+#[ignore = "needs LocalStack, see the module documentation"]
+async fn replaces_the_entry_then_cleans_up() {
+    let Some(endpoint) = endpoint() else { return };
+    let client = client(&endpoint).await;
+    let prefix_list_id = create_prefix_list(&client, "doorman-replaces").await;
+    let state_file = env::temp_dir().join(format!("doorman-localstack-{}.json", prefix_list_id));
+    let state = state_file.to_str().unwrap();
+    let target = [
+        "--prefix-list-id",
+        &prefix_list_id,
+        "--description",
+        DESCRIPTION,
+        "--state-file",
+        state,
+    ];
+
+    let run = doorman(
+        &endpoint,
+        &[&target[..], &["--once", "--ip", "192.0.2.1"]].concat(),
+    )
+    .await;
+    assert_success(&run);
+    assert_eq!(
+        entries(&client, &prefix_list_id).await,
+        [("192.0.2.1/32".to_string(), Some(DESCRIPTION.to_string()))]
+    );
+
+    // The state file tells the next run which entry to replace
+    let run = doorman(
+        &endpoint,
+        &[&target[..], &["--once", "--ip", "192.0.2.2"]].concat(),
+    )
+    .await;
+    assert_success(&run);
+    assert_eq!(
+        entries(&client, &prefix_list_id).await,
+        [("192.0.2.2/32".to_string(), Some(DESCRIPTION.to_string()))]
+    );
+
+    let run = doorman(&endpoint, &[&target[..], &["--cleanup"]].concat()).await;
+    assert_success(&run);
+    assert_eq!(entries(&client, &prefix_list_id).await, []);
+
+    client
+        .delete_managed_prefix_list()
+        .prefix_list_id(&prefix_list_id)
+        .send()
+        .await
+        .unwrap();
+    let _ = fs::remove_file(state_file);
+}
+
+#[tokio::test]
+#[ignore = "needs LocalStack, see the module documentation"]
+async fn missing_prefix_list_has_its_exit_code() {
+    let Some(endpoint) = endpoint() else { return };
+    let run = doorman(
+        &endpoint,
+        &[
+            "--prefix-list-id",
+            "pl-00000000000000000",
+            "--description",
+            DESCRIPTION,
+            "--once",
+            "--ip",
+            "192.0.2.1",
+        ],
+    )
+    .await;
+    // See the exit-codes subcommand
+    assert_eq!(run.status.code(), Some(4));
+}
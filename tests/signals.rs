@@ -0,0 +1,184 @@
+//! Stopping a running doorman with signals, as systemd or `docker stop` do.
+//!
+//! Doorman runs on a simulation, so it neither calls AWS nor looks up the IP.
+
+#![cfg(unix)]
+
+use std::fs;
+use std::path::Path;
+use std::process::{ExitStatus, Stdio};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, BufReader, Lines};
+use tokio::process::{Child, ChildStdout, Command};
+use tokio::time::timeout;
+
+const PREFIX_LIST_ID: &str = "pl-12345678";
+
+/// A simulation where the IP is always 198.51.100.7, and the prefix list starts empty.
+fn fixture(dir: &Path) {
+    fs::write(dir.join("ips.json"), r#"["198.51.100.7"]"#).unwrap();
+    fs::write(
+        dir.join("prefix_lists.json"),
+        format!(
+            r#"[{{"prefix_list_id": "{}", "version": 1, "max_entries": 10, "entries": []}}]"#,
+            PREFIX_LIST_ID
+        ),
+    )
+    .unwrap();
+}
+
+fn spawn(dir: &Path) -> (Child, Lines<BufReader<ChildStdout>>) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_aws_doorman"))
+        .args([
+            "--prefix-list-id",
+            PREFIX_LIST_ID,
+            "--description",
+            "office",
+        ])
+        .arg("--simulate")
+        .arg(dir)
+        .arg("--state-file")
+        .arg(dir.join("state.json"))
+        .args(["--notify-backend", "log", "--interval", "60"])
+        .args(["--log-timestamp", "off"])
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .unwrap();
+    let lines = BufReader::new(child.stdout.take().unwrap()).lines();
+    (child, lines)
+}
+
+/// Reads the log until a line contains `expected`, failing if it doesn't come soon.
+async fn wait_for(lines: &mut Lines<BufReader<ChildStdout>>, expected: &str) -> String {
+    timeout(Duration::from_secs(30), async {
+        while let Some(line) = lines.next_line().await.unwrap() {
+            if line.contains(expected) {
+                return line;
+            }
+        }
+        panic!("doorman stopped logging before '{}'", expected);
+    })
+    .await
+    .unwrap_or_else(|_| panic!("doorman didn't log '{}' in time", expected))
+}
+
+async fn send(child: &Child, signal: &str) {
+    send_to(&child.id().unwrap().to_string(), signal).await;
+}
+
+async fn send_to(pid: &str, signal: &str) {
+    let status = Command::new("kill")
+        .args([signal, pid])
+        .status()
+        .await
+        .unwrap();
+    assert!(status.success());
+}
+
+async fn exit(mut child: Child) -> ExitStatus {
+    timeout(Duration::from_secs(30), child.wait())
+        .await
+        .expect("doorman didn't exit in time")
+        .unwrap()
+}
+
+async fn stops_cleanly_on(signal: &str, name: &str) {
+    let dir = tempfile::tempdir().unwrap();
+    fixture(dir.path());
+    let (child, mut lines) = spawn(dir.path());
+    wait_for(&mut lines, "added [198.51.100.7/32], removed []").await;
+
+    send(&child, signal).await;
+    wait_for(&mut lines, &format!("Received {}. Cleaning up", name)).await;
+    wait_for(&mut lines, "added [], removed [198.51.100.7/32]").await;
+    assert!(exit(child).await.success());
+
+    // The state file no longer holds an entry to replace
+    let state: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(dir.path().join("state.json")).unwrap()).unwrap();
+    assert_eq!(state["cidr"], serde_json::Value::Null);
+}
+
+#[tokio::test]
+async fn sigterm_cleans_up_and_exits_successfully() {
+    stops_cleanly_on("-TERM", "SIGTERM").await;
+}
+
+#[tokio::test]
+async fn sigquit_cleans_up_and_exits_successfully() {
+    stops_cleanly_on("-QUIT", "SIGQUIT").await;
+}
+
+#[tokio::test]
+async fn sigint_cleans_up_and_exits_successfully() {
+    stops_cleanly_on("-INT", "^C").await;
+}
+
+#[tokio::test]
+async fn sighup_without_a_configuration_file_is_only_logged() {
+    let dir = tempfile::tempdir().unwrap();
+    fixture(dir.path());
+    let (child, mut lines) = spawn(dir.path());
+    wait_for(&mut lines, "added [198.51.100.7/32], removed []").await;
+
+    send(&child, "-HUP").await;
+    wait_for(&mut lines, "There is no configuration file to reload.").await;
+
+    // Still running, so it cleans up on the next signal as usual
+    send(&child, "-TERM").await;
+    wait_for(&mut lines, "Received SIGTERM. Cleaning up").await;
+    wait_for(&mut lines, "added [], removed [198.51.100.7/32]").await;
+    assert!(exit(child).await.success());
+}
+
+/// Reads the log file until it contains `expected`, failing if it doesn't come soon.
+async fn wait_for_log(path: &Path, expected: &str) {
+    timeout(Duration::from_secs(30), async {
+        while !fs::read_to_string(path)
+            .unwrap_or_default()
+            .contains(expected)
+        {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .unwrap_or_else(|_| panic!("doorman didn't log '{}' in time", expected))
+}
+
+#[tokio::test]
+async fn signals_work_the_same_in_the_background() {
+    let dir = tempfile::tempdir().unwrap();
+    fixture(dir.path());
+    let log = dir.path().join("doorman.log");
+    let pid_file = dir.path().join("doorman.pid");
+    let status = Command::new(env!("CARGO_BIN_EXE_aws_doorman"))
+        .args([
+            "--prefix-list-id",
+            PREFIX_LIST_ID,
+            "--description",
+            "office",
+        ])
+        .arg("--simulate")
+        .arg(dir.path())
+        .arg("--state-file")
+        .arg(dir.path().join("state.json"))
+        .args(["--notify-backend", "log", "--interval", "60"])
+        .arg("--daemon")
+        .arg("--log-file")
+        .arg(&log)
+        .arg("--pid-file")
+        .arg(&pid_file)
+        .status()
+        .await
+        .unwrap();
+    // The parent returns once the child is detached
+    assert!(status.success());
+    wait_for_log(&log, "added [198.51.100.7/32], removed []").await;
+    let pid = fs::read_to_string(&pid_file).unwrap().trim().to_string();
+
+    send_to(&pid, "-HUP").await;
+    wait_for_log(&log, "There is no configuration file to reload.").await;
+    send_to(&pid, "-TERM").await;
+    wait_for_log(&log, "added [], removed [198.51.100.7/32]").await;
+}